@@ -0,0 +1,291 @@
+//! Animated runs of charts, to show how control moves through them.
+//!
+//! The animation is plain SVG with SMIL: every block a run visits is
+//! highlighted in turn, the edge it was entered by pulses, and a panel beside
+//! the chart shows the variables after the block. It loops forever and needs
+//! no script.
+
+use std::collections::BTreeMap;
+
+use crate::{
+    block::{get_num_columns_num_lines, Block},
+    chart::Procedure,
+    config::Config,
+    expression::Variables,
+    interpreter::Trace,
+    structured::{Layouter, Point},
+    svg::{Svg, SvgShape},
+};
+
+/// How many characters of a value the panel shows at most.
+const MAX_VALUE_WIDTH: usize = 40;
+
+/// A block highlighted for one step of the animation.
+struct Frame<'a> {
+    block: &'a Block,
+    text: String,
+    branch: Option<&'a str>,
+    variables: Option<&'a Variables>,
+    output: Option<&'a str>,
+}
+
+pub struct Animator<'a> {
+    config: &'a Config,
+    step_duration: f64,
+}
+
+impl<'a> Animator<'a> {
+    pub fn new(config: &'a Config) -> Self {
+        Self {
+            config,
+            step_duration: 1.0,
+        }
+    }
+
+    /// Sets how many seconds every block stays highlighted, one by default.
+    pub fn step_duration(mut self, seconds: f64) -> Self {
+        self.step_duration = seconds;
+        self
+    }
+
+    /// Renders `procedure` with the steps of `trace` that ran in it, from its
+    /// start to its end terminal. Steps in the procedures it called are left
+    /// out, so a call stays highlighted until it returns.
+    pub fn animate(&self, procedure: &Procedure, trace: &Trace) -> Svg {
+        let link = |_: &str| None;
+        let mut layout = Layouter::new(self.config, &link).layout(procedure);
+        let margin = self.config.grid_size();
+        layout.displace(margin, margin);
+        let (start, end) = layout.terminals();
+        let mut frames = vec![Frame {
+            block: start,
+            text: procedure.name.clone(),
+            branch: None,
+            variables: None,
+            output: None,
+        }];
+        for step in &trace.steps {
+            if step.procedure != procedure.name {
+                continue;
+            }
+            let Some(block) = layout.block_of(step.statement) else {
+                continue;
+            };
+            frames.push(Frame {
+                block,
+                text: step.text.clone(),
+                branch: step.branch.as_deref(),
+                variables: Some(&step.variables),
+                output: step.output.as_deref(),
+            });
+        }
+        let variables = frames.last().and_then(|frame| frame.variables);
+        frames.push(Frame {
+            block: end,
+            text: String::from("end"),
+            branch: None,
+            variables,
+            output: None,
+        });
+
+        let duration = self.step_duration;
+        let period = frames.len() as f64 * duration;
+        let span = |k: usize| (k as f64 * duration, (k + 1) as f64 * duration);
+        // Blocks in the order they are first visited, and edges by their
+        // points.
+        let mut visits: Vec<(&Block, Vec<(f64, f64)>)> = vec![];
+        let mut trails: BTreeMap<Vec<Point>, Vec<(f64, f64)>> = BTreeMap::new();
+        for (k, frame) in frames.iter().enumerate() {
+            match visits
+                .iter_mut()
+                .find(|(block, _)| std::ptr::eq(*block, frame.block))
+            {
+                Some((_, spans)) => spans.push(span(k)),
+                None => visits.push((frame.block, vec![span(k)])),
+            }
+            if let Some(previous) = k.checked_sub(1).map(|k| &frames[k]) {
+                for points in layout.edges_between(previous.block, frame.block, previous.branch) {
+                    trails.entry(points).or_default().push(span(k));
+                }
+            }
+        }
+
+        let mut svg = Svg::new(self.config);
+        svg.add_style(".active rect", &[("fill", "#ffe082")]);
+        svg.add_style(".active polygon", &[("fill", "#ffe082")]);
+        svg.add_style(".active circle", &[("fill", "#ffe082")]);
+        svg.add_style(
+            ".trail polyline",
+            &[("stroke", "#e53935"), ("stroke-width", "3")],
+        );
+        svg.add_style(".variables text", &[("text-anchor", "start")]);
+        // The highlights go under the chart, whose blocks are not filled.
+        for (block, spans) in visits {
+            svg.push_shape(SvgShape::Timed {
+                class: String::from("active"),
+                period,
+                spans,
+                shape: Box::new(block.outline()),
+            });
+        }
+        let (chart, (width, height)) = layout.to_svg(self.config);
+        svg.push_shape(chart);
+        for (points, spans) in trails {
+            svg.push_shape(SvgShape::Timed {
+                class: String::from("trail"),
+                period,
+                spans,
+                shape: Box::new(SvgShape::Pulse {
+                    period: duration / 2.0,
+                    shape: Box::new(SvgShape::Polyline(points)),
+                }),
+            });
+        }
+
+        let font_size = self.config.font_size();
+        let line_height = font_size * 3 / 2;
+        let (left, top) = (width + 2 * margin, margin);
+        let panels: Vec<Vec<String>> = frames
+            .iter()
+            .enumerate()
+            .map(|(k, frame)| panel(k, frames.len(), frame))
+            .collect();
+        let lines = panels.iter().map(Vec::len).max().unwrap_or(0);
+        let columns = panels
+            .iter()
+            .flatten()
+            .map(|line| get_num_columns_num_lines(line).0)
+            .max()
+            .unwrap_or(0);
+        let panel_width = font_size / 2 * columns + 2 * font_size;
+        let panel_height = line_height * (lines + 1);
+        svg.push_shape(SvgShape::Rect {
+            x: left,
+            y: top,
+            width: panel_width,
+            height: panel_height,
+        });
+        for (k, lines) in panels.into_iter().enumerate() {
+            let texts = lines
+                .iter()
+                .enumerate()
+                .map(|(i, line)| SvgShape::Text {
+                    cx: left + font_size,
+                    cy: top + (i + 1) * line_height,
+                    content: String::from(line),
+                })
+                .collect();
+            svg.push_shape(SvgShape::Timed {
+                class: String::from("variables"),
+                period,
+                spans: vec![span(k)],
+                shape: Box::new(SvgShape::Group(texts)),
+            });
+        }
+        svg.set_size(
+            left + panel_width + margin,
+            height.max(top + panel_height) + margin,
+        );
+        svg
+    }
+}
+
+/// The lines of the panel for the `k`th of `count` frames: where the run is,
+/// then the variables and what the block printed.
+fn panel(k: usize, count: usize, frame: &Frame) -> Vec<String> {
+    let first = frame.text.lines().next().unwrap_or_default();
+    let mut lines = vec![format!("{}/{}: {}", k + 1, count, first)];
+    if let Some(variables) = frame.variables {
+        lines.extend(variables.iter().map(|(name, value)| {
+            let value = value.to_string();
+            match value.char_indices().nth(MAX_VALUE_WIDTH) {
+                Some((end, _)) => format!("{} = {}...", name, &value[..end]),
+                None => format!("{} = {}", name, value),
+            }
+        }));
+    }
+    if let Some(output) = frame.output {
+        lines.push(format!("> {}", output));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::ConfigBuilder, expression::Value, interpreter::Interpreter, parser::parse,
+    };
+
+    const SOURCE: &str = "proc main
+    io read n
+    while n > 0
+        process n -= 1
+    end
+    call helper
+end
+proc helper
+    io print \"hi\"
+end
+";
+
+    fn animate(duration: f64) -> String {
+        let config = ConfigBuilder::new().build();
+        let program = parse(SOURCE).unwrap();
+        let trace = Interpreter::new(&program)
+            .run("main", &[Value::Int(2)])
+            .unwrap();
+        Animator::new(&config)
+            .step_duration(duration)
+            .animate(&program.procedures[0], &trace)
+            .to_string()
+    }
+
+    #[test]
+    fn every_visited_block_is_highlighted() {
+        let svg = animate(1.0);
+        // The start, `read n`, the loop, `n -= 1`, the call and the end, of
+        // which the loop and its body are visited more than once.
+        assert_eq!(svg.matches(r#"<g class="active""#).count(), 6);
+        // Start, read, 3 checks of the loop, 2 decrements, the call and end.
+        let frames = 9;
+        assert_eq!(svg.matches(r#"<g class="variables""#).count(), frames);
+        assert!(svg.contains(&format!(r#"dur="{}s""#, frames)));
+        // The steps inside the called procedure are left out.
+        assert!(!svg.contains("> hi"));
+        assert!(svg.contains("8/9: helper"));
+        assert!(svg.contains("9/9: end"));
+        assert!(svg.contains(">n = 0<"));
+    }
+
+    #[test]
+    fn steps_last_their_duration() {
+        assert!(animate(0.5).contains(r#"dur="4.5s""#));
+    }
+
+    #[test]
+    fn long_values_are_cut() {
+        let config = ConfigBuilder::new().build();
+        let block = crate::block::BlockBuilder::new(&config)
+            .build(crate::block::BlockKind::Process, String::from("x"));
+        let mut variables = Variables::new();
+        variables.insert(String::from("s"), Value::Str("é".repeat(50)));
+        let frame = Frame {
+            block: &block,
+            text: String::from("s = x\nmore"),
+            branch: None,
+            variables: Some(&variables),
+            output: Some("done"),
+        };
+        let value = Value::Str("é".repeat(50)).to_string();
+        let cut: String = value.chars().take(MAX_VALUE_WIDTH).collect();
+        assert_eq!(
+            panel(0, 2, &frame),
+            [
+                String::from("1/2: s = x"),
+                format!("s = {}...", cut),
+                String::from("> done"),
+            ]
+        );
+    }
+}
@@ -1,15 +1,30 @@
 use std::cmp::Ordering;
 
 use crate::{
-    block::{Block, BlockBuilder, BlockKind},
-    config::Config,
+    block::{get_num_columns_num_lines, Block, BlockBuilder, BlockKind, Bounds},
+    config::{Config, Direction},
+    label::LabelPlacer,
     svg::SvgShape,
 };
 
+/// A titled frame around the blocks `start..end`.
+struct Group {
+    title: String,
+    start: usize,
+    end: usize,
+}
+
 pub struct BasicBlock<'a> {
+    config: &'a Config,
     block_builder: &'a BlockBuilder,
-    distance: usize,
     blocks: Vec<Block>,
+    labels: Vec<Option<String>>,
+    groups: Vec<Group>,
+    open_groups: Vec<usize>,
+    // Groups begun and ended since the last push, which need extra room
+    // between the last block and the next one.
+    entered: usize,
+    left: usize,
 }
 
 impl<'a> BasicBlock<'a> {
@@ -20,9 +35,14 @@ impl<'a> BasicBlock<'a> {
         content: String,
     ) -> Self {
         Self {
+            config,
             block_builder,
-            distance: config.distance(),
             blocks: vec![block_builder.build(kind, content)],
+            labels: vec![],
+            groups: vec![],
+            open_groups: vec![],
+            entered: 0,
+            left: 0,
         }
     }
 
@@ -34,15 +54,248 @@ impl<'a> BasicBlock<'a> {
         self.blocks.last().unwrap().bottom_pos()
     }
 
-    pub fn to_svg(&self) -> SvgShape {
-        let mut group: Vec<SvgShape> = self.blocks.iter().map(|block| block.to_svg()).collect();
-        for block in &self.blocks[..self.blocks.len() - 1] {
-            let (x, y) = block.bottom_pos();
-            group.push(SvgShape::DownArrow {
+    /// Where the flow enters the first block.
+    pub fn entry_pos(&self) -> (usize, usize) {
+        self.blocks
+            .first()
+            .unwrap()
+            .entry_pos(self.config.direction())
+    }
+
+    /// Where the flow leaves the last block.
+    pub fn exit_pos(&self) -> (usize, usize) {
+        self.blocks
+            .last()
+            .unwrap()
+            .exit_pos(self.config.direction())
+    }
+
+    /// Frames every block pushed from now until the matching `end_group`
+    /// in a cluster titled `title`. Groups can be nested.
+    pub fn begin_group(&mut self, title: String) {
+        self.groups.push(Group {
+            title,
+            start: self.blocks.len(),
+            end: self.blocks.len(),
+        });
+        self.open_groups.push(self.groups.len() - 1);
+        self.entered += 1;
+    }
+
+    /// Like [`begin_group`](Self::begin_group), but the cluster also frames
+    /// the last block, such as the one the chart was created with.
+    pub fn begin_group_from_last(&mut self, title: String) {
+        let start = self.blocks.len() - 1;
+        self.groups.push(Group {
+            title,
+            start,
+            end: start,
+        });
+        self.open_groups.push(self.groups.len() - 1);
+        // The frame needs the room that `begin_group` would have left
+        // before the block.
+        if start > 0 {
+            let gap = self.entered_gap();
+            let (last, earlier) = self.blocks.split_last_mut().unwrap();
+            match self.config.direction() {
+                Direction::TopToBottom => last.displace(0, gap),
+                Direction::LeftToRight => last.displace(gap, 0),
+                Direction::BottomToTop => earlier.iter_mut().for_each(|b| b.displace(0, gap)),
+                Direction::RightToLeft => earlier.iter_mut().for_each(|b| b.displace(gap, 0)),
+            }
+        }
+        self.make_room();
+    }
+
+    pub fn end_group(&mut self) {
+        if let Some(index) = self.open_groups.pop() {
+            self.groups[index].end = self.blocks.len();
+            self.left += 1;
+        }
+    }
+
+    /// Ends the innermost group by collapsing it into a predefined-process
+    /// block with its title, which links to the chart at `href` with its
+    /// expanded content. The blocks and the groups inside it are dropped.
+    pub fn collapse_group(&mut self, href: String) {
+        let Some(index) = self.open_groups.pop() else {
+            return;
+        };
+        let Group { title, start, .. } = self.groups.remove(index);
+        // Every group begun after this one is inside it.
+        self.groups.truncate(index);
+        if start == self.blocks.len() {
+            self.entered -= 1;
+        } else {
+            self.entered = 0;
+            self.left = 0;
+        }
+        if start == 0 {
+            self.blocks.clear();
+            self.labels.clear();
+            let mut block = self.block_builder.build(BlockKind::Subroutine, title);
+            block.set_link(href);
+            self.blocks.push(block);
+            self.make_room();
+            return;
+        }
+        let label = self.labels[start - 1].take();
+        self.blocks.truncate(start);
+        self.labels.truncate(start - 1);
+        self.push_edge(BlockKind::Subroutine, title, label);
+        self.blocks.last_mut().unwrap().set_link(href);
+    }
+
+    /// Pushes a block that hyperlinks to `href`.
+    pub fn push_linked(&mut self, kind: BlockKind, content: String, href: String) {
+        self.push(kind, content);
+        self.blocks.last_mut().unwrap().set_link(href);
+    }
+
+    fn group_padding(&self) -> usize {
+        self.config.grid_size() / 2
+    }
+
+    fn group_title_height(&self) -> usize {
+        self.config.font_size() * 3 / 2
+    }
+
+    fn fit_to_grid(&self, length: usize) -> usize {
+        let grid_size = self.config.grid_size();
+        length.div_ceil(grid_size) * grid_size
+    }
+
+    /// The room a group begun between two blocks adds between them. Titles
+    /// sit on top of their frames, so they only widen the gap on the side of
+    /// a group that faces up.
+    fn entered_gap(&self) -> usize {
+        let padding = self.group_padding();
+        match self.config.direction() {
+            Direction::TopToBottom => self.fit_to_grid(padding + self.group_title_height()),
+            _ => self.fit_to_grid(padding),
+        }
+    }
+
+    /// The room a group ended between two blocks adds between them.
+    fn left_gap(&self) -> usize {
+        let padding = self.group_padding();
+        match self.config.direction() {
+            Direction::BottomToTop => self.fit_to_grid(padding + self.group_title_height()),
+            _ => self.fit_to_grid(padding),
+        }
+    }
+
+    /// Keeps enough room above and to the left of the last block for the
+    /// frames around it.
+    fn make_room(&mut self) {
+        let depth = self.open_groups.len();
+        let padding = self.group_padding();
+        let title_height = self.group_title_height();
+        let (x, y) = self.blocks.last().unwrap().pos();
+        let dx = self.fit_to_grid((depth * padding).saturating_sub(x));
+        let dy = self.fit_to_grid((depth * (padding + title_height)).saturating_sub(y));
+        if dx > 0 || dy > 0 {
+            self.displace(dx, dy);
+        }
+    }
+
+    fn group_frames(&self) -> Vec<Option<Bounds>> {
+        let padding = self.group_padding();
+        let title_height = self.group_title_height();
+        let ends: Vec<usize> = (0..self.groups.len())
+            .map(|index| match self.open_groups.contains(&index) {
+                true => self.blocks.len(),
+                false => self.groups[index].end,
+            })
+            .collect();
+        let mut frames: Vec<Option<Bounds>> = vec![None; self.groups.len()];
+        // Nested groups are always begun after the groups around them, so
+        // walking backwards computes inner frames first.
+        for (index, group) in self.groups.iter().enumerate().rev() {
+            let end = ends[index];
+            let inner = self.groups[index + 1..]
+                .iter()
+                .zip(&ends[index + 1..])
+                .zip(&frames[index + 1..])
+                .filter(|((inner, inner_end), _)| group.start <= inner.start && **inner_end <= end)
+                .filter_map(|(_, frame)| *frame);
+            let Some(Bounds {
                 x,
                 y,
-                height: self.distance,
-            })
+                width,
+                height,
+            }) = self.blocks[group.start..end]
+                .iter()
+                .map(|block| block.bounds())
+                .chain(inner)
+                .reduce(|a, b| a.union(&b))
+            else {
+                continue;
+            };
+            frames[index] = Some(Bounds {
+                x: x.saturating_sub(padding),
+                y: y.saturating_sub(padding + title_height),
+                width: width + 2 * padding,
+                height: height + 2 * padding + title_height,
+            });
+        }
+        frames
+    }
+
+    /// The area covered by the blocks and the frames around them.
+    pub fn bounds(&self) -> Bounds {
+        self.blocks
+            .iter()
+            .map(|block| block.bounds())
+            .chain(self.group_frames().into_iter().flatten())
+            .reduce(|a, b| a.union(&b))
+            .unwrap()
+    }
+
+    pub fn to_svg(&self) -> SvgShape {
+        let direction = self.config.direction();
+        let font_size = self.config.font_size();
+        let padding = self.group_padding();
+        let title_height = self.group_title_height();
+        let mut placer = LabelPlacer::new(self.config);
+        let mut group = vec![];
+        for (frame, cluster) in self.group_frames().into_iter().zip(&self.groups) {
+            let Some(frame) = frame else {
+                continue;
+            };
+            let (num_columns, _) = get_num_columns_num_lines(&cluster.title);
+            let title_width = font_size / 2 * num_columns;
+            group.push(SvgShape::Frame {
+                x: frame.x,
+                y: frame.y,
+                width: frame.width,
+                height: frame.height,
+                r: padding,
+            });
+            group.push(SvgShape::Text {
+                cx: frame.x + padding + title_width / 2,
+                cy: frame.y + (padding + title_height) / 2,
+                content: cluster.title.clone(),
+            });
+            placer.add_obstacle(Bounds {
+                x: frame.x + padding,
+                y: frame.y,
+                width: title_width,
+                height: padding + title_height,
+            });
+        }
+        group.extend(self.blocks.iter().map(|block| block.to_svg()));
+        self.blocks
+            .iter()
+            .for_each(|block| placer.add_obstacle(block.bounds()));
+        for (pair, label) in self.blocks.windows(2).zip(&self.labels) {
+            let from = pair[0].exit_pos(direction);
+            let to = pair[1].entry_pos(direction);
+            let length = from.0.abs_diff(to.0) + from.1.abs_diff(to.1);
+            group.push(SvgShape::arrow(direction, from, length));
+            if let Some(label) = label {
+                group.push(placer.place(from, to, label));
+            }
         }
         SvgShape::Group(group)
     }
@@ -54,16 +307,200 @@ impl<'a> BasicBlock<'a> {
     }
 
     pub fn push(&mut self, kind: BlockKind, content: String) {
-        let (bb_bottom_x, bb_bottom_y) = self.bottom_pos();
+        self.push_edge(kind, content, None);
+    }
+
+    /// Pushes a block whose incoming edge is annotated with `label`, such as
+    /// the "yes" or "no" branch of a decision.
+    pub fn push_with_label(&mut self, kind: BlockKind, content: String, label: String) {
+        self.push_edge(kind, content, Some(label));
+    }
+
+    fn push_edge(&mut self, kind: BlockKind, content: String, label: Option<String>) {
+        let direction = self.config.direction();
+        let distance = self.config.distance()
+            + self.entered * self.entered_gap()
+            + self.left * self.left_gap();
+        self.entered = 0;
+        self.left = 0;
         let mut block = self.block_builder.build(kind, content);
-        let (block_top_x, _) = block.top_pos();
-        match block_top_x.cmp(&bb_bottom_x) {
-            Ordering::Less => block.displace(bb_bottom_x - block_top_x, 0),
-            Ordering::Greater => self.displace(block_top_x - bb_bottom_x, 0),
-            _ => (),
-        }
-        let dy = bb_bottom_y + self.distance;
-        block.displace(0, dy);
+        let (bb_exit_x, bb_exit_y) = self.exit_pos();
+        let (block_entry_x, block_entry_y) = block.entry_pos(direction);
+        match direction {
+            Direction::TopToBottom | Direction::BottomToTop => {
+                match block_entry_x.cmp(&bb_exit_x) {
+                    Ordering::Less => block.displace(bb_exit_x - block_entry_x, 0),
+                    Ordering::Greater => self.displace(block_entry_x - bb_exit_x, 0),
+                    _ => (),
+                }
+            }
+            Direction::LeftToRight | Direction::RightToLeft => {
+                match block_entry_y.cmp(&bb_exit_y) {
+                    Ordering::Less => block.displace(0, bb_exit_y - block_entry_y),
+                    Ordering::Greater => self.displace(0, block_entry_y - bb_exit_y),
+                    _ => (),
+                }
+            }
+        }
+        // A new block is built at the origin, so growing towards the origin
+        // first makes room by moving the existing blocks away from it.
+        match direction {
+            Direction::TopToBottom => {
+                let (_, bb_exit_y) = self.exit_pos();
+                block.displace(0, bb_exit_y + distance);
+            }
+            Direction::BottomToTop => {
+                let needed = block.height() + distance;
+                if bb_exit_y < needed {
+                    self.displace(0, needed - bb_exit_y);
+                }
+                let (_, bb_exit_y) = self.exit_pos();
+                block.displace(0, bb_exit_y - needed);
+            }
+            Direction::LeftToRight => {
+                let (bb_exit_x, _) = self.exit_pos();
+                block.displace(bb_exit_x + distance, 0);
+            }
+            Direction::RightToLeft => {
+                let needed = block.width() + distance;
+                if bb_exit_x < needed {
+                    self.displace(needed - bb_exit_x, 0);
+                }
+                let (bb_exit_x, _) = self.exit_pos();
+                block.displace(bb_exit_x - needed, 0);
+            }
+        }
         self.blocks.push(block);
+        self.labels.push(label);
+        self.make_room();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigBuilder;
+
+    fn contains(outer: &Bounds, inner: &Bounds) -> bool {
+        outer.x <= inner.x
+            && outer.y <= inner.y
+            && inner.x + inner.width <= outer.x + outer.width
+            && inner.y + inner.height <= outer.y + outer.height
+    }
+
+    fn content(name: &str) -> String {
+        String::from(name)
+    }
+
+    #[test]
+    fn groups_frame_their_blocks() {
+        for direction in [
+            Direction::TopToBottom,
+            Direction::BottomToTop,
+            Direction::LeftToRight,
+            Direction::RightToLeft,
+        ] {
+            let config = ConfigBuilder::new().direction(direction).build();
+            let builder = BlockBuilder::new(&config);
+            let mut chart = BasicBlock::new(&config, &builder, BlockKind::Terminal, content("a"));
+            chart.begin_group(content("outer"));
+            chart.push(BlockKind::Process, content("b"));
+            chart.begin_group(content("inner"));
+            chart.push(BlockKind::Process, content("c"));
+            chart.end_group();
+            chart.end_group();
+            chart.push(BlockKind::Terminal, content("d"));
+            let frames: Vec<Bounds> = chart.group_frames().into_iter().flatten().collect();
+            let blocks: Vec<Bounds> = chart.blocks.iter().map(Block::bounds).collect();
+            assert_eq!(frames.len(), 2);
+            assert!(contains(&frames[0], &frames[1]));
+            assert!(contains(&frames[0], &blocks[1]));
+            assert!(contains(&frames[1], &blocks[2]));
+            assert!(!frames[1].overlaps(&blocks[1]));
+            for outside in [&blocks[0], &blocks[3]] {
+                assert!(!frames[0].overlaps(outside));
+            }
+        }
+    }
+
+    #[test]
+    fn groups_can_frame_the_first_block() {
+        for direction in [Direction::TopToBottom, Direction::BottomToTop] {
+            let config = ConfigBuilder::new().direction(direction).build();
+            let builder = BlockBuilder::new(&config);
+            let mut chart = BasicBlock::new(&config, &builder, BlockKind::Terminal, content("a"));
+            chart.begin_group_from_last(content("start"));
+            chart.push(BlockKind::Process, content("b"));
+            chart.end_group();
+            chart.push(BlockKind::Process, content("c"));
+            chart.begin_group_from_last(content("end"));
+            chart.end_group();
+            let frames: Vec<Bounds> = chart.group_frames().into_iter().flatten().collect();
+            let blocks: Vec<Bounds> = chart.blocks.iter().map(Block::bounds).collect();
+            assert!(contains(&frames[0], &blocks[0]));
+            assert!(contains(&frames[0], &blocks[1]));
+            assert!(!frames[0].overlaps(&blocks[2]));
+            assert!(contains(&frames[1], &blocks[2]));
+            assert!(!frames[1].overlaps(&blocks[1]));
+            assert!(!frames[0].overlaps(&frames[1]));
+        }
+    }
+
+    #[test]
+    fn collapsed_groups_become_one_block() {
+        let config = ConfigBuilder::new().build();
+        let builder = BlockBuilder::new(&config);
+        let mut chart = BasicBlock::new(&config, &builder, BlockKind::Terminal, content("a"));
+        chart.begin_group(content("phase"));
+        chart.push_with_label(BlockKind::Process, content("b"), content("yes"));
+        chart.begin_group(content("inner"));
+        chart.push(BlockKind::Process, content("c"));
+        chart.end_group();
+        chart.collapse_group(content("phase.svg"));
+        chart.push(BlockKind::Terminal, content("d"));
+        assert!(chart.groups.is_empty());
+        let kinds: Vec<BlockKind> = chart.blocks.iter().map(Block::kind).collect();
+        assert!(matches!(
+            kinds.as_slice(),
+            [
+                BlockKind::Terminal,
+                BlockKind::Subroutine,
+                BlockKind::Terminal
+            ]
+        ));
+        assert_eq!(chart.labels, [Some(content("yes")), None]);
+        let SvgShape::Group(shapes) = chart.to_svg() else {
+            panic!("a chart is a group");
+        };
+        let links = shapes
+            .iter()
+            .filter(|shape| matches!(shape, SvgShape::Link { href, .. } if href == "phase.svg"))
+            .count();
+        assert_eq!(links, 1);
+    }
+
+    #[test]
+    fn collapsing_keeps_the_outer_groups() {
+        let config = ConfigBuilder::new().build();
+        let builder = BlockBuilder::new(&config);
+        let mut chart = BasicBlock::new(&config, &builder, BlockKind::Terminal, content("a"));
+        chart.begin_group_from_last(content("outer"));
+        chart.begin_group(content("inner"));
+        chart.push(BlockKind::Process, content("b"));
+        chart.collapse_group(content("inner.svg"));
+        chart.end_group();
+        assert_eq!(chart.groups.len(), 1);
+        let frames: Vec<Bounds> = chart.group_frames().into_iter().flatten().collect();
+        for block in &chart.blocks {
+            assert!(contains(&frames[0], &block.bounds()));
+        }
+
+        let mut chart = BasicBlock::new(&config, &builder, BlockKind::Process, content("a"));
+        chart.begin_group_from_last(content("all"));
+        chart.push(BlockKind::Process, content("b"));
+        chart.collapse_group(content("all.svg"));
+        assert_eq!(chart.blocks.len(), 1);
+        assert!(chart.labels.is_empty());
+        assert!(matches!(chart.blocks[0].kind(), BlockKind::Subroutine));
     }
 }
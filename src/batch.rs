@@ -0,0 +1,585 @@
+//! Rendering every source under a directory into a mirrored output tree.
+//!
+//! The charts of the source `a/b.flow` go to `a/b/<chart>.svg` as SVG, to
+//! `a/b/<chart>.html` as viewer pages, and to `a/b.html` as one page with
+//! all of them. Sources are rendered in parallel.
+//!
+//! A cache manifest in the output directory, `.flowchart-cache.json`,
+//! records the hash of every source and of the settings it was rendered
+//! with, and the files it was rendered to. A source whose hashes did not
+//! change and whose files are all still there is skipped. Files a source is
+//! no longer rendered to, and those of sources that are gone, are removed.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{config::Config, frontend, html, svg::SvgWriter};
+
+/// The name of the cache manifest in the output directory.
+pub const MANIFEST: &str = ".flowchart-cache.json";
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Format {
+    /// Every chart as an SVG document.
+    Svg,
+    /// Every chart as a viewer page, which also shows the source.
+    Viewer,
+    /// All the charts of a source on one page.
+    Html,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "svg" => Ok(Format::Svg),
+            "viewer" => Ok(Format::Viewer),
+            "html" => Ok(Format::Html),
+            _ => Err(format!("unknown format `{}`", s)),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Format::Svg => "svg",
+            Format::Viewer => "viewer",
+            Format::Html => "html",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// What a batch did with the sources, by their paths from the source
+/// directory, and the files it removed, by their paths from the output
+/// directory.
+#[derive(Default)]
+pub struct Report {
+    pub rendered: Vec<PathBuf>,
+    pub skipped: Vec<PathBuf>,
+    pub failed: Vec<(PathBuf, String)>,
+    pub removed: Vec<PathBuf>,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+struct Manifest {
+    sources: BTreeMap<String, Entry>,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+struct Entry {
+    /// The hash of the source, empty if rendering it failed.
+    hash: String,
+    /// The hash of the settings it was rendered with.
+    settings: String,
+    /// The files it was rendered to, from the output directory.
+    outputs: Vec<String>,
+}
+
+/// What became of one source.
+enum Outcome {
+    Skipped,
+    Rendered(Vec<String>),
+    /// The message, with the files written before the source failed.
+    Failed(String, Vec<String>),
+}
+
+pub struct Batch<'a> {
+    config: &'a Config,
+    formats: Vec<Format>,
+    layered: bool,
+    jobs: usize,
+    config_key: String,
+    /// Files under the source directory that are not sources.
+    excluded: Vec<PathBuf>,
+}
+
+impl<'a> Batch<'a> {
+    pub fn new(config: &'a Config) -> Self {
+        Self {
+            config,
+            formats: vec![Format::Svg],
+            layered: false,
+            jobs: thread::available_parallelism().map_or(1, usize::from),
+            config_key: String::new(),
+            excluded: vec![],
+        }
+    }
+
+    /// Sets the formats to render to, only SVG by default.
+    pub fn formats(mut self, formats: &[Format]) -> Self {
+        self.formats = formats.to_vec();
+        self
+    }
+
+    /// Draws graphs in layers, as they are.
+    pub fn layered(mut self, layered: bool) -> Self {
+        self.layered = layered;
+        self
+    }
+
+    /// Sets how many sources are rendered at once, one per processor by
+    /// default.
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs.max(1);
+        self
+    }
+
+    /// Sets what tells the config apart for the cache, such as the text of
+    /// the file it was read from. Sources rendered with another key are
+    /// rendered again.
+    pub fn config_key(mut self, key: &str) -> Self {
+        self.config_key = String::from(key);
+        self
+    }
+
+    /// Leaves `path` out of the sources, such as a config file that is
+    /// among them.
+    pub fn exclude(mut self, path: &Path) -> Self {
+        self.excluded.push(path.to_path_buf());
+        self
+    }
+
+    /// Renders the sources under `source_dir` that changed since the last
+    /// run into `output_dir`. Sources that fail are reported, and do not
+    /// stop the others.
+    pub fn run(&self, source_dir: &Path, output_dir: &Path) -> io::Result<Report> {
+        fs::create_dir_all(output_dir)?;
+        let manifest_path = output_dir.join(MANIFEST);
+        // A manifest that cannot be read only means rendering everything.
+        let mut manifest: Manifest = fs::read_to_string(&manifest_path)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        let settings = self.settings_hash();
+
+        let canonical = |path: &Path| fs::canonicalize(path).ok();
+        let excluded: Vec<_> = self.excluded.iter().map(|path| canonical(path)).collect();
+        let output = canonical(output_dir);
+        let sources: Vec<PathBuf> = frontend::find_sources(source_dir)
+            .into_iter()
+            .filter(|source| {
+                let source = canonical(source);
+                !excluded.contains(&source)
+                    && !source
+                        .zip(output.as_ref())
+                        .is_some_and(|(s, o)| s.starts_with(o))
+            })
+            .collect();
+
+        // Sources whose charts would go to the same place, such as `a.c`
+        // and `a.py`, are not rendered over each other.
+        let mut stems: HashMap<PathBuf, &Path> = HashMap::new();
+        let mut outcomes: Vec<Option<(String, Outcome)>> = sources
+            .iter()
+            .map(|source| {
+                let relative = source.strip_prefix(source_dir).unwrap_or(source);
+                let other = stems.insert(relative.with_extension(""), relative)?;
+                let message = format!(
+                    "{}: its charts would go where those of {} go",
+                    source.display(),
+                    source_dir.join(other).display()
+                );
+                Some((String::new(), Outcome::Failed(message, vec![])))
+            })
+            .collect();
+
+        let next = AtomicUsize::new(0);
+        let done = Mutex::new(vec![]);
+        thread::scope(|scope| {
+            for _ in 0..self.jobs.min(sources.len()) {
+                scope.spawn(|| loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(source) = sources.get(i) else {
+                        break;
+                    };
+                    if outcomes[i].is_some() {
+                        continue;
+                    }
+                    let relative = relative(source, source_dir);
+                    let entry = manifest.sources.get(&relative);
+                    let outcome = self.render(source, &relative, output_dir, &settings, entry);
+                    done.lock().unwrap().push((i, outcome));
+                });
+            }
+        });
+        for (i, outcome) in done.into_inner().unwrap() {
+            outcomes[i] = Some(outcome);
+        }
+
+        let mut report = Report::default();
+        let mut kept: BTreeMap<String, Entry> = BTreeMap::new();
+        for (source, outcome) in sources.iter().zip(outcomes) {
+            let relative_path = source.strip_prefix(source_dir).unwrap_or(source);
+            let relative = relative(source, source_dir);
+            let old = manifest.sources.remove(&relative);
+            let (hash, outcome) = outcome.unwrap();
+            match outcome {
+                Outcome::Skipped => {
+                    report.skipped.push(relative_path.to_path_buf());
+                    kept.insert(relative, old.unwrap());
+                }
+                Outcome::Rendered(outputs) => {
+                    report.rendered.push(relative_path.to_path_buf());
+                    let stale = old.map(|old| old.outputs).unwrap_or_default();
+                    for file in stale.into_iter().filter(|file| !outputs.contains(file)) {
+                        remove(output_dir, &file, &mut report)?;
+                    }
+                    let entry = Entry {
+                        hash,
+                        settings: settings.clone(),
+                        outputs,
+                    };
+                    kept.insert(relative, entry);
+                }
+                Outcome::Failed(message, written) => {
+                    report.failed.push((relative_path.to_path_buf(), message));
+                    // The files stay until the source renders again, which
+                    // the empty hash makes sure it does. Those written before
+                    // it failed are listed with them, to be removed as well
+                    // once they are stale.
+                    if old.is_some() || !written.is_empty() {
+                        let mut outputs = old.map(|old| old.outputs).unwrap_or_default();
+                        for file in written {
+                            if !outputs.contains(&file) {
+                                outputs.push(file);
+                            }
+                        }
+                        let entry = Entry {
+                            hash: String::new(),
+                            settings: settings.clone(),
+                            outputs,
+                        };
+                        kept.insert(relative, entry);
+                    }
+                }
+            }
+        }
+        // Whatever is left is from sources that are gone.
+        for entry in manifest.sources.into_values() {
+            for file in entry.outputs {
+                remove(output_dir, &file, &mut report)?;
+            }
+        }
+        let manifest = Manifest { sources: kept };
+        fs::write(
+            &manifest_path,
+            serde_json::to_string_pretty(&manifest).map_err(io::Error::other)?,
+        )?;
+        Ok(report)
+    }
+
+    /// The hash of everything besides the source that its files depend on.
+    fn settings_hash(&self) -> String {
+        let formats: Vec<String> = self.formats.iter().map(Format::to_string).collect();
+        let settings = format!(
+            "{}\n{}\n{}\n{}",
+            env!("CARGO_PKG_VERSION"),
+            formats.join(","),
+            self.layered,
+            self.config_key
+        );
+        hash(settings.as_bytes())
+    }
+
+    /// Renders `source` unless `entry` shows it is up to date, and returns
+    /// its hash with what became of it.
+    fn render(
+        &self,
+        source: &Path,
+        relative: &str,
+        output_dir: &Path,
+        settings: &str,
+        entry: Option<&Entry>,
+    ) -> (String, Outcome) {
+        let text = match fs::read_to_string(source) {
+            Ok(text) => text,
+            Err(e) => {
+                let message = format!("{}: {}", source.display(), e);
+                return (String::new(), Outcome::Failed(message, vec![]));
+            }
+        };
+        let hash = hash(text.as_bytes());
+        let up_to_date = entry.is_some_and(|entry| {
+            entry.hash == hash
+                && entry.settings == settings
+                && entry
+                    .outputs
+                    .iter()
+                    .all(|file| is_inside(file) && output_dir.join(file).is_file())
+        });
+        if up_to_date {
+            return (hash, Outcome::Skipped);
+        }
+        let mut outputs = vec![];
+        let outcome = match self.write(source, relative, &text, output_dir, &mut outputs) {
+            Ok(()) => Outcome::Rendered(outputs),
+            Err(message) => Outcome::Failed(message, outputs),
+        };
+        (hash, outcome)
+    }
+
+    /// Writes the files of a source, and adds their paths from the output
+    /// directory to `outputs` as they are written.
+    fn write(
+        &self,
+        source: &Path,
+        relative: &str,
+        text: &str,
+        output_dir: &Path,
+        outputs: &mut Vec<String>,
+    ) -> Result<(), String> {
+        let input = frontend::read(source, text).map_err(|e| e.to_string())?;
+        let base = match relative.rsplit_once('.') {
+            Some((base, _)) if !base.is_empty() && !base.ends_with('/') => base,
+            _ => relative,
+        };
+        let title = base.rsplit('/').next().unwrap_or(base);
+        for format in &self.formats {
+            match format {
+                Format::Svg | Format::Viewer => {
+                    let extension = if *format == Format::Svg {
+                        "svg"
+                    } else {
+                        "html"
+                    };
+                    let href = |stem: &str| format!("{}.{}", stem, extension);
+                    for (stem, svg) in input.to_svgs(self.config, self.layered, &href) {
+                        let file = format!("{}/{}", base, href(&stem));
+                        write_file(output_dir, &file, |mut out| match format {
+                            Format::Svg => SvgWriter::new(out).write(&svg).map(drop),
+                            _ => {
+                                let page = html::render_viewer(&svg, &stem, Some(text));
+                                out.write_all(page.as_bytes())
+                            }
+                        })?;
+                        outputs.push(file);
+                    }
+                }
+                Format::Html => {
+                    let href = |stem: &str| format!("#{}", stem);
+                    let charts = input.to_svgs(self.config, self.layered, &href);
+                    let file = format!("{}.html", base);
+                    let page = html::render_charts(&charts, title);
+                    write_file(output_dir, &file, |mut out| out.write_all(page.as_bytes()))?;
+                    outputs.push(file);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Creates the file at the path `file` from the output directory, with the
+/// directories it is in, and writes it with `write`.
+fn write_file(
+    output_dir: &Path,
+    file: &str,
+    write: impl FnOnce(File) -> io::Result<()>,
+) -> Result<(), String> {
+    let path = output_dir.join(file);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("{}: {}", parent.display(), e))?;
+    }
+    File::create(&path)
+        .and_then(write)
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// The path of a source from the source directory, with slashes.
+fn relative(source: &Path, source_dir: &Path) -> String {
+    let relative = source.strip_prefix(source_dir).unwrap_or(source);
+    let parts: Vec<_> = relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect();
+    parts.join("/")
+}
+
+/// Whether `file` is a path down into the output directory. Anything else
+/// in a manifest was not written by a batch, and is left alone.
+fn is_inside(file: &str) -> bool {
+    let path = Path::new(file);
+    path.components().next().is_some()
+        && path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+}
+
+/// Removes a file the manifest lists, unless it is already gone or not in
+/// the output directory, and the directories it leaves empty.
+fn remove(output_dir: &Path, file: &str, report: &mut Report) -> io::Result<()> {
+    if !is_inside(file) {
+        return Ok(());
+    }
+    let path = output_dir.join(file);
+    match fs::remove_file(&path) {
+        Ok(()) => {
+            report.removed.push(PathBuf::from(file));
+            for dir in path.ancestors().skip(1) {
+                if dir == output_dir || fs::remove_dir(dir).is_err() {
+                    break;
+                }
+            }
+            Ok(())
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+fn hash(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::ConfigBuilder, temp_dir::TempDir};
+
+    fn paths(paths: &[PathBuf]) -> Vec<String> {
+        let mut paths: Vec<String> = paths
+            .iter()
+            .map(|path| relative(path, Path::new("")))
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn cache() {
+        let dir = TempDir::new("batch-cache");
+        let (source_dir, output_dir) = (dir.0.join("src"), dir.0.join("out"));
+        dir.write(
+            "src/a.flow",
+            "proc main\n    call helper\nend\n\nproc helper\nend\n",
+        );
+        dir.write("src/sub/b.flow", "proc main\nend\n");
+        let config = ConfigBuilder::new().build();
+        let batch = Batch::new(&config).jobs(2);
+
+        let report = batch.run(&source_dir, &output_dir).unwrap();
+        assert_eq!(paths(&report.rendered), ["a.flow", "sub/b.flow"]);
+        for file in ["a/main.svg", "a/helper.svg", "sub/b/main.svg", MANIFEST] {
+            assert!(output_dir.join(file).is_file(), "{}", file);
+        }
+
+        let report = batch.run(&source_dir, &output_dir).unwrap();
+        assert_eq!(paths(&report.skipped), ["a.flow", "sub/b.flow"]);
+
+        // A file that is gone is written again.
+        fs::remove_file(output_dir.join("sub/b/main.svg")).unwrap();
+        let report = batch.run(&source_dir, &output_dir).unwrap();
+        assert_eq!(paths(&report.rendered), ["sub/b.flow"]);
+
+        dir.write("src/a.flow", "proc main\nend\n");
+        fs::remove_file(source_dir.join("sub/b.flow")).unwrap();
+        let report = batch.run(&source_dir, &output_dir).unwrap();
+        assert_eq!(paths(&report.rendered), ["a.flow"]);
+        assert_eq!(paths(&report.removed), ["a/helper.svg", "sub/b/main.svg"]);
+        assert!(!output_dir.join("sub").exists());
+
+        // Other settings render everything again.
+        let report = Batch::new(&config)
+            .formats(&[Format::Html])
+            .run(&source_dir, &output_dir)
+            .unwrap();
+        assert_eq!(paths(&report.rendered), ["a.flow"]);
+        assert_eq!(paths(&report.removed), ["a/main.svg"]);
+        assert!(output_dir.join("a.html").is_file());
+    }
+
+    #[test]
+    fn failures() {
+        let dir = TempDir::new("batch-failures");
+        let (source_dir, output_dir) = (dir.0.join("src"), dir.0.join("out"));
+        dir.write("src/a.flow", "proc main\nend\n");
+        dir.write("src/a.c", "int main(void) { return 0; }\n");
+        dir.write("src/bad.flow", "proc main\n");
+        let config = ConfigBuilder::new().build();
+        let batch = Batch::new(&config);
+
+        let report = batch.run(&source_dir, &output_dir).unwrap();
+        assert_eq!(paths(&report.rendered), ["a.c"]);
+        let failed: Vec<PathBuf> = report.failed.iter().map(|(path, _)| path.clone()).collect();
+        assert_eq!(paths(&failed), ["a.flow", "bad.flow"]);
+
+        // A source that failed is tried again, even if it did not change.
+        fs::remove_file(source_dir.join("a.flow")).unwrap();
+        let report = batch.run(&source_dir, &output_dir).unwrap();
+        assert_eq!(paths(&report.skipped), ["a.c"]);
+        assert_eq!(report.failed.len(), 1);
+    }
+
+    #[test]
+    fn files_written_before_a_failure_are_listed() {
+        let dir = TempDir::new("batch-partial");
+        let (source_dir, output_dir) = (dir.0.join("src"), dir.0.join("out"));
+        dir.write("src/a.flow", "proc main\nend\n");
+        // A directory where the page of `a.flow` goes fails it after its
+        // charts are written.
+        fs::create_dir_all(output_dir.join("a.html")).unwrap();
+        let config = ConfigBuilder::new().build();
+        let batch = Batch::new(&config).formats(&[Format::Svg, Format::Html]);
+
+        let report = batch.run(&source_dir, &output_dir).unwrap();
+        assert_eq!(report.failed.len(), 1);
+        assert!(output_dir.join("a/main.svg").is_file());
+        let manifest = fs::read_to_string(output_dir.join(MANIFEST)).unwrap();
+        assert!(manifest.contains("a/main.svg"));
+
+        fs::remove_file(source_dir.join("a.flow")).unwrap();
+        let report = batch.run(&source_dir, &output_dir).unwrap();
+        assert_eq!(paths(&report.removed), ["a/main.svg"]);
+    }
+
+    #[test]
+    fn manifest_stays_in_the_output_directory() {
+        let dir = TempDir::new("batch-manifest");
+        let (source_dir, output_dir) = (dir.0.join("src"), dir.0.join("out"));
+        fs::create_dir_all(&source_dir).unwrap();
+        dir.write("kept.svg", "");
+        dir.write("out/sub/kept.svg", "");
+        let outside = dir.0.join("kept.svg");
+        let manifest = serde_json::json!({
+            "sources": {
+                "gone.flow": {
+                    "hash": "",
+                    "settings": "",
+                    "outputs": [
+                        "../kept.svg",
+                        "sub/../../kept.svg",
+                        outside.to_string_lossy(),
+                        "",
+                    ],
+                },
+            },
+        });
+        dir.write(&format!("out/{}", MANIFEST), &manifest.to_string());
+        let config = ConfigBuilder::new().build();
+
+        let report = Batch::new(&config).run(&source_dir, &output_dir).unwrap();
+        assert!(report.removed.is_empty());
+        assert!(outside.is_file());
+        assert!(output_dir.join("sub/kept.svg").is_file());
+    }
+}
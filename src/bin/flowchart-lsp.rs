@@ -0,0 +1,21 @@
+//! The language server of the chart source language, over the standard input
+//! and output.
+
+use std::process::ExitCode;
+
+use flowchart::{config::ConfigBuilder, lsp::Server};
+use lsp_server::Connection;
+
+fn main() -> ExitCode {
+    let (connection, io_threads) = Connection::stdio();
+    let result = Server::new(ConfigBuilder::new().build()).run(&connection);
+    // The writer thread ends once the connection is dropped.
+    drop(connection);
+    match result.and_then(|()| Ok(io_threads.join()?)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("flowchart-lsp: {}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,41 @@
+//! The mdBook preprocessor rendering `flowchart` code blocks, set up in
+//! `book.toml` with `[preprocessor.flowchart]`.
+
+use std::{
+    io::{self, Read},
+    process::ExitCode,
+};
+
+use flowchart::mdbook;
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    match args.next().as_deref() {
+        // mdBook asks before running the preprocessor for a renderer.
+        Some("supports") => match args.next() {
+            Some(renderer) if mdbook::supports(&renderer) => ExitCode::SUCCESS,
+            _ => ExitCode::FAILURE,
+        },
+        Some(_) => {
+            eprintln!("usage: mdbook-flowchart [supports <renderer>]");
+            ExitCode::FAILURE
+        }
+        None => {
+            let mut input = String::new();
+            let result = io::stdin()
+                .read_to_string(&mut input)
+                .map_err(|e| e.to_string())
+                .and_then(|_| mdbook::preprocess(&input).map_err(|e| e.to_string()));
+            match result {
+                Ok(book) => {
+                    print!("{}", book);
+                    ExitCode::SUCCESS
+                }
+                Err(message) => {
+                    eprintln!("mdbook-flowchart: {}", message);
+                    ExitCode::FAILURE
+                }
+            }
+        }
+    }
+}
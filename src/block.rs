@@ -1,13 +1,87 @@
-use crate::{config::Config, svg::SvgShape};
+use std::fmt;
+
+use crate::{
+    config::{Config, Direction},
+    svg::SvgShape,
+};
+use serde::Deserialize;
 use unicode_width::UnicodeWidthStr;
 
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize)]
 pub enum BlockKind {
     Terminal,
     IO,
     Process,
     Decision,
+    Subroutine,
+    /// An on-page connector: a small circle that names where the flow jumps
+    /// to or continues from.
+    Connector,
+    /// A comment beside the flow, which edges neither enter nor leave.
+    Annotation,
+}
+
+/// Where something comes from in the source, in lines and columns counted
+/// from 1, with columns counted in characters. The end is exclusive.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct Span {
+    #[serde(default)]
+    pub file: Option<String>,
+    pub line: usize,
+    pub column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+}
+
+impl Span {
+    pub fn new(line: usize, column: usize, end_line: usize, end_column: usize) -> Self {
+        Self {
+            file: None,
+            line,
+            column,
+            end_line,
+            end_column,
+        }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file)?;
+        }
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Bounds {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Bounds {
+    pub fn overlaps(&self, other: &Bounds) -> bool {
+        self.x < other.x + other.width
+            && other.x < self.x + self.width
+            && self.y < other.y + other.height
+            && other.y < self.y + self.height
+    }
+
+    pub fn union(&self, other: &Bounds) -> Bounds {
+        let (x, y) = (self.x.min(other.x), self.y.min(other.y));
+        Bounds {
+            x,
+            y,
+            width: (self.x + self.width).max(other.x + other.width) - x,
+            height: (self.y + self.height).max(other.y + other.height) - y,
+        }
+    }
 }
 
+#[derive(Clone)]
 pub struct Block {
     kind: BlockKind,
     x: usize,
@@ -16,6 +90,12 @@ pub struct Block {
     height: usize,
     theta: Option<f64>,
     texts: Vec<(String, usize)>,
+    link: Option<String>,
+    /// The index in [`Procedure::statements`](crate::chart::Procedure::statements)
+    /// of the statement the block shows, to find the block of a statement
+    /// after layout.
+    statement: Option<usize>,
+    span: Option<Span>,
 }
 
 impl Block {
@@ -25,6 +105,31 @@ impl Block {
         self.texts.iter_mut().for_each(|(_, cy)| *cy += dy);
     }
 
+    /// Moves the block so that its top left corner is at `(x, y)`.
+    pub(crate) fn move_to(&mut self, x: usize, y: usize) {
+        let dy = y as isize - self.y as isize;
+        self.x = x;
+        self.y = y;
+        self.shift_texts(dy);
+    }
+
+    /// Swaps the axes of the block, mirroring its position and size along
+    /// the diagonal, while its text stays centered and upright. Layouts
+    /// that run sideways lay out transposed blocks from top to bottom, and
+    /// transpose them back.
+    pub(crate) fn transpose(&mut self) {
+        let center = self.y + self.height / 2;
+        (self.x, self.y) = (self.y, self.x);
+        (self.width, self.height) = (self.height, self.width);
+        self.shift_texts((self.y + self.height / 2) as isize - center as isize);
+    }
+
+    fn shift_texts(&mut self, dy: isize) {
+        self.texts
+            .iter_mut()
+            .for_each(|(_, cy)| *cy = (*cy as isize + dy) as usize);
+    }
+
     pub fn pos(&self) -> (usize, usize) {
         (self.x, self.y)
     }
@@ -37,6 +142,70 @@ impl Block {
         (self.x + self.width / 2, self.y + self.height)
     }
 
+    pub fn left_pos(&self) -> (usize, usize) {
+        (self.x, self.y + self.height / 2)
+    }
+
+    pub fn right_pos(&self) -> (usize, usize) {
+        (self.x + self.width, self.y + self.height / 2)
+    }
+
+    /// Where an edge flowing in `direction` enters this block.
+    pub fn entry_pos(&self, direction: Direction) -> (usize, usize) {
+        match direction {
+            Direction::TopToBottom => self.top_pos(),
+            Direction::BottomToTop => self.bottom_pos(),
+            Direction::LeftToRight => self.left_pos(),
+            Direction::RightToLeft => self.right_pos(),
+        }
+    }
+
+    /// Where an edge flowing in `direction` leaves this block.
+    pub fn exit_pos(&self, direction: Direction) -> (usize, usize) {
+        match direction {
+            Direction::TopToBottom => self.bottom_pos(),
+            Direction::BottomToTop => self.top_pos(),
+            Direction::LeftToRight => self.right_pos(),
+            Direction::RightToLeft => self.left_pos(),
+        }
+    }
+
+    pub fn bounds(&self) -> Bounds {
+        Bounds {
+            x: self.x,
+            y: self.y,
+            width: self.width,
+            height: self.height,
+        }
+    }
+
+    pub fn kind(&self) -> BlockKind {
+        self.kind
+    }
+
+    /// Makes the rendered block a hyperlink to `href`.
+    pub fn set_link(&mut self, href: String) {
+        self.link = Some(href);
+    }
+
+    pub(crate) fn set_statement(&mut self, index: usize) {
+        self.statement = Some(index);
+    }
+
+    /// Whether the block shows the statement at `index`.
+    pub(crate) fn shows(&self, index: usize) -> bool {
+        self.statement == Some(index)
+    }
+
+    /// Where in the source the block comes from, if the frontend knows.
+    pub fn span(&self) -> Option<&Span> {
+        self.span.as_ref()
+    }
+
+    pub fn set_span(&mut self, span: Span) {
+        self.span = Some(span);
+    }
+
     pub fn width(&self) -> usize {
         self.width
     }
@@ -46,10 +215,41 @@ impl Block {
     }
 
     pub fn to_svg(&self) -> SvgShape {
+        let mut shapes = self.to_texts();
+        shapes.push(self.outline());
+        let mut data = vec![];
+        if let Some(span) = &self.span {
+            if let Some(file) = &span.file {
+                data.push((String::from("file"), file.clone()));
+            }
+            for (name, value) in [
+                ("line", span.line),
+                ("column", span.column),
+                ("end-line", span.end_line),
+                ("end-column", span.end_column),
+            ] {
+                data.push((String::from(name), value.to_string()));
+            }
+        }
+        let group = SvgShape::Classed {
+            class: String::from("block"),
+            data,
+            shapes,
+        };
+        match &self.link {
+            Some(href) => SvgShape::Link {
+                href: href.clone(),
+                shape: Box::new(group),
+            },
+            None => group,
+        }
+    }
+
+    /// The shape of the block without its text.
+    pub fn outline(&self) -> SvgShape {
         let (x, y) = (self.x, self.y);
         let (width, height) = (self.width, self.height);
-        let mut items = self.to_texts();
-        items.push(match self.kind {
+        match self.kind {
             BlockKind::Terminal => SvgShape::Stadium {
                 x,
                 y,
@@ -75,18 +275,44 @@ impl Block {
                 width,
                 height,
             },
-        });
-        SvgShape::Group(items)
+            BlockKind::Subroutine => SvgShape::Subroutine {
+                x,
+                y,
+                width,
+                height,
+            },
+            BlockKind::Connector => SvgShape::Circle {
+                cx: x + width / 2,
+                cy: y + height / 2,
+                r: width / 2,
+            },
+            BlockKind::Annotation => SvgShape::Annotation {
+                x,
+                y,
+                width,
+                height,
+                leader: self.leader(),
+            },
+        }
+    }
+
+    /// The length of the leader of an annotation, which comes before the
+    /// bracket.
+    fn leader(&self) -> usize {
+        self.height / 2
     }
 
     fn to_texts(&self) -> Vec<SvgShape> {
-        let cx = self.x + self.width / 2;
+        let cx = match self.kind {
+            BlockKind::Annotation => self.x + (self.leader() + self.width) / 2,
+            _ => self.x + self.width / 2,
+        };
         self.texts
             .iter()
             .map(|(content, cy)| SvgShape::Text {
                 cx,
                 cy: *cy,
-                content: escape_xml(content),
+                content: content.clone(),
             })
             .collect()
     }
@@ -141,6 +367,9 @@ impl BlockBuilder {
             height,
             theta: None,
             texts: get_texts(content, height / 2, self.font_size),
+            link: None,
+            statement: None,
+            span: None,
         }
     }
 
@@ -156,6 +385,9 @@ impl BlockBuilder {
             height,
             theta: Some(self.theta),
             texts: get_texts(content, height / 2, self.font_size),
+            link: None,
+            statement: None,
+            span: None,
         }
     }
 
@@ -170,6 +402,9 @@ impl BlockBuilder {
             height,
             theta: None,
             texts: get_texts(content, height / 2, self.font_size),
+            link: None,
+            statement: None,
+            span: None,
         }
     }
 
@@ -185,6 +420,66 @@ impl BlockBuilder {
             height,
             theta: None,
             texts: get_texts(content, height / 2, self.font_size),
+            link: None,
+            statement: None,
+            span: None,
+        }
+    }
+
+    fn build_subroutine(&self, content: String) -> Block {
+        let (width, height) = self.estimate_text_width_height(&content);
+        // Leave room for the two vertical bars, each a quarter of the height
+        // away from the sides.
+        let (_, height) = self.fit_to_grid(width, height);
+        let (width, height) = self.fit_to_grid(width + height / 2, height);
+        Block {
+            kind: BlockKind::Subroutine,
+            x: 0,
+            y: 0,
+            width,
+            height,
+            theta: None,
+            texts: get_texts(content, height / 2, self.font_size),
+            link: None,
+            statement: None,
+            span: None,
+        }
+    }
+
+    fn build_connector(&self, content: String) -> Block {
+        let (width, height) = self.estimate_text_width_height(&content);
+        // A connector is kept small, so it skips the minimum block size.
+        let diameter = width.max(height).div_ceil(self.grid_size) * self.grid_size;
+        Block {
+            kind: BlockKind::Connector,
+            x: 0,
+            y: 0,
+            width: diameter,
+            height: diameter,
+            theta: None,
+            texts: get_texts(content, diameter / 2, self.font_size),
+            link: None,
+            statement: None,
+            span: None,
+        }
+    }
+
+    fn build_annotation(&self, content: String) -> Block {
+        let (width, height) = self.estimate_text_width_height(&content);
+        let height = height.div_ceil(self.grid_size) * self.grid_size;
+        // Leave room for the leader, which is half the height long.
+        let width = (width + height / 2).div_ceil(self.grid_size) * self.grid_size;
+        Block {
+            kind: BlockKind::Annotation,
+            x: 0,
+            y: 0,
+            width,
+            height,
+            theta: None,
+            texts: get_texts(content, height / 2, self.font_size),
+            link: None,
+            statement: None,
+            span: None,
         }
     }
 
@@ -194,11 +489,14 @@ impl BlockBuilder {
             BlockKind::IO => self.build_io(content),
             BlockKind::Process => self.build_process(content),
             BlockKind::Decision => self.build_decision(content),
+            BlockKind::Subroutine => self.build_subroutine(content),
+            BlockKind::Connector => self.build_connector(content),
+            BlockKind::Annotation => self.build_annotation(content),
         }
     }
 }
 
-fn get_num_columns_num_lines(content: &str) -> (usize, usize) {
+pub(crate) fn get_num_columns_num_lines(content: &str) -> (usize, usize) {
     let mut num_columns = 0;
     let mut num_lines = 0;
     for line in content.lines() {
@@ -213,23 +511,38 @@ fn get_texts(content: String, cy: usize, font_size: usize) -> Vec<(String, usize
     let dy = (num_lines as isize - 1) * font_size as isize / 2 - cy as isize;
     content
         .lines()
-        .into_iter()
         .zip(0..)
         .map(|(line, i)| (String::from(line), (i * font_size as isize - dy) as usize))
         .collect()
 }
 
-fn escape_xml(content: &str) -> String {
-    let mut s = String::new();
-    for c in content.chars() {
-        match c {
-            '"' => s.push_str("&quot;"),
-            '\'' => s.push_str("&apos;"),
-            '<' => s.push_str("&lt;"),
-            '>' => s.push_str("&gt;"),
-            '&' => s.push_str("&amp;"),
-            _ => s.push(c),
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigBuilder;
+
+    fn process() -> Block {
+        let config = ConfigBuilder::new().build();
+        let mut block = BlockBuilder::new(&config).build(BlockKind::Process, String::from("x"));
+        block.displace(100, 200);
+        block
+    }
+
+    #[test]
+    fn edges_enter_and_leave_in_their_direction() {
+        let block = process();
+        let (width, height) = (block.width(), block.height());
+        let (left, top) = (100, 200);
+        let (right, bottom) = (left + width, top + height);
+        let (middle, center) = (left + width / 2, top + height / 2);
+        for (direction, entry, exit) in [
+            (Direction::TopToBottom, (middle, top), (middle, bottom)),
+            (Direction::BottomToTop, (middle, bottom), (middle, top)),
+            (Direction::LeftToRight, (left, center), (right, center)),
+            (Direction::RightToLeft, (right, center), (left, center)),
+        ] {
+            assert_eq!(block.entry_pos(direction), entry);
+            assert_eq!(block.exit_pos(direction), exit);
         }
     }
-    s
 }
@@ -0,0 +1,349 @@
+//! Rendering charts from a build script, so that documentation can show
+//! them without their SVG being checked in.
+//!
+//! In `build.rs`:
+//!
+//! ```no_run
+//! flowchart::build::Build::new()
+//!     .sources("charts")
+//!     .compile("charts")
+//!     .unwrap();
+//! ```
+//!
+//! The charts of `charts/sort/merge.flow` are written to
+//! `$OUT_DIR/charts/sort/merge/<name>.svg`, and the module
+//! `$OUT_DIR/charts.rs` has a constant for each of them, such as
+//! `SORT_MERGE_MAIN` for `main`, documented with the chart itself:
+//!
+//! ```ignore
+//! mod charts {
+//!     include!(concat!(env!("OUT_DIR"), "/charts.rs"));
+//! }
+//!
+//! /// Sorts by merging.
+//! ///
+//! #[doc = include_str!(concat!(env!("OUT_DIR"), "/charts/sort/merge/main.svg"))]
+//! pub fn merge_sort() {}
+//! ```
+//!
+//! The files hold the `<svg>` element alone, which Markdown and HTML take
+//! as it is.
+
+use std::{
+    collections::HashMap,
+    env, fmt,
+    fmt::Write as _,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    config::{Config, ConfigBuilder, Settings},
+    frontend::{self, ReadError},
+};
+
+#[derive(Debug)]
+pub enum BuildError {
+    /// `OUT_DIR` is not set, as the build is not run by Cargo.
+    NoOutDir,
+    Io(PathBuf, io::Error),
+    Settings(PathBuf, serde_json::Error),
+    Read(ReadError),
+    /// Two charts are named by the same constant.
+    DuplicateConstant(String),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::NoOutDir => write!(f, "`OUT_DIR` is not set"),
+            BuildError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            BuildError::Settings(path, e) => write!(f, "{}: {}", path.display(), e),
+            BuildError::Read(e) => write!(f, "{}", e),
+            BuildError::DuplicateConstant(name) => {
+                write!(f, "two charts would be named `{}`", name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+/// A source, with its path from where it was found.
+struct Source {
+    path: PathBuf,
+    name: PathBuf,
+}
+
+pub struct Build {
+    sources: Vec<Source>,
+    /// The paths to rerun the build script for when they change.
+    watched: Vec<PathBuf>,
+    config: Config,
+    config_file: Option<PathBuf>,
+    layered: bool,
+    out_dir: Option<PathBuf>,
+}
+
+impl Default for Build {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Build {
+    pub fn new() -> Self {
+        Self {
+            sources: vec![],
+            watched: vec![],
+            config: ConfigBuilder::new().build(),
+            config_file: None,
+            layered: false,
+            out_dir: None,
+        }
+    }
+
+    /// Adds the source at `path`, named after its file.
+    pub fn source(mut self, path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        let name = PathBuf::from(path.file_name().unwrap_or_default());
+        self.sources.push(Source {
+            path: path.to_path_buf(),
+            name,
+        });
+        self.watched.push(path.to_path_buf());
+        self
+    }
+
+    /// Adds the sources under the directory `dir`, named after their paths
+    /// from it. They are found as [`find_sources`](frontend::find_sources)
+    /// finds them.
+    pub fn sources(mut self, dir: impl AsRef<Path>) -> Self {
+        let dir = dir.as_ref();
+        for path in frontend::find_sources(dir) {
+            let name = path.strip_prefix(dir).unwrap_or(&path).to_path_buf();
+            self.sources.push(Source { path, name });
+        }
+        self.watched.push(dir.to_path_buf());
+        self
+    }
+
+    /// Sets the settings of the charts.
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Reads the settings of the charts from a JSON file, as [`Settings`],
+    /// instead.
+    pub fn config_file(mut self, path: impl AsRef<Path>) -> Self {
+        self.config_file = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Draws graphs in layers, as they are.
+    pub fn layered(mut self, layered: bool) -> Self {
+        self.layered = layered;
+        self
+    }
+
+    /// Writes to `dir` instead of `OUT_DIR`.
+    pub fn out_dir(mut self, dir: impl AsRef<Path>) -> Self {
+        self.out_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Renders the charts into the directory `name` of the output
+    /// directory, writes the module of their constants to `<name>.rs` next
+    /// to it, and returns the path of the module. Tells Cargo to run the
+    /// build script again when a source or the config file changes.
+    pub fn compile(&self, name: &str) -> Result<PathBuf, BuildError> {
+        let out_dir = match &self.out_dir {
+            Some(dir) => dir.clone(),
+            None => PathBuf::from(env::var_os("OUT_DIR").ok_or(BuildError::NoOutDir)?),
+        };
+        // The constants hold absolute paths, which do not depend on where the
+        // module is included.
+        let out_dir = fs::create_dir_all(&out_dir)
+            .and_then(|()| fs::canonicalize(&out_dir))
+            .map_err(|e| BuildError::Io(out_dir, e))?;
+        for path in self.watched.iter().chain(&self.config_file) {
+            println!("cargo:rerun-if-changed={}", path.display());
+        }
+        let config_file_config;
+        let config = match &self.config_file {
+            Some(path) => {
+                let json = fs::read_to_string(path).map_err(|e| BuildError::Io(path.clone(), e))?;
+                let settings = Settings::from_json(&json)
+                    .map_err(|e| BuildError::Settings(path.clone(), e))?;
+                config_file_config = settings.apply(ConfigBuilder::new()).build();
+                &config_file_config
+            }
+            None => &self.config,
+        };
+
+        let dir = out_dir.join(name);
+        let mut module =
+            String::from("// The charts rendered by the build script, generated by flowchart.\n");
+        let mut constants = HashMap::new();
+        for source in &self.sources {
+            let text = fs::read_to_string(&source.path)
+                .map_err(|e| BuildError::Io(source.path.clone(), e))?;
+            let input = frontend::read(&source.path, &text).map_err(BuildError::Read)?;
+            let base = source.name.with_extension("");
+            let href = |stem: &str| format!("{}.svg", stem);
+            for (stem, svg) in input.to_svgs(config, self.layered, &href) {
+                let path = dir.join(&base).join(href(&stem));
+                let constant = constant_name(&base.join(&stem));
+                // A source added twice is rendered once.
+                match constants.insert(constant.clone(), path.clone()) {
+                    Some(other) if other == path => continue,
+                    Some(_) => return Err(BuildError::DuplicateConstant(constant)),
+                    None => {}
+                }
+                // The documentation of a module shows its charts on one page.
+                let id = constant.to_lowercase().replace('_', "-");
+                write(&path, &svg.inline().id(&id).to_string())?;
+                // `{:?}` makes a string literal of the path.
+                let path = path.display().to_string();
+                writeln!(module).unwrap();
+                writeln!(module, "/// `{}` of `{}`:", stem, source.path.display()).unwrap();
+                writeln!(module, "///").unwrap();
+                writeln!(module, "#[doc = include_str!({:?})]", path).unwrap();
+                writeln!(
+                    module,
+                    "pub const {}: &str = include_str!({:?});",
+                    constant, path
+                )
+                .unwrap();
+            }
+        }
+        let path = out_dir.join(format!("{}.rs", name));
+        write(&path, &module)?;
+        Ok(path)
+    }
+}
+
+/// The name of the constant of a chart, from its path: `sort/merge/main`
+/// is `SORT_MERGE_MAIN`.
+fn constant_name(path: &Path) -> String {
+    let mut name: String = path
+        .to_string_lossy()
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c.to_ascii_uppercase(),
+            false => '_',
+        })
+        .collect();
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        name.insert(0, 'C');
+    }
+    name
+}
+
+/// Writes `content` to `path`, unless it is there already, so that the
+/// files that did not change keep their times.
+fn write(path: &Path, content: &str) -> Result<(), BuildError> {
+    if fs::read_to_string(path).is_ok_and(|old| old == content) {
+        return Ok(());
+    }
+    let io_error = |e| BuildError::Io(path.to_path_buf(), e);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(io_error)?;
+    }
+    fs::write(path, content).map_err(io_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn constant_names() {
+        assert_eq!(
+            constant_name(Path::new("sort/merge/main")),
+            "SORT_MERGE_MAIN"
+        );
+        assert_eq!(constant_name(Path::new("a-b/c d")), "A_B_C_D");
+        assert_eq!(constant_name(Path::new("2d/main")), "C2D_MAIN");
+        assert_eq!(constant_name(Path::new("_x")), "C_X");
+    }
+
+    #[test]
+    fn compiles_charts_and_their_module() {
+        let dir = TempDir::new("compile");
+        dir.write(
+            "charts/sort/merge.flow",
+            "proc main\n  call split\nend\n\nproc split\nend\n",
+        );
+        dir.write("other.c", "int f(void) { return 1; }\n");
+        let out = dir.0.join("out");
+        let build = Build::new()
+            .sources(dir.0.join("charts"))
+            .source(dir.0.join("other.c"))
+            .source(dir.0.join("other.c"))
+            .out_dir(&out);
+        let module_path = build.compile("charts").unwrap();
+        let out = fs::canonicalize(&out).unwrap();
+        assert_eq!(module_path, out.join("charts.rs"));
+
+        let module = fs::read_to_string(&module_path).unwrap();
+        let constants: Vec<&str> = module
+            .lines()
+            .filter_map(|line| line.strip_prefix("pub const "))
+            .filter_map(|line| line.split(':').next())
+            .collect();
+        assert_eq!(
+            constants,
+            ["SORT_MERGE_MAIN", "SORT_MERGE_SPLIT", "OTHER_F"]
+        );
+        let main = out.join("charts/sort/merge/main.svg");
+        assert!(module.contains(&format!("#[doc = include_str!({:?})]", main.display())));
+
+        let svg = fs::read_to_string(&main).unwrap();
+        assert!(svg.starts_with(r#"<svg id="sort-merge-main""#));
+        assert!(svg.contains(r#"xlink:href="split.svg""#));
+        assert!(out.join("charts/other/f.svg").is_file());
+
+        // Files that did not change are left as they are.
+        let modified = || fs::metadata(&main).unwrap().modified().unwrap();
+        let before = modified();
+        build.compile("charts").unwrap();
+        assert_eq!(modified(), before);
+    }
+
+    #[test]
+    fn errors() {
+        let dir = TempDir::new("errors");
+        dir.write("a-b.flow", "proc main\nend\n");
+        dir.write("a_b.flow", "proc main\nend\n");
+        dir.write("broken.flow", "proc a\n  jump\nend\n");
+        dir.write("config.json", "{\"colour\": \"red\"}");
+        let build = || Build::new().out_dir(dir.0.join("out"));
+
+        let error = build()
+            .source(dir.0.join("a-b.flow"))
+            .source(dir.0.join("a_b.flow"))
+            .compile("charts")
+            .unwrap_err();
+        assert_eq!(error.to_string(), "two charts would be named `A_B_MAIN`");
+
+        let broken = dir.0.join("broken.flow");
+        let error = build().source(&broken).compile("charts").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("{}:2:3: unknown statement `jump`", broken.display())
+        );
+
+        let error = build()
+            .config_file(dir.0.join("config.json"))
+            .compile("charts")
+            .unwrap_err();
+        assert!(matches!(error, BuildError::Settings(..)));
+
+        let missing = dir.0.join("missing.flow");
+        let error = build().source(&missing).compile("charts").unwrap_err();
+        assert!(matches!(error, BuildError::Io(path, _) if path == missing));
+    }
+}
@@ -0,0 +1,380 @@
+//! Control-flow graphs imported from JSON.
+//!
+//! A graph lists its nodes, each with an `id`, a `kind` named after
+//! [`BlockKind`] and a `label`, and its edges, each with the ids of the nodes
+//! it goes `from` and `to` and an optional `label`:
+//!
+//! ```json
+//! {
+//!   "nodes": [
+//!     { "id": "entry", "kind": "Terminal", "label": "start" },
+//!     { "id": 1, "kind": "Decision", "label": "x > 0" }
+//!   ],
+//!   "edges": [
+//!     { "from": "entry", "to": 1 },
+//!     { "from": 1, "to": 1, "label": "yes" }
+//!   ]
+//! }
+//! ```
+//!
+//! A node may also give the `span` of source it comes from, with a `line`,
+//! `column`, `end_line` and `end_column` and optionally a `file`.
+//!
+//! Ids may be strings or numbers. The graph does not need to be structured:
+//! any edge between two nodes is allowed.
+//!
+//! Nodes may instead each name the `lane` of who does the step, such as an
+//! actor, service or team. The graph is then drawn as a
+//! [swimlane](crate::swimlane) chart, going through the nodes in the order
+//! they are listed, so its edges must lead from every node to the next one.
+
+use std::{collections::HashSet, fmt};
+
+use serde::{Deserialize, Deserializer};
+
+use crate::{
+    block::{BlockBuilder, BlockKind, Span},
+    config::Config,
+    layered::LayeredLayouter,
+    structuring,
+    svg::Svg,
+    swimlane::Swimlane,
+};
+
+#[derive(Deserialize)]
+pub struct Node {
+    #[serde(deserialize_with = "id")]
+    pub id: String,
+    pub kind: BlockKind,
+    pub label: String,
+    #[serde(default)]
+    pub span: Option<Span>,
+    #[serde(default)]
+    pub lane: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct Edge {
+    #[serde(deserialize_with = "id")]
+    pub from: String,
+    #[serde(deserialize_with = "id")]
+    pub to: String,
+    #[serde(default)]
+    pub label: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct Graph {
+    pub nodes: Vec<Node>,
+    #[serde(default)]
+    pub edges: Vec<Edge>,
+}
+
+#[derive(Debug)]
+pub enum GraphError {
+    Json(serde_json::Error),
+    DuplicateNode(String),
+    UnknownNode(String),
+    /// A node without a lane in a graph whose other nodes have one.
+    MissingLane(String),
+    /// The edges of a graph with lanes do not lead from each node to the
+    /// next one.
+    OutOfOrder,
+}
+
+impl fmt::Display for GraphError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GraphError::Json(e) => write!(f, "{}", e),
+            GraphError::DuplicateNode(id) => write!(f, "node `{}` is defined more than once", id),
+            GraphError::UnknownNode(id) => write!(f, "an edge refers to unknown node `{}`", id),
+            GraphError::MissingLane(id) => {
+                write!(f, "node `{}` has no lane, unlike the others", id)
+            }
+            GraphError::OutOfOrder => write!(
+                f,
+                "the edges of a graph with lanes must lead from each node to the next one"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for GraphError {}
+
+/// Reads an id that is either a string or a number.
+fn id<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Id {
+        Number(u64),
+        String(String),
+    }
+    Ok(match Id::deserialize(deserializer)? {
+        Id::Number(number) => number.to_string(),
+        Id::String(string) => string,
+    })
+}
+
+impl Graph {
+    pub fn from_json(json: &str) -> Result<Graph, GraphError> {
+        let graph: Graph = serde_json::from_str(json).map_err(GraphError::Json)?;
+        let mut ids = HashSet::new();
+        for node in &graph.nodes {
+            if !ids.insert(node.id.as_str()) {
+                return Err(GraphError::DuplicateNode(node.id.clone()));
+            }
+        }
+        for edge in &graph.edges {
+            for id in [&edge.from, &edge.to] {
+                if !ids.contains(id.as_str()) {
+                    return Err(GraphError::UnknownNode(id.clone()));
+                }
+            }
+        }
+        if graph.has_lanes() {
+            if let Some(node) = graph.nodes.iter().find(|node| node.lane.is_none()) {
+                return Err(GraphError::MissingLane(node.id.clone()));
+            }
+            let steps: HashSet<(&str, &str)> = graph
+                .nodes
+                .windows(2)
+                .map(|pair| (pair[0].id.as_str(), pair[1].id.as_str()))
+                .collect();
+            let edges: HashSet<(&str, &str)> = graph
+                .edges
+                .iter()
+                .map(|edge| (edge.from.as_str(), edge.to.as_str()))
+                .collect();
+            if steps != edges {
+                return Err(GraphError::OutOfOrder);
+            }
+        }
+        Ok(graph)
+    }
+
+    fn has_lanes(&self) -> bool {
+        self.nodes.iter().any(|node| node.lane.is_some())
+    }
+
+    /// Names `file` as the source of the spans that do not name their own.
+    pub fn set_file(&mut self, file: &str) {
+        for span in self.nodes.iter_mut().filter_map(|node| node.span.as_mut()) {
+            span.file.get_or_insert_with(|| String::from(file));
+        }
+    }
+
+    /// Renders the graph in its lanes if it has any, else with the structured
+    /// layout if its flow can be fully structured, and in layers otherwise.
+    pub fn to_svg(&self, config: &Config) -> Svg {
+        if self.has_lanes() {
+            return self.to_swimlane_svg(config);
+        }
+        match structuring::structure(self) {
+            Some(procedure) if !structuring::has_goto(&procedure.body) => {
+                procedure.to_svg(config, &|_| None)
+            }
+            _ => self.to_layered_svg(config),
+        }
+    }
+
+    fn to_swimlane_svg(&self, config: &Config) -> Svg {
+        let block_builder = BlockBuilder::new(config);
+        let mut swimlane = Swimlane::new(config, &block_builder);
+        let mut previous: Option<&str> = None;
+        for node in &self.nodes {
+            let lane = node.lane.as_deref().unwrap_or_default();
+            let label = previous.and_then(|from| {
+                self.edges
+                    .iter()
+                    .find(|edge| edge.from == from && edge.to == node.id)
+                    .and_then(|edge| edge.label.clone())
+            });
+            match label {
+                Some(label) => swimlane.push_with_label(lane, node.kind, node.label.clone(), label),
+                None => swimlane.push(lane, node.kind, node.label.clone()),
+            }
+            if let Some(span) = &node.span {
+                swimlane.set_span(span.clone());
+            }
+            previous = Some(&node.id);
+        }
+        let margin = config.grid_size();
+        swimlane.displace(margin, margin);
+        let (shape, (width, height)) = swimlane.to_svg();
+        let mut svg = Svg::new(config);
+        svg.set_size(width + margin, height + margin);
+        svg.push_shape(shape);
+        svg
+    }
+
+    /// Renders the graph in layers, as it is.
+    pub fn to_layered_svg(&self, config: &Config) -> Svg {
+        let mut layout = LayeredLayouter::new(config).layout(self);
+        let margin = config.grid_size();
+        layout.displace(margin, margin);
+        let (shape, (width, height)) = layout.to_svg(config);
+        let mut svg = Svg::new(config);
+        svg.set_size(width + margin, height + margin);
+        svg.push_shape(shape);
+        svg
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigBuilder;
+
+    fn error(json: &str) -> String {
+        Graph::from_json(json)
+            .err()
+            .expect("an invalid graph")
+            .to_string()
+    }
+
+    #[test]
+    fn ids_are_strings_or_numbers() {
+        let graph = Graph::from_json(
+            r#"{
+                "nodes": [
+                    { "id": "entry", "kind": "Terminal", "label": "start" },
+                    { "id": 1, "kind": "Decision", "label": "x > 0",
+                      "span": { "line": 2, "column": 3, "end_line": 2, "end_column": 8 } }
+                ],
+                "edges": [
+                    { "from": "entry", "to": 1 },
+                    { "from": 1, "to": "1", "label": "yes" }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let ids: Vec<&str> = graph.nodes.iter().map(|node| node.id.as_str()).collect();
+        assert_eq!(ids, ["entry", "1"]);
+        assert!(matches!(graph.nodes[1].kind, BlockKind::Decision));
+        assert_eq!(graph.nodes[1].span, Some(Span::new(2, 3, 2, 8)));
+        assert_eq!(graph.edges[1].to, "1");
+        assert_eq!(graph.edges[1].label.as_deref(), Some("yes"));
+        assert!(graph.edges[0].label.is_none());
+    }
+
+    #[test]
+    fn edges_are_optional() {
+        let mut graph = Graph::from_json(
+            r#"{ "nodes": [{ "id": 0, "kind": "Process", "label": "x",
+                "span": { "line": 1, "column": 1, "end_line": 1, "end_column": 2 } }] }"#,
+        )
+        .unwrap();
+        assert!(graph.edges.is_empty());
+        graph.set_file("g.json");
+        assert_eq!(
+            graph.nodes[0].span.as_ref().unwrap().file.as_deref(),
+            Some("g.json")
+        );
+    }
+
+    #[test]
+    fn spans_keep_their_own_file() {
+        let mut graph = Graph::from_json(
+            r#"{ "nodes": [{ "id": 0, "kind": "Process", "label": "x",
+                "span": { "file": "src/main.c", "line": 3, "column": 5,
+                          "end_line": 3, "end_column": 9 } }] }"#,
+        )
+        .unwrap();
+        graph.set_file("g.json");
+        let span = graph.nodes[0].span.as_ref().unwrap();
+        assert_eq!(span.file.as_deref(), Some("src/main.c"));
+        let svg = graph.to_svg(&ConfigBuilder::new().build()).to_string();
+        assert!(svg.contains(r#"data-file="src/main.c" data-line="3""#));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            error(
+                r#"{ "nodes": [{ "id": 1, "kind": "Process", "label": "a" },
+                { "id": "1", "kind": "Process", "label": "b" }] }"#
+            ),
+            "node `1` is defined more than once"
+        );
+        assert_eq!(
+            error(
+                r#"{ "nodes": [{ "id": 1, "kind": "Process", "label": "a" }],
+                "edges": [{ "from": 1, "to": 2 }] }"#
+            ),
+            "an edge refers to unknown node `2`"
+        );
+        assert!(
+            error(r#"{ "nodes": [{ "id": 1, "kind": "Box", "label": "a" }] }"#)
+                .contains("unknown variant `Box`")
+        );
+        assert!(matches!(Graph::from_json("[]"), Err(GraphError::Json(_))));
+        assert_eq!(
+            error(
+                r#"{ "nodes": [{ "id": 1, "kind": "Process", "label": "a", "lane": "A" },
+                { "id": 2, "kind": "Process", "label": "b" }],
+                "edges": [{ "from": 1, "to": 2 }] }"#
+            ),
+            "node `2` has no lane, unlike the others"
+        );
+        for edges in ["[]", r#"[{ "from": 2, "to": 1 }]"#] {
+            assert_eq!(
+                error(&format!(
+                    r#"{{ "nodes": [{{ "id": 1, "kind": "Process", "label": "a", "lane": "A" }},
+                    {{ "id": 2, "kind": "Process", "label": "b", "lane": "B" }}],
+                    "edges": {} }}"#,
+                    edges
+                )),
+                "the edges of a graph with lanes must lead from each node to the next one"
+            );
+        }
+    }
+
+    #[test]
+    fn graphs_with_lanes_are_drawn_in_their_lanes() {
+        let config = ConfigBuilder::new().build();
+        let graph = Graph::from_json(
+            r#"{
+                "nodes": [
+                    { "id": 0, "kind": "Terminal", "label": "start", "lane": "Client" },
+                    { "id": 1, "kind": "Decision", "label": "valid?", "lane": "Server",
+                      "span": { "line": 4, "column": 1, "end_line": 4, "end_column": 9 } },
+                    { "id": 2, "kind": "Process", "label": "reply", "lane": "Server" }
+                ],
+                "edges": [
+                    { "from": 0, "to": 1 },
+                    { "from": 1, "to": 2, "label": "yes" }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let svg = graph.to_svg(&config).to_string();
+        assert_ne!(svg, graph.to_layered_svg(&config).to_string());
+        for text in [">Client<", ">Server<", ">yes<", r#"data-line="4""#] {
+            assert!(svg.contains(text), "{}", text);
+        }
+    }
+
+    #[test]
+    fn unstructured_graphs_are_drawn_in_layers() {
+        let config = crate::config::ConfigBuilder::new().build();
+        // Two entries into a cycle cannot be structured.
+        let graph = Graph::from_json(
+            r#"{
+                "nodes": [
+                    { "id": 0, "kind": "Decision", "label": "c" },
+                    { "id": 1, "kind": "Process", "label": "a" },
+                    { "id": 2, "kind": "Process", "label": "b" }
+                ],
+                "edges": [
+                    { "from": 0, "to": 1, "label": "yes" },
+                    { "from": 0, "to": 2, "label": "no" },
+                    { "from": 1, "to": 2 },
+                    { "from": 2, "to": 1 }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let layered = graph.to_layered_svg(&config).to_string();
+        assert_eq!(graph.to_svg(&config).to_string(), layered);
+    }
+}
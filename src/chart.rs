@@ -0,0 +1,297 @@
+use crate::{
+    block::{BlockKind, Span},
+    config::Config,
+    structured::Layouter,
+    svg::Svg,
+};
+
+pub enum LoopKind {
+    /// Loops until a `Break`.
+    Infinite,
+    /// Checks the condition before every iteration.
+    While(String),
+    /// Takes the next item before every iteration; the text is the loop
+    /// header, such as `x in xs`.
+    For(String),
+    /// Checks the condition after every iteration.
+    DoWhile(String),
+}
+
+pub enum Statement {
+    Block(BlockKind, String),
+    /// A call to another procedure, drawn as a subroutine block.
+    Call(String),
+    /// A process block that returns early when it fails, like Rust's `?`.
+    Try(String),
+    If {
+        condition: String,
+        then_body: Vec<Statement>,
+        else_body: Vec<Statement>,
+    },
+    /// A multi-way branch on `subject`, with one labeled body per case.
+    ///
+    /// With `fall_through`, as in C, a case that does not `break` runs on
+    /// into the next one, and `break` leaves the switch instead of the loop
+    /// around it.
+    Switch {
+        subject: String,
+        cases: Vec<(String, Vec<Statement>)>,
+        fall_through: bool,
+    },
+    Loop {
+        kind: LoopKind,
+        body: Vec<Statement>,
+    },
+    /// Returns from the procedure, optionally through a block showing the
+    /// returned value.
+    Return(Option<String>),
+    Break,
+    Continue,
+    /// A jump to the `Label` of the same name, drawn as a connector the flow
+    /// ends in.
+    Goto(String),
+    /// Where a `Goto` continues, drawn as a connector the flow passes.
+    Label(String),
+    /// A comment beside the flow.
+    Annotation(String),
+}
+
+impl Statement {
+    /// How many statements the statement holds, itself included, as
+    /// [`Procedure::statements`] lists them.
+    pub(crate) fn count(&self) -> usize {
+        1 + match self {
+            Statement::If {
+                then_body,
+                else_body,
+                ..
+            } => count(then_body) + count(else_body),
+            Statement::Switch { cases, .. } => cases.iter().map(|(_, body)| count(body)).sum(),
+            Statement::Loop { body, .. } => count(body),
+            _ => 0,
+        }
+    }
+}
+
+/// How many statements `body` holds, nested ones included.
+pub(crate) fn count(body: &[Statement]) -> usize {
+    body.iter().map(Statement::count).sum()
+}
+
+pub struct Procedure {
+    pub name: String,
+    pub body: Vec<Statement>,
+    /// Where the procedure is defined, from its header to its end.
+    pub span: Option<Span>,
+    /// Where every statement comes from, by its index in
+    /// [`statements`](Procedure::statements). Empty if the frontend does not
+    /// know.
+    pub spans: Vec<Option<Span>>,
+}
+
+impl Procedure {
+    /// Every statement of the procedure in pre-order, so that its position
+    /// in the list identifies a statement, as in the steps of a
+    /// [`Trace`](crate::interpreter::Trace).
+    pub fn statements(&self) -> Vec<&Statement> {
+        fn walk<'a>(body: &'a [Statement], statements: &mut Vec<&'a Statement>) {
+            for statement in body {
+                statements.push(statement);
+                match statement {
+                    Statement::If {
+                        then_body,
+                        else_body,
+                        ..
+                    } => {
+                        walk(then_body, statements);
+                        walk(else_body, statements);
+                    }
+                    Statement::Switch { cases, .. } => {
+                        cases.iter().for_each(|(_, body)| walk(body, statements));
+                    }
+                    Statement::Loop { body, .. } => walk(body, statements),
+                    _ => {}
+                }
+            }
+        }
+        let mut statements = vec![];
+        walk(&self.body, &mut statements);
+        statements
+    }
+
+    /// Where the statement at `index` in
+    /// [`statements`](Procedure::statements) comes from, if known.
+    pub fn span(&self, index: usize) -> Option<&Span> {
+        self.spans.get(index).and_then(Option::as_ref)
+    }
+
+    /// Renders the procedure between a start and an end terminal. Calls link
+    /// to whatever `link` returns for the callee.
+    pub fn to_svg(&self, config: &Config, link: &dyn Fn(&str) -> Option<String>) -> Svg {
+        let mut layout = Layouter::new(config, link).layout(self);
+        let margin = config.grid_size();
+        layout.displace(margin, margin);
+        let (shape, (width, height)) = layout.to_svg(config);
+        let mut svg = Svg::new(config);
+        svg.set_size(width + margin, height + margin);
+        svg.push_shape(shape);
+        svg
+    }
+}
+
+pub struct Program {
+    pub procedures: Vec<Procedure>,
+}
+
+impl Program {
+    pub fn procedure(&self, name: &str) -> Option<&Procedure> {
+        self.procedures
+            .iter()
+            .find(|procedure| procedure.name == name)
+    }
+
+    /// Names `file` as the source of every span.
+    pub fn set_file(&mut self, file: &str) {
+        for procedure in &mut self.procedures {
+            let spans = procedure.spans.iter_mut().flatten();
+            for span in procedure.span.iter_mut().chain(spans) {
+                span.file = Some(String::from(file));
+            }
+        }
+    }
+
+    /// Renders every procedure to its own document named after
+    /// [`file_stem`], where calls link to the callee's document next to it.
+    pub fn to_svgs(&self, config: &Config) -> Vec<(String, Svg)> {
+        let link = |name: &str| {
+            self.procedure(name)
+                .map(|_| format!("{}.svg", file_stem(name)))
+        };
+        self.procedures
+            .iter()
+            .map(|procedure| (file_stem(&procedure.name), procedure.to_svg(config, &link)))
+            .collect()
+    }
+}
+
+/// The file name, without extension, of a procedure's document. Paths such as
+/// `Type::method` are joined with dots instead.
+pub fn file_stem(name: &str) -> String {
+    name.replace("::", ".")
+}
+
+/// The statements of `procedure`, one line each in pre-order, indented by
+/// their depth, for tests to compare.
+#[cfg(test)]
+pub(crate) fn outline(procedure: &Procedure) -> Vec<String> {
+    fn walk(body: &[Statement], depth: usize, lines: &mut Vec<String>) {
+        for statement in body {
+            let indent = "  ".repeat(depth);
+            let line = match statement {
+                Statement::Block(_, text) => text.clone(),
+                Statement::Call(text) => format!("call {}", text),
+                Statement::Try(text) => format!("try {}", text),
+                Statement::If { condition, .. } => format!("if {}", condition),
+                Statement::Switch { subject, .. } => format!("match {}", subject),
+                Statement::Loop { kind, .. } => match kind {
+                    LoopKind::Infinite => String::from("loop"),
+                    LoopKind::While(condition) => format!("while {}", condition),
+                    LoopKind::For(header) => format!("for {}", header),
+                    LoopKind::DoWhile(condition) => format!("do while {}", condition),
+                },
+                Statement::Return(_) => String::from("return"),
+                Statement::Break => String::from("break"),
+                Statement::Continue => String::from("continue"),
+                Statement::Goto(label) => format!("goto {}", label.replace('\n', " ")),
+                Statement::Label(label) => format!("label {}", label.replace('\n', " ")),
+                Statement::Annotation(text) => format!("// {}", text),
+            };
+            lines.push(format!("{}{}", indent, line));
+            match statement {
+                Statement::If {
+                    then_body,
+                    else_body,
+                    ..
+                } => {
+                    walk(then_body, depth + 1, lines);
+                    lines.push(format!("{}else", indent));
+                    walk(else_body, depth + 1, lines);
+                }
+                Statement::Switch { cases, .. } => {
+                    for (label, body) in cases {
+                        lines.push(format!("{}case {}", indent, label));
+                        walk(body, depth + 1, lines);
+                    }
+                }
+                Statement::Loop { body, .. } => walk(body, depth + 1, lines),
+                _ => {}
+            }
+        }
+    }
+    if !procedure.spans.is_empty() {
+        assert_eq!(procedure.spans.len(), procedure.statements().len());
+    }
+    let mut lines = vec![];
+    walk(&procedure.body, 0, &mut lines);
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::ConfigBuilder, parser::parse};
+
+    const SOURCE: &str = "proc main
+    call helper
+    call missing
+end
+proc helper
+    if x
+        process a = 1
+    else
+        process a = 2
+    end
+end
+";
+
+    #[test]
+    fn calls_link_to_the_callee_chart() {
+        let mut program = parse(SOURCE).unwrap();
+        program.procedures[1].name = String::from("Type::helper");
+        program.procedures[0].body[0] = Statement::Call(String::from("Type::helper"));
+        let config = ConfigBuilder::new().build();
+        let svgs = program.to_svgs(&config);
+        let stems: Vec<&str> = svgs.iter().map(|(stem, _)| stem.as_str()).collect();
+        assert_eq!(stems, ["main", "Type.helper"]);
+        let main = svgs[0].1.to_string();
+        // Calls of procedures that are not in the program are not linked.
+        assert_eq!(main.matches(" href=").count(), 1);
+        assert!(main.contains(r#" href="Type.helper.svg""#));
+    }
+
+    #[test]
+    fn statements_are_in_pre_order() {
+        let program = parse(SOURCE).unwrap();
+        let helper = &program.procedures[1];
+        assert_eq!(helper.statements().len(), 3);
+        assert_eq!(count(&helper.body), 3);
+        assert!(matches!(
+            helper.statements()[2],
+            Statement::Block(_, text) if text == "a = 2"
+        ));
+        assert_eq!(helper.span(2).map(|span| span.line), Some(9));
+        assert!(helper.span(3).is_none());
+    }
+
+    #[test]
+    fn files_are_named_in_every_span() {
+        let mut program = parse(SOURCE).unwrap();
+        program.set_file("a.flow");
+        for procedure in &program.procedures {
+            let spans = procedure.spans.iter().flatten();
+            for span in procedure.span.iter().chain(spans) {
+                assert_eq!(span.file.as_deref(), Some("a.flow"));
+            }
+        }
+    }
+}
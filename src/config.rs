@@ -1,3 +1,25 @@
+use std::f64::consts::FRAC_PI_2;
+
+use serde::{
+    de::{Error, Unexpected},
+    Deserialize, Deserializer,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum Direction {
+    TopToBottom,
+    BottomToTop,
+    LeftToRight,
+    RightToLeft,
+}
+
+impl Direction {
+    /// Whether the flow runs across, rather than up or down.
+    pub fn is_sideways(self) -> bool {
+        matches!(self, Direction::LeftToRight | Direction::RightToLeft)
+    }
+}
+
 pub struct Config {
     grid_size: usize,
     font_size: usize,
@@ -5,6 +27,8 @@ pub struct Config {
     min_height: usize,
     theta: f64,
     distance: usize,
+    label_background: bool,
+    direction: Direction,
 }
 
 impl Config {
@@ -31,12 +55,26 @@ impl Config {
     pub fn distance(&self) -> usize {
         self.distance
     }
+
+    pub fn label_background(&self) -> bool {
+        self.label_background
+    }
+
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
 }
 
 pub struct ConfigBuilder {
     config: Config,
 }
 
+impl Default for ConfigBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ConfigBuilder {
     pub fn new() -> Self {
         Self {
@@ -47,6 +85,8 @@ impl ConfigBuilder {
                 min_height: 40,
                 theta: 1.25,
                 distance: 40,
+                label_background: true,
+                direction: Direction::TopToBottom,
             },
         }
     }
@@ -108,4 +148,144 @@ impl ConfigBuilder {
             },
         }
     }
+
+    pub fn label_background(self, label_background: bool) -> Self {
+        Self {
+            config: Config {
+                label_background,
+                ..self.config
+            },
+        }
+    }
+
+    pub fn direction(self, direction: Direction) -> Self {
+        Self {
+            config: Config {
+                direction,
+                ..self.config
+            },
+        }
+    }
+}
+
+/// Settings read from a file, each named after the method of
+/// [`ConfigBuilder`] that sets it, or with dashes for underscores as is usual
+/// in TOML. Settings not given are left as they are. A `grid_size` of 0, or a
+/// `theta` that is not between 0 and π/2, is rejected.
+///
+/// ```json
+/// { "font_size": 14, "direction": "LeftToRight" }
+/// ```
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    #[serde(alias = "grid-size", default, deserialize_with = "grid_size")]
+    pub grid_size: Option<usize>,
+    #[serde(alias = "font-size")]
+    pub font_size: Option<usize>,
+    #[serde(alias = "min-width")]
+    pub min_width: Option<usize>,
+    #[serde(alias = "min-height")]
+    pub min_height: Option<usize>,
+    #[serde(default, deserialize_with = "theta")]
+    pub theta: Option<f64>,
+    pub distance: Option<usize>,
+    #[serde(alias = "label-background")]
+    pub label_background: Option<bool>,
+    pub direction: Option<Direction>,
+}
+
+/// The size of the grid, which blocks are fitted to by dividing by it.
+fn grid_size<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<usize>, D::Error> {
+    let grid_size = Option::<usize>::deserialize(deserializer)?;
+    if grid_size == Some(0) {
+        return Err(D::Error::invalid_value(
+            Unexpected::Unsigned(0),
+            &"a grid size of at least 1",
+        ));
+    }
+    Ok(grid_size)
+}
+
+/// The slant of IO blocks, whose sides run at `theta` to their base.
+fn theta<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f64>, D::Error> {
+    let theta = Option::<f64>::deserialize(deserializer)?;
+    match theta {
+        Some(theta) if !(theta > 0.0 && theta < FRAC_PI_2) => Err(D::Error::invalid_value(
+            Unexpected::Float(theta),
+            &"an angle between 0 and π/2 radians",
+        )),
+        _ => Ok(theta),
+    }
+}
+
+impl Settings {
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    /// Sets every setting given on `builder`.
+    pub fn apply(&self, mut builder: ConfigBuilder) -> ConfigBuilder {
+        if let Some(grid_size) = self.grid_size {
+            builder = builder.grid_size(grid_size);
+        }
+        if let Some(font_size) = self.font_size {
+            builder = builder.font_size(font_size);
+        }
+        if let Some(min_width) = self.min_width {
+            builder = builder.min_width(min_width);
+        }
+        if let Some(min_height) = self.min_height {
+            builder = builder.min_height(min_height);
+        }
+        if let Some(theta) = self.theta {
+            builder = builder.theta(theta);
+        }
+        if let Some(distance) = self.distance {
+            builder = builder.distance(distance);
+        }
+        if let Some(label_background) = self.label_background {
+            builder = builder.label_background(label_background);
+        }
+        if let Some(direction) = self.direction {
+            builder = builder.direction(direction);
+        }
+        builder
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(json: &str) -> String {
+        Settings::from_json(json)
+            .err()
+            .expect("invalid settings")
+            .to_string()
+    }
+
+    #[test]
+    fn settings() {
+        let settings =
+            Settings::from_json(r#"{ "grid-size": 10, "theta": 1.0, "direction": "LeftToRight" }"#)
+                .unwrap();
+        let config = settings.apply(ConfigBuilder::new().font_size(14)).build();
+        assert_eq!(config.grid_size(), 10);
+        assert_eq!(config.theta(), 1.0);
+        assert_eq!(config.font_size(), 14);
+        assert!(config.direction() == Direction::LeftToRight);
+        assert!(Settings::from_json(r#"{ "grid_size": null, "theta": null }"#).is_ok());
+    }
+
+    #[test]
+    fn settings_that_cannot_be_drawn_are_rejected() {
+        assert!(error(r#"{ "grid_size": 0 }"#)
+            .starts_with("invalid value: integer `0`, expected a grid size of at least 1"));
+        for theta in ["0", "-1", "1.5708", "2"] {
+            assert!(error(&format!(r#"{{ "theta": {} }}"#, theta))
+                .contains("expected an angle between 0 and π/2 radians"));
+        }
+        assert!(error(r#"{ "colour": "red" }"#).starts_with("unknown field `colour`"));
+    }
 }
@@ -0,0 +1,921 @@
+//! Values and the expressions in the text of blocks, as run by the
+//! [`interpreter`](crate::interpreter).
+//!
+//! Expressions know numbers, strings in single or double quotes, `true` and
+//! `false`, variables, lists such as `[1, 2]`, indexing such as `a[i]`, and
+//! ranges such as `1..n` or `1..=n`. The operators are, from the loosest:
+//!
+//! - `or` or `||`, and `and` or `&&`,
+//! - `not` or `!`,
+//! - the comparisons `==` or `=`, `!=` or `<>`, `<`, `<=`, `>` and `>=`,
+//! - `+` and `-`, where `+` also joins strings and lists,
+//! - `*`, `/`, `//` or `div` for floor division, and `%` or `mod`,
+//! - unary `-`, and `**` or `^` for powers.
+//!
+//! Dividing integers with `/` gives an integer when it is exact. The
+//! functions are `len`, `abs`, `min`, `max`, `sqrt`, `int`, `str` and
+//! `range`.
+
+use std::{collections::BTreeMap, fmt};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+    List(Vec<Value>),
+}
+
+impl Value {
+    /// Reads an input: a number, `true` or `false`, a list in brackets, or
+    /// a string otherwise.
+    pub fn parse(text: &str) -> Value {
+        let text = text.trim();
+        if let Ok(int) = text.parse() {
+            return Value::Int(int);
+        }
+        if let Ok(float) = text.parse() {
+            return Value::Float(float);
+        }
+        match text {
+            "true" => Value::Bool(true),
+            "false" => Value::Bool(false),
+            _ => match text
+                .strip_prefix('[')
+                .and_then(|text| text.strip_suffix(']'))
+            {
+                Some("") => Value::List(vec![]),
+                Some(items) => Value::List(items.split(',').map(Value::parse).collect()),
+                None => Value::Str(text.to_string()),
+            },
+        }
+    }
+
+    /// The name of the type of the value, with its article.
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "an integer",
+            Value::Float(_) => "a number",
+            Value::Bool(_) => "a boolean",
+            Value::Str(_) => "a string",
+            Value::List(_) => "a list",
+        }
+    }
+
+    fn as_float(&self) -> Option<f64> {
+        match *self {
+            Value::Int(int) => Some(int as f64),
+            Value::Float(float) => Some(float),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(int) => write!(f, "{}", int),
+            // Debug keeps the point of whole numbers, and writes large and
+            // small ones with an exponent rather than all of their digits.
+            Value::Float(float) => write!(f, "{:?}", float),
+            Value::Bool(bool) => write!(f, "{}", bool),
+            Value::Str(string) => write!(f, "{}", string),
+            Value::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    match (i, item) {
+                        (0, Value::Str(string)) => write!(f, "{:?}", string)?,
+                        (0, item) => write!(f, "{}", item)?,
+                        (_, Value::Str(string)) => write!(f, ", {:?}", string)?,
+                        (_, item) => write!(f, ", {}", item)?,
+                    }
+                }
+                write!(f, "]")
+            }
+        }
+    }
+}
+
+/// The variables of a run, by name.
+pub type Variables = BTreeMap<String, Value>;
+
+/// Operators of two or more characters, longest first.
+const OPERATORS: &[&str] = &[
+    "..=", "**", "//", "==", "!=", "<=", ">=", "<>", "&&", "||", ":=", "<-", "+=", "-=", "*=",
+    "/=", "%=", "..",
+];
+
+#[derive(Clone, Copy, PartialEq)]
+enum TokenKind {
+    Number,
+    String,
+    Name,
+    Operator,
+    /// A line break or `;`, which ends an assignment.
+    Separator,
+}
+
+#[derive(Clone)]
+struct Token {
+    kind: TokenKind,
+    text: String,
+    /// Where the token is in the text tokenized, in bytes.
+    start: usize,
+    end: usize,
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let offsets: Vec<usize> = text
+        .char_indices()
+        .map(|(offset, _)| offset)
+        .chain([text.len()])
+        .collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        let kind = if c == '\n' || c == ';' {
+            i += 1;
+            TokenKind::Separator
+        } else if c.is_whitespace() {
+            i += 1;
+            continue;
+        } else if c.is_ascii_digit() {
+            while i < chars.len() && chars[i].is_ascii_digit() {
+                i += 1;
+            }
+            // A dot followed by a digit continues the number; `1..n` is a
+            // range.
+            if i + 1 < chars.len() && chars[i] == '.' && chars[i + 1].is_ascii_digit() {
+                i += 1;
+                while i < chars.len() && chars[i].is_ascii_digit() {
+                    i += 1;
+                }
+            }
+            TokenKind::Number
+        } else if c.is_alphabetic() || c == '_' {
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            TokenKind::Name
+        } else if c == '"' || c == '\'' {
+            i += 1;
+            while i < chars.len() && chars[i] != c {
+                i += 1;
+            }
+            if i == chars.len() {
+                return Err(String::from("a string is not closed"));
+            }
+            i += 1;
+            tokens.push(Token {
+                kind: TokenKind::String,
+                text: chars[start + 1..i - 1].iter().collect(),
+                start: offsets[start],
+                end: offsets[i],
+            });
+            continue;
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 3)].iter().collect();
+            let length = OPERATORS
+                .iter()
+                .find(|operator| rest.starts_with(*operator))
+                .map_or(1, |operator| operator.chars().count());
+            i += length;
+            TokenKind::Operator
+        };
+        let text: String = chars[start..i].iter().collect();
+        // Symbols written as in print.
+        let text = match text.as_str() {
+            "←" => String::from("<-"),
+            "≤" => String::from("<="),
+            "≥" => String::from(">="),
+            "≠" => String::from("!="),
+            _ => text,
+        };
+        tokens.push(Token {
+            kind,
+            text,
+            start: offsets[start],
+            end: offsets[i],
+        });
+    }
+    Ok(tokens)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Binary {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Range,
+    RangeInclusive,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    FloorDivide,
+    Remainder,
+    Power,
+}
+
+pub(crate) enum Expression {
+    Literal(Value),
+    Variable(String),
+    List(Vec<Expression>),
+    Index(Box<Expression>, Box<Expression>),
+    Call(String, Vec<Expression>),
+    Negate(Box<Expression>),
+    Not(Box<Expression>),
+    Binary(Binary, Box<Expression>, Box<Expression>),
+}
+
+/// Assigns to a variable or to an item of a list.
+pub(crate) enum Target {
+    Variable(String),
+    Index(String, Vec<Expression>),
+}
+
+/// `targets = values`, or `target op= value` with `operator`.
+pub(crate) struct Assignment {
+    pub(crate) targets: Vec<Target>,
+    pub(crate) operator: Option<char>,
+    pub(crate) values: Vec<Expression>,
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    /// Parses `text` as one line: only a `;` separates.
+    fn new(text: &str) -> Result<Self, String> {
+        let mut tokens = tokenize(text)?;
+        tokens.retain(|token| token.text != "\n");
+        Ok(Self { tokens, pos: 0 })
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens
+            .get(self.pos)
+            .filter(|token| matches!(token.kind, TokenKind::Operator | TokenKind::Name))
+            .map(|token| token.text.as_str())
+    }
+
+    fn eat(&mut self, texts: &[&str]) -> Option<String> {
+        let text = self.peek().filter(|text| texts.contains(text))?.to_string();
+        self.pos += 1;
+        Some(text)
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), String> {
+        match self.eat(&[text]) {
+            Some(_) => Ok(()),
+            None => Err(format!("expected `{}`", text)),
+        }
+    }
+
+    fn finish(&self) -> Result<(), String> {
+        match self.tokens.get(self.pos) {
+            None => Ok(()),
+            Some(token) => Err(format!("unexpected `{}`", token.text)),
+        }
+    }
+
+    fn targets(&mut self) -> Result<Vec<Target>, String> {
+        let mut targets = vec![];
+        loop {
+            targets.push(match self.postfix()? {
+                Expression::Variable(name) => Target::Variable(name),
+                mut expression => {
+                    let mut indices = vec![];
+                    while let Expression::Index(list, index) = expression {
+                        indices.push(*index);
+                        expression = *list;
+                    }
+                    indices.reverse();
+                    match expression {
+                        Expression::Variable(name) => Target::Index(name, indices),
+                        _ => {
+                            return Err(String::from(
+                                "only variables and their items can be assigned",
+                            ))
+                        }
+                    }
+                }
+            });
+            if self.eat(&[","]).is_none() {
+                break;
+            }
+        }
+        self.finish()?;
+        Ok(targets)
+    }
+
+    fn expressions(&mut self) -> Result<Vec<Expression>, String> {
+        let mut expressions = vec![];
+        while self.tokens.get(self.pos).is_some() {
+            if !expressions.is_empty() {
+                self.expect(",")?;
+            }
+            expressions.push(self.expression()?);
+        }
+        Ok(expressions)
+    }
+
+    fn expression(&mut self) -> Result<Expression, String> {
+        let mut left = self.and()?;
+        while self.eat(&["or", "||"]).is_some() {
+            left = Expression::Binary(Binary::Or, Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expression, String> {
+        let mut left = self.not()?;
+        while self.eat(&["and", "&&"]).is_some() {
+            left = Expression::Binary(Binary::And, Box::new(left), Box::new(self.not()?));
+        }
+        Ok(left)
+    }
+
+    fn not(&mut self) -> Result<Expression, String> {
+        match self.eat(&["not", "!"]) {
+            Some(_) => Ok(Expression::Not(Box::new(self.not()?))),
+            None => self.comparison(),
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Expression, String> {
+        let mut left = self.range()?;
+        while let Some(operator) = self.eat(&["==", "=", "!=", "<>", "<", "<=", ">", ">="]) {
+            let operator = match operator.as_str() {
+                "==" | "=" => Binary::Equal,
+                "!=" | "<>" => Binary::NotEqual,
+                "<" => Binary::Less,
+                "<=" => Binary::LessEqual,
+                ">" => Binary::Greater,
+                _ => Binary::GreaterEqual,
+            };
+            left = Expression::Binary(operator, Box::new(left), Box::new(self.range()?));
+        }
+        Ok(left)
+    }
+
+    fn range(&mut self) -> Result<Expression, String> {
+        let left = self.sum()?;
+        match self.eat(&["..", "..="]).as_deref() {
+            Some("..") => Ok(Expression::Binary(
+                Binary::Range,
+                Box::new(left),
+                Box::new(self.sum()?),
+            )),
+            Some(_) => Ok(Expression::Binary(
+                Binary::RangeInclusive,
+                Box::new(left),
+                Box::new(self.sum()?),
+            )),
+            None => Ok(left),
+        }
+    }
+
+    fn sum(&mut self) -> Result<Expression, String> {
+        let mut left = self.product()?;
+        while let Some(operator) = self.eat(&["+", "-"]) {
+            let operator = match operator.as_str() {
+                "+" => Binary::Add,
+                _ => Binary::Subtract,
+            };
+            left = Expression::Binary(operator, Box::new(left), Box::new(self.product()?));
+        }
+        Ok(left)
+    }
+
+    fn product(&mut self) -> Result<Expression, String> {
+        let mut left = self.unary()?;
+        while let Some(operator) = self.eat(&["*", "/", "//", "div", "%", "mod"]) {
+            let operator = match operator.as_str() {
+                "*" => Binary::Multiply,
+                "/" => Binary::Divide,
+                "//" | "div" => Binary::FloorDivide,
+                _ => Binary::Remainder,
+            };
+            left = Expression::Binary(operator, Box::new(left), Box::new(self.unary()?));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expression, String> {
+        match self.eat(&["-", "+"]).as_deref() {
+            Some("-") => Ok(Expression::Negate(Box::new(self.unary()?))),
+            Some(_) => self.unary(),
+            None => {
+                let base = self.postfix()?;
+                match self.eat(&["**", "^"]) {
+                    Some(_) => Ok(Expression::Binary(
+                        Binary::Power,
+                        Box::new(base),
+                        Box::new(self.unary()?),
+                    )),
+                    None => Ok(base),
+                }
+            }
+        }
+    }
+
+    fn postfix(&mut self) -> Result<Expression, String> {
+        let mut expression = self.primary()?;
+        while self.eat(&["["]).is_some() {
+            let index = self.expression()?;
+            self.expect("]")?;
+            expression = Expression::Index(Box::new(expression), Box::new(index));
+        }
+        Ok(expression)
+    }
+
+    fn list(&mut self, close: &str) -> Result<Vec<Expression>, String> {
+        let mut items = vec![];
+        if self.eat(&[close]).is_some() {
+            return Ok(items);
+        }
+        loop {
+            items.push(self.expression()?);
+            if self.eat(&[close]).is_some() {
+                return Ok(items);
+            }
+            self.expect(",")?;
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expression, String> {
+        let Some(token) = self.tokens.get(self.pos).cloned() else {
+            return Err(String::from("expected a value"));
+        };
+        self.pos += 1;
+        match token.kind {
+            TokenKind::Number => Ok(Expression::Literal(Value::parse(&token.text))),
+            TokenKind::String => Ok(Expression::Literal(Value::Str(token.text))),
+            TokenKind::Name => match token.text.as_str() {
+                "true" => Ok(Expression::Literal(Value::Bool(true))),
+                "false" => Ok(Expression::Literal(Value::Bool(false))),
+                _ if self.eat(&["("]).is_some() => {
+                    Ok(Expression::Call(token.text, self.list(")")?))
+                }
+                _ => Ok(Expression::Variable(token.text)),
+            },
+            TokenKind::Operator => match token.text.as_str() {
+                "(" => {
+                    let expression = self.expression()?;
+                    self.expect(")")?;
+                    Ok(expression)
+                }
+                "[" => Ok(Expression::List(self.list("]")?)),
+                text => Err(format!("unexpected `{}`", text)),
+            },
+            TokenKind::Separator => Err(format!("unexpected `{}`", token.text)),
+        }
+    }
+}
+
+/// Parses a whole expression.
+pub(crate) fn parse_expression(text: &str) -> Result<Expression, String> {
+    let mut parser = Parser::new(text)?;
+    let expression = parser.expression()?;
+    parser.finish()?;
+    Ok(expression)
+}
+
+/// Parses variables and items of lists separated by commas, as assigned or
+/// read into.
+pub(crate) fn parse_targets(text: &str) -> Result<Vec<Target>, String> {
+    Parser::new(text)?.targets()
+}
+
+/// Parses a list of expressions separated by commas, which may be empty.
+pub(crate) fn parse_expressions(text: &str) -> Result<Vec<Expression>, String> {
+    Parser::new(text)?.expressions()
+}
+
+/// Parses assignments such as `a = 1`, `a, b = b, a`, `a[i] := x`,
+/// `n <- n - 1` or `s += x`, one per line or separated by `;`.
+pub(crate) fn parse_assignments(text: &str) -> Result<Vec<Assignment>, String> {
+    let tokens = tokenize(text)?;
+    tokens
+        .split(|token| token.kind == TokenKind::Separator)
+        .filter(|tokens| !tokens.is_empty())
+        .map(|tokens| {
+            let text = &text[tokens[0].start..tokens[tokens.len() - 1].end];
+            assignment(tokens)?.ok_or_else(|| format!("`{}` is not an assignment", text))
+        })
+        .collect()
+}
+
+/// The assignment of `tokens`, or `None` if they do not assign.
+fn assignment(tokens: &[Token]) -> Result<Option<Assignment>, String> {
+    let mut depth = 0i32;
+    let position = tokens.iter().position(|token| {
+        if token.kind != TokenKind::Operator {
+            return false;
+        }
+        match token.text.as_str() {
+            "(" | "[" => depth += 1,
+            ")" | "]" => depth -= 1,
+            "=" | ":=" | "<-" | "+=" | "-=" | "*=" | "/=" | "%=" => return depth == 0,
+            _ => {}
+        }
+        false
+    });
+    let Some(position) = position else {
+        return Ok(None);
+    };
+    let operator = tokens[position]
+        .text
+        .chars()
+        .next()
+        .filter(|_| tokens[position].text.len() == 2 && tokens[position].text.ends_with('='));
+    let operator = operator.filter(|&c| c != ':');
+    let targets = Parser {
+        tokens: tokens[..position].to_vec(),
+        pos: 0,
+    }
+    .targets()?;
+    let values = Parser {
+        tokens: tokens[position + 1..].to_vec(),
+        pos: 0,
+    }
+    .expressions()?;
+    if values.len() != targets.len() || (operator.is_some() && targets.len() > 1) {
+        return Err(format!(
+            "{} values are assigned to {} variables",
+            values.len(),
+            targets.len()
+        ));
+    }
+    Ok(Some(Assignment {
+        targets,
+        operator,
+        values,
+    }))
+}
+
+impl Expression {
+    pub(crate) fn evaluate(&self, variables: &Variables) -> Result<Value, String> {
+        match self {
+            Expression::Literal(value) => Ok(value.clone()),
+            Expression::Variable(name) => variables
+                .get(name)
+                .cloned()
+                .ok_or_else(|| format!("`{}` has no value", name)),
+            Expression::List(items) => Ok(Value::List(
+                items
+                    .iter()
+                    .map(|item| item.evaluate(variables))
+                    .collect::<Result<_, _>>()?,
+            )),
+            Expression::Index(list, index) => {
+                let list = list.evaluate(variables)?;
+                let index = index.evaluate(variables)?;
+                index_of(&list, &index).map(|i| match &list {
+                    Value::List(items) => items[i].clone(),
+                    Value::Str(string) => Value::Str(string.chars().nth(i).unwrap().to_string()),
+                    _ => unreachable!(),
+                })
+            }
+            Expression::Call(name, arguments) => {
+                let arguments: Vec<Value> = arguments
+                    .iter()
+                    .map(|argument| argument.evaluate(variables))
+                    .collect::<Result<_, _>>()?;
+                call(name, &arguments)
+            }
+            Expression::Negate(operand) => match operand.evaluate(variables)? {
+                Value::Int(int) => int
+                    .checked_neg()
+                    .map(Value::Int)
+                    .ok_or_else(|| String::from("the number is too large")),
+                Value::Float(float) => Ok(Value::Float(-float)),
+                value => Err(format!("cannot negate {}", value.type_name())),
+            },
+            Expression::Not(operand) => Ok(Value::Bool(!truth(&operand.evaluate(variables)?)?)),
+            Expression::Binary(Binary::And, left, right) => Ok(Value::Bool(
+                truth(&left.evaluate(variables)?)? && truth(&right.evaluate(variables)?)?,
+            )),
+            Expression::Binary(Binary::Or, left, right) => Ok(Value::Bool(
+                truth(&left.evaluate(variables)?)? || truth(&right.evaluate(variables)?)?,
+            )),
+            Expression::Binary(operator, left, right) => binary(
+                *operator,
+                left.evaluate(variables)?,
+                right.evaluate(variables)?,
+            ),
+        }
+    }
+}
+
+/// The value of a condition, which must be `true` or `false`.
+pub(crate) fn truth(value: &Value) -> Result<bool, String> {
+    match value {
+        Value::Bool(bool) => Ok(*bool),
+        value => Err(format!("`{}` is not true or false", value)),
+    }
+}
+
+/// The position of an index in a list or string.
+pub(crate) fn index_of(list: &Value, index: &Value) -> Result<usize, String> {
+    let length = match list {
+        Value::List(items) => items.len(),
+        Value::Str(string) => string.chars().count(),
+        value => return Err(format!("cannot index {}", value.type_name())),
+    };
+    match *index {
+        Value::Int(i) if i >= 0 && (i as usize) < length => Ok(i as usize),
+        Value::Int(i) => Err(format!("index {} is out of range for length {}", i, length)),
+        ref value => Err(format!("cannot index with {}", value.type_name())),
+    }
+}
+
+fn binary(operator: Binary, left: Value, right: Value) -> Result<Value, String> {
+    use Value::*;
+    let mismatch = |left: &Value, right: &Value| {
+        format!(
+            "cannot combine {} and {}",
+            left.type_name(),
+            right.type_name()
+        )
+    };
+    let overflow = || String::from("the number is too large");
+    match (operator, &left, &right) {
+        (Binary::Equal, ..) => Ok(Bool(equal(&left, &right))),
+        (Binary::NotEqual, ..) => Ok(Bool(!equal(&left, &right))),
+        (Binary::Less | Binary::LessEqual | Binary::Greater | Binary::GreaterEqual, ..) => {
+            let ordering = match (&left, &right) {
+                (Str(a), Str(b)) => Some(a.cmp(b)),
+                _ => match (left.as_float(), right.as_float()) {
+                    (Some(a), Some(b)) => a.partial_cmp(&b),
+                    _ => return Err(mismatch(&left, &right)),
+                },
+            };
+            let Some(ordering) = ordering else {
+                return Ok(Bool(false));
+            };
+            Ok(Bool(match operator {
+                Binary::Less => ordering.is_lt(),
+                Binary::LessEqual => ordering.is_le(),
+                Binary::Greater => ordering.is_gt(),
+                _ => ordering.is_ge(),
+            }))
+        }
+        (Binary::Range, Int(a), Int(b)) => Ok(List((*a..*b).map(Int).collect())),
+        (Binary::RangeInclusive, Int(a), Int(b)) => Ok(List((*a..=*b).map(Int).collect())),
+        (Binary::Add, Str(a), b) => Ok(Str(format!("{}{}", a, b))),
+        (Binary::Add, a, Str(b)) => Ok(Str(format!("{}{}", a, b))),
+        (Binary::Add, List(a), List(b)) => Ok(List(a.iter().chain(b).cloned().collect())),
+        (_, Int(a), Int(b)) => {
+            let (a, b) = (*a, *b);
+            match operator {
+                Binary::Add => a.checked_add(b).map(Int).ok_or_else(overflow),
+                Binary::Subtract => a.checked_sub(b).map(Int).ok_or_else(overflow),
+                Binary::Multiply => a.checked_mul(b).map(Int).ok_or_else(overflow),
+                _ if b == 0 && operator != Binary::Power => Err(String::from("division by zero")),
+                Binary::Divide if a.checked_rem(b) == Some(0) => {
+                    a.checked_div(b).map(Int).ok_or_else(overflow)
+                }
+                Binary::Divide => Ok(Float(a as f64 / b as f64)),
+                Binary::FloorDivide => a.checked_div_euclid(b).map(Int).ok_or_else(overflow),
+                Binary::Remainder => a.checked_rem_euclid(b).map(Int).ok_or_else(overflow),
+                Binary::Power if b >= 0 => u32::try_from(b)
+                    .ok()
+                    .and_then(|b| a.checked_pow(b))
+                    .map(Int)
+                    .ok_or_else(overflow),
+                Binary::Power => Ok(Float((a as f64).powf(b as f64))),
+                _ => Err(mismatch(&left, &right)),
+            }
+        }
+        _ => match (left.as_float(), right.as_float()) {
+            (Some(a), Some(b)) => match operator {
+                Binary::Add => Ok(Float(a + b)),
+                Binary::Subtract => Ok(Float(a - b)),
+                Binary::Multiply => Ok(Float(a * b)),
+                _ if b == 0.0 && operator != Binary::Power => Err(String::from("division by zero")),
+                Binary::Divide => Ok(Float(a / b)),
+                Binary::FloorDivide => Ok(Float((a / b).floor())),
+                Binary::Remainder => Ok(Float(a.rem_euclid(b))),
+                Binary::Power => Ok(Float(a.powf(b))),
+                _ => Err(mismatch(&left, &right)),
+            },
+            _ => Err(mismatch(&left, &right)),
+        },
+    }
+}
+
+/// Equality, where integers equal the same numbers.
+pub(crate) fn equal(left: &Value, right: &Value) -> bool {
+    match (left.as_float(), right.as_float()) {
+        (Some(a), Some(b)) => a == b,
+        _ => left == right,
+    }
+}
+
+fn call(name: &str, arguments: &[Value]) -> Result<Value, String> {
+    use Value::*;
+    let numbers = || -> Result<Vec<f64>, String> {
+        arguments
+            .iter()
+            .map(|argument| {
+                argument
+                    .as_float()
+                    .ok_or_else(|| format!("`{}` takes numbers", name))
+            })
+            .collect()
+    };
+    match (name, arguments) {
+        ("len", [List(items)]) => Ok(Int(items.len() as i64)),
+        ("len", [Str(string)]) => Ok(Int(string.chars().count() as i64)),
+        ("abs", [Int(int)]) => Ok(Int(int.abs())),
+        ("abs", [Float(float)]) => Ok(Float(float.abs())),
+        ("min" | "max", [List(items)]) => call(name, items),
+        ("min" | "max", [_, ..]) => {
+            let mut best = arguments[0].clone();
+            for argument in &arguments[1..] {
+                let operator = match name {
+                    "min" => Binary::Less,
+                    _ => Binary::Greater,
+                };
+                if truth(&binary(operator, argument.clone(), best.clone())?)? {
+                    best = argument.clone();
+                }
+            }
+            Ok(best)
+        }
+        ("sqrt", [_]) => Ok(Float(numbers()?[0].sqrt())),
+        ("int", [Int(int)]) => Ok(Int(*int)),
+        ("int", [Float(float)]) => Ok(Int(float.trunc() as i64)),
+        ("int", [Str(string)]) => string
+            .trim()
+            .parse()
+            .map(Int)
+            .map_err(|_| format!("`{}` is not an integer", string)),
+        ("str", [value]) => Ok(Str(value.to_string())),
+        ("range", [Int(end)]) => Ok(List((0..*end).map(Int).collect())),
+        ("range", [Int(start), Int(end)]) => Ok(List((*start..*end).map(Int).collect())),
+        ("len" | "abs" | "min" | "max" | "sqrt" | "int" | "str" | "range", _) => Err(format!(
+            "`{}` does not take {} argument(s) of these types",
+            name,
+            arguments.len()
+        )),
+        _ => Err(format!("there is no function `{}`", name)),
+    }
+}
+
+impl Assignment {
+    /// Assigns all values, which are computed before any is assigned, so
+    /// that `a, b = b, a` swaps.
+    pub(crate) fn execute(&self, variables: &mut Variables) -> Result<(), String> {
+        let values: Vec<Value> = self
+            .values
+            .iter()
+            .map(|value| value.evaluate(variables))
+            .collect::<Result<_, _>>()?;
+        for (target, value) in self.targets.iter().zip(values) {
+            let (name, indices) = match target {
+                Target::Variable(name) => (name, vec![]),
+                Target::Index(name, indices) => (
+                    name,
+                    indices
+                        .iter()
+                        .map(|index| index.evaluate(variables))
+                        .collect::<Result<_, _>>()?,
+                ),
+            };
+            let mut slot = match variables.get_mut(name) {
+                Some(slot) => slot,
+                None if indices.is_empty() && self.operator.is_none() => {
+                    variables.insert(name.clone(), value);
+                    continue;
+                }
+                None => return Err(format!("`{}` has no value", name)),
+            };
+            for index in &indices {
+                let i = index_of(slot, index)?;
+                slot = match slot {
+                    Value::List(items) => &mut items[i],
+                    _ => return Err(String::from("only the items of lists can be assigned")),
+                };
+            }
+            *slot = match self.operator {
+                None => value,
+                Some(operator) => {
+                    let operator = match operator {
+                        '+' => Binary::Add,
+                        '-' => Binary::Subtract,
+                        '*' => Binary::Multiply,
+                        '/' => Binary::Divide,
+                        _ => Binary::Remainder,
+                    };
+                    binary(operator, slot.clone(), value)?
+                }
+            };
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn evaluate(text: &str) -> Result<Value, String> {
+        let variables = Variables::from([
+            (String::from("n"), Value::Int(7)),
+            (String::from("xs"), Value::parse("[3, 1, 4]")),
+        ]);
+        parse_expression(text)?.evaluate(&variables)
+    }
+
+    fn assign(text: &str) -> Result<Variables, String> {
+        let mut variables = Variables::new();
+        for assignment in parse_assignments(text)? {
+            assignment.execute(&mut variables)?;
+        }
+        Ok(variables)
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(evaluate("1 + 2 * 3 ** 2"), Ok(Value::Int(19)));
+        assert_eq!(evaluate("-2 ^ 2"), Ok(Value::Int(-4)));
+        assert_eq!(evaluate("not n > 3 or n % 2 = 1"), Ok(Value::Bool(true)));
+        assert_eq!(evaluate("n ≥ 7 && n ≠ 8"), Ok(Value::Bool(true)));
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(evaluate("6 / 3"), Ok(Value::Int(2)));
+        assert_eq!(evaluate("7 / 2"), Ok(Value::Float(3.5)));
+        assert_eq!(evaluate("-7 // 2"), Ok(Value::Int(-4)));
+        assert_eq!(evaluate("-7 mod 2"), Ok(Value::Int(1)));
+        assert_eq!(evaluate("1 / 0"), Err(String::from("division by zero")));
+        assert_eq!(
+            evaluate("9223372036854775807 + 1"),
+            Err(String::from("the number is too large"))
+        );
+        assert_eq!(
+            evaluate("1 - 'a'"),
+            Err(String::from("cannot combine an integer and a string"))
+        );
+        assert_eq!(evaluate("-[1]"), Err(String::from("cannot negate a list")));
+
+        let display = |source| evaluate(source).unwrap().to_string();
+        assert_eq!(display("4 / 2.0"), "2.0");
+        assert_eq!(display("1 / 4"), "0.25");
+        assert_eq!(display("2.0 ** 100"), "1.2676506002282294e30");
+    }
+
+    #[test]
+    fn lists_and_strings() {
+        assert_eq!(evaluate("xs[2] + len(xs)"), Ok(Value::Int(7)));
+        assert_eq!(evaluate("max(xs) - min(1..=3)"), Ok(Value::Int(3)));
+        assert_eq!(
+            evaluate("'n = ' + n"),
+            Ok(Value::Str(String::from("n = 7")))
+        );
+        assert_eq!(evaluate("\"a;b\"[1]"), Ok(Value::Str(String::from(";"))));
+        assert_eq!(
+            evaluate("xs[3]"),
+            Err(String::from("index 3 is out of range for length 3"))
+        );
+    }
+
+    #[test]
+    fn assignments() {
+        let variables = assign("a, b = 1, 2; a, b = b, a\nxs := [a, b]; xs[0] += 10").unwrap();
+        assert_eq!(variables["a"], Value::Int(2));
+        assert_eq!(variables["xs"], Value::parse("[12, 1]"));
+        let variables = assign("s <- \"x;y\"; t = 'a\nb'").unwrap();
+        assert_eq!(variables["s"], Value::Str(String::from("x;y")));
+        assert_eq!(variables["t"], Value::Str(String::from("a\nb")));
+        assert_eq!(
+            assign("a = 1; print a"),
+            Err(String::from("`print a` is not an assignment"))
+        );
+        assert_eq!(
+            assign("a, b = 1"),
+            Err(String::from("1 values are assigned to 2 variables"))
+        );
+    }
+
+    #[test]
+    fn separators_only_split_assignments() {
+        assert_eq!(evaluate("n >\n3"), Ok(Value::Bool(true)));
+        assert_eq!(evaluate("n; 3"), Err(String::from("unexpected `;`")));
+    }
+}
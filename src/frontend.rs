@@ -0,0 +1,293 @@
+//! Frontends that build charts from the source code of other languages, and
+//! graphs from assembly listings.
+
+use std::{
+    collections::HashSet,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    cfg::{Graph, GraphError},
+    chart::{file_stem, Program},
+    config::Config,
+    parser::{self, ParseError},
+    svg::Svg,
+};
+
+pub mod asm;
+pub mod c;
+pub mod python;
+pub mod rust;
+
+/// The extensions of the sources looked for in directories, with `flow` for
+/// the chart source language, which [`read`] reads from any other file too.
+pub const EXTENSIONS: &[&str] = &["flow", "c", "py", "rs", "json", "s", "S", "asm", "dis"];
+
+/// The sources under `dir` and its subdirectories, found by their
+/// [extensions](EXTENSIONS) and sorted. Hidden files and directories, whose
+/// names begin with a dot, are left out. Directories are searched in the
+/// order of their paths, and one reached again through a symbolic link only
+/// the first time.
+pub fn find_sources(dir: &Path) -> Vec<PathBuf> {
+    let mut sources = vec![];
+    let mut visited = HashSet::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(canonical) = fs::canonicalize(&dir) else {
+            continue;
+        };
+        if !visited.insert(canonical) {
+            continue;
+        }
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        let mut paths: Vec<PathBuf> = entries.flatten().map(|entry| entry.path()).collect();
+        paths.sort();
+        // The directories are pushed last first, so the first is searched
+        // first.
+        for path in paths.into_iter().rev() {
+            let hidden = path
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+            if hidden {
+                continue;
+            }
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path
+                .extension()
+                .is_some_and(|extension| EXTENSIONS.iter().any(|known| extension == *known))
+            {
+                sources.push(path);
+            }
+        }
+    }
+    sources.sort();
+    sources
+}
+
+/// Whether [`read`] reads `path` as the chart source language, the only one
+/// whose procedures can be run.
+pub fn is_chart_source(path: &Path) -> bool {
+    path.extension().is_none_or(|extension| {
+        extension == "flow" || !EXTENSIONS.iter().any(|known| extension == *known)
+    })
+}
+
+/// What a source holds.
+pub enum Input {
+    /// Procedures, from the chart source language or from source code.
+    Program(Program),
+    /// A control-flow graph, named after its file.
+    Graph(String, Graph),
+    /// The control-flow graph of every function of an assembly listing.
+    Listing(Vec<(String, Graph)>),
+}
+
+#[derive(Debug)]
+pub enum ReadError {
+    Parse { file: String, error: ParseError },
+    Graph { file: String, error: GraphError },
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadError::Parse { file, error } => write!(f, "{}:{}", file, error),
+            ReadError::Graph { file, error } => write!(f, "{}: {}", file, error),
+        }
+    }
+}
+
+impl std::error::Error for ReadError {}
+
+/// Reads `source` as told by the extension of its `path`: C, Python or Rust
+/// source code, a control-flow graph in JSON, an assembly listing, or else
+/// the chart source language. Spans name `path` as their file,
+/// unless those of a graph name their own.
+pub fn read(path: &Path, source: &str) -> Result<Input, ReadError> {
+    let file = path.display().to_string();
+    let parse_error = |error| ReadError::Parse {
+        file: file.clone(),
+        error,
+    };
+    let parse = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("json") => {
+            let mut graph = Graph::from_json(source).map_err(|error| ReadError::Graph {
+                file: file.clone(),
+                error,
+            })?;
+            graph.set_file(&file);
+            let name = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            return Ok(Input::Graph(name, graph));
+        }
+        Some("s" | "S" | "asm" | "dis") => {
+            let mut functions = asm::parse(source).map_err(parse_error)?;
+            for (_, graph) in &mut functions {
+                graph.set_file(&file);
+            }
+            return Ok(Input::Listing(functions));
+        }
+        Some("c") => c::parse,
+        Some("py") => python::parse,
+        Some("rs") => rust::parse,
+        _ => parser::parse,
+    };
+    let mut program = parse(source).map_err(parse_error)?;
+    program.set_file(&file);
+    Ok(Input::Program(program))
+}
+
+impl Input {
+    /// Renders every chart, named after its [`file_stem`]. Calls link to
+    /// whatever `href` returns for the name of the callee's chart. Graphs are
+    /// drawn with their loops and branches recovered unless `layered`, and
+    /// listings always in layers.
+    pub fn to_svgs(
+        &self,
+        config: &Config,
+        layered: bool,
+        href: &dyn Fn(&str) -> String,
+    ) -> Vec<(String, Svg)> {
+        match self {
+            Input::Program(program) => {
+                let link = |name: &str| program.procedure(name).map(|_| href(&file_stem(name)));
+                program
+                    .procedures
+                    .iter()
+                    .map(|procedure| {
+                        let svg = procedure.to_svg(config, &link);
+                        (file_stem(&procedure.name), svg)
+                    })
+                    .collect()
+            }
+            Input::Graph(name, graph) => {
+                let svg = match layered {
+                    true => graph.to_layered_svg(config),
+                    false => graph.to_svg(config),
+                };
+                vec![(name.clone(), svg)]
+            }
+            Input::Listing(functions) => functions
+                .iter()
+                .map(|(name, graph)| (file_stem(name), graph.to_layered_svg(config)))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::ConfigBuilder, temp_dir::TempDir};
+
+    /// The names and charts of `path` read from `source`.
+    fn stems(path: &str, source: &str, layered: bool) -> Vec<(String, String)> {
+        let config = ConfigBuilder::new().build();
+        let input = read(Path::new(path), source).unwrap_or_else(|error| panic!("{}", error));
+        input
+            .to_svgs(&config, layered, &|stem| format!("{}.svg", stem))
+            .into_iter()
+            .map(|(stem, svg)| (stem, svg.to_string()))
+            .collect()
+    }
+
+    fn names(charts: &[(String, String)]) -> Vec<&str> {
+        charts.iter().map(|(stem, _)| stem.as_str()).collect()
+    }
+
+    #[test]
+    fn finds_sources() {
+        let dir = TempDir::new("frontend");
+        for file in [
+            "b/c.rs",
+            "a.flow",
+            "d.S",
+            "notes.txt",
+            ".hidden/e.flow",
+            "b/.f.py",
+        ] {
+            dir.write(file, "");
+        }
+        let sources = find_sources(&dir.0);
+        let relative: Vec<&Path> = sources
+            .iter()
+            .map(|source| source.strip_prefix(&dir.0).unwrap())
+            .collect();
+        assert_eq!(
+            relative,
+            [Path::new("a.flow"), Path::new("b/c.rs"), Path::new("d.S")]
+        );
+
+        // Links back up, or to a directory searched already, are not
+        // followed again.
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(&dir.0, dir.0.join("b/up")).unwrap();
+            std::os::unix::fs::symlink(dir.0.join("b"), dir.0.join("e")).unwrap();
+            assert_eq!(find_sources(&dir.0), sources);
+        }
+    }
+
+    #[test]
+    fn reads_by_extension() {
+        let charts = stems(
+            "a.flow",
+            "proc main\n  call helper\nend\n\nproc helper\nend\n",
+            false,
+        );
+        assert_eq!(names(&charts), ["main", "helper"]);
+        // Calls link to the charts of the procedures they call.
+        assert!(charts[0].1.contains(r#"xlink:href="helper.svg""#));
+        assert_eq!(names(&stems("a.txt", "proc x\nend\n", false)), ["x"]);
+        assert_eq!(
+            names(&stems("a.c", "int f(void) { return 1; }\n", false)),
+            ["f"]
+        );
+        assert_eq!(names(&stems("a.py", "def g():\n    pass\n", false)), ["g"]);
+        assert_eq!(names(&stems("a.rs", "fn h() {}\n", false)), ["h"]);
+        assert_eq!(
+            names(&stems(
+                "a.asm",
+                "main:\n  call g\n  ret\ng:\n  ret\n",
+                false
+            )),
+            ["main", "g"]
+        );
+
+        let json = r#"{ "nodes": [{ "id": 0, "kind": "Process", "label": "x",
+            "span": { "line": 1, "column": 1, "end_line": 1, "end_column": 2 } }] }"#;
+        let charts = stems("graphs/g.json", json, false);
+        assert_eq!(names(&charts), ["g"]);
+        assert!(charts[0].1.contains(r#"data-file="graphs/g.json""#));
+        assert_eq!(names(&stems("graphs/g.json", json, true)), ["g"]);
+
+        for chart in ["a.flow", "a.txt", "a"] {
+            assert!(is_chart_source(Path::new(chart)));
+        }
+        for other in ["a.c", "a.py", "a.rs", "a.json", "a.asm"] {
+            assert!(!is_chart_source(Path::new(other)));
+        }
+    }
+
+    #[test]
+    fn errors_name_their_file() {
+        let error = |path: &str, source: &str| {
+            read(Path::new(path), source)
+                .err()
+                .expect("an error")
+                .to_string()
+        };
+        assert_eq!(
+            error("a.flow", "io x\n"),
+            "a.flow:1:1: expected `proc`, found `io`"
+        );
+        assert!(error("a.c", "int f(void) {\n").starts_with("a.c:1:"));
+        assert!(error("g.json", "{").starts_with("g.json: "));
+    }
+}
@@ -0,0 +1,604 @@
+//! Builds control-flow graphs from assembly listings.
+//!
+//! Both assembler source, in AT&T or Intel syntax for x86-64 or in RISC-V
+//! syntax, and the output of `objdump -d` are read. Every basic block becomes
+//! a process block with its instructions, one per line, under its label or
+//! address. A block ends at a jump, a branch or a return, and a new one
+//! begins at every label and every address jumped to. A conditional branch
+//! leads to its target, `taken`, and to the next block, `not taken`.
+//!
+//! A listing holds one graph per function. Functions begin at the headers
+//! of `objdump`, at labels declared with `.globl` or `.type … @function`, and
+//! at the labels called; without any of these the listing is one function.
+//! A function holds the blocks reached from its beginning without passing
+//! the beginning of another. Blocks that no function reaches, such as code
+//! after a computed jump, belong to the function they follow in the listing
+//! and are drawn apart from its flow. Directives and data are skipped.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    block::{BlockKind, Span},
+    cfg::{Edge, Graph, Node},
+    parser::ParseError,
+};
+
+/// Instruction prefixes skipped to find the mnemonic.
+const PREFIXES: &[&str] = &[
+    "rep", "repe", "repz", "repne", "repnz", "lock", "notrack", "bnd", "data16", "cs", "ds",
+];
+
+/// NASM directives and data definitions, which are skipped like the
+/// directives of GNU `as` that begin with a dot.
+const DIRECTIVES: &[&str] = &[
+    "section", "segment", "extern", "bits", "default", "align", "db", "dw", "dd", "dq", "dt",
+    "resb", "resw", "resd", "resq", "times", "equ", "global",
+];
+
+/// RISC-V branches, which compare registers and take their target last.
+const RISCV_BRANCHES: &[&str] = &[
+    "beq", "bne", "blt", "bge", "bltu", "bgeu", "beqz", "bnez", "blez", "bgez", "bltz", "bgtz",
+    "bgt", "ble", "bgtu", "bleu",
+];
+
+/// Where a jump or a call goes.
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Target {
+    Label(String),
+    Address(u64),
+}
+
+/// How an instruction passes the flow on.
+#[derive(Clone)]
+enum Flow {
+    /// To the next instruction.
+    Next,
+    /// To the next instruction, after a call to the target, if it is known.
+    Call(Option<Target>),
+    /// To the target only, unless it is computed.
+    Jump(Option<Target>),
+    /// To the target or to the next instruction.
+    Branch(Target),
+    /// Out of the function.
+    Return,
+}
+
+enum Item {
+    /// A function header of `objdump`.
+    Function(String),
+    /// A label, with its line and column.
+    Label(String, usize, usize),
+    /// A label declared to begin a function.
+    Entry(String),
+    Instruction {
+        address: Option<u64>,
+        /// The line of the listing it is on.
+        line: usize,
+        text: String,
+        flow: Flow,
+    },
+}
+
+/// The instructions of a basic block, as read from the listing.
+struct Run {
+    title: String,
+    lines: Vec<String>,
+    flow: Flow,
+    /// The lines of the listing from the first instruction to the last.
+    first_line: usize,
+    last_line: usize,
+}
+
+/// Reads a listing into the graph of every function, named after it.
+pub fn parse(source: &str) -> Result<Vec<(String, Graph)>, ParseError> {
+    let source_lines: Vec<&str> = source.lines().collect();
+    let mut items = vec![];
+    for (number, line) in source_lines.iter().enumerate() {
+        parse_line(line, number + 1, &mut items);
+    }
+
+    let mut leaders = HashSet::new();
+    let mut entries = HashSet::new();
+    for item in &items {
+        match item {
+            Item::Entry(name) => {
+                entries.insert(Target::Label(name.clone()));
+            }
+            Item::Instruction { flow, .. } => match flow {
+                Flow::Jump(Some(Target::Address(address)))
+                | Flow::Branch(Target::Address(address)) => {
+                    leaders.insert(*address);
+                }
+                Flow::Call(Some(target)) => {
+                    entries.insert(target.clone());
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    let mut blocks: Vec<Run> = vec![];
+    let mut labels: HashMap<Target, usize> = HashMap::new();
+    let mut functions: Vec<(usize, String)> = vec![];
+    let mut pending: Vec<String> = vec![];
+    let mut function = None;
+    let mut start = true;
+    for item in items {
+        match item {
+            Item::Function(name) => {
+                function = Some(name.clone());
+                pending.push(name);
+                start = true;
+            }
+            Item::Label(name, line, column) => {
+                let target = Target::Label(name.clone());
+                if labels.contains_key(&target) || pending.contains(&name) {
+                    return Err(ParseError {
+                        line,
+                        column,
+                        message: format!("label `{}` is defined more than once", name),
+                    });
+                }
+                pending.push(name);
+                start = true;
+            }
+            Item::Entry(_) => {}
+            Item::Instruction {
+                address,
+                line,
+                text,
+                flow,
+            } => {
+                if start || address.is_some_and(|address| leaders.contains(&address)) {
+                    let index = blocks.len();
+                    let title = match (pending.first(), address) {
+                        (Some(label), _) => format!("{}:", label),
+                        (None, Some(address)) => format!("{:x}:", address),
+                        (None, None) => String::new(),
+                    };
+                    if let Some(name) = function.take() {
+                        functions.push((index, name));
+                    }
+                    for label in pending.drain(..) {
+                        if entries.contains(&Target::Label(label.clone())) {
+                            functions.push((index, label.clone()));
+                        }
+                        labels.insert(Target::Label(label), index);
+                    }
+                    if let Some(address) = address {
+                        if entries.contains(&Target::Address(address)) {
+                            functions.push((index, format!("{:x}", address)));
+                        }
+                        labels.insert(Target::Address(address), index);
+                    }
+                    blocks.push(Run {
+                        title,
+                        lines: vec![],
+                        flow: Flow::Next,
+                        first_line: line,
+                        last_line: line,
+                    });
+                }
+                let block = blocks.last_mut().unwrap();
+                block.lines.push(text);
+                block.last_line = line;
+                start = matches!(flow, Flow::Jump(_) | Flow::Branch(_) | Flow::Return);
+                block.flow = flow;
+            }
+        }
+    }
+    if blocks.is_empty() {
+        return Err(ParseError {
+            line: 1,
+            column: 1,
+            message: String::from("the listing has no instructions"),
+        });
+    }
+    // Labels called and declared both name the same function.
+    functions.sort_by_key(|&(index, _)| index);
+    functions.dedup_by_key(|&mut (index, _)| index);
+    if functions.first().is_none_or(|&(index, _)| index > 0) {
+        let name = match blocks[0].title.strip_suffix(':') {
+            Some(label) if !label.is_empty() => label.to_string(),
+            _ => String::from("code"),
+        };
+        functions.insert(0, (0, name));
+    }
+
+    let resolve = |target: &Target| labels.get(target).copied();
+    let successors: Vec<Vec<(usize, Option<&str>)>> = blocks
+        .iter()
+        .enumerate()
+        .map(|(index, block)| {
+            let next = (index + 1 < blocks.len()).then_some(index + 1);
+            match &block.flow {
+                Flow::Next | Flow::Call(_) => next.map(|next| (next, None)).into_iter().collect(),
+                Flow::Jump(target) => target
+                    .as_ref()
+                    .and_then(resolve)
+                    .map(|to| (to, None))
+                    .into_iter()
+                    .collect(),
+                Flow::Branch(target) => resolve(target)
+                    .map(|to| (to, Some("taken")))
+                    .into_iter()
+                    .chain(next.map(|next| (next, Some("not taken"))))
+                    .collect(),
+                Flow::Return => vec![],
+            }
+        })
+        .collect();
+
+    let beginnings: HashSet<usize> = functions.iter().map(|&(index, _)| index).collect();
+    let mut reached: Vec<Vec<bool>> = functions
+        .iter()
+        .map(|&(entry, _)| {
+            let mut reached = vec![false; blocks.len()];
+            reached[entry] = true;
+            let mut stack = vec![entry];
+            while let Some(index) = stack.pop() {
+                for &(to, _) in &successors[index] {
+                    if !reached[to] && !beginnings.contains(&to) {
+                        reached[to] = true;
+                        stack.push(to);
+                    }
+                }
+            }
+            reached
+        })
+        .collect();
+    // Unreachable blocks are kept in the function before them.
+    for index in 0..blocks.len() {
+        if reached.iter().all(|reached| !reached[index]) {
+            let function = functions.partition_point(|&(entry, _)| entry <= index) - 1;
+            reached[function][index] = true;
+        }
+    }
+    Ok(functions
+        .iter()
+        .zip(reached)
+        .map(|((_, name), reached)| {
+            let nodes = (0..blocks.len())
+                .filter(|&index| reached[index])
+                .map(|index| {
+                    let block = &blocks[index];
+                    let label = std::iter::once(block.title.as_str())
+                        .filter(|title| !title.is_empty())
+                        .chain(block.lines.iter().map(String::as_str))
+                        .collect::<Vec<_>>()
+                        .join("\n");
+                    let end_column = source_lines[block.last_line - 1].chars().count() + 1;
+                    Node {
+                        id: index.to_string(),
+                        kind: BlockKind::Process,
+                        label,
+                        span: Some(Span::new(block.first_line, 1, block.last_line, end_column)),
+                        lane: None,
+                    }
+                })
+                .collect();
+            let edges = (0..blocks.len())
+                .filter(|&index| reached[index])
+                .flat_map(|from| {
+                    successors[from].iter().filter(|&&(to, _)| reached[to]).map(
+                        move |&(to, label)| Edge {
+                            from: from.to_string(),
+                            to: to.to_string(),
+                            label: label.map(String::from),
+                        },
+                    )
+                })
+                .collect();
+            (name.clone(), Graph { nodes, edges })
+        })
+        .collect())
+}
+
+fn parse_line(line: &str, number: usize, items: &mut Vec<Item>) {
+    // An instruction line of `objdump`: the address, the bytes if shown,
+    // and the instruction, separated by tabs.
+    if let Some((address, rest)) = line.trim_start().split_once(":\t") {
+        if let Ok(address) = u64::from_str_radix(address, 16) {
+            let fields: Vec<&str> = rest.split('\t').collect();
+            let instruction = match fields.as_slice() {
+                [bytes] if is_bytes(bytes) => return,
+                [instruction] => instruction.to_string(),
+                [_, instruction @ ..] => instruction.join(" "),
+                [] => return,
+            };
+            parse_instruction(strip_comment(&instruction), Some(address), number, items);
+            return;
+        }
+    }
+    let text = strip_comment(line);
+    let trimmed = text.trim();
+    if trimmed.is_empty()
+        || trimmed == "..."
+        || trimmed.starts_with("Disassembly of section")
+        || trimmed.contains("file format")
+    {
+        return;
+    }
+    // A function header of `objdump`, such as `0000000000001139 <main>:`.
+    if let Some((address, rest)) = trimmed.split_once(" <") {
+        if u64::from_str_radix(address, 16).is_ok() {
+            if let Some(name) = rest.strip_suffix(">:") {
+                items.push(Item::Function(name.to_string()));
+            }
+            return;
+        }
+    }
+    let indent = text.len() - text.trim_start().len();
+    let mut rest = trimmed;
+    if let Some((label, after)) = rest.split_once(':') {
+        if is_label(label) {
+            let column = text[..indent].chars().count() + 1;
+            items.push(Item::Label(label.to_string(), number, column));
+            rest = after.trim();
+        }
+    }
+    let Some(keyword) = rest.split_whitespace().next() else {
+        return;
+    };
+    let operands = rest[keyword.len()..].trim();
+    match keyword.to_lowercase().as_str() {
+        ".globl" | ".global" | "global" => items.extend(
+            operands
+                .split(',')
+                .map(|name| Item::Entry(name.trim().to_string())),
+        ),
+        ".type" => {
+            if let Some((name, kind)) = operands.split_once(',') {
+                if kind.trim().ends_with("function") {
+                    items.push(Item::Entry(name.trim().to_string()));
+                }
+            }
+        }
+        keyword if keyword.starts_with('.') || keyword.starts_with('%') => {}
+        keyword if DIRECTIVES.contains(&keyword) => {}
+        // NASM data named without a colon, such as `message db "hi"`.
+        _ if rest
+            .split_whitespace()
+            .nth(1)
+            .is_some_and(|word| DIRECTIVES.contains(&word.to_lowercase().as_str())) => {}
+        _ => parse_instruction(rest, None, number, items),
+    }
+}
+
+fn parse_instruction(text: &str, address: Option<u64>, line: usize, items: &mut Vec<Item>) {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let Some(position) = words
+        .iter()
+        .position(|word| !PREFIXES.contains(&word.to_lowercase().as_str()))
+    else {
+        return;
+    };
+    let mnemonic = words[position].to_lowercase();
+    // The operands as they are, after the mnemonic.
+    let start = text.find(words[position]).unwrap() + words[position].len();
+    let operands = text[start..].trim();
+    let operand_list: Vec<&str> = operands.split(',').map(str::trim).collect();
+    let last = operand_list.last().copied().unwrap_or("");
+    let target = |operand: &str| target(operand, address.is_some());
+    let flow = match mnemonic.as_str() {
+        // RISC-V, whose mnemonics are told apart first as some begin with
+        // `j` like the jumps of x86.
+        "j" => Flow::Jump(target(operands)),
+        "jal" => match operand_list.as_slice() {
+            [rd, to] if matches!(*rd, "zero" | "x0") => Flow::Jump(target(to)),
+            _ => Flow::Call(target(last)),
+        },
+        "jr" if matches!(operands, "ra" | "x1") => Flow::Return,
+        "jr" => Flow::Jump(None),
+        "jalr" if matches!(operand_list[0], "zero" | "x0") => Flow::Jump(None),
+        "jalr" => Flow::Call(None),
+        "call" | "callq" | "calll" => Flow::Call(target(last)),
+        "tail" => Flow::Jump(None),
+        mnemonic if RISCV_BRANCHES.contains(&mnemonic) => match target(last) {
+            Some(to) => Flow::Branch(to),
+            None => Flow::Next,
+        },
+        // x86-64.
+        "jmp" | "jmpq" | "jmpl" | "ljmp" => Flow::Jump(target(operands)),
+        "ret" | "retq" | "retl" | "retn" | "iret" | "iretq" | "sysret" | "hlt" | "ud2" => {
+            Flow::Return
+        }
+        mnemonic
+            if mnemonic.starts_with('j')
+                || matches!(mnemonic, "loop" | "loope" | "loopz" | "loopne" | "loopnz") =>
+        {
+            match target(operands) {
+                Some(to) => Flow::Branch(to),
+                None => Flow::Next,
+            }
+        }
+        _ => Flow::Next,
+    };
+    items.push(Item::Instruction {
+        address,
+        line,
+        text: words.join(" "),
+        flow,
+    });
+}
+
+/// The target of a jump or call operand, unless it is computed, as through
+/// the registers and memory of Intel syntax. Listings of `objdump` give
+/// targets as addresses followed by the symbol.
+fn target(operand: &str, addressed: bool) -> Option<Target> {
+    let mut operand = operand.trim();
+    for size in ["short ", "near ", "far "] {
+        operand = operand.strip_prefix(size).unwrap_or(operand);
+    }
+    if operand.contains('[') {
+        return None;
+    }
+    let first = operand.split_whitespace().next()?;
+    if is_register(first) {
+        return None;
+    }
+    if addressed {
+        if let Ok(address) = u64::from_str_radix(first, 16) {
+            return Some(Target::Address(address));
+        }
+    }
+    is_label(first).then(|| Target::Label(first.to_string()))
+}
+
+/// Whether `word` names a general-purpose register of x86-64, or the
+/// instruction pointer.
+fn is_register(word: &str) -> bool {
+    let word = word.to_lowercase();
+    let numbered = word.strip_prefix('r').and_then(|rest| {
+        let number = rest.trim_end_matches(['d', 'w', 'b']);
+        let suffix = &rest[number.len()..];
+        (suffix.len() <= 1).then(|| number.parse::<u8>().ok())?
+    });
+    numbered.is_some_and(|number| (8..16).contains(&number))
+        || ["ax", "bx", "cx", "dx", "si", "di", "bp", "sp", "ip"]
+            .iter()
+            .any(|name| {
+                ["", "e", "r"]
+                    .iter()
+                    .any(|size| word == format!("{}{}", size, name))
+            })
+        || [
+            "al", "bl", "cl", "dl", "ah", "bh", "ch", "dh", "sil", "dil", "bpl", "spl",
+        ]
+        .contains(&word.as_str())
+}
+
+fn is_label(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || "_.$@?".contains(c))
+        && chars.all(|c| c.is_ascii_alphanumeric() || "_.$@?".contains(c))
+}
+
+/// Whether a field of `objdump` holds only instruction bytes, as on the
+/// lines continuing a long instruction.
+fn is_bytes(field: &str) -> bool {
+    field
+        .split_whitespace()
+        .all(|word| word.len() % 2 == 0 && word.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+fn strip_comment(line: &str) -> &str {
+    let end = ["#", ";", "//"]
+        .iter()
+        .filter_map(|marker| line.find(marker))
+        .min()
+        .unwrap_or(line.len());
+    &line[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The first line of every node of `graph`, and its edges between them.
+    fn outline(graph: &Graph) -> (Vec<&str>, Vec<String>) {
+        let first = |id: &str| {
+            let node = graph.nodes.iter().find(|node| node.id == id).unwrap();
+            node.label.lines().next().unwrap().to_string()
+        };
+        let nodes = graph
+            .nodes
+            .iter()
+            .map(|node| node.label.lines().next().unwrap())
+            .collect();
+        let edges = graph
+            .edges
+            .iter()
+            .map(|edge| {
+                let arrow = format!("{} -> {}", first(&edge.from), first(&edge.to));
+                match &edge.label {
+                    Some(label) => format!("{} {}", arrow, label),
+                    None => arrow,
+                }
+            })
+            .collect();
+        (nodes, edges)
+    }
+
+    #[test]
+    fn branches_split_blocks() {
+        let functions = parse("f:\n  cmp $0, %rdi\n  je .L2\n  inc %rax\n.L2:\n  ret\n").unwrap();
+        assert_eq!(functions.len(), 1);
+        let (name, graph) = &functions[0];
+        assert_eq!(name, "f");
+        let (nodes, edges) = outline(graph);
+        assert_eq!(nodes, ["f:", "inc %rax", ".L2:"]);
+        assert_eq!(
+            edges,
+            [
+                "f: -> .L2: taken",
+                "f: -> inc %rax not taken",
+                "inc %rax -> .L2:",
+            ]
+        );
+    }
+
+    #[test]
+    fn functions_begin_at_calls() {
+        let functions = parse("main:\n  call g\n  ret\ng:\n  ret\n").unwrap();
+        let names: Vec<&str> = functions.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["main", "g"]);
+        assert_eq!(outline(&functions[0].1).0, ["main:"]);
+        assert_eq!(outline(&functions[1].1).0, ["g:"]);
+    }
+
+    #[test]
+    fn unreachable_blocks_are_kept() {
+        let functions = parse("f:\n  jmp *%rax\n.L3:\n  jmp .L3\n").unwrap();
+        let (nodes, edges) = outline(&functions[0].1);
+        assert_eq!(nodes, ["f:", ".L3:"]);
+        assert_eq!(edges, [".L3: -> .L3:"]);
+
+        let functions = parse("ret\njmp\nje\n").unwrap();
+        assert_eq!(functions.len(), 1);
+        let (nodes, edges) = outline(&functions[0].1);
+        assert_eq!(nodes, ["ret", "jmp", "je"]);
+        assert!(edges.is_empty());
+    }
+
+    #[test]
+    fn unreachable_blocks_stay_with_their_function() {
+        let functions = parse("f:\n  ret\n  nop\ng:\n  ret\n.globl f\n.globl g\n").unwrap();
+        assert_eq!(outline(&functions[0].1).0, ["f:", "nop"]);
+        assert_eq!(outline(&functions[1].1).0, ["g:"]);
+    }
+
+    #[test]
+    fn indirect_targets_are_unknown() {
+        for operand in [
+            "qword [rax]",
+            "QWORD PTR [rip+0x10]",
+            "rax",
+            "r12d",
+            "[rel f]",
+        ] {
+            assert!(target(operand, false).is_none(), "{}", operand);
+        }
+        assert!(target("f", false) == Some(Target::Label(String::from("f"))));
+        assert!(target("r16", false).is_some());
+
+        let source = "main:\n  call rax\n  call qword [rax]\n  jmp QWORD PTR [rip+0x10]\n";
+        let functions = parse(source).unwrap();
+        assert_eq!(functions.len(), 1);
+        assert!(outline(&functions[0].1).1.is_empty());
+        // RISC-V calls through a register, here one named like a label.
+        let functions = parse("f:\n  jalr t0\n  ret\nt0:\n  ret\n").unwrap();
+        assert_eq!(functions.len(), 1);
+    }
+
+    #[test]
+    fn labels_are_defined_once() {
+        let error = parse("a:\n  nop\n a:\n  ret\n")
+            .err()
+            .expect("a duplicate label");
+        assert_eq!((error.line, error.column), (3, 2));
+    }
+}
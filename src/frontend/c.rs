@@ -0,0 +1,848 @@
+//! Builds charts from C source.
+//!
+//! Every function definition becomes a procedure; declarations and other
+//! items at file level are skipped. Inside a function, preprocessor lines
+//! are kept as annotations beside the flow, and `goto` and its labels are
+//! drawn as connectors. Loops without a condition, such as `for (;;)` and
+//! `while (1)`, are drawn as infinite loops.
+
+use std::collections::HashSet;
+
+use crate::{
+    block::{BlockKind, Span},
+    chart::{LoopKind, Procedure, Program, Statement},
+    parser::ParseError,
+};
+
+/// Functions whose calls are drawn as IO blocks.
+const IO_FUNCTIONS: &[&str] = &[
+    "printf", "scanf", "puts", "gets", "fgets", "getchar", "putchar", "fprintf", "fscanf", "fputs",
+    "fputc", "fgetc", "getc", "putc", "perror", "getline", "vprintf", "vfprintf",
+];
+
+const OPERATORS: &[&str] = &[
+    "<<=", ">>=", "...", "->", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+=",
+    "-=", "*=", "/=", "%=", "&=", "|=", "^=", "##", "(", ")", "[", "]", "{", "}", ";", ":", ",",
+    ".", "?", "~", "!", "+", "-", "*", "/", "%", "<", ">", "&", "|", "^", "=", "#",
+];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum TokenKind {
+    Name,
+    Number,
+    /// A string or character literal.
+    Literal,
+    Operator,
+    /// A whole preprocessor line, continuations included.
+    Directive,
+}
+
+#[derive(Clone, Copy)]
+struct Token<'a> {
+    kind: TokenKind,
+    text: &'a str,
+    /// Byte offset of the token in the source.
+    start: usize,
+    line: usize,
+    column: usize,
+}
+
+impl Token<'_> {
+    fn is(&self, text: &str) -> bool {
+        matches!(self.kind, TokenKind::Name | TokenKind::Operator) && self.text == text
+    }
+
+    fn error(&self, message: String) -> ParseError {
+        ParseError {
+            line: self.line,
+            column: self.column,
+            message,
+        }
+    }
+
+    /// The line and column right after the token.
+    fn end(&self) -> (usize, usize) {
+        match self.text.rsplit_once('\n') {
+            Some((before, last)) => (
+                self.line + before.matches('\n').count() + 1,
+                last.chars().count() + 1,
+            ),
+            None => (self.line, self.column + self.text.chars().count()),
+        }
+    }
+}
+
+/// The span from the first to the last of `tokens`, which are not empty.
+fn span(tokens: &[Token]) -> Span {
+    let (first, last) = (tokens[0], tokens[tokens.len() - 1]);
+    let (end_line, end_column) = last.end();
+    Span::new(first.line, first.column, end_line, end_column)
+}
+
+struct Tokenizer<'a> {
+    source: &'a str,
+    pos: usize,
+    line: usize,
+    line_start: usize,
+    tokens: Vec<Token<'a>>,
+}
+
+impl<'a> Tokenizer<'a> {
+    fn peek(&self) -> Option<char> {
+        self.source[self.pos..].chars().next()
+    }
+
+    fn column(&self, pos: usize) -> usize {
+        self.source[self.line_start..pos].chars().count() + 1
+    }
+
+    fn error(&self, message: &str) -> ParseError {
+        ParseError {
+            line: self.line,
+            column: self.column(self.pos),
+            message: String::from(message),
+        }
+    }
+
+    fn newline(&mut self) {
+        self.pos += 1;
+        self.line += 1;
+        self.line_start = self.pos;
+    }
+
+    /// Whether only whitespace comes before `pos` on its line.
+    fn at_line_start(&self, pos: usize) -> bool {
+        self.source[self.line_start..pos].trim().is_empty()
+    }
+
+    fn tokenize(mut self) -> Result<Vec<Token<'a>>, ParseError> {
+        while let Some(c) = self.peek() {
+            let (start, line, column) = (self.pos, self.line, self.column(self.pos));
+            let kind = match c {
+                '\n' => {
+                    self.newline();
+                    continue;
+                }
+                _ if c.is_whitespace() => {
+                    self.pos += c.len_utf8();
+                    continue;
+                }
+                '/' if self.source[self.pos..].starts_with("//") => {
+                    self.skip_line();
+                    continue;
+                }
+                '/' if self.source[self.pos..].starts_with("/*") => {
+                    self.comment()?;
+                    continue;
+                }
+                '#' if self.at_line_start(self.pos) => {
+                    self.skip_line();
+                    TokenKind::Directive
+                }
+                '"' | '\'' => {
+                    self.literal(c)?;
+                    TokenKind::Literal
+                }
+                _ if c.is_ascii_digit()
+                    || (c == '.'
+                        && self.source[self.pos + 1..]
+                            .starts_with(|c: char| c.is_ascii_digit())) =>
+                {
+                    self.number();
+                    TokenKind::Number
+                }
+                _ if c.is_alphabetic() || c == '_' => {
+                    self.pos = start
+                        + self.source[start..]
+                            .char_indices()
+                            .find(|(_, c)| !(c.is_alphanumeric() || *c == '_'))
+                            .map_or(self.source.len() - start, |(end, _)| end);
+                    // Prefixed literals such as `L"wide"` are one token.
+                    match self.peek() {
+                        Some(quote @ ('"' | '\''))
+                            if matches!(&self.source[start..self.pos], "L" | "u" | "U" | "u8") =>
+                        {
+                            self.literal(quote)?;
+                            TokenKind::Literal
+                        }
+                        _ => TokenKind::Name,
+                    }
+                }
+                _ => {
+                    let Some(operator) = OPERATORS
+                        .iter()
+                        .find(|operator| self.source[self.pos..].starts_with(**operator))
+                    else {
+                        return Err(self.error(&format!("unexpected character `{}`", c)));
+                    };
+                    self.pos += operator.len();
+                    TokenKind::Operator
+                }
+            };
+            let end = match kind {
+                // Leave out the trailing whitespace of a directive.
+                TokenKind::Directive => start + self.source[start..self.pos].trim_end().len(),
+                _ => self.pos,
+            };
+            self.tokens.push(Token {
+                kind,
+                text: &self.source[start..end],
+                start,
+                line,
+                column,
+            });
+        }
+        Ok(self.tokens)
+    }
+
+    /// Skips to the end of the line, past backslash continuations.
+    fn skip_line(&mut self) {
+        while let Some(c) = self.peek() {
+            match c {
+                '\n' => break,
+                '\\' if self.source[self.pos + 1..].starts_with('\n') => {
+                    self.pos += 1;
+                    self.newline();
+                }
+                _ => self.pos += c.len_utf8(),
+            }
+        }
+    }
+
+    fn comment(&mut self) -> Result<(), ParseError> {
+        let unterminated = self.error("unterminated comment");
+        self.pos += 2;
+        loop {
+            match self.peek() {
+                None => return Err(unterminated),
+                Some('\n') => self.newline(),
+                Some('*') if self.source[self.pos..].starts_with("*/") => {
+                    self.pos += 2;
+                    return Ok(());
+                }
+                Some(c) => self.pos += c.len_utf8(),
+            }
+        }
+    }
+
+    fn literal(&mut self, quote: char) -> Result<(), ParseError> {
+        let unterminated = self.error("unterminated literal");
+        self.pos += 1;
+        loop {
+            match self.peek() {
+                None | Some('\n') => return Err(unterminated),
+                Some('\\') => {
+                    self.pos += 1;
+                    match self.peek() {
+                        Some('\n') => self.newline(),
+                        Some(c) => self.pos += c.len_utf8(),
+                        None => return Err(unterminated),
+                    }
+                }
+                Some(c) if c == quote => {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                Some(c) => self.pos += c.len_utf8(),
+            }
+        }
+    }
+
+    fn number(&mut self) {
+        let start = self.pos;
+        let hex = self.source[start..].starts_with("0x") || self.source[start..].starts_with("0X");
+        while let Some(c) = self.peek() {
+            let exponent_sign = (c == '+' || c == '-')
+                && self.source[start..self.pos].ends_with(if hex {
+                    ['p', 'P']
+                } else {
+                    ['e', 'E']
+                });
+            if !(c.is_ascii_alphanumeric() || c == '_' || c == '.' || exponent_sign) {
+                break;
+            }
+            self.pos += 1;
+        }
+    }
+}
+
+/// The index of the bracket closing the one at `open`.
+/// Whether a loop condition always holds, as in `while (1)`.
+fn is_always(condition: &str) -> bool {
+    matches!(condition.trim(), "1" | "true")
+}
+
+fn closing_bracket(tokens: &[Token], open: usize) -> Option<usize> {
+    let mut depth = 0usize;
+    for (i, token) in tokens.iter().enumerate().skip(open) {
+        if token.kind != TokenKind::Operator {
+            continue;
+        }
+        match token.text {
+            "(" | "[" | "{" => depth += 1,
+            ")" | "]" | "}" => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// A function definition: its name, the token its header starts at and the
+/// range of tokens inside its body.
+struct Definition {
+    name: String,
+    start: usize,
+    body: (usize, usize),
+}
+
+/// Finds the function definitions at file level: a name, a parenthesized
+/// parameter list, and a brace-enclosed body.
+fn definitions(tokens: &[Token]) -> Result<Vec<Definition>, ParseError> {
+    let mut definitions = vec![];
+    let mut i = 0;
+    while i < tokens.len() {
+        let token = tokens[i];
+        if !token.is("{") {
+            i += 1;
+            continue;
+        }
+        let Some(close) = closing_bracket(tokens, i) else {
+            return Err(token.error(String::from("`{` is never closed")));
+        };
+        // Old-style parameter declarations may come between the list and
+        // the body, so look back for the list from the last `)` before the
+        // body instead of requiring it right before.
+        let header = tokens[..i]
+            .iter()
+            .rposition(|token| token.is(";") || token.is("}") || token.is("="))
+            .map_or(0, |end| end + 1);
+        let name = tokens[header..i]
+            .iter()
+            .position(|token| token.is("("))
+            .and_then(|open| tokens[header..i][..open].last())
+            .filter(|name| name.kind == TokenKind::Name);
+        if let Some(name) = name {
+            definitions.push(Definition {
+                name: String::from(name.text),
+                start: header
+                    + tokens[header..i]
+                        .iter()
+                        .take_while(|token| token.kind == TokenKind::Directive)
+                        .count(),
+                body: (i + 1, close),
+            });
+        }
+        i = close + 1;
+    }
+    Ok(definitions)
+}
+
+/// The statements around the one being parsed that `break` and `continue`
+/// can leave.
+#[derive(Clone, Copy, Default)]
+struct Nesting {
+    in_loop: bool,
+    in_switch: bool,
+}
+
+impl Nesting {
+    fn with_loop(self) -> Self {
+        Self {
+            in_loop: true,
+            ..self
+        }
+    }
+
+    fn with_switch(self) -> Self {
+        Self {
+            in_switch: true,
+            ..self
+        }
+    }
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    tokens: &'a [Token<'a>],
+    pos: usize,
+    end: usize,
+    functions: &'a HashSet<String>,
+    /// The spans of the statements parsed so far, in pre-order.
+    spans: Vec<Option<Span>>,
+}
+
+impl<'a> Parser<'a> {
+    /// The source text of `tokens`, one line per physical line.
+    fn text(&self, tokens: &[Token]) -> String {
+        let mut lines: Vec<&str> = vec![];
+        let mut tokens = tokens.iter().peekable();
+        while let Some(first) = tokens.next() {
+            let mut last = first;
+            while let Some(token) = tokens.next_if(|token| token.line == first.line) {
+                last = token;
+            }
+            lines.push(&self.source[first.start..last.start + last.text.len()]);
+        }
+        lines.join("\n")
+    }
+
+    fn peek(&self) -> Option<Token<'a>> {
+        self.tokens[..self.end].get(self.pos).copied()
+    }
+
+    fn peek_is(&self, text: &str) -> bool {
+        self.peek().is_some_and(|token| token.is(text))
+    }
+
+    fn next(&mut self) -> Result<Token<'a>, ParseError> {
+        match self.peek() {
+            Some(token) => {
+                self.pos += 1;
+                Ok(token)
+            }
+            None => Err(self.tokens[self.end.min(self.tokens.len() - 1)]
+                .error(String::from("unexpected end of the function"))),
+        }
+    }
+
+    fn expect(&mut self, text: &str) -> Result<Token<'a>, ParseError> {
+        let token = self.next()?;
+        if !token.is(text) {
+            return Err(token.error(format!("expected `{}`, found `{}`", text, token.text)));
+        }
+        Ok(token)
+    }
+
+    /// Takes the span of the tokens from `start` up to the current one as
+    /// that of the next statement. Compound statements take it before the
+    /// statements nested in them, as the spans are in pre-order.
+    fn mark(&mut self, start: usize) {
+        self.spans.push(Some(span(&self.tokens[start..self.pos])));
+    }
+
+    /// Parses `( ... )` and returns the text inside.
+    fn parenthesized(&mut self) -> Result<String, ParseError> {
+        let open = self.expect("(")?;
+        let Some(close) = closing_bracket(&self.tokens[..self.end], self.pos - 1) else {
+            return Err(open.error(String::from("`(` is never closed")));
+        };
+        let inside = self.text(&self.tokens[self.pos..close]);
+        self.pos = close + 1;
+        Ok(inside)
+    }
+
+    /// Parses the statements up to the closing brace of the current block.
+    fn parse_block(&mut self, nesting: Nesting) -> Result<Vec<Statement>, ParseError> {
+        let mut body = vec![];
+        while !self.peek_is("}") {
+            if self.peek().is_none() {
+                return Err(self.tokens[self.pos - 1].error(String::from("missing `}`")));
+            }
+            self.parse_statement(nesting, &mut body)?;
+        }
+        self.pos += 1;
+        Ok(body)
+    }
+
+    /// Parses the body of a control statement, a block or a single
+    /// statement.
+    fn parse_body(&mut self, nesting: Nesting) -> Result<Vec<Statement>, ParseError> {
+        let mut body = vec![];
+        self.parse_statement(nesting, &mut body)?;
+        Ok(body)
+    }
+
+    fn parse_statement(
+        &mut self,
+        nesting: Nesting,
+        body: &mut Vec<Statement>,
+    ) -> Result<(), ParseError> {
+        let start = self.pos;
+        let token = self.next()?;
+        if token.kind == TokenKind::Directive {
+            self.mark(start);
+            body.push(Statement::Annotation(self.text(&[token])));
+            return Ok(());
+        }
+        match token.text {
+            _ if token.kind != TokenKind::Name && !token.is("{") && !token.is(";") => {
+                self.pos -= 1;
+                let statement = self.parse_simple()?;
+                self.mark(start);
+                body.push(statement);
+            }
+            "{" => body.extend(self.parse_block(nesting)?),
+            ";" => {}
+            "if" => body.push(self.parse_if(start, nesting)?),
+            "switch" => {
+                let subject = self.parenthesized()?;
+                self.mark(start);
+                body.push(self.parse_switch(subject, nesting)?);
+            }
+            "while" => {
+                let condition = self.parenthesized()?;
+                self.mark(start);
+                body.push(Statement::Loop {
+                    kind: match is_always(&condition) {
+                        true => LoopKind::Infinite,
+                        false => LoopKind::While(condition),
+                    },
+                    body: self.parse_body(nesting.with_loop())?,
+                });
+            }
+            "for" => {
+                let header = self.parenthesized()?;
+                self.mark(start);
+                let is_empty = header.chars().all(|c| c == ';' || c.is_whitespace());
+                body.push(Statement::Loop {
+                    kind: match is_empty {
+                        true => LoopKind::Infinite,
+                        false => LoopKind::For(header),
+                    },
+                    body: self.parse_body(nesting.with_loop())?,
+                });
+            }
+            "do" => {
+                // The condition comes after the body, but its span goes
+                // before those of the body.
+                let slot = self.spans.len();
+                self.spans.push(None);
+                let loop_body = self.parse_body(nesting.with_loop())?;
+                let condition_start = self.pos;
+                self.expect("while")?;
+                let condition = self.parenthesized()?;
+                self.spans[slot] = Some(span(&self.tokens[condition_start..self.pos]));
+                self.expect(";")?;
+                body.push(Statement::Loop {
+                    kind: match is_always(&condition) {
+                        true => LoopKind::Infinite,
+                        false => LoopKind::DoWhile(condition),
+                    },
+                    body: loop_body,
+                });
+            }
+            "goto" => {
+                let label = self.next()?;
+                if label.kind != TokenKind::Name {
+                    return Err(label.error(String::from("`goto` needs a label")));
+                }
+                self.expect(";")?;
+                self.mark(start);
+                body.push(Statement::Goto(String::from(label.text)));
+            }
+            "break" if !nesting.in_loop && !nesting.in_switch => {
+                return Err(token.error(String::from("`break` outside of a loop or switch")))
+            }
+            "continue" if !nesting.in_loop => {
+                return Err(token.error(String::from("`continue` outside of a loop")))
+            }
+            "break" => {
+                self.expect(";")?;
+                self.mark(start);
+                body.push(Statement::Break);
+            }
+            "continue" => {
+                self.expect(";")?;
+                self.mark(start);
+                body.push(Statement::Continue);
+            }
+            "return" => {
+                self.pos -= 1;
+                let tokens = self.until_semicolon()?;
+                self.mark(start);
+                body.push(Statement::Return(match tokens.len() {
+                    1 => None,
+                    _ => Some(self.text(tokens)),
+                }));
+            }
+            "case" | "default" => {
+                return Err(token.error(format!("`{}` outside of a switch", token.text)))
+            }
+            "else" => return Err(token.error(String::from("`else` without `if`"))),
+            _ if self.peek_is(":") => {
+                self.pos += 1;
+                self.mark(start);
+                body.push(Statement::Label(String::from(token.text)));
+            }
+            _ => {
+                self.pos -= 1;
+                let statement = self.parse_simple()?;
+                self.mark(start);
+                body.push(statement);
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_if(&mut self, start: usize, nesting: Nesting) -> Result<Statement, ParseError> {
+        let condition = self.parenthesized()?;
+        self.mark(start);
+        let then_body = self.parse_body(nesting)?;
+        let else_body = if self.peek_is("else") {
+            self.pos += 1;
+            self.parse_body(nesting)?
+        } else {
+            vec![]
+        };
+        Ok(Statement::If {
+            condition,
+            then_body,
+            else_body,
+        })
+    }
+
+    /// Parses a switch body. Labels that follow each other share a case, so
+    /// `case 1: case 2:` is one case labeled `1, 2`.
+    fn parse_switch(&mut self, subject: String, nesting: Nesting) -> Result<Statement, ParseError> {
+        self.expect("{")?;
+        let mut cases: Vec<(String, Vec<Statement>)> = vec![];
+        loop {
+            let Some(token) = self.peek() else {
+                return Err(self.tokens[self.pos - 1].error(String::from("missing `}`")));
+            };
+            self.pos += 1;
+            let label = if token.is("}") {
+                break;
+            } else if token.is("case") {
+                let start = self.pos;
+                // A `?:` in the constant has colons of its own.
+                let mut depth = 0;
+                loop {
+                    let token = self.next()?;
+                    if token.is("?") {
+                        depth += 1;
+                    } else if token.is(":") && depth == 0 {
+                        break;
+                    } else if token.is(":") {
+                        depth -= 1;
+                    }
+                }
+                self.text(&self.tokens[start..self.pos - 1])
+            } else if token.is("default") {
+                self.expect(":")?;
+                String::from("default")
+            } else {
+                self.pos -= 1;
+                let Some((_, body)) = cases.last_mut() else {
+                    return Err(token.error(String::from("statement before the first `case`")));
+                };
+                let mut statements = vec![];
+                self.parse_statement(nesting.with_switch(), &mut statements)?;
+                body.extend(statements);
+                continue;
+            };
+            match cases.last_mut() {
+                Some((labels, body)) if body.is_empty() => {
+                    labels.push_str(", ");
+                    labels.push_str(&label);
+                }
+                _ => cases.push((label, vec![])),
+            }
+        }
+        Ok(Statement::Switch {
+            subject,
+            cases,
+            fall_through: true,
+        })
+    }
+
+    /// Takes the tokens up to the `;` at the current nesting level, which is
+    /// consumed but not returned.
+    fn until_semicolon(&mut self) -> Result<&'a [Token<'a>], ParseError> {
+        let start = self.pos;
+        loop {
+            let token = self.next()?;
+            if token.is(";") {
+                return Ok(&self.tokens[start..self.pos - 1]);
+            }
+            if token.is("(") || token.is("[") || token.is("{") {
+                match closing_bracket(&self.tokens[..self.end], self.pos - 1) {
+                    Some(close) => self.pos = close + 1,
+                    None => return Err(token.error(format!("`{}` is never closed", token.text))),
+                }
+            }
+        }
+    }
+
+    /// Parses a declaration or an expression statement.
+    fn parse_simple(&mut self) -> Result<Statement, ParseError> {
+        let tokens = self.until_semicolon()?;
+        let text = self.text(tokens);
+        let is_io = tokens.windows(2).any(|pair| {
+            pair[0].kind == TokenKind::Name
+                && IO_FUNCTIONS.contains(&pair[0].text)
+                && pair[1].is("(")
+        });
+        if is_io {
+            return Ok(Statement::Block(BlockKind::IO, text));
+        }
+        // A statement that is only a call to a function of the file.
+        let is_call = matches!(tokens, [name, open, ..]
+            if name.kind == TokenKind::Name
+                && open.is("(")
+                && self.functions.contains(name.text)
+                && closing_bracket(tokens, 1) == Some(tokens.len() - 1));
+        Ok(match is_call {
+            true => Statement::Call(String::from(tokens[0].text)),
+            false => Statement::Block(BlockKind::Process, text),
+        })
+    }
+}
+
+pub fn parse(source: &str) -> Result<Program, ParseError> {
+    let tokens = Tokenizer {
+        source,
+        pos: 0,
+        line: 1,
+        line_start: 0,
+        tokens: vec![],
+    }
+    .tokenize()?;
+    let definitions = definitions(&tokens)?;
+    let functions: HashSet<String> = definitions
+        .iter()
+        .map(|definition| definition.name.clone())
+        .collect();
+    let mut procedures: Vec<Procedure> = vec![];
+    for Definition { name, start, body } in definitions {
+        let mut parser = Parser {
+            source,
+            tokens: &tokens,
+            pos: body.0,
+            end: body.1 + 1,
+            functions: &functions,
+            spans: vec![],
+        };
+        let close = body.1;
+        let body = parser.parse_block(Nesting::default())?;
+        procedures.retain(|procedure| procedure.name != name);
+        procedures.push(Procedure {
+            name,
+            body,
+            span: Some(span(&tokens[start..=close])),
+            spans: parser.spans,
+        });
+    }
+    Ok(Program { procedures })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chart;
+
+    /// The statements of the first function in `source`.
+    fn outline(source: &str) -> Vec<String> {
+        let program = parse(source).unwrap();
+        chart::outline(&program.procedures[0])
+    }
+
+    #[test]
+    fn loops_without_a_condition_are_infinite() {
+        let lines = outline(
+            "void f(int n) {
+                for (;;) { g(); }
+                for ( ; ; ) { g(); }
+                while (1) { g(); }
+                while (true) { g(); }
+                do { g(); } while (1);
+                for (i = 0; ; i++) { g(); }
+                while (n) { g(); }
+            }",
+        );
+        assert_eq!(
+            lines,
+            [
+                "loop",
+                "  g()",
+                "loop",
+                "  g()",
+                "loop",
+                "  g()",
+                "loop",
+                "  g()",
+                "loop",
+                "  g()",
+                "for i = 0; ; i++",
+                "  g()",
+                "while n",
+                "  g()",
+            ]
+        );
+    }
+
+    #[test]
+    fn gotos_lead_to_labels() {
+        let lines = outline(
+            "int f(int n) {
+                if (n < 0) goto fail;
+                return n;
+            fail:
+                return -1;
+            }",
+        );
+        assert_eq!(
+            lines,
+            [
+                "if n < 0",
+                "  goto fail",
+                "else",
+                "return",
+                "label fail",
+                "return"
+            ]
+        );
+    }
+
+    #[test]
+    fn switches_fall_through() {
+        let program = parse(
+            "void f(int n) {
+                switch (n) {
+                case 1:
+                    a();
+                case 2:
+                    b();
+                    break;
+                default:
+                    c();
+                }
+            }",
+        )
+        .unwrap();
+        let procedure = &program.procedures[0];
+        assert!(matches!(
+            procedure.body[0],
+            Statement::Switch {
+                fall_through: true,
+                ..
+            }
+        ));
+        assert_eq!(
+            chart::outline(procedure),
+            [
+                "match n",
+                "case 1",
+                "  a()",
+                "case 2",
+                "  b()",
+                "  break",
+                "case default",
+                "  c()",
+            ]
+        );
+    }
+
+    #[test]
+    fn unclosed_braces_are_errors() {
+        let error = parse("void f() {\n    if (x) {\n")
+            .err()
+            .expect("a parse error");
+        assert_eq!(error.message, "`{` is never closed");
+    }
+}
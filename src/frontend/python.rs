@@ -0,0 +1,1006 @@
+//! Builds charts from Python source.
+//!
+//! Every function becomes a procedure; methods are named `Class.method`.
+//! Statements at module level, other than definitions and imports, make up a
+//! procedure named `__main__`.
+//!
+//! A `try` statement is drawn as its body followed by a switch on the raised
+//! exception, with a `none` case running the `else` clause and one case per
+//! `except` clause. The `finally` clause follows the switch.
+//!
+//! The `else` clause of a loop follows the loop, and a `break` of the loop
+//! jumps past it through a connector.
+
+use std::collections::HashSet;
+
+use crate::{
+    block::{BlockKind, Span},
+    chart::{LoopKind, Procedure, Program, Statement},
+    parser::ParseError,
+};
+
+/// Calls drawn as IO blocks.
+const IO_FUNCTIONS: &[&str] = &["input", "print"];
+
+/// Statements that only declare something and are left out of the chart.
+const DECLARATIONS: &[&str] = &["import", "from", "global", "nonlocal", "pass"];
+
+const OPERATORS: &[&str] = &[
+    "**=", "//=", ">>=", "<<=", "...", "->", ":=", "==", "!=", "<=", ">=", "**", "//", "<<", ">>",
+    "+=", "-=", "*=", "/=", "%=", "&=", "|=", "^=", "@=", "(", ")", "[", "]", "{", "}", ":", ",",
+    ";", ".", "+", "-", "*", "/", "%", "&", "|", "^", "~", "<", ">", "=", "@", "!",
+];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum TokenKind {
+    Name,
+    Number,
+    String,
+    Operator,
+}
+
+#[derive(Clone, Copy)]
+struct Token<'a> {
+    kind: TokenKind,
+    text: &'a str,
+    /// Byte offset of the token in the source.
+    start: usize,
+    line: usize,
+    column: usize,
+}
+
+impl Token<'_> {
+    fn is(&self, text: &str) -> bool {
+        self.kind != TokenKind::String && self.text == text
+    }
+
+    fn error(&self, message: String) -> ParseError {
+        ParseError {
+            line: self.line,
+            column: self.column,
+            message,
+        }
+    }
+
+    /// The line and column just past the token.
+    fn end(&self) -> (usize, usize) {
+        match self.text.rsplit_once('\n') {
+            Some((before, last)) => (
+                self.line + before.matches('\n').count() + 1,
+                last.chars().count() + 1,
+            ),
+            None => (self.line, self.column + self.text.chars().count()),
+        }
+    }
+}
+
+/// Turns the `break`s of the loop whose body is `body` into jumps to the
+/// `label`, returning whether there are any.
+fn jump_breaks(body: &mut [Statement], label: &str) -> bool {
+    let mut found = false;
+    for statement in body {
+        match statement {
+            Statement::Break => {
+                *statement = Statement::Goto(String::from(label));
+                found = true;
+            }
+            Statement::If {
+                then_body,
+                else_body,
+                ..
+            } => {
+                found |= jump_breaks(then_body, label);
+                found |= jump_breaks(else_body, label);
+            }
+            Statement::Switch { cases, .. } => {
+                for (_, body) in cases {
+                    found |= jump_breaks(body, label);
+                }
+            }
+            _ => {}
+        }
+    }
+    found
+}
+
+/// The span from the first to the last of `tokens`, which are not empty.
+fn span(tokens: &[Token]) -> Span {
+    let (first, last) = (tokens[0], tokens[tokens.len() - 1]);
+    let (end_line, end_column) = last.end();
+    Span::new(first.line, first.column, end_line, end_column)
+}
+
+/// A logical line: a statement that may span several physical lines inside
+/// brackets or after a backslash.
+#[derive(Clone)]
+struct Line<'a> {
+    indent: usize,
+    tokens: Vec<Token<'a>>,
+}
+
+struct Tokenizer<'a> {
+    source: &'a str,
+    pos: usize,
+    line: usize,
+    line_start: usize,
+    /// Open brackets, innermost last.
+    brackets: Vec<Token<'a>>,
+    lines: Vec<Line<'a>>,
+    tokens: Vec<Token<'a>>,
+    indent: usize,
+}
+
+impl<'a> Tokenizer<'a> {
+    fn new(source: &'a str) -> Self {
+        Self {
+            source,
+            pos: 0,
+            line: 1,
+            line_start: 0,
+            brackets: vec![],
+            lines: vec![],
+            tokens: vec![],
+            indent: 0,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.source[self.pos..].chars().next()
+    }
+
+    fn column(&self, pos: usize) -> usize {
+        self.source[self.line_start..pos].chars().count() + 1
+    }
+
+    fn error(&self, message: String) -> ParseError {
+        ParseError {
+            line: self.line,
+            column: self.column(self.pos),
+            message,
+        }
+    }
+
+    fn newline(&mut self) {
+        self.pos += 1;
+        self.line += 1;
+        self.line_start = self.pos;
+    }
+
+    fn push(&mut self, kind: TokenKind, start: usize, line: usize, column: usize) {
+        self.tokens.push(Token {
+            kind,
+            text: &self.source[start..self.pos],
+            start,
+            line,
+            column,
+        });
+    }
+
+    /// Measures the indentation of a new physical line. Tabs advance to the
+    /// next multiple of eight, as in Python.
+    fn indentation(&mut self) -> usize {
+        let mut indent = 0;
+        while let Some(c) = self.peek() {
+            match c {
+                ' ' => indent += 1,
+                '\t' => indent = (indent / 8 + 1) * 8,
+                '\x0c' => indent = 0,
+                _ => break,
+            }
+            self.pos += 1;
+        }
+        indent
+    }
+
+    fn end_line(&mut self) {
+        if !self.tokens.is_empty() {
+            self.lines.push(Line {
+                indent: self.indent,
+                tokens: std::mem::take(&mut self.tokens),
+            });
+        }
+    }
+
+    fn tokenize(mut self) -> Result<Vec<Line<'a>>, ParseError> {
+        let mut at_line_start = true;
+        while let Some(c) = self.peek() {
+            if at_line_start {
+                self.indent = self.indentation();
+                at_line_start = false;
+                continue;
+            }
+            let (start, line, column) = (self.pos, self.line, self.column(self.pos));
+            match c {
+                '\n' => {
+                    self.newline();
+                    if self.brackets.is_empty() {
+                        self.end_line();
+                        at_line_start = true;
+                    }
+                }
+                ' ' | '\t' | '\r' | '\x0c' => self.pos += 1,
+                '#' => {
+                    self.pos = self.source[self.pos..]
+                        .find('\n')
+                        .map_or(self.source.len(), |end| self.pos + end);
+                }
+                '\\' => {
+                    self.pos += 1;
+                    if self.source[self.pos..].starts_with("\r\n") {
+                        self.pos += 1;
+                    }
+                    if self.peek() != Some('\n') {
+                        return Err(self.error(String::from("unexpected character after `\\`")));
+                    }
+                    self.newline();
+                }
+                '\'' | '"' => {
+                    self.string()?;
+                    self.push(TokenKind::String, start, line, column);
+                }
+                _ if c.is_ascii_digit()
+                    || (c == '.'
+                        && self.source[self.pos + 1..]
+                            .starts_with(|c: char| c.is_ascii_digit())) =>
+                {
+                    self.number();
+                    self.push(TokenKind::Number, start, line, column);
+                }
+                _ if c.is_alphabetic() || c == '_' => {
+                    self.pos = start
+                        + self.source[start..]
+                            .char_indices()
+                            .find(|(_, c)| !(c.is_alphanumeric() || *c == '_'))
+                            .map_or(self.source.len() - start, |(end, _)| end);
+                    let name = &self.source[start..self.pos];
+                    let is_prefix = name.len() <= 2
+                        && name.chars().all(|c| "rRbBuUfF".contains(c))
+                        && matches!(self.peek(), Some('\'' | '"'));
+                    if is_prefix {
+                        self.string()?;
+                        self.push(TokenKind::String, start, line, column);
+                    } else {
+                        self.push(TokenKind::Name, start, line, column);
+                    }
+                }
+                _ => {
+                    let Some(operator) = OPERATORS
+                        .iter()
+                        .find(|operator| self.source[self.pos..].starts_with(**operator))
+                    else {
+                        return Err(self.error(format!("unexpected character `{}`", c)));
+                    };
+                    self.pos += operator.len();
+                    self.push(TokenKind::Operator, start, line, column);
+                    let token = self.tokens[self.tokens.len() - 1];
+                    match *operator {
+                        "(" | "[" | "{" => self.brackets.push(token),
+                        ")" | "]" | "}" if self.brackets.pop().is_none() => {
+                            return Err(token.error(format!("unmatched `{}`", operator)));
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        if let Some(bracket) = self.brackets.last() {
+            return Err(bracket.error(format!("`{}` is never closed", bracket.text)));
+        }
+        self.end_line();
+        Ok(self.lines)
+    }
+
+    /// Skips a string literal starting at its opening quote.
+    fn string(&mut self) -> Result<(), ParseError> {
+        let (line, column) = (self.line, self.column(self.pos));
+        let unterminated = || ParseError {
+            line,
+            column,
+            message: String::from("unterminated string"),
+        };
+        let quote = self.peek().unwrap();
+        let triple: String = [quote; 3].iter().collect();
+        let is_triple = self.source[self.pos..].starts_with(&triple);
+        self.pos += if is_triple { 3 } else { 1 };
+        loop {
+            match self.peek() {
+                None => return Err(unterminated()),
+                Some('\\') => {
+                    self.pos += 1;
+                    match self.peek() {
+                        Some('\n') => self.newline(),
+                        Some(c) => self.pos += c.len_utf8(),
+                        None => return Err(unterminated()),
+                    }
+                }
+                Some('\n') if !is_triple => return Err(unterminated()),
+                Some('\n') => self.newline(),
+                Some(_) if is_triple && self.source[self.pos..].starts_with(&triple) => {
+                    self.pos += 3;
+                    return Ok(());
+                }
+                Some(c) if !is_triple && c == quote => {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                Some(c) => self.pos += c.len_utf8(),
+            }
+        }
+    }
+
+    fn number(&mut self) {
+        let start = self.pos;
+        let hex = self.source[start..].starts_with("0x") || self.source[start..].starts_with("0X");
+        while let Some(c) = self.peek() {
+            let exponent_sign = (c == '+' || c == '-')
+                && !hex
+                && self.source[start..self.pos].ends_with(['e', 'E']);
+            if !(c.is_ascii_alphanumeric() || c == '_' || c == '.' || exponent_sign) {
+                break;
+            }
+            self.pos += 1;
+        }
+    }
+}
+
+/// Where a statement is, which decides what its definitions become.
+#[derive(Clone, Copy)]
+enum Scope<'a> {
+    Module,
+    Class(&'a str),
+    /// A function body, with the class of the method, if any.
+    Function(Option<&'a str>),
+}
+
+/// Finds the colon that ends the header of a compound statement.
+fn header_colon(tokens: &[Token]) -> Option<usize> {
+    let mut depth = 0usize;
+    for (i, token) in tokens.iter().enumerate() {
+        if token.kind != TokenKind::Operator {
+            continue;
+        }
+        match token.text {
+            "(" | "[" | "{" => depth += 1,
+            ")" | "]" | "}" => depth = depth.saturating_sub(1),
+            ":" if depth == 0 => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Splits simple statements at semicolons.
+fn split_statements<'t, 'a>(tokens: &'t [Token<'a>]) -> impl Iterator<Item = &'t [Token<'a>]> {
+    tokens
+        .split(|token| token.is(";"))
+        .filter(|tokens| !tokens.is_empty())
+}
+
+/// The names of every function and method, found before parsing so calls to
+/// functions defined further down can be linked.
+fn function_names(lines: &[Line]) -> HashSet<String> {
+    let mut names = HashSet::new();
+    let mut class: Option<(&str, Option<usize>)> = None;
+    for line in lines {
+        let tokens = match line.tokens.as_slice() {
+            [first, rest @ ..] if first.is("async") => rest,
+            tokens => tokens,
+        };
+        let (keyword, name) = match tokens {
+            [keyword, name, ..] if name.kind == TokenKind::Name => (keyword.text, name.text),
+            _ => ("", ""),
+        };
+        if line.indent == 0 {
+            class = None;
+            match keyword {
+                "def" => {
+                    names.insert(String::from(name));
+                }
+                "class" => class = Some((name, None)),
+                _ => {}
+            }
+        } else if let Some((class_name, body_indent)) = &mut class {
+            let body_indent = *body_indent.get_or_insert(line.indent);
+            if keyword == "def" && line.indent == body_indent {
+                names.insert(format!("{}.{}", class_name, name));
+            }
+        }
+    }
+    names
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    lines: Vec<Line<'a>>,
+    pos: usize,
+    functions: HashSet<String>,
+    procedures: Vec<Procedure>,
+    /// The spans of the statements parsed so far in the current procedure,
+    /// in pre-order.
+    spans: Vec<Option<Span>>,
+}
+
+impl<'a> Parser<'a> {
+    /// The source text of `tokens`, one line per physical line.
+    fn text(&self, tokens: &[Token]) -> String {
+        let mut lines: Vec<&str> = vec![];
+        let mut tokens = tokens.iter().peekable();
+        while let Some(first) = tokens.next() {
+            let mut last = first;
+            while let Some(token) = tokens.next_if(|token| token.line == first.line) {
+                last = token;
+            }
+            lines.push(&self.source[first.start..last.start + last.text.len()]);
+        }
+        lines.join("\n")
+    }
+
+    /// The span from `first` to the end of the last line parsed.
+    fn span_from(&self, first: Token) -> Span {
+        let last = self.lines[self.pos - 1].tokens.last().copied().unwrap();
+        span(&[first, last])
+    }
+
+    fn peek_keyword(&self, indent: usize, keyword: &str) -> Option<Line<'a>> {
+        self.lines
+            .get(self.pos)
+            .filter(|line| line.indent == indent && line.tokens[0].is(keyword))
+            .cloned()
+    }
+
+    fn parse_module(&mut self) -> Result<Program, ParseError> {
+        let body = self.parse_suite(0, 0, Scope::Module, false)?;
+        let mut procedures = std::mem::take(&mut self.procedures);
+        if !body.is_empty() {
+            procedures.insert(
+                0,
+                Procedure {
+                    name: String::from("__main__"),
+                    body,
+                    span: None,
+                    spans: std::mem::take(&mut self.spans),
+                },
+            );
+        }
+        Ok(Program { procedures })
+    }
+
+    /// Parses the statements indented by `indent`, up to the first line that
+    /// is indented less.
+    fn parse_suite(
+        &mut self,
+        indent: usize,
+        outer: usize,
+        scope: Scope<'a>,
+        in_loop: bool,
+    ) -> Result<Vec<Statement>, ParseError> {
+        let mut body = vec![];
+        while let Some(line) = self.lines.get(self.pos) {
+            if line.indent < indent {
+                if line.indent > outer {
+                    return Err(line.tokens[0].error(String::from(
+                        "unindent does not match any outer indentation level",
+                    )));
+                }
+                break;
+            }
+            if line.indent > indent {
+                return Err(line.tokens[0].error(String::from("unexpected indent")));
+            }
+            let line = line.clone();
+            self.pos += 1;
+            self.parse_statement(&line, scope, in_loop, &mut body)?;
+        }
+        Ok(body)
+    }
+
+    /// Parses the body of the compound statement whose header is `line`,
+    /// either after its colon or as an indented block on the next lines.
+    fn parse_body(
+        &mut self,
+        line: &Line<'a>,
+        colon: usize,
+        scope: Scope<'a>,
+        in_loop: bool,
+    ) -> Result<Vec<Statement>, ParseError> {
+        let inline = &line.tokens[colon + 1..];
+        if !inline.is_empty() {
+            let mut body = vec![];
+            for tokens in split_statements(inline) {
+                self.push_simple(tokens, scope, in_loop, &mut body)?;
+            }
+            return Ok(body);
+        }
+        match self.lines.get(self.pos) {
+            Some(next) if next.indent > line.indent => {
+                self.parse_suite(next.indent, line.indent, scope, in_loop)
+            }
+            _ => Err(line.tokens[colon].error(String::from("expected an indented block"))),
+        }
+    }
+
+    /// Splits a compound statement header into its keyword and the tokens up
+    /// to its colon.
+    fn header<'l>(
+        &self,
+        line: &'l Line<'a>,
+        skip: usize,
+    ) -> Result<(&'l [Token<'a>], usize), ParseError> {
+        match header_colon(&line.tokens) {
+            Some(colon) => Ok((&line.tokens[skip + 1..colon], colon)),
+            None => {
+                let last = line.tokens[line.tokens.len() - 1];
+                Err(last.error(String::from("expected `:`")))
+            }
+        }
+    }
+
+    fn parse_statement(
+        &mut self,
+        line: &Line<'a>,
+        scope: Scope<'a>,
+        in_loop: bool,
+        body: &mut Vec<Statement>,
+    ) -> Result<(), ParseError> {
+        let first = line.tokens[0];
+        // `async` only changes how a `def`, `for` or `with` runs.
+        let skip = match line.tokens.get(1) {
+            Some(next) if first.is("async") && ["def", "for", "with"].contains(&next.text) => 1,
+            _ => 0,
+        };
+        let keyword = line.tokens[skip];
+        if keyword.kind != TokenKind::Name && !keyword.is("@") {
+            for tokens in split_statements(&line.tokens) {
+                self.push_simple(tokens, scope, in_loop, body)?;
+            }
+            return Ok(());
+        }
+        match keyword.text {
+            // Decorators do not change the flow of the function.
+            "@" => {}
+            "def" => {
+                let (header, colon) = self.header(line, skip)?;
+                let Some(name) = header.first().filter(|name| name.kind == TokenKind::Name) else {
+                    return Err(keyword.error(String::from("`def` needs a function name")));
+                };
+                let (qualified, class) = match scope {
+                    Scope::Module => (Some(String::from(name.text)), None),
+                    Scope::Class(class) => (Some(format!("{}.{}", class, name.text)), Some(class)),
+                    Scope::Function(class) => (None, class),
+                };
+                let outer = std::mem::take(&mut self.spans);
+                let function_body = self.parse_body(line, colon, Scope::Function(class), false)?;
+                let spans = std::mem::replace(&mut self.spans, outer);
+                // Nested functions are only definitions in the enclosing flow.
+                if let Some(name) = qualified {
+                    // A later definition replaces an earlier one, as in Python.
+                    self.procedures.retain(|procedure| procedure.name != name);
+                    self.procedures.push(Procedure {
+                        name,
+                        body: function_body,
+                        span: Some(self.span_from(first)),
+                        spans,
+                    });
+                }
+            }
+            "class" => {
+                let (header, colon) = self.header(line, skip)?;
+                let Some(name) = header.first().filter(|name| name.kind == TokenKind::Name) else {
+                    return Err(keyword.error(String::from("`class` needs a class name")));
+                };
+                let class_scope = match scope {
+                    Scope::Module => Scope::Class(name.text),
+                    _ => Scope::Function(None),
+                };
+                // Only the methods of a class are charted.
+                let outer = std::mem::take(&mut self.spans);
+                self.parse_body(line, colon, class_scope, false)?;
+                self.spans = outer;
+            }
+            "if" => body.push(self.parse_if(line, scope, in_loop)?),
+            "while" | "for" => {
+                let (header, colon) = self.header(line, skip)?;
+                let text = self.text(header);
+                let kind = match keyword.text {
+                    "while" => LoopKind::While(text),
+                    _ => LoopKind::For(text),
+                };
+                self.spans.push(Some(span(&line.tokens[..colon])));
+                let mut loop_body = self.parse_body(line, colon, scope, true)?;
+                let Some(other) = self.peek_keyword(line.indent, "else") else {
+                    body.push(Statement::Loop {
+                        kind,
+                        body: loop_body,
+                    });
+                    return Ok(());
+                };
+                self.pos += 1;
+                let label = format!("break\nline {}", keyword.line);
+                let broken = jump_breaks(&mut loop_body, &label);
+                body.push(Statement::Loop {
+                    kind,
+                    body: loop_body,
+                });
+                let (_, colon) = self.header(&other, 0)?;
+                body.extend(self.parse_body(&other, colon, scope, in_loop)?);
+                if broken {
+                    self.spans.push(None);
+                    body.push(Statement::Label(label));
+                }
+            }
+            "try" => self.parse_try(line, scope, in_loop, body)?,
+            "with" => {
+                let (_, colon) = self.header(line, skip)?;
+                self.spans.push(Some(span(&line.tokens[..colon])));
+                body.push(Statement::Block(
+                    BlockKind::Process,
+                    self.text(&line.tokens[..colon]),
+                ));
+                body.extend(self.parse_body(line, colon, scope, in_loop)?);
+            }
+            "match" if self.is_match(line) => body.push(self.parse_match(line, scope, in_loop)?),
+            "elif" | "else" | "except" | "finally" => {
+                return Err(keyword.error(format!("`{}` without a matching block", keyword.text)))
+            }
+            _ => {
+                for tokens in split_statements(&line.tokens) {
+                    self.push_simple(tokens, scope, in_loop, body)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_if(
+        &mut self,
+        line: &Line<'a>,
+        scope: Scope<'a>,
+        in_loop: bool,
+    ) -> Result<Statement, ParseError> {
+        let (header, colon) = self.header(line, 0)?;
+        let condition = self.text(header);
+        self.spans.push(Some(span(&line.tokens[..colon])));
+        let then_body = self.parse_body(line, colon, scope, in_loop)?;
+        let else_body = if let Some(elif) = self.peek_keyword(line.indent, "elif") {
+            self.pos += 1;
+            vec![self.parse_if(&elif, scope, in_loop)?]
+        } else if let Some(other) = self.peek_keyword(line.indent, "else") {
+            self.pos += 1;
+            let (_, colon) = self.header(&other, 0)?;
+            self.parse_body(&other, colon, scope, in_loop)?
+        } else {
+            vec![]
+        };
+        Ok(Statement::If {
+            condition,
+            then_body,
+            else_body,
+        })
+    }
+
+    fn parse_try(
+        &mut self,
+        line: &Line<'a>,
+        scope: Scope<'a>,
+        in_loop: bool,
+        body: &mut Vec<Statement>,
+    ) -> Result<(), ParseError> {
+        let (_, colon) = self.header(line, 0)?;
+        body.extend(self.parse_body(line, colon, scope, in_loop)?);
+        // The switch on the exception comes before the statements of its
+        // cases.
+        let slot = self.spans.len();
+        self.spans.push(Some(span(&line.tokens[..colon])));
+        let mut handlers = vec![];
+        while let Some(except) = self.peek_keyword(line.indent, "except") {
+            self.pos += 1;
+            let (header, colon) = self.header(&except, 0)?;
+            let label = match header {
+                [] => String::from("any"),
+                [star, rest @ ..] if star.is("*") => self.text(rest),
+                header => self.text(header),
+            };
+            handlers.push((label, self.parse_body(&except, colon, scope, in_loop)?));
+        }
+        let handlers_end = self.spans.len();
+        let mut otherwise = vec![];
+        if let Some(other) = self.peek_keyword(line.indent, "else") {
+            self.pos += 1;
+            let (_, colon) = self.header(&other, 0)?;
+            otherwise = self.parse_body(&other, colon, scope, in_loop)?;
+        }
+        // The `else` clause is the first case, and without handlers there is
+        // no switch.
+        match handlers.is_empty() {
+            true => self.spans.truncate(slot),
+            false => {
+                let otherwise_len = self.spans.len() - handlers_end;
+                self.spans[slot + 1..].rotate_right(otherwise_len);
+            }
+        }
+        let finally = match self.peek_keyword(line.indent, "finally") {
+            Some(finally) => {
+                self.pos += 1;
+                let (_, colon) = self.header(&finally, 0)?;
+                Some(self.parse_body(&finally, colon, scope, in_loop)?)
+            }
+            None => None,
+        };
+        if handlers.is_empty() && finally.is_none() {
+            return Err(
+                line.tokens[0].error(String::from("`try` needs an `except` or `finally` clause"))
+            );
+        }
+        if !handlers.is_empty() {
+            handlers.insert(0, (String::from("none"), otherwise));
+            body.push(Statement::Switch {
+                subject: String::from("exception"),
+                cases: handlers,
+                fall_through: false,
+            });
+        }
+        body.extend(finally.unwrap_or_default());
+        Ok(())
+    }
+
+    /// Tells a `match` statement from a use of `match` as a name, which
+    /// Python allows.
+    fn is_match(&self, line: &Line) -> bool {
+        let starts_subject = match line.tokens[1].kind {
+            TokenKind::Operator => ["(", "[", "{", "-", "*"].contains(&line.tokens[1].text),
+            _ => true,
+        };
+        starts_subject && header_colon(&line.tokens).is_some_and(|colon| colon > 1)
+    }
+
+    fn parse_match(
+        &mut self,
+        line: &Line<'a>,
+        scope: Scope<'a>,
+        in_loop: bool,
+    ) -> Result<Statement, ParseError> {
+        let (header, colon) = self.header(line, 0)?;
+        let subject = self.text(header);
+        self.spans.push(Some(span(&line.tokens[..colon])));
+        let case_indent = match self.lines.get(self.pos) {
+            Some(next) if next.indent > line.indent && colon + 1 == line.tokens.len() => {
+                next.indent
+            }
+            _ => return Err(line.tokens[colon].error(String::from("expected an indented block"))),
+        };
+        let mut cases = vec![];
+        while let Some(case) = self.lines.get(self.pos) {
+            if case.indent < case_indent {
+                if case.indent > line.indent {
+                    return Err(case.tokens[0].error(String::from(
+                        "unindent does not match any outer indentation level",
+                    )));
+                }
+                break;
+            }
+            let case = case.clone();
+            if case.indent > case_indent || !case.tokens[0].is("case") {
+                return Err(case.tokens[0].error(String::from("expected `case`")));
+            }
+            self.pos += 1;
+            let (header, colon) = self.header(&case, 0)?;
+            let label = self.text(header);
+            cases.push((label, self.parse_body(&case, colon, scope, in_loop)?));
+        }
+        Ok(Statement::Switch {
+            subject,
+            cases,
+            fall_through: false,
+        })
+    }
+
+    /// Parses a simple statement into `body`, unless it is left out of the
+    /// chart.
+    fn push_simple(
+        &mut self,
+        tokens: &[Token],
+        scope: Scope<'a>,
+        in_loop: bool,
+        body: &mut Vec<Statement>,
+    ) -> Result<(), ParseError> {
+        if let Some(statement) = self.parse_simple(tokens, scope, in_loop)? {
+            self.spans.push(Some(span(tokens)));
+            body.push(statement);
+        }
+        Ok(())
+    }
+
+    fn parse_simple(
+        &self,
+        tokens: &[Token],
+        scope: Scope<'a>,
+        in_loop: bool,
+    ) -> Result<Option<Statement>, ParseError> {
+        let first = tokens[0];
+        let text = self.text(tokens);
+        let statement = match first.text {
+            // A docstring or other lone string literal documents the code.
+            _ if tokens.iter().all(|token| token.kind == TokenKind::String) => None,
+            _ if DECLARATIONS.contains(&first.text) => None,
+            "..." if tokens.len() == 1 => None,
+            "return" if tokens.len() == 1 => Some(Statement::Return(None)),
+            "return" | "raise" => Some(Statement::Return(Some(text))),
+            "break" | "continue" if !in_loop => {
+                return Err(first.error(format!("`{}` outside of a loop", first.text)))
+            }
+            "break" => Some(Statement::Break),
+            "continue" => Some(Statement::Continue),
+            _ if is_io(tokens) => Some(Statement::Block(BlockKind::IO, text)),
+            _ => Some(match self.called_function(tokens, scope) {
+                Some(name) => Statement::Call(name),
+                None => Statement::Block(BlockKind::Process, text),
+            }),
+        };
+        Ok(statement)
+    }
+
+    /// The procedure called by a statement that is only a call, such as
+    /// `helper(x)` or `self.helper(x)`.
+    fn called_function(&self, tokens: &[Token], scope: Scope) -> Option<String> {
+        let mut names = vec![];
+        let mut i = 0;
+        loop {
+            let name = tokens
+                .get(i)
+                .filter(|token| token.kind == TokenKind::Name)?;
+            names.push(name.text);
+            match tokens.get(i + 1) {
+                Some(token) if token.is(".") => i += 2,
+                Some(token) if token.is("(") => break,
+                _ => return None,
+            }
+        }
+        let open = i + 1;
+        let close = open + closing_bracket(&tokens[open..])?;
+        if close + 1 != tokens.len() {
+            return None;
+        }
+        if let (["self" | "cls", ..], Scope::Function(Some(class))) = (names.as_slice(), scope) {
+            names[0] = class;
+        }
+        let name = names.join(".");
+        self.functions.contains(&name).then_some(name)
+    }
+}
+
+/// The index of the bracket closing the one that starts `tokens`.
+fn closing_bracket(tokens: &[Token]) -> Option<usize> {
+    let mut depth = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        if token.kind != TokenKind::Operator {
+            continue;
+        }
+        match token.text {
+            "(" | "[" | "{" => depth += 1,
+            ")" | "]" | "}" => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Whether the statement calls `input()` or `print()`.
+fn is_io(tokens: &[Token]) -> bool {
+    tokens.windows(2).enumerate().any(|(i, pair)| {
+        pair[0].kind == TokenKind::Name
+            && IO_FUNCTIONS.contains(&pair[0].text)
+            && pair[1].is("(")
+            && !(i > 0 && tokens[i - 1].is("."))
+    })
+}
+
+pub fn parse(source: &str) -> Result<Program, ParseError> {
+    let lines = Tokenizer::new(source).tokenize()?;
+    Parser {
+        source,
+        functions: function_names(&lines),
+        lines,
+        pos: 0,
+        procedures: vec![],
+        spans: vec![],
+    }
+    .parse_module()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chart;
+
+    /// The statements of the procedure `name` in `source`.
+    fn outline(source: &str, name: &str) -> Vec<String> {
+        let program = parse(source).unwrap();
+        chart::outline(program.procedure(name).unwrap())
+    }
+
+    #[test]
+    fn loop_else_runs_unless_the_loop_breaks() {
+        let lines = outline(
+            "def find(xs, y):
+    for x in xs:
+        for z in x:
+            if z:
+                break
+        if x == y:
+            print(x)
+            break
+    else:
+        print('none')
+    return y
+",
+            "find",
+        );
+        assert_eq!(
+            lines,
+            [
+                "for x in xs",
+                "  for z in x",
+                "    if z",
+                "      break",
+                "    else",
+                "  if x == y",
+                "    print(x)",
+                "    goto break line 2",
+                "  else",
+                "print('none')",
+                "label break line 2",
+                "return",
+            ]
+        );
+    }
+
+    #[test]
+    fn loop_else_without_break_follows_the_loop() {
+        let lines = outline("while n > 0:\n    n -= 1\nelse:\n    n = 1\n", "__main__");
+        assert_eq!(lines, ["while n > 0", "  n -= 1", "n = 1"]);
+    }
+
+    #[test]
+    fn try_is_a_switch_on_the_exception() {
+        let lines = outline(
+            "class A:
+    def f(self):
+        try:
+            g()
+        except ValueError:
+            h()
+        else:
+            i()
+        finally:
+            j()
+",
+            "A.f",
+        );
+        assert_eq!(
+            lines,
+            [
+                "g()",
+                "match exception",
+                "case none",
+                "  i()",
+                "case ValueError",
+                "  h()",
+                "j()",
+            ]
+        );
+    }
+
+    #[test]
+    fn stray_else_is_an_error() {
+        let error = parse("x = 1\nelse:\n    pass\n")
+            .err()
+            .expect("a parse error");
+        assert_eq!((error.line, error.column), (2, 1));
+        assert_eq!(error.message, "`else` without a matching block");
+    }
+}
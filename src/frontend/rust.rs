@@ -0,0 +1,651 @@
+//! Builds charts from Rust source.
+//!
+//! Every function, including the methods of `impl` blocks, becomes a
+//! procedure. Methods are named `Type::method`. Block labels are taken from
+//! the source text of the statements.
+//!
+//! A labeled `break` or `continue` that leaves more than the innermost loop
+//! is drawn as a connector, which the flow continues from after the loop or
+//! at the end of its body. A value that may `return`, `break` or `continue`,
+//! as in `let x = match y { Some(x) => x, None => return }`, is split into
+//! the branches it is chosen by.
+
+use std::collections::HashSet;
+
+use proc_macro2::Span;
+use syn::{spanned::Spanned, visit::Visit, Block, Expr, ImplItem, Item, Stmt};
+
+use crate::{
+    block::{self, BlockKind},
+    chart::{LoopKind, Procedure, Program, Statement},
+    parser::ParseError,
+};
+
+/// Macros that print, drawn as IO blocks.
+const IO_MACROS: &[&str] = &[
+    "print", "println", "eprint", "eprintln", "write", "writeln", "dbg",
+];
+
+/// Finds `?` outside of closures, async blocks and nested items, where it
+/// would not return from the function itself.
+#[derive(Default)]
+struct TryFinder {
+    found: bool,
+}
+
+impl<'ast> Visit<'ast> for TryFinder {
+    fn visit_expr_try(&mut self, _: &'ast syn::ExprTry) {
+        self.found = true;
+    }
+
+    fn visit_expr_closure(&mut self, _: &'ast syn::ExprClosure) {}
+
+    fn visit_expr_async(&mut self, _: &'ast syn::ExprAsync) {}
+
+    fn visit_item(&mut self, _: &'ast Item) {}
+}
+
+fn has_try(stmt: &Stmt) -> bool {
+    let mut finder = TryFinder::default();
+    finder.visit_stmt(stmt);
+    finder.found
+}
+
+/// Finds `return`, `break` and `continue` outside of closures, async blocks
+/// and nested items.
+#[derive(Default)]
+struct JumpFinder {
+    found: bool,
+}
+
+impl<'ast> Visit<'ast> for JumpFinder {
+    fn visit_expr_return(&mut self, _: &'ast syn::ExprReturn) {
+        self.found = true;
+    }
+
+    fn visit_expr_break(&mut self, _: &'ast syn::ExprBreak) {
+        self.found = true;
+    }
+
+    fn visit_expr_continue(&mut self, _: &'ast syn::ExprContinue) {
+        self.found = true;
+    }
+
+    fn visit_expr_closure(&mut self, _: &'ast syn::ExprClosure) {}
+
+    fn visit_expr_async(&mut self, _: &'ast syn::ExprAsync) {}
+
+    fn visit_item(&mut self, _: &'ast Item) {}
+}
+
+fn has_jump(expr: &Expr) -> bool {
+    let mut finder = JumpFinder::default();
+    finder.visit_expr(expr);
+    finder.found
+}
+
+/// The source text of `span`, with every line trimmed.
+fn text(span: Span) -> String {
+    let text = span.source_text().unwrap_or_default();
+    let lines: Vec<&str> = text.lines().map(str::trim).collect();
+    lines.join("\n")
+}
+
+/// The source span from the start of `start` to the end of `end`.
+fn source_span(start: Span, end: Span) -> block::Span {
+    let (start, end) = (start.start(), end.end());
+    block::Span::new(start.line, start.column + 1, end.line, end.column + 1)
+}
+
+fn statement_text(stmt: &Stmt) -> String {
+    let text = text(stmt.span());
+    match text.strip_suffix(';') {
+        Some(text) => String::from(text),
+        None => text,
+    }
+}
+
+fn path_name(path: &syn::Path, self_type: Option<&str>) -> String {
+    path.segments
+        .iter()
+        .map(
+            |segment| match (segment.ident.to_string().as_str(), self_type) {
+                ("Self", Some(self_type)) => String::from(self_type),
+                (ident, _) => String::from(ident),
+            },
+        )
+        .collect::<Vec<_>>()
+        .join("::")
+}
+
+/// A loop or a labeled block, which `break` can leave.
+struct Target {
+    label: Option<String>,
+    is_loop: bool,
+    /// Whether a jump leaves it, or continues it, through a connector.
+    broken: bool,
+    continued: bool,
+}
+
+impl Target {
+    fn new(label: Option<&syn::Label>, is_loop: bool) -> Self {
+        Self {
+            label: label.map(|label| label.name.to_string()),
+            is_loop,
+            broken: false,
+            continued: false,
+        }
+    }
+
+    /// The text of the connectors of a labeled `break` or `continue`.
+    fn connector(&self, keyword: &str) -> String {
+        format!("{}\n{}", keyword, self.label.as_deref().unwrap_or_default())
+    }
+}
+
+struct Converter<'a> {
+    functions: &'a HashSet<String>,
+    self_type: Option<&'a str>,
+    /// The spans of the statements converted so far, in pre-order.
+    spans: Vec<Option<block::Span>>,
+    /// The loops and labeled blocks around the statement converted, from the
+    /// outermost.
+    targets: Vec<Target>,
+}
+
+impl Converter<'_> {
+    fn convert_block(&mut self, block: &Block) -> Vec<Statement> {
+        block
+            .stmts
+            .iter()
+            .flat_map(|stmt| self.convert_stmt(stmt))
+            .collect()
+    }
+
+    fn convert_stmt(&mut self, stmt: &Stmt) -> Vec<Statement> {
+        match stmt {
+            Stmt::Item(_) => vec![],
+            Stmt::Expr(expr, _) => self.convert_expr(expr, stmt),
+            Stmt::Macro(stmt_macro) => {
+                self.spans.push(Some(source_span(stmt.span(), stmt.span())));
+                vec![self.convert_macro(&stmt_macro.mac, stmt)]
+            }
+            Stmt::Local(local) => match &local.init {
+                Some(init) if init.diverge.is_some() || has_jump(&init.expr) => {
+                    self.convert_let(local, init)
+                }
+                _ => {
+                    self.spans.push(Some(source_span(stmt.span(), stmt.span())));
+                    vec![self.plain(stmt)]
+                }
+            },
+        }
+    }
+
+    /// Converts a `let` whose value may jump away, or with an `else`.
+    fn convert_let(&mut self, local: &syn::Local, init: &syn::LocalInit) -> Vec<Statement> {
+        let prefix = format!("let {} =", text(local.pat.span()));
+        let Some((_, diverge)) = &init.diverge else {
+            return self.convert_value(&prefix, &init.expr);
+        };
+        // The `else` runs when the pattern does not match.
+        self.spans
+            .push(Some(source_span(local.let_token.span, init.expr.span())));
+        vec![Statement::If {
+            condition: format!("{} {}", prefix, text(init.expr.span())),
+            then_body: vec![],
+            else_body: self.convert_arm_body(diverge),
+        }]
+    }
+
+    /// Converts the assignment of `expr` with `prefix`, such as `let x =`,
+    /// into the branches that choose the value, when it may jump away.
+    fn convert_value(&mut self, prefix: &str, expr: &Expr) -> Vec<Statement> {
+        if !has_jump(expr) {
+            let stmt = Stmt::Expr(expr.clone(), None);
+            self.spans.push(Some(source_span(expr.span(), expr.span())));
+            let content = format!("{} {}", prefix, text(expr.span()));
+            return vec![match has_try(&stmt) {
+                true => Statement::Try(content),
+                false => Statement::Block(BlockKind::Process, content),
+            }];
+        }
+        match expr {
+            Expr::Block(expr_block) if expr_block.label.is_none() => {
+                self.convert_value_block(prefix, &expr_block.block)
+            }
+            Expr::Unsafe(expr_unsafe) => self.convert_value_block(prefix, &expr_unsafe.block),
+            Expr::If(expr_if) => {
+                let condition = expr_if.cond.span();
+                self.spans.push(Some(source_span(condition, condition)));
+                vec![Statement::If {
+                    condition: text(condition),
+                    then_body: self.convert_value_block(prefix, &expr_if.then_branch),
+                    else_body: match &expr_if.else_branch {
+                        Some((_, expr)) => self.convert_value(prefix, expr),
+                        None => vec![],
+                    },
+                }]
+            }
+            Expr::Match(expr_match) => {
+                let subject = expr_match.expr.span();
+                self.spans.push(Some(source_span(subject, subject)));
+                vec![Statement::Switch {
+                    subject: text(subject),
+                    cases: expr_match
+                        .arms
+                        .iter()
+                        .map(|arm| (arm_label(arm), self.convert_value(prefix, &arm.body)))
+                        .collect(),
+                    fall_through: false,
+                }]
+            }
+            _ => self.convert_stmt(&Stmt::Expr(expr.clone(), None)),
+        }
+    }
+
+    /// Converts the statements of `block`, and the assignment of its value.
+    fn convert_value_block(&mut self, prefix: &str, block: &Block) -> Vec<Statement> {
+        let mut body = vec![];
+        for (i, stmt) in block.stmts.iter().enumerate() {
+            match stmt {
+                Stmt::Expr(expr, None) if i + 1 == block.stmts.len() => {
+                    body.extend(self.convert_value(prefix, expr))
+                }
+                _ => body.extend(self.convert_stmt(stmt)),
+            }
+        }
+        body
+    }
+
+    /// A process block, or a try block if the statement may return early.
+    fn plain(&self, stmt: &Stmt) -> Statement {
+        if has_try(stmt) {
+            Statement::Try(statement_text(stmt))
+        } else {
+            Statement::Block(BlockKind::Process, statement_text(stmt))
+        }
+    }
+
+    fn convert_macro(&self, mac: &syn::Macro, stmt: &Stmt) -> Statement {
+        let is_io = mac
+            .path
+            .get_ident()
+            .is_some_and(|ident| IO_MACROS.iter().any(|name| ident == name));
+        if is_io {
+            Statement::Block(BlockKind::IO, statement_text(stmt))
+        } else {
+            Statement::Block(BlockKind::Process, statement_text(stmt))
+        }
+    }
+
+    fn convert_expr(&mut self, expr: &Expr, stmt: &Stmt) -> Vec<Statement> {
+        // The span of what the block shows, taken before the statements
+        // nested in this one, as the spans are in pre-order.
+        let span = match expr {
+            Expr::Block(_) | Expr::Unsafe(_) | Expr::If(_) => None,
+            Expr::Assign(assign) if has_jump(&assign.right) => None,
+            Expr::Match(expr_match) => Some((expr_match.expr.span(), expr_match.expr.span())),
+            Expr::Loop(expr_loop) => Some((expr_loop.loop_token.span, expr_loop.loop_token.span)),
+            Expr::While(expr_while) => Some((expr_while.cond.span(), expr_while.cond.span())),
+            Expr::ForLoop(expr_for) => Some((expr_for.pat.span(), expr_for.expr.span())),
+            _ => Some((stmt.span(), stmt.span())),
+        };
+        if let Some((start, end)) = span {
+            self.spans.push(Some(source_span(start, end)));
+        }
+        let statement = match expr {
+            Expr::Block(block) if block.label.is_some() => {
+                self.targets.push(Target::new(block.label.as_ref(), false));
+                let body = self.convert_block(&block.block);
+                return self.leave(body);
+            }
+            Expr::Block(block) => return self.convert_block(&block.block),
+            Expr::Unsafe(block) => return self.convert_block(&block.block),
+            Expr::If(expr_if) => self.convert_if(expr_if),
+            Expr::Match(expr_match) => Statement::Switch {
+                subject: text(expr_match.expr.span()),
+                cases: expr_match
+                    .arms
+                    .iter()
+                    .map(|arm| (arm_label(arm), self.convert_arm_body(&arm.body)))
+                    .collect(),
+                fall_through: false,
+            },
+            Expr::Loop(expr_loop) => {
+                let body = self.convert_loop_body(expr_loop.label.as_ref(), &expr_loop.body);
+                let kind = LoopKind::Infinite;
+                return self.leave(vec![Statement::Loop { kind, body }]);
+            }
+            Expr::While(expr_while) => {
+                let body = self.convert_loop_body(expr_while.label.as_ref(), &expr_while.body);
+                let kind = LoopKind::While(text(expr_while.cond.span()));
+                return self.leave(vec![Statement::Loop { kind, body }]);
+            }
+            Expr::ForLoop(expr_for) => {
+                let body = self.convert_loop_body(expr_for.label.as_ref(), &expr_for.body);
+                let kind = LoopKind::For(format!(
+                    "{} in {}",
+                    text(expr_for.pat.span()),
+                    text(expr_for.expr.span())
+                ));
+                return self.leave(vec![Statement::Loop { kind, body }]);
+            }
+            Expr::Assign(assign) if has_jump(&assign.right) => {
+                let prefix = format!("{} =", text(assign.left.span()));
+                return self.convert_value(&prefix, &assign.right);
+            }
+            Expr::Return(expr_return) => {
+                Statement::Return(expr_return.expr.as_ref().map(|_| statement_text(stmt)))
+            }
+            Expr::Break(expr_break) => self.jump(expr_break.label.as_ref(), "break"),
+            Expr::Continue(expr_continue) => self.jump(expr_continue.label.as_ref(), "continue"),
+            Expr::Macro(expr_macro) => self.convert_macro(&expr_macro.mac, stmt),
+            Expr::Call(call) if !has_try(stmt) => match &*call.func {
+                Expr::Path(func) if self.is_function(&func.path) => {
+                    Statement::Call(path_name(&func.path, self.self_type))
+                }
+                _ => self.plain(stmt),
+            },
+            _ => self.plain(stmt),
+        };
+        vec![statement]
+    }
+
+    /// Converts the body of a loop, which is the innermost target while it
+    /// is, and ends with the connector a labeled `continue` leads to.
+    fn convert_loop_body(&mut self, label: Option<&syn::Label>, block: &Block) -> Vec<Statement> {
+        self.targets.push(Target::new(label, true));
+        let mut body = self.convert_block(block);
+        let target = self.targets.last().unwrap();
+        if target.continued {
+            body.push(Statement::Label(target.connector("continue")));
+            self.spans.push(None);
+        }
+        body
+    }
+
+    /// Ends the innermost target, converted to `statements`, which are
+    /// followed by the connector a labeled `break` leads to.
+    fn leave(&mut self, mut statements: Vec<Statement>) -> Vec<Statement> {
+        let target = self.targets.pop().unwrap();
+        if target.broken {
+            statements.push(Statement::Label(target.connector("break")));
+            self.spans.push(None);
+        }
+        statements
+    }
+
+    /// A `break` or `continue` of the loop or block labeled `label`, or of
+    /// the innermost loop.
+    fn jump(&mut self, label: Option<&syn::Lifetime>, keyword: &str) -> Statement {
+        let innermost = self.targets.iter().rposition(|target| target.is_loop);
+        let target = match label {
+            Some(label) => {
+                let label = label.to_string();
+                self.targets
+                    .iter()
+                    .rposition(|target| target.label.as_ref() == Some(&label))
+            }
+            None => innermost,
+        };
+        let plain = match keyword {
+            "break" => Statement::Break,
+            _ => Statement::Continue,
+        };
+        match target {
+            Some(target) if Some(target) != innermost => {
+                let target = &mut self.targets[target];
+                match keyword {
+                    "break" => target.broken = true,
+                    _ => target.continued = true,
+                }
+                Statement::Goto(target.connector(keyword))
+            }
+            _ => plain,
+        }
+    }
+
+    fn convert_if(&mut self, expr_if: &syn::ExprIf) -> Statement {
+        let condition = expr_if.cond.span();
+        self.spans.push(Some(source_span(condition, condition)));
+        Statement::If {
+            condition: text(expr_if.cond.span()),
+            then_body: self.convert_block(&expr_if.then_branch),
+            else_body: match expr_if.else_branch.as_ref().map(|(_, expr)| &**expr) {
+                Some(Expr::If(else_if)) => vec![self.convert_if(else_if)],
+                Some(Expr::Block(block)) => self.convert_block(&block.block),
+                _ => vec![],
+            },
+        }
+    }
+
+    fn convert_arm_body(&mut self, body: &Expr) -> Vec<Statement> {
+        match body {
+            Expr::Block(block) => self.convert_block(&block.block),
+            Expr::Tuple(tuple) if tuple.elems.is_empty() => vec![],
+            _ => self.convert_stmt(&Stmt::Expr(body.clone(), None)),
+        }
+    }
+
+    fn is_function(&self, path: &syn::Path) -> bool {
+        self.functions.contains(&path_name(path, self.self_type))
+    }
+}
+
+/// The label of the case of a `match` arm.
+fn arm_label(arm: &syn::Arm) -> String {
+    let label = text(arm.pat.span());
+    match &arm.guard {
+        Some((_, guard)) => format!("{} if {}", label, text(guard.span())),
+        None => label,
+    }
+}
+
+fn self_type_name(item_impl: &syn::ItemImpl) -> Option<String> {
+    match &*item_impl.self_ty {
+        syn::Type::Path(type_path) => type_path
+            .path
+            .segments
+            .last()
+            .map(|segment| segment.ident.to_string()),
+        _ => None,
+    }
+}
+
+/// Every function of the file with the name of its `impl` type, if any.
+fn functions(file: &syn::File) -> Vec<(Option<String>, &syn::Signature, &Block)> {
+    let mut functions = vec![];
+    for item in &file.items {
+        match item {
+            Item::Fn(item_fn) => functions.push((None, &item_fn.sig, &*item_fn.block)),
+            Item::Impl(item_impl) => {
+                let self_type = self_type_name(item_impl);
+                for impl_item in &item_impl.items {
+                    if let ImplItem::Fn(method) = impl_item {
+                        functions.push((self_type.clone(), &method.sig, &method.block));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    functions
+}
+
+fn procedure_name(self_type: Option<&str>, sig: &syn::Signature) -> String {
+    match self_type {
+        Some(self_type) => format!("{}::{}", self_type, sig.ident),
+        None => sig.ident.to_string(),
+    }
+}
+
+pub fn parse(source: &str) -> Result<Program, ParseError> {
+    let file = syn::parse_file(source).map_err(|e| {
+        let start = e.span().start();
+        ParseError {
+            line: start.line,
+            column: start.column + 1,
+            message: e.to_string(),
+        }
+    })?;
+    let functions = functions(&file);
+    let names: HashSet<String> = functions
+        .iter()
+        .map(|(self_type, sig, _)| procedure_name(self_type.as_deref(), sig))
+        .collect();
+    let procedures = functions
+        .iter()
+        .map(|(self_type, sig, block)| {
+            let mut converter = Converter {
+                functions: &names,
+                self_type: self_type.as_deref(),
+                spans: vec![],
+                targets: vec![],
+            };
+            let body = converter.convert_block(block);
+            Procedure {
+                name: procedure_name(self_type.as_deref(), sig),
+                body,
+                span: Some(source_span(sig.span(), block.span())),
+                spans: converter.spans,
+            }
+        })
+        .collect();
+    // Spans are kept in a thread-local source map that would otherwise grow
+    // with every parsed file.
+    proc_macro2::extra::invalidate_current_thread_spans();
+    Ok(Program { procedures })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chart;
+
+    /// The statements of the first procedure in `source`.
+    fn outline(source: &str) -> Vec<String> {
+        let program = parse(source).unwrap();
+        chart::outline(&program.procedures[0])
+    }
+
+    #[test]
+    fn labeled_jumps_leave_the_outer_loop() {
+        let lines = outline(
+            "fn f(xs: &[Vec<i32>]) {
+                'rows: for row in xs {
+                    for x in row {
+                        if *x < 0 { continue 'rows; }
+                        if *x == 0 { break 'rows; }
+                        if *x == 1 { break; }
+                    }
+                }
+            }",
+        );
+        assert_eq!(
+            lines,
+            [
+                "for row in xs",
+                "  for x in row",
+                "    if *x < 0",
+                "      goto continue 'rows",
+                "    else",
+                "    if *x == 0",
+                "      goto break 'rows",
+                "    else",
+                "    if *x == 1",
+                "      break",
+                "    else",
+                "  label continue 'rows",
+                "label break 'rows",
+            ]
+        );
+    }
+
+    #[test]
+    fn labels_of_the_innermost_loop_are_plain_jumps() {
+        let lines = outline("fn f() { 'a: loop { if g() { break 'a; } continue 'a; } }");
+        assert_eq!(
+            lines,
+            ["loop", "  if g()", "    break", "  else", "  continue"]
+        );
+    }
+
+    #[test]
+    fn labeled_blocks_are_left_through_a_connector() {
+        let lines = outline("fn f() { 'b: { if g() { break 'b; } h(); } i(); }");
+        assert_eq!(
+            lines,
+            [
+                "if g()",
+                "  goto break 'b",
+                "else",
+                "h()",
+                "label break 'b",
+                "i()",
+            ]
+        );
+    }
+
+    #[test]
+    fn returns_in_values_end_the_procedure() {
+        let lines = outline(
+            "fn f(y: Option<i32>) -> i32 {
+                let x = match y { Some(x) => x, None => return 0 };
+                let z = if x > 0 { x } else { return 1 };
+                x + z
+            }",
+        );
+        assert_eq!(
+            lines,
+            [
+                "match y",
+                "case Some(x)",
+                "  let x = x",
+                "case None",
+                "  return",
+                "if x > 0",
+                "  let z = x",
+                "else",
+                "  return",
+                "x + z",
+            ]
+        );
+    }
+
+    #[test]
+    fn let_else_branches_to_its_else() {
+        let lines = outline(
+            "fn f(y: Option<i32>) {
+                let Some(x) = y else { return };
+                g(x)?;
+            }",
+        );
+        assert_eq!(
+            lines,
+            ["if let Some(x) = y", "else", "  return", "try g(x)?"]
+        );
+    }
+
+    #[test]
+    fn assignments_with_jumps_are_split() {
+        let lines = outline(
+            "fn f(ys: &[Option<i32>]) {
+                let mut x = 0;
+                for y in ys { x = match y { Some(y) => *y, None => continue }; }
+            }",
+        );
+        assert_eq!(
+            lines,
+            [
+                "let mut x = 0",
+                "for y in ys",
+                "  match y",
+                "  case Some(y)",
+                "    x = *y",
+                "  case None",
+                "    continue",
+            ]
+        );
+    }
+}
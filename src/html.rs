@@ -0,0 +1,275 @@
+use std::fmt::Write;
+
+use crate::{chart::Program, config::Config, svg::Svg, xml::escape};
+
+/// Renders every procedure of `program` into one page, where calls link to
+/// the callee's section.
+pub fn render_program(config: &Config, program: &Program, title: &str) -> String {
+    let link = |name: &str| program.procedure(name).map(|_| format!("#{}", name));
+    let charts: Vec<(String, Svg)> = program
+        .procedures
+        .iter()
+        .map(|procedure| (procedure.name.clone(), procedure.to_svg(config, &link)))
+        .collect();
+    render_charts(&charts, title)
+}
+
+/// Renders named charts into one page, with a section for every chart whose
+/// id is its name. The charts are numbered, as `chart-1` and on, to scope
+/// their styles.
+pub fn render_charts(charts: &[(String, Svg)], title: &str) -> String {
+    let mut html = String::new();
+    writeln!(html, "<!DOCTYPE html>").unwrap();
+    writeln!(html, "<html>").unwrap();
+    writeln!(html, "<head>").unwrap();
+    writeln!(html, r#"<meta charset="utf-8">"#).unwrap();
+    writeln!(html, "<title>{}</title>", escape(title)).unwrap();
+    writeln!(html, "</head>").unwrap();
+    writeln!(html, "<body>").unwrap();
+    for (index, (name, svg)) in charts.iter().enumerate() {
+        let name = escape(name);
+        let id = format!("chart-{}", index + 1);
+        writeln!(html, r#"<section id="{}">"#, name).unwrap();
+        writeln!(html, "<h2>{}</h2>", name).unwrap();
+        write!(html, "{}", svg.inline().id(&id)).unwrap();
+        writeln!(html, "</section>").unwrap();
+    }
+    writeln!(html, "</body>").unwrap();
+    writeln!(html, "</html>").unwrap();
+    html
+}
+
+const VIEWER_STYLE: &str = r##"html, body { height: 100%; margin: 0; }
+body { display: flex; font-family: sans-serif; }
+#chart { flex: 1; position: relative; overflow: hidden; cursor: grab; }
+#chart.dragging { cursor: grabbing; }
+#chart > svg { width: 100%; height: 100%; display: block; }
+#tools { position: absolute; top: 8px; left: 8px; }
+#tip { position: fixed; display: none; pointer-events: none; white-space: pre;
+  font-family: monospace; background: #ffffe0; border: 1px solid #999; padding: 4px; }
+#source { width: 40%; margin: 0; overflow: auto; border-left: 1px solid #999;
+  counter-reset: line; }
+#source span { display: block; }
+#source span::before { counter-increment: line; content: counter(line);
+  display: inline-block; width: 4em; color: #999; }
+#source .hit { background: #ffe082; }
+g.block { cursor: pointer; }
+g.block:hover rect, g.block:hover polygon, g.block:hover circle { stroke: #1e88e5; }"##;
+
+const VIEWER_SCRIPT: &str = r##"(function () {
+  const chart = document.getElementById("chart");
+  const svg = chart.querySelector("svg");
+  const tip = document.getElementById("tip");
+  const source = document.getElementById("source");
+  const box = (svg.getAttribute("viewBox") || "0 0 " + svg.getAttribute("width") + " " +
+    svg.getAttribute("height")).split(" ").map(Number);
+  svg.removeAttribute("width");
+  svg.removeAttribute("height");
+  let view = box.slice();
+  const show = () => svg.setAttribute("viewBox", view.join(" "));
+  const fit = () => { view = box.slice(); show(); };
+  const scale = () => {
+    const rect = svg.getBoundingClientRect();
+    return Math.max(view[2] / rect.width, view[3] / rect.height);
+  };
+  const at = (x, y) => new DOMPoint(x, y).matrixTransform(svg.getScreenCTM().inverse());
+  const zoom = (factor, p) => {
+    view = [p.x - (p.x - view[0]) * factor, p.y - (p.y - view[1]) * factor,
+      view[2] * factor, view[3] * factor];
+    show();
+  };
+  const middle = () => {
+    const rect = svg.getBoundingClientRect();
+    return at(rect.left + rect.width / 2, rect.top + rect.height / 2);
+  };
+  chart.addEventListener("wheel", (e) => {
+    e.preventDefault();
+    zoom(Math.exp(e.deltaY * 0.002), at(e.clientX, e.clientY));
+  }, { passive: false });
+  let drag = null;
+  let moved = false;
+  chart.addEventListener("pointerdown", (e) => {
+    if (e.target.closest("#tools")) return;
+    drag = { x: e.clientX, y: e.clientY, view: view.slice(), scale: scale() };
+    moved = false;
+  });
+  window.addEventListener("pointermove", (e) => {
+    if (!drag) return;
+    const dx = e.clientX - drag.x, dy = e.clientY - drag.y;
+    if (Math.abs(dx) + Math.abs(dy) > 3) {
+      moved = true;
+      chart.classList.add("dragging");
+    }
+    view = [drag.view[0] - dx * drag.scale, drag.view[1] - dy * drag.scale, view[2], view[3]];
+    show();
+  });
+  window.addEventListener("pointerup", () => {
+    drag = null;
+    chart.classList.remove("dragging");
+  });
+  window.addEventListener("keydown", (e) => {
+    if (e.key === "f" || e.key === "0") fit();
+    if (e.key === "+" || e.key === "=") zoom(0.8, middle());
+    if (e.key === "-") zoom(1.25, middle());
+  });
+  document.getElementById("fit").onclick = fit;
+  document.getElementById("in").onclick = () => zoom(0.8, middle());
+  document.getElementById("out").onclick = () => zoom(1.25, middle());
+  const text = (block) =>
+    Array.from(block.querySelectorAll("text"), (t) => t.textContent).join("\n");
+  svg.querySelectorAll("g.block").forEach((block) => {
+    block.addEventListener("mousemove", (e) => {
+      tip.textContent = text(block);
+      tip.style.display = "block";
+      tip.style.left = e.clientX + 12 + "px";
+      tip.style.top = e.clientY + 12 + "px";
+    });
+    block.addEventListener("mouseleave", () => { tip.style.display = "none"; });
+    block.addEventListener("click", (e) => {
+      if (moved || !source) return;
+      // Blocks that link to another chart still follow the link.
+      if (!e.target.closest("a")) e.preventDefault();
+      reveal(block);
+    });
+  });
+  // Marks the lines of the source the block comes from: those of its span,
+  // or else those holding its text.
+  function reveal(block) {
+    const lines = Array.from(source.children);
+    lines.forEach((line) => line.classList.remove("hit"));
+    const first = Number(block.dataset.line);
+    const last = Number(block.dataset.endLine || first);
+    let hits;
+    if (first) {
+      hits = lines.slice(first - 1, last);
+    } else {
+      const needle = text(block).split("\n").map((line) => line.trim()).find((line) => line);
+      if (!needle) return;
+      hits = lines.filter((line) => line.textContent.includes(needle));
+    }
+    hits.forEach((line) => line.classList.add("hit"));
+    if (hits.length) hits[0].scrollIntoView({ block: "center" });
+  }
+  fit();
+})();"##;
+
+/// Renders `svg` into a page that pans on drag, zooms on the wheel and fits
+/// the chart to the window with `f`. Hovering a block shows its text, and
+/// with the `source` the chart came from, clicking a block marks the lines
+/// of its span, or the lines holding its text if it has none.
+pub fn render_viewer(svg: &Svg, title: &str, source: Option<&str>) -> String {
+    let mut html = String::new();
+    writeln!(html, "<!DOCTYPE html>").unwrap();
+    writeln!(html, "<html>").unwrap();
+    writeln!(html, "<head>").unwrap();
+    writeln!(html, r#"<meta charset="utf-8">"#).unwrap();
+    writeln!(html, "<title>{}</title>", escape(title)).unwrap();
+    writeln!(html, "<style>\n{}\n</style>", VIEWER_STYLE).unwrap();
+    writeln!(html, "</head>").unwrap();
+    writeln!(html, "<body>").unwrap();
+    writeln!(html, r#"<div id="chart">"#).unwrap();
+    writeln!(html, r#"<div id="tools">"#).unwrap();
+    writeln!(html, r#"<button id="fit" title="Fit (f)">Fit</button>"#).unwrap();
+    writeln!(html, r#"<button id="in" title="Zoom in (+)">+</button>"#).unwrap();
+    writeln!(html, r#"<button id="out" title="Zoom out (-)">-</button>"#).unwrap();
+    writeln!(html, "</div>").unwrap();
+    write!(html, "{}", svg.inline()).unwrap();
+    writeln!(html, "</div>").unwrap();
+    if let Some(source) = source {
+        write!(html, r#"<pre id="source">"#).unwrap();
+        for line in source.lines() {
+            write!(html, "<span>{} </span>", escape(line)).unwrap();
+        }
+        writeln!(html, "</pre>").unwrap();
+    }
+    writeln!(html, r#"<div id="tip"></div>"#).unwrap();
+    writeln!(html, "<script>\n{}\n</script>", VIEWER_SCRIPT).unwrap();
+    writeln!(html, "</body>").unwrap();
+    writeln!(html, "</html>").unwrap();
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::ConfigBuilder, parser::parse};
+
+    const SOURCE: &str = "proc main\n    call helper\nend\nproc helper\n    process x = 1\nend\n";
+
+    fn page() -> String {
+        let config = ConfigBuilder::new().build();
+        render_program(&config, &parse(SOURCE).unwrap(), "two charts")
+    }
+
+    /// The values of the attribute `name` in `html`.
+    fn values<'a>(html: &'a str, name: &str) -> Vec<&'a str> {
+        let start = format!(" {}=\"", name);
+        html.split(&start)
+            .skip(1)
+            .map(|rest| &rest[..rest.find('"').unwrap()])
+            .collect()
+    }
+
+    #[test]
+    fn charts_have_their_own_ids() {
+        let html = page();
+        let mut ids = values(&html, "id");
+        assert_eq!(
+            ids,
+            [
+                "main",
+                "chart-1",
+                "chart-1-arrow",
+                "helper",
+                "chart-2",
+                "chart-2-arrow"
+            ]
+        );
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), 6);
+        assert!(!html.contains("url(#arrow)"));
+        assert!(html.contains(r##"marker-end: url(#chart-2-arrow);"##));
+    }
+
+    #[test]
+    fn styles_are_scoped_to_their_chart() {
+        let html = page();
+        for (index, style) in html.split("<style>").skip(1).enumerate() {
+            let style = &style[..style.find("</style>").unwrap()];
+            let scope = format!("#chart-{} ", index + 1);
+            for rule in style.lines().filter(|line| line.ends_with('{')) {
+                assert!(rule.trim().starts_with(&scope), "`{}` is not scoped", rule);
+            }
+        }
+    }
+
+    #[test]
+    fn calls_link_to_their_section() {
+        assert_eq!(values(&page(), "href"), ["#helper"]);
+    }
+
+    #[test]
+    fn viewer_shows_the_source() {
+        let config = ConfigBuilder::new().build();
+        let program = parse(SOURCE).unwrap();
+        let svg = program.procedures[0].to_svg(&config, &|name| Some(format!("#{}", name)));
+        let html = render_viewer(&svg, "<main>", Some("a < b\n"));
+        assert!(html.contains("<title>&lt;main&gt;</title>"));
+        assert!(html.contains(r#"<pre id="source"><span>a &lt; b </span></pre>"#));
+        assert_eq!(values(&html, "href"), ["#helper"]);
+        // Clicking a call reveals its source and still follows its link.
+        assert!(!VIEWER_SCRIPT.contains("\n      e.preventDefault();"));
+        assert!(VIEWER_SCRIPT.contains(r#"if (!e.target.closest("a")) e.preventDefault();"#));
+    }
+
+    #[test]
+    fn viewer_without_source() {
+        let config = ConfigBuilder::new().build();
+        let program = parse(SOURCE).unwrap();
+        let svg = program.procedures[1].to_svg(&config, &|_| None);
+        let html = render_viewer(&svg, "helper", None);
+        assert!(!html.contains(r#"id="source""#));
+        assert!(html.contains("x = 1"));
+    }
+}
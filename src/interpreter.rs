@@ -0,0 +1,655 @@
+//! Runs charts, to check that they compute what they claim.
+//!
+//! Every block has simple semantics:
+//!
+//! - a process block assigns, one assignment per line or separated by `;`,
+//!   as in `x = 1`, `a, b = b, a`, `a[i] := 0`, `n <- n - 1` or `s += x`,
+//! - an IO block reads inputs into variables, as in `read a, b`, or prints
+//!   values, as in `print "sum", s`,
+//! - a decision is a condition that must be true or false; a trailing `?` is
+//!   ignored,
+//! - a switch compares its subject with the values of every case, such as
+//!   `1, 2`; `default`, `else` and `otherwise` match any value,
+//! - a `for` loop runs over the values of `x in <list>`, or over the
+//!   integers of `i = 1 to n` or `i = n to 1 step -1`,
+//! - a call runs the procedure called, and a return block gives the value
+//!   of its expression.
+//!
+//! All procedures share one set of variables. See
+//! [`expression`](crate::expression) for the expressions.
+
+use std::{collections::VecDeque, fmt};
+
+use crate::{
+    block::{BlockKind, Span},
+    chart::{count, LoopKind, Procedure, Program, Statement},
+    expression::{
+        equal, parse_assignments, parse_expression, parse_expressions, parse_targets, truth,
+        Assignment, Expression, Value, Variables,
+    },
+};
+
+/// How many steps a run takes at most, unless set otherwise.
+const MAX_STEPS: usize = 10_000;
+
+/// How deeply calls nest at most.
+const MAX_DEPTH: usize = 256;
+
+/// A block run.
+pub struct Step {
+    pub procedure: String,
+    /// The statement of the block, by its index in
+    /// [`Procedure::statements`].
+    pub statement: usize,
+    /// The text of the block.
+    pub text: String,
+    /// The branch a decision took, as its edge is labeled: `yes` or `no`,
+    /// `next` or `done` for a `for` loop, or the label of a case.
+    pub branch: Option<String>,
+    /// What an IO block printed.
+    pub output: Option<String>,
+    /// The variables after the block.
+    pub variables: Variables,
+}
+
+pub struct Trace {
+    pub steps: Vec<Step>,
+    /// Every line printed.
+    pub outputs: Vec<String>,
+    /// The value the procedure run returned, if any, not counting the
+    /// procedures it called.
+    pub result: Option<Value>,
+}
+
+#[derive(Debug)]
+pub struct RunError {
+    pub procedure: String,
+    /// The text of the block that failed.
+    pub text: String,
+    /// Where the block that failed comes from, if known.
+    pub span: Option<Box<Span>>,
+    pub message: String,
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(span) = &self.span {
+            write!(f, "{}: ", span)?;
+        }
+        match self.text.as_str() {
+            "" => write!(f, "in `{}`: {}", self.procedure, self.message),
+            text => write!(
+                f,
+                "in `{}`, at `{}`: {}",
+                self.procedure, text, self.message
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RunError {}
+
+pub struct Interpreter<'a> {
+    program: &'a Program,
+    max_steps: usize,
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new(program: &'a Program) -> Self {
+        Self {
+            program,
+            max_steps: MAX_STEPS,
+        }
+    }
+
+    /// Stops runs that take more than `max_steps` steps, as they may not end.
+    pub fn max_steps(mut self, max_steps: usize) -> Self {
+        self.max_steps = max_steps;
+        self
+    }
+
+    /// Runs the procedure named `name`, reading `inputs` in order.
+    pub fn run(&self, name: &str, inputs: &[Value]) -> Result<Trace, RunError> {
+        let mut run = Run {
+            program: self.program,
+            max_steps: self.max_steps,
+            ticks: 0,
+            depth: 0,
+            variables: Variables::new(),
+            inputs: inputs.iter().cloned().collect(),
+            trace: Trace {
+                steps: vec![],
+                outputs: vec![],
+                result: None,
+            },
+        };
+        let procedure = self.program.procedure(name).ok_or_else(|| RunError {
+            procedure: name.to_string(),
+            text: String::new(),
+            span: None,
+            message: String::from("there is no such procedure"),
+        })?;
+        run.body(procedure, &procedure.body, 0)?;
+        Ok(run.trace)
+    }
+}
+
+/// What a statement makes the flow do next.
+enum Control {
+    Next,
+    Break,
+    Continue,
+    Return,
+}
+
+struct Run<'a> {
+    program: &'a Program,
+    max_steps: usize,
+    /// Steps taken, and iterations of loops with no blocks.
+    ticks: usize,
+    depth: usize,
+    variables: Variables,
+    inputs: VecDeque<Value>,
+    trace: Trace,
+}
+
+impl<'a> Run<'a> {
+    /// Runs `body`, whose first statement is at `index` in
+    /// [`Procedure::statements`].
+    fn body(
+        &mut self,
+        procedure: &'a Procedure,
+        body: &'a [Statement],
+        mut index: usize,
+    ) -> Result<Control, RunError> {
+        for statement in body {
+            match self.statement(procedure, statement, index)? {
+                Control::Next => {}
+                control => return Ok(control),
+            }
+            index += statement.count();
+        }
+        Ok(Control::Next)
+    }
+
+    fn statement(
+        &mut self,
+        procedure: &'a Procedure,
+        statement: &'a Statement,
+        index: usize,
+    ) -> Result<Control, RunError> {
+        let span = procedure.span(index).cloned().map(Box::new);
+        let error = |message: String| RunError {
+            procedure: procedure.name.clone(),
+            text: text(statement),
+            span: span.clone(),
+            message,
+        };
+        match statement {
+            Statement::Block(BlockKind::Process, content) | Statement::Try(content) => {
+                for assignment in parse_assignments(content).map_err(error)? {
+                    assignment.execute(&mut self.variables).map_err(error)?;
+                }
+                self.step(procedure, statement, index, None, None)?;
+            }
+            Statement::Block(BlockKind::IO, content) => {
+                let output = self.io(content).map_err(error)?;
+                self.step(procedure, statement, index, None, output)?;
+            }
+            Statement::Block(BlockKind::Decision, condition) => {
+                let value = self.condition(condition).map_err(error)?;
+                self.step(procedure, statement, index, Some(yes_no(value)), None)?;
+            }
+            Statement::Block(..) | Statement::Label(_) => {
+                self.step(procedure, statement, index, None, None)?;
+            }
+            Statement::Call(name) => {
+                self.step(procedure, statement, index, None, None)?;
+                let callee = self
+                    .program
+                    .procedure(name)
+                    .ok_or_else(|| error(String::from("there is no such procedure")))?;
+                if self.depth == MAX_DEPTH {
+                    return Err(error(String::from("calls nest too deeply")));
+                }
+                self.depth += 1;
+                self.body(callee, &callee.body, 0)?;
+                self.depth -= 1;
+            }
+            Statement::If {
+                condition,
+                then_body,
+                else_body,
+            } => {
+                let value = self.condition(condition).map_err(error)?;
+                self.step(procedure, statement, index, Some(yes_no(value)), None)?;
+                return match value {
+                    true => self.body(procedure, then_body, index + 1),
+                    false => {
+                        let first = index + 1 + count(then_body);
+                        self.body(procedure, else_body, first)
+                    }
+                };
+            }
+            Statement::Switch {
+                subject,
+                cases,
+                fall_through,
+            } => {
+                let value = parse_expression(subject)
+                    .and_then(|subject| subject.evaluate(&self.variables))
+                    .map_err(error)?;
+                let mut chosen = None;
+                for (i, (label, _)) in cases.iter().enumerate() {
+                    if matches(label, &value, &self.variables).map_err(error)? {
+                        chosen = Some(i);
+                        break;
+                    }
+                }
+                let branch = chosen.map(|i| cases[i].0.clone());
+                self.step(procedure, statement, index, branch, None)?;
+                let Some(chosen) = chosen else {
+                    return Ok(Control::Next);
+                };
+                let mut first = index + 1;
+                for (_, body) in &cases[..chosen] {
+                    first += count(body);
+                }
+                if !fall_through {
+                    return self.body(procedure, &cases[chosen].1, first);
+                }
+                for (_, body) in &cases[chosen..] {
+                    let control = self.body(procedure, body, first)?;
+                    first += count(body);
+                    match control {
+                        Control::Next => {}
+                        Control::Break => break,
+                        control => return Ok(control),
+                    }
+                }
+            }
+            Statement::Loop { kind, body } => {
+                return self.run_loop(procedure, statement, index, kind, body)
+            }
+            Statement::Return(value) => {
+                if let Some(value) = value {
+                    // Blocks read `return x`, as charts show them.
+                    let value = value.strip_prefix("return").unwrap_or(value);
+                    let value = parse_expression(value)
+                        .and_then(|value| value.evaluate(&self.variables))
+                        .map_err(error)?;
+                    if self.depth == 0 {
+                        self.trace.result = Some(value);
+                    }
+                    self.step(procedure, statement, index, None, None)?;
+                }
+                return Ok(Control::Return);
+            }
+            Statement::Break => return Ok(Control::Break),
+            Statement::Continue => return Ok(Control::Continue),
+            Statement::Goto(_) => return Err(error(String::from("`goto` cannot be run"))),
+            Statement::Annotation(_) => {}
+        }
+        Ok(Control::Next)
+    }
+
+    fn run_loop(
+        &mut self,
+        procedure: &'a Procedure,
+        statement: &'a Statement,
+        index: usize,
+        kind: &'a LoopKind,
+        body: &'a [Statement],
+    ) -> Result<Control, RunError> {
+        let span = procedure.span(index).cloned().map(Box::new);
+        let error = |message: String| RunError {
+            procedure: procedure.name.clone(),
+            text: text(statement),
+            span: span.clone(),
+            message,
+        };
+        let mut items = match kind {
+            LoopKind::For(header) => {
+                let (name, values) = self.for_items(header).map_err(error)?;
+                Some((name, values.into_iter()))
+            }
+            _ => None,
+        };
+        loop {
+            match kind {
+                LoopKind::Infinite => self.tick(procedure)?,
+                LoopKind::While(condition) => {
+                    let value = self.condition(condition).map_err(error)?;
+                    self.step(procedure, statement, index, Some(yes_no(value)), None)?;
+                    if !value {
+                        break;
+                    }
+                }
+                LoopKind::For(_) => {
+                    let (name, values) = items.as_mut().unwrap();
+                    let next = values.next();
+                    let more = next.is_some();
+                    if let Some(value) = next {
+                        self.variables.insert(name.clone(), value);
+                    }
+                    let branch = String::from(if more { "next" } else { "done" });
+                    self.step(procedure, statement, index, Some(branch), None)?;
+                    if !more {
+                        break;
+                    }
+                }
+                LoopKind::DoWhile(_) => {}
+            }
+            match self.body(procedure, body, index + 1)? {
+                Control::Break => break,
+                Control::Return => return Ok(Control::Return),
+                Control::Next | Control::Continue => {}
+            }
+            if let LoopKind::DoWhile(condition) = kind {
+                let value = self.condition(condition).map_err(error)?;
+                self.step(procedure, statement, index, Some(yes_no(value)), None)?;
+                if !value {
+                    break;
+                }
+            }
+        }
+        Ok(Control::Next)
+    }
+
+    /// Records a step of the statement at `index`.
+    fn step(
+        &mut self,
+        procedure: &'a Procedure,
+        statement: &'a Statement,
+        index: usize,
+        branch: Option<String>,
+        output: Option<String>,
+    ) -> Result<(), RunError> {
+        self.tick(procedure)?;
+        if let Some(output) = &output {
+            self.trace.outputs.push(output.clone());
+        }
+        self.trace.steps.push(Step {
+            procedure: procedure.name.clone(),
+            statement: index,
+            text: text(statement),
+            branch,
+            output,
+            variables: self.variables.clone(),
+        });
+        Ok(())
+    }
+
+    fn tick(&mut self, procedure: &Procedure) -> Result<(), RunError> {
+        self.ticks += 1;
+        match self.ticks > self.max_steps {
+            true => Err(RunError {
+                procedure: procedure.name.clone(),
+                text: String::new(),
+                span: None,
+                message: format!(
+                    "stopped after {} steps, as the chart may not end",
+                    self.max_steps
+                ),
+            }),
+            false => Ok(()),
+        }
+    }
+
+    fn condition(&self, condition: &str) -> Result<bool, String> {
+        let condition = condition.trim().trim_end_matches('?');
+        truth(&parse_expression(condition)?.evaluate(&self.variables)?)
+    }
+
+    /// Runs an IO block and returns what it printed, if anything.
+    fn io(&mut self, content: &str) -> Result<Option<String>, String> {
+        let content = content.trim();
+        let (keyword, rest) = content
+            .split_once(char::is_whitespace)
+            .unwrap_or((content, ""));
+        match keyword.to_lowercase().as_str() {
+            "read" | "input" | "get" | "enter" | "accept" => {
+                for target in parse_targets(rest)? {
+                    let value = self
+                        .inputs
+                        .pop_front()
+                        .ok_or_else(|| String::from("there is no input left"))?;
+                    Assignment {
+                        targets: vec![target],
+                        operator: None,
+                        values: vec![Expression::Literal(value)],
+                    }
+                    .execute(&mut self.variables)?;
+                }
+                Ok(None)
+            }
+            "print" | "output" | "write" | "display" | "show" => {
+                let values: Vec<String> = parse_expressions(rest)?
+                    .iter()
+                    .map(|value| {
+                        value
+                            .evaluate(&self.variables)
+                            .map(|value| value.to_string())
+                    })
+                    .collect::<Result<_, _>>()?;
+                Ok(Some(values.join(" ")))
+            }
+            _ => Err(String::from(
+                "an IO block reads with `read` or prints with `print`",
+            )),
+        }
+    }
+
+    /// The variable and the values of a `for` loop.
+    fn for_items(&self, header: &str) -> Result<(String, Vec<Value>), String> {
+        let evaluate = |text: &str| parse_expression(text)?.evaluate(&self.variables);
+        let integer = |text: &str| match evaluate(text)? {
+            Value::Int(int) => Ok(int),
+            value => Err(format!("`{}` is not an integer", value)),
+        };
+        let (name, values) = if let Some((name, list)) = header.split_once(" in ") {
+            let values = match evaluate(list)? {
+                Value::List(items) => items,
+                Value::Str(string) => string.chars().map(|c| Value::Str(c.to_string())).collect(),
+                value => return Err(format!("cannot loop over `{}`", value)),
+            };
+            (name, values)
+        } else if let Some((start, end)) = header.split_once(" to ") {
+            let (name, start) = start
+                .split_once(" from ")
+                .or_else(|| start.split_once('='))
+                .ok_or_else(|| {
+                    String::from("a `for` loop runs over `x in <list>` or `i = 1 to n`")
+                })?;
+            let (end, step) = end.split_once(" step ").unwrap_or((end, "1"));
+            let (start, end, step) = (integer(start)?, integer(end)?, integer(step)?);
+            if step == 0 {
+                return Err(String::from("the step is zero"));
+            }
+            let mut values = vec![];
+            let mut i = start;
+            // A run stops after `max_steps` anyway, so longer ranges are cut.
+            while ((step > 0 && i <= end) || (step < 0 && i >= end))
+                && values.len() <= self.max_steps
+            {
+                values.push(Value::Int(i));
+                i = match i.checked_add(step) {
+                    Some(i) => i,
+                    None => break,
+                };
+            }
+            (name, values)
+        } else {
+            return Err(String::from(
+                "a `for` loop runs over `x in <list>` or `i = 1 to n`",
+            ));
+        };
+        let name = name.trim().trim_start_matches("each ").trim();
+        match parse_expression(name)? {
+            Expression::Variable(name) => Ok((name, values)),
+            _ => Err(format!("`{}` is not a variable", name)),
+        }
+    }
+}
+
+fn yes_no(value: bool) -> String {
+    String::from(if value { "yes" } else { "no" })
+}
+
+/// Whether the label of a case matches the subject of a switch.
+fn matches(label: &str, value: &Value, variables: &Variables) -> Result<bool, String> {
+    if matches!(
+        label.trim().to_lowercase().as_str(),
+        "default" | "else" | "otherwise" | "_"
+    ) {
+        return Ok(true);
+    }
+    for case in parse_expressions(label)? {
+        if equal(value, &case.evaluate(variables)?) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// The text of the block of a statement.
+fn text(statement: &Statement) -> String {
+    match statement {
+        Statement::Block(_, text)
+        | Statement::Call(text)
+        | Statement::Try(text)
+        | Statement::If {
+            condition: text, ..
+        }
+        | Statement::Switch { subject: text, .. }
+        | Statement::Return(Some(text))
+        | Statement::Goto(text)
+        | Statement::Label(text)
+        | Statement::Annotation(text) => text.clone(),
+        Statement::Loop { kind, .. } => match kind {
+            LoopKind::Infinite => String::from("loop"),
+            LoopKind::While(text) | LoopKind::For(text) | LoopKind::DoWhile(text) => text.clone(),
+        },
+        Statement::Return(None) | Statement::Break | Statement::Continue => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    fn run(source: &str, inputs: &[&str]) -> Result<Trace, RunError> {
+        let program = parse(source).unwrap();
+        let inputs: Vec<Value> = inputs.iter().map(|input| Value::parse(input)).collect();
+        let name = program.procedures[0].name.clone();
+        Interpreter::new(&program).run(&name, &inputs)
+    }
+
+    fn variable(trace: &Trace, name: &str) -> Value {
+        trace.steps.last().unwrap().variables[name].clone()
+    }
+
+    #[test]
+    fn assignments_are_split_outside_strings() {
+        let trace = run(
+            "proc main\n    process s = \"x;y\"; t = 'a;b'\\nn = 1\nend\n",
+            &[],
+        )
+        .unwrap();
+        assert_eq!(variable(&trace, "s"), Value::Str(String::from("x;y")));
+        assert_eq!(variable(&trace, "t"), Value::Str(String::from("a;b")));
+        assert_eq!(variable(&trace, "n"), Value::Int(1));
+    }
+
+    #[test]
+    fn unclosed_string() {
+        let error = run("proc main\n    process s = \"x; y = 1\nend\n", &[])
+            .err()
+            .expect("the run fails");
+        assert_eq!(error.message, "a string is not closed");
+    }
+
+    #[test]
+    fn steps_name_their_statements() {
+        let source = "proc main
+    io read n
+    if n > 2
+        process a = 1
+    else
+        process a = 2
+    end
+    switch n
+    case 1
+        process b = 1
+    case 2, 3
+        process b = 2
+    end
+    for i = 1 to 2
+        process a += i
+    end
+    return a
+end
+";
+        let program = parse(source).unwrap();
+        // The indices do not depend on where the program is.
+        let program = Box::new(program);
+        let trace = Interpreter::new(&program)
+            .run("main", &[Value::Int(2)])
+            .unwrap();
+        let statements = program.procedures[0].statements();
+        let run: Vec<(&str, Option<&str>)> = trace
+            .steps
+            .iter()
+            .map(|step| {
+                assert_eq!(text(statements[step.statement]), step.text);
+                (step.text.as_str(), step.branch.as_deref())
+            })
+            .collect();
+        assert_eq!(
+            run,
+            [
+                ("read n", None),
+                ("n > 2", Some("no")),
+                ("a = 2", None),
+                ("n", Some("2, 3")),
+                ("b = 2", None),
+                ("i = 1 to 2", Some("next")),
+                ("a += i", None),
+                ("i = 1 to 2", Some("next")),
+                ("a += i", None),
+                ("i = 1 to 2", Some("done")),
+                ("return a", None),
+            ]
+        );
+        assert_eq!(trace.result, Some(Value::Int(5)));
+    }
+
+    #[test]
+    fn calls_share_variables() {
+        let source = "proc main\n    io read n\n    call double\n    io print \"n\", n\nend\n\nproc double\n    process n = n * 2\nend\n";
+        let trace = run(source, &["21"]).unwrap();
+        assert_eq!(trace.outputs, ["n 42"]);
+        assert_eq!(trace.steps[2].procedure, "double");
+    }
+
+    #[test]
+    fn endless_runs_stop() {
+        let program = parse("proc main\n    loop\n        process x = 1\n    end\nend\n").unwrap();
+        let error = Interpreter::new(&program)
+            .max_steps(50)
+            .run("main", &[])
+            .err()
+            .expect("the run fails");
+        assert!(error.message.starts_with("stopped after 50 steps"));
+    }
+
+    #[test]
+    fn errors_name_the_block() {
+        let error = run("proc main\n    process x = y + 1\nend\n", &[])
+            .err()
+            .expect("the run fails");
+        assert_eq!(error.text, "x = y + 1");
+        assert_eq!(error.span.unwrap().line, 2);
+    }
+}
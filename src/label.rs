@@ -0,0 +1,186 @@
+use crate::{
+    block::{get_num_columns_num_lines, Bounds},
+    config::Config,
+    svg::SvgShape,
+};
+
+/// Places edge labels next to their edges without covering blocks or labels
+/// that were placed before.
+pub struct LabelPlacer {
+    font_size: usize,
+    background: bool,
+    obstacles: Vec<Bounds>,
+}
+
+impl LabelPlacer {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            font_size: config.font_size(),
+            background: config.label_background(),
+            obstacles: vec![],
+        }
+    }
+
+    pub fn add_obstacle(&mut self, bounds: Bounds) {
+        self.obstacles.push(bounds);
+    }
+
+    /// The bottom right corner of everything placed so far, obstacles
+    /// included.
+    pub fn extent(&self) -> (usize, usize) {
+        self.obstacles.iter().fold((0, 0), |(x, y), bounds| {
+            (
+                x.max(bounds.x + bounds.width),
+                y.max(bounds.y + bounds.height),
+            )
+        })
+    }
+
+    /// Places `content` beside the segment from `from` to `to`.
+    ///
+    /// Candidates are tried at several points along the segment and at
+    /// increasing distances from it. If every candidate collides, the label
+    /// falls back to the first one, beside the middle of the segment, and
+    /// may then cover what is there: a label out of place reads better than
+    /// one far from its edge. Either way it becomes an obstacle for the
+    /// labels placed after it.
+    pub fn place(&mut self, from: (usize, usize), to: (usize, usize), content: &str) -> SvgShape {
+        let (width, height) = self.estimate_width_height(content);
+        let bounds = self
+            .candidates(from, to, width, height)
+            .find(|candidate| !self.obstacles.iter().any(|o| o.overlaps(candidate)))
+            .unwrap_or_else(|| self.candidates(from, to, width, height).next().unwrap());
+        self.obstacles.push(bounds);
+        self.to_svg(&bounds, content)
+    }
+
+    fn estimate_width_height(&self, content: &str) -> (usize, usize) {
+        let (num_columns, num_lines) = get_num_columns_num_lines(content);
+        (
+            self.font_size / 2 * num_columns + self.font_size / 2,
+            self.font_size * num_lines.max(1) + self.font_size / 4,
+        )
+    }
+
+    fn candidates(
+        &self,
+        (x1, y1): (usize, usize),
+        (x2, y2): (usize, usize),
+        width: usize,
+        height: usize,
+    ) -> impl Iterator<Item = Bounds> {
+        let vertical = x1.abs_diff(x2) <= y1.abs_diff(y2);
+        let gap = self.font_size / 2;
+        [(1, 2), (1, 3), (2, 3), (1, 4), (3, 4)]
+            .into_iter()
+            .flat_map(move |(num, den)| {
+                let x = (x1 * (den - num) + x2 * num) / den;
+                let y = (y1 * (den - num) + y2 * num) / den;
+                (1..=3).flat_map(move |k| {
+                    let offset = gap * k;
+                    if vertical {
+                        let y = y.saturating_sub(height / 2);
+                        [
+                            Some((x + offset, y)),
+                            x.checked_sub(offset + width).map(|x| (x, y)),
+                        ]
+                    } else {
+                        let x = x.saturating_sub(width / 2);
+                        [
+                            y.checked_sub(offset + height).map(|y| (x, y)),
+                            Some((x, y + offset)),
+                        ]
+                    }
+                })
+            })
+            .flatten()
+            .map(move |(x, y)| Bounds {
+                x,
+                y,
+                width,
+                height,
+            })
+    }
+
+    fn to_svg(&self, bounds: &Bounds, content: &str) -> SvgShape {
+        let mut items = vec![];
+        if self.background {
+            items.push(SvgShape::Background {
+                x: bounds.x,
+                y: bounds.y,
+                width: bounds.width,
+                height: bounds.height,
+            });
+        }
+        let cx = bounds.x + bounds.width / 2;
+        let top = bounds.y + (bounds.height - self.font_size * content.lines().count()) / 2;
+        items.extend(content.lines().zip(0..).map(|(line, i)| SvgShape::Text {
+            cx,
+            cy: top + i * self.font_size + self.font_size / 2,
+            content: String::from(line),
+        }));
+        SvgShape::Group(items)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigBuilder;
+
+    fn bounds(x: usize, y: usize, width: usize, height: usize) -> Bounds {
+        Bounds {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// The bounds of the label placed last.
+    fn placed(placer: &LabelPlacer) -> Bounds {
+        *placer.obstacles.last().unwrap()
+    }
+
+    #[test]
+    fn labels_go_beside_the_middle_of_their_edge() {
+        let config = ConfigBuilder::new().build();
+        let mut placer = LabelPlacer::new(&config);
+        placer.place((100, 0), (100, 200), "yes");
+        let label = placed(&placer);
+        assert!(label.x > 100);
+        assert!(label.y < 100 && 100 < label.y + label.height);
+
+        placer.place((0, 100), (200, 100), "no");
+        let label = placed(&placer);
+        assert!(label.y + label.height < 100);
+        assert!(label.x < 100 && 100 < label.x + label.width);
+    }
+
+    #[test]
+    fn labels_avoid_blocks_and_each_other() {
+        let config = ConfigBuilder::new().build();
+        let mut placer = LabelPlacer::new(&config);
+        let block = bounds(100, 80, 60, 40);
+        placer.add_obstacle(block);
+        placer.place((100, 0), (100, 200), "yes");
+        let first = placed(&placer);
+        assert!(!first.overlaps(&block));
+        placer.place((100, 0), (100, 200), "yes");
+        let second = placed(&placer);
+        assert!(!second.overlaps(&block));
+        assert!(!second.overlaps(&first));
+    }
+
+    #[test]
+    fn labels_fall_back_beside_the_middle() {
+        let config = ConfigBuilder::new().build();
+        let mut free = LabelPlacer::new(&config);
+        free.place((100, 0), (100, 200), "yes");
+        let mut crowded = LabelPlacer::new(&config);
+        crowded.add_obstacle(bounds(0, 0, 400, 400));
+        crowded.place((100, 0), (100, 200), "yes");
+        let (label, expected) = (placed(&crowded), placed(&free));
+        assert_eq!((label.x, label.y), (expected.x, expected.y));
+    }
+}
@@ -0,0 +1,741 @@
+//! Layered layout of arbitrary directed graphs, in the manner of Sugiyama.
+//!
+//! 1. Edges that close cycles are found by a depth-first search and laid out
+//!    as if they were turned around, so the rest of the graph flows down.
+//! 2. Every node is put one layer under the lowest of its predecessors.
+//!    Edges that span several layers get a dummy node in every layer between,
+//!    which keeps room for them so they pass beside the blocks. Edges going
+//!    back up also get dummies in the layers of their ends, next to them.
+//! 3. The order of every layer is improved by sweeping down and up, sorting
+//!    each layer by the barycenter of its neighbors in the layer before, and
+//!    the order with the fewest crossings is kept.
+//! 4. Nodes are pulled towards their neighbors and their coordinates are
+//!    aligned to the grid.
+//! 5. Edges run vertically through the layers and horizontally on tracks in
+//!    the channels between them: one track per source node for the edges
+//!    leaving it, one per target node for the edges entering it from afar,
+//!    and one per edge elsewhere. Edges going back up leave their source
+//!    from the side. Tracks that do not overlap are shared, and the tracks
+//!    into targets come under the others.
+//!
+//! Graphs are laid out from top to bottom and turned to flow in the
+//! direction of the config, as structured procedures are.
+
+use std::collections::HashMap;
+
+use crate::{
+    block::{Block, BlockBuilder},
+    cfg::Graph,
+    config::{Config, Direction},
+    structured::{Layout, Path, Point},
+};
+
+/// How many down and up sweeps the crossing reduction makes.
+const SWEEPS: usize = 12;
+
+/// How many times nodes are pulled towards their neighbors.
+const PULLS: usize = 4;
+
+/// A horizontal track in a channel, shared by the edges leaving a source or
+/// entering a target.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Track {
+    Out(usize),
+    In(usize),
+    Edge(usize),
+}
+
+/// A node of the layered graph: a block or a dummy holding room for an edge.
+struct Item {
+    layer: usize,
+    width: usize,
+    /// Neighbors in the layer above and in the layer below.
+    up: Vec<usize>,
+    down: Vec<usize>,
+    /// The node a dummy is kept next to, in the same layer.
+    anchor: Option<usize>,
+}
+
+pub struct LayeredLayouter {
+    block_builder: BlockBuilder,
+    direction: Direction,
+    distance: usize,
+    lane: usize,
+}
+
+impl LayeredLayouter {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            block_builder: BlockBuilder::new(config),
+            direction: config.direction(),
+            distance: config.distance(),
+            lane: config.grid_size(),
+        }
+    }
+
+    pub fn layout(&self, graph: &Graph) -> Layout {
+        let index: HashMap<&str, usize> = graph
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| (node.id.as_str(), i))
+            .collect();
+        let edges: Vec<(usize, usize)> = graph
+            .edges
+            .iter()
+            .map(|edge| (index[edge.from.as_str()], index[edge.to.as_str()]))
+            .collect();
+        let n = graph.nodes.len();
+        let back = back_edges(n, &edges);
+        let layers = layers(n, &edges, &back);
+        let num_layers = layers.iter().max().map_or(0, |max| max + 1);
+
+        let mut blocks: Vec<Block> = graph
+            .nodes
+            .iter()
+            .map(|node| {
+                let mut block = self.block_builder.build(node.kind, node.label.clone());
+                // Turned sideways, the blocks are laid out transposed.
+                if self.direction.is_sideways() {
+                    block.transpose();
+                }
+                if let Some(span) = &node.span {
+                    block.set_span(span.clone());
+                }
+                block
+            })
+            .collect();
+        let mut items: Vec<Item> = (0..n)
+            .map(|node| Item {
+                layer: layers[node],
+                width: blocks[node].width(),
+                up: vec![],
+                down: vec![],
+                anchor: None,
+            })
+            .collect();
+        let dummies: Vec<Vec<usize>> = edges
+            .iter()
+            .zip(&back)
+            .map(|(&(from, to), &back)| add_dummies(&mut items, &layers, from, to, back))
+            .collect();
+
+        let mut orders: Vec<Vec<usize>> = vec![vec![]; num_layers];
+        (0..items.len()).for_each(|item| orders[items[item].layer].push(item));
+        anchor_dummies(&items, &mut orders);
+        reduce_crossings(&items, &mut orders);
+        let centers = self.place(&items, &orders, n);
+
+        // Rows of blocks and the channels above them, with channel `k` above
+        // layer `k` and the last one under the last layer.
+        let mut channels: Vec<Vec<(Track, usize, usize)>> = vec![vec![]; num_layers + 1];
+        for (e, &(from, to)) in edges.iter().enumerate() {
+            for (channel, track, x1, x2) in segments(&items, &centers, &dummies[e], e, from, to) {
+                let (x1, x2) = (x1.min(x2), x1.max(x2));
+                match channels[channel].iter_mut().find(|(t, ..)| *t == track) {
+                    Some((_, start, end)) => {
+                        *start = (*start).min(x1);
+                        *end = (*end).max(x2);
+                    }
+                    None => channels[channel].push((track, x1, x2)),
+                }
+            }
+        }
+        let tracks: Vec<HashMap<Track, usize>> = channels
+            .iter()
+            .map(|segments| self.assign_tracks(segments))
+            .collect();
+        let channel_heights: Vec<usize> = tracks
+            .iter()
+            .enumerate()
+            .map(|(k, tracks)| {
+                let count = tracks.values().map(|j| j + 1).max().unwrap_or(0);
+                let height = (count + 1) * self.lane;
+                match count {
+                    0 if k == 0 || k == num_layers => 0,
+                    _ if k == 0 || k == num_layers => height,
+                    _ => height.max(self.distance),
+                }
+            })
+            .collect();
+        let mut channel_tops = vec![];
+        let mut y = 0;
+        for (k, order) in orders.iter().enumerate() {
+            channel_tops.push(y);
+            y += channel_heights[k];
+            let height = order
+                .iter()
+                .filter(|&&item| item < n)
+                .map(|&node| blocks[node].height())
+                .max()
+                .unwrap_or(0);
+            for &node in order.iter().filter(|&&item| item < n) {
+                let block = &mut blocks[node];
+                // Blocks of a layer share their middle line, as far as the
+                // grid allows.
+                let dy = (height - block.height()) / 2 / self.lane * self.lane;
+                block.displace(centers[node] - block.width() / 2, y + dy);
+            }
+            y += height;
+        }
+        channel_tops.push(y);
+
+        let track_y = |channel: usize, track: Track| match tracks[channel].get(&track) {
+            Some(j) => channel_tops[channel] + (j + 1) * self.lane,
+            None => channel_tops[channel] + channel_heights[channel] / 2,
+        };
+        let paths = edges
+            .iter()
+            .enumerate()
+            .map(|(e, &(from, to))| {
+                let mut points: Vec<Point> = vec![];
+                let mut x = match back[e] {
+                    true => {
+                        let (x, y) = blocks[from].right_pos();
+                        let dummy = centers[dummies[e][0]];
+                        points.extend([(x, y), (dummy, y)]);
+                        dummy
+                    }
+                    false => {
+                        points.push(blocks[from].bottom_pos());
+                        centers[from]
+                    }
+                };
+                for (channel, track, x1, x2) in segments(&items, &centers, &dummies[e], e, from, to)
+                {
+                    debug_assert_eq!(x, x1);
+                    let y = track_y(channel, track);
+                    points.push((x1, y));
+                    points.push((x2, y));
+                    x = x2;
+                }
+                points.push(blocks[to].top_pos());
+                match &graph.edges[e].label {
+                    Some(label) => Path::labeled(points, label, usize::from(!back[e])),
+                    None => Path::new(points),
+                }
+            })
+            .collect();
+        let mut layout = Layout::new(blocks, paths);
+        layout.orient(self.direction);
+        layout
+    }
+
+    /// The gap kept between two neighbors in a layer.
+    fn gap(&self, n: usize, a: usize, b: usize) -> usize {
+        match (a < n, b < n) {
+            (true, true) => self.distance,
+            _ => self.lane,
+        }
+    }
+
+    /// Assigns the x of the center of every item, with the left edges of
+    /// blocks and the dummies on the grid.
+    fn place(&self, items: &[Item], orders: &[Vec<usize>], n: usize) -> Vec<usize> {
+        let half = |item: usize| items[item].width as isize / 2;
+        let mut centers = vec![0isize; items.len()];
+        for order in orders {
+            let mut right = 0isize;
+            for (i, &item) in order.iter().enumerate() {
+                if i > 0 {
+                    right += self.gap(n, order[i - 1], item) as isize;
+                }
+                centers[item] = right + half(item);
+                right += items[item].width as isize;
+            }
+        }
+        for _ in 0..PULLS {
+            for order in orders.iter().skip(1) {
+                self.pull(items, order, &mut centers, n, |item| &items[item].up);
+            }
+            for order in orders.iter().rev().skip(1) {
+                self.pull(items, order, &mut centers, n, |item| &items[item].down);
+            }
+        }
+
+        // Align to the grid, keeping the gaps.
+        let left = orders
+            .iter()
+            .filter_map(|order| order.first())
+            .map(|&item| centers[item] - half(item))
+            .min()
+            .unwrap_or(0);
+        let grid = self.lane as isize;
+        let mut aligned = vec![0usize; items.len()];
+        for order in orders {
+            let mut min_left = 0isize;
+            for (i, &item) in order.iter().enumerate() {
+                if i > 0 {
+                    let previous = order[i - 1];
+                    min_left = aligned[previous] as isize
+                        + (items[previous].width as isize - half(previous))
+                        + self.gap(n, previous, item) as isize;
+                    min_left = (min_left + grid - 1).div_euclid(grid) * grid;
+                }
+                let x = centers[item] - half(item) - left;
+                let x = ((x + grid / 2).div_euclid(grid) * grid).max(min_left);
+                aligned[item] = (x + half(item)) as usize;
+            }
+        }
+        aligned
+    }
+
+    /// Moves the items of a layer towards the mean of their neighbors,
+    /// keeping their order and gaps.
+    fn pull<'a>(
+        &self,
+        items: &'a [Item],
+        order: &[usize],
+        centers: &mut [isize],
+        n: usize,
+        neighbors: impl Fn(usize) -> &'a Vec<usize>,
+    ) {
+        let desired: Vec<isize> = order
+            .iter()
+            .map(|&item| {
+                let neighbors: Vec<usize> = match items[item].anchor {
+                    Some(anchor) => vec![anchor],
+                    None => neighbors(item).clone(),
+                };
+                match neighbors.len() {
+                    0 => centers[item],
+                    len => {
+                        neighbors.iter().map(|&other| centers[other]).sum::<isize>() / len as isize
+                    }
+                }
+            })
+            .collect();
+        let min_distance = |i: usize| {
+            let (a, b) = (order[i - 1], order[i]);
+            (items[a].width / 2 + self.gap(n, a, b) + items[b].width.div_ceil(2)) as isize
+        };
+        let mut from_left = desired.clone();
+        for i in 1..order.len() {
+            from_left[i] = from_left[i].max(from_left[i - 1] + min_distance(i));
+        }
+        let mut from_right = desired;
+        for i in (1..order.len()).rev() {
+            from_right[i - 1] = from_right[i - 1].min(from_right[i] - min_distance(i));
+        }
+        for (i, &item) in order.iter().enumerate() {
+            centers[item] = (from_left[i] + from_right[i]) / 2;
+            if i > 0 {
+                centers[item] = centers[item].max(centers[order[i - 1]] + min_distance(i));
+            }
+        }
+    }
+
+    /// Puts the segments of a channel on tracks, sharing a track between
+    /// segments that keep a lane apart. Edges entering a target get the
+    /// lowest tracks, so they do not cross the edges leaving a source.
+    fn assign_tracks(&self, segments: &[(Track, usize, usize)]) -> HashMap<Track, usize> {
+        let mut sorted: Vec<&(Track, usize, usize)> = segments
+            .iter()
+            .filter(|(_, start, end)| start != end)
+            .collect();
+        sorted.sort_by_key(|(track, start, end)| (matches!(track, Track::In(_)), *start, *end));
+        let mut ends: Vec<usize> = vec![];
+        let mut first = None;
+        let mut assigned = HashMap::new();
+        for &(track, start, end) in sorted {
+            if matches!(track, Track::In(_)) && first.is_none() {
+                first = Some(ends.len());
+            }
+            let free = ends
+                .iter()
+                .enumerate()
+                .skip(first.unwrap_or(0))
+                .find(|(_, &last)| last + self.lane <= start);
+            let j = match free {
+                Some((j, _)) => j,
+                None => {
+                    ends.push(0);
+                    ends.len() - 1
+                }
+            };
+            ends[j] = end;
+            assigned.insert(track, j);
+        }
+        assigned
+    }
+}
+
+/// Adds the dummies of an edge to `items` and links its chain. Returns the
+/// dummies in the order the edge passes them.
+fn add_dummies(
+    items: &mut Vec<Item>,
+    layers: &[usize],
+    from: usize,
+    to: usize,
+    back: bool,
+) -> Vec<usize> {
+    let chain_layers: Vec<usize> = if back {
+        (layers[to]..=layers[from]).rev().collect()
+    } else {
+        (layers[from] + 1..layers[to]).collect()
+    };
+    let dummies: Vec<usize> = chain_layers
+        .iter()
+        .enumerate()
+        .map(|(i, &layer)| {
+            items.push(Item {
+                layer,
+                width: 0,
+                up: vec![],
+                down: vec![],
+                anchor: match (back, i) {
+                    (true, 0) => Some(from),
+                    (true, i) if i + 1 == chain_layers.len() => Some(to),
+                    _ => None,
+                },
+            });
+            items.len() - 1
+        })
+        .collect();
+    let chain: Vec<usize> = if back {
+        dummies.clone()
+    } else {
+        std::iter::once(from)
+            .chain(dummies.iter().copied())
+            .chain(std::iter::once(to))
+            .collect()
+    };
+    for pair in chain.windows(2) {
+        let (upper, lower) = match items[pair[0]].layer < items[pair[1]].layer {
+            true => (pair[0], pair[1]),
+            false => (pair[1], pair[0]),
+        };
+        items[upper].down.push(lower);
+        items[lower].up.push(upper);
+    }
+    dummies
+}
+
+/// The horizontal runs of an edge in the order it passes them, as the
+/// channel, the track, and the x where the run starts and ends.
+fn segments(
+    items: &[Item],
+    centers: &[usize],
+    dummies: &[usize],
+    e: usize,
+    from: usize,
+    to: usize,
+) -> Vec<(usize, Track, usize, usize)> {
+    let (from_layer, to_layer) = (items[from].layer, items[to].layer);
+    let mut segments = vec![];
+    if from_layer < to_layer {
+        let mut x = centers[from];
+        for (k, channel) in (from_layer + 1..=to_layer).enumerate() {
+            let next = dummies.get(k).map_or(centers[to], |&dummy| centers[dummy]);
+            let track = match channel {
+                _ if channel == from_layer + 1 => Track::Out(from),
+                _ if channel == to_layer => Track::In(to),
+                _ => Track::Edge(e),
+            };
+            segments.push((channel, track, x, next));
+            x = next;
+        }
+    } else {
+        // Out of the side of the source to the first dummy, up along the
+        // others, and down into the target from above.
+        for pair in dummies.windows(2) {
+            let channel = items[pair[0]].layer;
+            segments.push((channel, Track::Edge(e), centers[pair[0]], centers[pair[1]]));
+        }
+        let last = dummies[dummies.len() - 1];
+        segments.push((to_layer, Track::In(to), centers[last], centers[to]));
+    }
+    segments
+}
+
+/// Moves every dummy with an anchor right after it.
+fn anchor_dummies(items: &[Item], orders: &mut [Vec<usize>]) {
+    for order in orders {
+        let (mut anchored, mut rest): (Vec<usize>, Vec<usize>) = order
+            .iter()
+            .partition(|&&item| items[item].anchor.is_some());
+        // The dummies of edges leaving the anchor come closest to it, so the
+        // edges do not cross those coming back to it.
+        anchored.sort_by_key(|&dummy| !items[dummy].down.is_empty());
+        for dummy in anchored {
+            let anchor = items[dummy].anchor.unwrap();
+            let position = rest.iter().position(|&item| item == anchor).unwrap();
+            // After the anchor and the dummies already put after it.
+            let after = rest[position + 1..]
+                .iter()
+                .take_while(|&&item| items[item].anchor == Some(anchor))
+                .count();
+            rest.insert(position + 1 + after, dummy);
+        }
+        *order = rest;
+    }
+}
+
+/// Sweeps down and up the layers, sorting each by the barycenter of its
+/// neighbors in the layer before, and keeps the orders with the fewest
+/// crossings.
+fn reduce_crossings(items: &[Item], orders: &mut [Vec<usize>]) {
+    let mut positions = vec![0; items.len()];
+    let update = |positions: &mut Vec<usize>, order: &[usize]| {
+        order
+            .iter()
+            .enumerate()
+            .for_each(|(i, &item)| positions[item] = i);
+    };
+    orders
+        .iter()
+        .for_each(|order| update(&mut positions, order));
+    let mut best = orders.to_vec();
+    let mut fewest = crossings(items, orders, &positions);
+    for sweep in 0..2 * SWEEPS {
+        let down = sweep % 2 == 0;
+        let layers: Vec<usize> = match down {
+            true => (1..orders.len()).collect(),
+            false => (0..orders.len().saturating_sub(1)).rev().collect(),
+        };
+        for k in layers {
+            let keys: HashMap<usize, f64> = orders[k]
+                .iter()
+                .map(|&item| {
+                    let neighbors = match down {
+                        true => &items[item].up,
+                        false => &items[item].down,
+                    };
+                    let key = match neighbors.len() {
+                        0 => positions[item] as f64,
+                        len => {
+                            neighbors
+                                .iter()
+                                .map(|&other| positions[other])
+                                .sum::<usize>() as f64
+                                / len as f64
+                        }
+                    };
+                    (item, key)
+                })
+                .collect();
+            orders[k].sort_by(|a, b| keys[a].total_cmp(&keys[b]));
+            anchor_dummies(items, &mut orders[k..=k]);
+            update(&mut positions, &orders[k]);
+        }
+        let count = crossings(items, orders, &positions);
+        if count < fewest {
+            fewest = count;
+            best = orders.to_vec();
+        }
+    }
+    orders.clone_from_slice(&best);
+}
+
+/// Counts the pairs of links between neighboring layers that cross.
+fn crossings(items: &[Item], orders: &[Vec<usize>], positions: &[usize]) -> usize {
+    orders
+        .iter()
+        .map(|order| {
+            let links: Vec<(usize, usize)> = order
+                .iter()
+                .flat_map(|&upper| {
+                    items[upper]
+                        .down
+                        .iter()
+                        .map(move |&lower| (positions[upper], positions[lower]))
+                })
+                .collect();
+            let mut count = 0;
+            for (i, a) in links.iter().enumerate() {
+                for b in &links[i + 1..] {
+                    if (a.0 < b.0 && a.1 > b.1) || (a.0 > b.0 && a.1 < b.1) {
+                        count += 1;
+                    }
+                }
+            }
+            count
+        })
+        .sum()
+}
+
+/// Marks the edges that close a cycle, found by a depth-first search from
+/// the nodes without predecessors, in the order the nodes are given.
+fn back_edges(n: usize, edges: &[(usize, usize)]) -> Vec<bool> {
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    enum State {
+        New,
+        Open,
+        Done,
+    }
+    let mut outgoing: Vec<Vec<usize>> = vec![vec![]; n];
+    let mut has_predecessor = vec![false; n];
+    for (e, &(from, to)) in edges.iter().enumerate() {
+        outgoing[from].push(e);
+        if from != to {
+            has_predecessor[to] = true;
+        }
+    }
+    let mut back = vec![false; edges.len()];
+    let mut state = vec![State::New; n];
+    let roots = (0..n)
+        .filter(|&node| !has_predecessor[node])
+        .chain(0..n)
+        .collect::<Vec<_>>();
+    for root in roots {
+        if state[root] != State::New {
+            continue;
+        }
+        state[root] = State::Open;
+        // Every entry is a node and how many of its edges were followed.
+        let mut stack = vec![(root, 0)];
+        while let Some((node, next)) = stack.last_mut() {
+            let Some(&e) = outgoing[*node].get(*next) else {
+                state[*node] = State::Done;
+                stack.pop();
+                continue;
+            };
+            *next += 1;
+            let (_, to) = edges[e];
+            match state[to] {
+                State::New => {
+                    state[to] = State::Open;
+                    stack.push((to, 0));
+                }
+                State::Open => back[e] = true,
+                State::Done => {}
+            }
+        }
+    }
+    back
+}
+
+/// Puts every node one layer under the lowest of its predecessors, not
+/// counting the edges that close cycles.
+fn layers(n: usize, edges: &[(usize, usize)], back: &[bool]) -> Vec<usize> {
+    let mut outgoing: Vec<Vec<usize>> = vec![vec![]; n];
+    let mut indegree = vec![0; n];
+    for (&(from, to), &back) in edges.iter().zip(back) {
+        if !back {
+            outgoing[from].push(to);
+            indegree[to] += 1;
+        }
+    }
+    let mut layers = vec![0; n];
+    let mut ready: Vec<usize> = (0..n).rev().filter(|&node| indegree[node] == 0).collect();
+    while let Some(node) = ready.pop() {
+        for &to in &outgoing[node] {
+            layers[to] = layers[to].max(layers[node] + 1);
+            indegree[to] -= 1;
+            if indegree[to] == 0 {
+                ready.push(to);
+            }
+        }
+    }
+    layers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigBuilder;
+
+    /// A graph of process blocks named by their index.
+    fn graph(n: usize, edges: &[(usize, usize)]) -> Graph {
+        let nodes: Vec<String> = (0..n)
+            .map(|i| {
+                format!(
+                    r#"{{ "id": {}, "kind": "Process", "label": "node {}" }}"#,
+                    i, i
+                )
+            })
+            .collect();
+        let edges: Vec<String> = edges
+            .iter()
+            .map(|(from, to)| format!(r#"{{ "from": {}, "to": {} }}"#, from, to))
+            .collect();
+        let json = format!(
+            r#"{{ "nodes": [{}], "edges": [{}] }}"#,
+            nodes.join(", "),
+            edges.join(", ")
+        );
+        Graph::from_json(&json).unwrap()
+    }
+
+    #[test]
+    fn cycles_are_broken_at_their_back_edge() {
+        let edges = [(0, 1), (1, 2), (2, 1), (2, 3), (3, 3)];
+        assert_eq!(back_edges(4, &edges), [false, false, true, false, true]);
+        let back = back_edges(4, &edges);
+        assert_eq!(layers(4, &edges, &back), [0, 1, 2, 3]);
+        // Without a node free of predecessors, the search starts at the
+        // first node.
+        let edges = [(0, 1), (1, 0)];
+        assert_eq!(back_edges(2, &edges), [false, true]);
+    }
+
+    #[test]
+    fn nodes_go_under_their_lowest_predecessor() {
+        let edges = [(0, 1), (0, 2), (1, 3), (2, 3), (0, 3)];
+        let back = back_edges(4, &edges);
+        assert_eq!(layers(4, &edges, &back), [0, 1, 1, 2]);
+    }
+
+    #[test]
+    fn crossings_are_removed() {
+        let config = ConfigBuilder::new().build();
+        let layout = LayeredLayouter::new(&config).layout(&graph(4, &[(0, 3), (1, 2)]));
+        let x = |node: usize| layout.blocks()[node].pos().0;
+        assert_eq!(x(0) < x(1), x(3) < x(2));
+    }
+
+    #[test]
+    fn edges_run_between_their_blocks() {
+        let config = ConfigBuilder::new().build();
+        let edges = [(0, 1), (0, 2), (1, 3), (2, 3), (0, 3), (3, 0), (3, 1)];
+        let layout = LayeredLayouter::new(&config).layout(&graph(4, &edges));
+        let blocks = layout.blocks();
+        for (i, a) in blocks.iter().enumerate() {
+            for b in &blocks[i + 1..] {
+                assert!(!a.bounds().overlaps(&b.bounds()));
+            }
+        }
+        for &(from, to) in &edges {
+            let paths = layout.edges_between(&blocks[from], &blocks[to], None);
+            assert_eq!(paths.len(), 1, "edge {} -> {}", from, to);
+            for pair in paths[0].windows(2) {
+                assert!(pair[0].0 == pair[1].0 || pair[0].1 == pair[1].1);
+            }
+            if from < to {
+                assert!(blocks[from].bottom_pos().1 < blocks[to].top_pos().1);
+            }
+        }
+    }
+
+    #[test]
+    fn graphs_flow_in_the_direction_of_the_config() {
+        let edges = [(0, 1), (0, 2), (1, 3), (2, 3), (3, 0)];
+        let graph = graph(4, &edges);
+        for direction in [
+            Direction::TopToBottom,
+            Direction::BottomToTop,
+            Direction::LeftToRight,
+            Direction::RightToLeft,
+        ] {
+            let config = ConfigBuilder::new().direction(direction).build();
+            let layout = LayeredLayouter::new(&config).layout(&graph);
+            let blocks = layout.blocks();
+            for &(from, to) in &edges[..4] {
+                let (exit, entry) = (
+                    blocks[from].exit_pos(direction),
+                    blocks[to].entry_pos(direction),
+                );
+                let ahead = match direction {
+                    Direction::TopToBottom => exit.1 < entry.1,
+                    Direction::BottomToTop => exit.1 > entry.1,
+                    Direction::LeftToRight => exit.0 < entry.0,
+                    Direction::RightToLeft => exit.0 > entry.0,
+                };
+                assert!(ahead, "edge {} -> {}", from, to);
+                let path = &layout.edges_between(&blocks[from], &blocks[to], None)[0];
+                assert_eq!((path[0], path[path.len() - 1]), (exit, entry));
+            }
+            assert!(blocks.iter().all(|block| block.width() > block.height()));
+        }
+    }
+}
@@ -1,4 +1,27 @@
+pub mod animation;
 pub mod basic_block;
+pub mod batch;
 pub mod block;
+pub mod build;
+pub mod cfg;
+pub mod chart;
 pub mod config;
+pub mod expression;
+pub mod frontend;
+pub mod html;
+pub mod interpreter;
+pub mod label;
+pub mod layered;
+pub mod lsp;
+pub mod markdown;
+pub mod mdbook;
+pub mod parser;
+pub mod preview;
+pub mod structured;
+pub mod structuring;
 pub mod svg;
+pub mod swimlane;
+#[cfg(test)]
+mod temp_dir;
+pub mod watch;
+mod xml;
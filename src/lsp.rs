@@ -0,0 +1,607 @@
+//! A language server for the chart source language.
+//!
+//! The server reports the errors of the parser as diagnostics, and calls to
+//! procedures that are not defined as warnings. It completes statements and,
+//! after `call`, the names of procedures, goes to the definition of the
+//! procedure called on a line, and lists the procedures as document symbols.
+//!
+//! Besides the standard requests, it answers `flowchart/render`, whose params
+//! name the `textDocument` and optionally one `procedure`, with the `charts`
+//! rendered from the current text of the document, each with the `name` of
+//! its procedure and its `svg`.
+
+use std::{collections::HashMap, error::Error};
+
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::{
+    notification::{
+        DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _,
+        PublishDiagnostics,
+    },
+    request::{Completion, DocumentSymbolRequest, GotoDefinition, Request as _},
+    CompletionItem, CompletionItemKind, CompletionOptions, CompletionParams, CompletionResponse,
+    Diagnostic, DiagnosticSeverity, DocumentSymbol, DocumentSymbolParams, DocumentSymbolResponse,
+    GotoDefinitionParams, GotoDefinitionResponse, Location, OneOf, Position,
+    PublishDiagnosticsParams, Range, ServerCapabilities, SymbolKind, TextDocumentIdentifier,
+    TextDocumentSyncCapability, TextDocumentSyncKind, Uri,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{block::Span, chart::Statement, config::Config, parser};
+
+/// The statements of the language, with what they make.
+const STATEMENTS: &[(&str, &str)] = &[
+    ("proc", "procedure"),
+    ("io", "input/output block"),
+    ("process", "process block"),
+    ("call", "subroutine block calling a procedure"),
+    ("if", "decision"),
+    ("else", "branch taken when the decision is false"),
+    ("switch", "decision with one branch per case"),
+    ("case", "branch of a switch"),
+    ("while", "loop checking its condition first"),
+    ("for", "loop over the items of its header"),
+    ("loop", "loop until a break"),
+    ("break", "leaves the loop"),
+    ("continue", "goes on with the next iteration"),
+    ("return", "leaves the procedure"),
+    ("end", "ends a procedure, decision or loop"),
+];
+
+/// The custom request for the charts of a document.
+pub enum Render {}
+
+impl lsp_types::request::Request for Render {
+    type Params = RenderParams;
+    type Result = RenderResult;
+    const METHOD: &'static str = "flowchart/render";
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RenderParams {
+    pub text_document: TextDocumentIdentifier,
+    /// The procedure to render, or every procedure if not given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub procedure: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct RenderResult {
+    pub charts: Vec<RenderedChart>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct RenderedChart {
+    pub name: String,
+    pub svg: String,
+}
+
+pub struct Server {
+    config: Config,
+    /// The text of every open document.
+    documents: HashMap<Uri, String>,
+}
+
+impl Server {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            documents: HashMap::new(),
+        }
+    }
+
+    /// Answers the client on `connection` until it shuts the server down.
+    pub fn run(mut self, connection: &Connection) -> Result<(), Box<dyn Error + Send + Sync>> {
+        connection.initialize(serde_json::to_value(capabilities())?)?;
+        for message in &connection.receiver {
+            match message {
+                Message::Request(request) => {
+                    if connection.handle_shutdown(&request)? {
+                        return Ok(());
+                    }
+                    connection
+                        .sender
+                        .send(self.handle_request(request).into())?;
+                }
+                Message::Notification(notification) => {
+                    if let Some(reply) = self.handle_notification(notification) {
+                        connection.sender.send(reply.into())?;
+                    }
+                }
+                Message::Response(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_request(&self, request: Request) -> Response {
+        match request.method.as_str() {
+            Completion::METHOD => handle::<Completion>(request, |params| Ok(self.complete(params))),
+            GotoDefinition::METHOD => {
+                handle::<GotoDefinition>(request, |params| Ok(self.definition(params)))
+            }
+            DocumentSymbolRequest::METHOD => {
+                handle::<DocumentSymbolRequest>(request, |params| Ok(self.symbols(params)))
+            }
+            Render::METHOD => handle::<Render>(request, |params| self.render(params)),
+            method => Response::new_err(
+                request.id,
+                ErrorCode::MethodNotFound as i32,
+                format!("unknown request `{}`", method),
+            ),
+        }
+    }
+
+    /// Keeps track of the open documents, and returns their diagnostics
+    /// when they change.
+    fn handle_notification(&mut self, notification: Notification) -> Option<Notification> {
+        let (uri, diagnostics) = match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params = extract::<DidOpenTextDocument>(notification)?;
+                let document = params.text_document;
+                let diagnostics = diagnostics(&document.text);
+                self.documents.insert(document.uri.clone(), document.text);
+                (document.uri, diagnostics)
+            }
+            DidChangeTextDocument::METHOD => {
+                let params = extract::<DidChangeTextDocument>(notification)?;
+                // The whole text is sent on every change.
+                let text = params.content_changes.into_iter().last()?.text;
+                let diagnostics = diagnostics(&text);
+                let uri = params.text_document.uri;
+                self.documents.insert(uri.clone(), text);
+                (uri, diagnostics)
+            }
+            DidCloseTextDocument::METHOD => {
+                let params = extract::<DidCloseTextDocument>(notification)?;
+                self.documents.remove(&params.text_document.uri);
+                (params.text_document.uri, vec![])
+            }
+            _ => return None,
+        };
+        let params = PublishDiagnosticsParams {
+            uri,
+            diagnostics,
+            version: None,
+        };
+        Some(Notification::new(
+            String::from(PublishDiagnostics::METHOD),
+            params,
+        ))
+    }
+
+    /// Statements at the beginning of a line, and procedures after `call`.
+    fn complete(&self, params: CompletionParams) -> Option<CompletionResponse> {
+        let position = params.text_document_position;
+        let text = self.documents.get(&position.text_document.uri)?;
+        let (line, column) = line_column(text, position.position);
+        let before: String = text
+            .lines()
+            .nth(line - 1)
+            .unwrap_or_default()
+            .chars()
+            .take(column - 1)
+            .collect();
+        let words: Vec<&str> = before.split_whitespace().collect();
+        let after_word = before.ends_with(char::is_whitespace);
+        let items = match (words.as_slice(), after_word) {
+            ([], _) | ([_], false) => STATEMENTS
+                .iter()
+                .map(|(keyword, detail)| CompletionItem {
+                    label: String::from(*keyword),
+                    kind: Some(CompletionItemKind::KEYWORD),
+                    detail: Some(String::from(*detail)),
+                    ..Default::default()
+                })
+                .collect(),
+            (["call"], true) | (["call", _], false) => parser::definitions(text)
+                .into_iter()
+                .filter(|definition| !definition.name.is_empty())
+                .map(|definition| CompletionItem {
+                    label: String::from(definition.name),
+                    kind: Some(CompletionItemKind::FUNCTION),
+                    detail: Some(String::from("procedure")),
+                    ..Default::default()
+                })
+                .collect(),
+            _ => return None,
+        };
+        Some(CompletionResponse::Array(items))
+    }
+
+    /// The definition of the procedure called on the line of the cursor.
+    fn definition(&self, params: GotoDefinitionParams) -> Option<GotoDefinitionResponse> {
+        let position = params.text_document_position_params;
+        let uri = position.text_document.uri;
+        let text = self.documents.get(&uri)?;
+        let (line, _) = line_column(text, position.position);
+        let (name, _) = parser::call_on_line(text, line)?;
+        let definition = parser::definitions(text)
+            .into_iter()
+            .find(|definition| definition.name == name)?;
+        let range = range(text, &definition.name_span);
+        Some(GotoDefinitionResponse::Scalar(Location::new(uri, range)))
+    }
+
+    fn symbols(&self, params: DocumentSymbolParams) -> Option<DocumentSymbolResponse> {
+        let text = self.documents.get(&params.text_document.uri)?;
+        let symbols = parser::definitions(text)
+            .into_iter()
+            .filter(|definition| !definition.name.is_empty())
+            .map(|definition| {
+                #[allow(deprecated)]
+                DocumentSymbol {
+                    name: String::from(definition.name),
+                    detail: None,
+                    kind: SymbolKind::FUNCTION,
+                    tags: None,
+                    deprecated: None,
+                    range: range(text, &definition.span),
+                    selection_range: range(text, &definition.name_span),
+                    children: None,
+                }
+            })
+            .collect();
+        Some(DocumentSymbolResponse::Nested(symbols))
+    }
+
+    fn render(&self, params: RenderParams) -> Result<RenderResult, String> {
+        let uri = params.text_document.uri;
+        let text = self
+            .documents
+            .get(&uri)
+            .ok_or_else(|| format!("`{}` is not open", uri.as_str()))?;
+        let program = parser::parse(text).map_err(|e| e.to_string())?;
+        let procedures: Vec<_> = match &params.procedure {
+            Some(name) => vec![program
+                .procedure(name)
+                .ok_or_else(|| format!("there is no procedure `{}`", name))?],
+            None => program.procedures.iter().collect(),
+        };
+        let charts = procedures
+            .into_iter()
+            .map(|procedure| RenderedChart {
+                name: procedure.name.clone(),
+                svg: procedure.to_svg(&self.config, &|_| None).to_string(),
+            })
+            .collect();
+        Ok(RenderResult { charts })
+    }
+}
+
+fn capabilities() -> ServerCapabilities {
+    ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        completion_provider: Some(CompletionOptions {
+            trigger_characters: Some(vec![String::from(" ")]),
+            ..Default::default()
+        }),
+        definition_provider: Some(OneOf::Left(true)),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        ..Default::default()
+    }
+}
+
+/// Answers `request` with what `handler` returns for its params.
+fn handle<R: lsp_types::request::Request>(
+    request: Request,
+    handler: impl FnOnce(R::Params) -> Result<R::Result, String>,
+) -> Response {
+    let id = request.id.clone();
+    match request.extract::<R::Params>(R::METHOD) {
+        Ok((id, params)) => match handler(params) {
+            Ok(result) => Response::new_ok(id, result),
+            Err(message) => Response::new_err(id, ErrorCode::RequestFailed as i32, message),
+        },
+        Err(error) => Response::new_err(id, ErrorCode::InvalidParams as i32, error.to_string()),
+    }
+}
+
+fn extract<N: lsp_types::notification::Notification>(
+    notification: Notification,
+) -> Option<N::Params> {
+    notification.extract(N::METHOD).ok()
+}
+
+/// The error of the parser, or a warning for every call to a procedure that
+/// is not defined.
+fn diagnostics(text: &str) -> Vec<Diagnostic> {
+    let diagnostic = |range, severity, message| Diagnostic {
+        range,
+        severity: Some(severity),
+        source: Some(String::from("flowchart")),
+        message,
+        ..Default::default()
+    };
+    let program = match parser::parse(text) {
+        Ok(program) => program,
+        Err(error) => {
+            let end_column = text
+                .lines()
+                .nth(error.line - 1)
+                .map_or(1, |line| line.chars().count() + 1)
+                .max(error.column);
+            let span = Span::new(error.line, error.column, error.line, end_column);
+            return vec![diagnostic(
+                range(text, &span),
+                DiagnosticSeverity::ERROR,
+                error.message,
+            )];
+        }
+    };
+    let mut diagnostics = vec![];
+    for procedure in &program.procedures {
+        for (index, statement) in procedure.statements().into_iter().enumerate() {
+            let (Statement::Call(name), Some(span)) = (statement, procedure.span(index)) else {
+                continue;
+            };
+            if program.procedure(name).is_none() {
+                diagnostics.push(diagnostic(
+                    range(text, span),
+                    DiagnosticSeverity::WARNING,
+                    format!("there is no procedure `{}`", name),
+                ));
+            }
+        }
+    }
+    diagnostics
+}
+
+/// The position of a line and column counted from 1 in characters, as LSP
+/// counts them: from 0, in UTF-16 code units.
+fn position(text: &str, line: usize, column: usize) -> Position {
+    let character: usize = text
+        .lines()
+        .nth(line - 1)
+        .unwrap_or_default()
+        .chars()
+        .take(column - 1)
+        .map(char::len_utf16)
+        .sum();
+    Position::new((line - 1) as u32, character as u32)
+}
+
+fn range(text: &str, span: &Span) -> Range {
+    Range::new(
+        position(text, span.line, span.column),
+        position(text, span.end_line, span.end_column),
+    )
+}
+
+/// The line and column counted from 1 in characters of an LSP position.
+fn line_column(text: &str, position: Position) -> (usize, usize) {
+    let line = text.lines().nth(position.line as usize).unwrap_or_default();
+    let mut units = 0;
+    let column = line
+        .chars()
+        .take_while(|c| {
+            units += c.len_utf16();
+            units <= position.character as usize
+        })
+        .count();
+    (position.line as usize + 1, column + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::config::ConfigBuilder;
+
+    const URI: &str = "file:///main.flow";
+    const SOURCE: &str =
+        "proc main\n  io read\n  call helper\n  call missing\nend\n\nproc helper\nend\n";
+
+    /// A server with `text` open, and the diagnostics published on opening it.
+    fn open(text: &str) -> (Server, Value) {
+        let mut server = Server::new(ConfigBuilder::new().build());
+        let params = json!({
+            "textDocument": { "uri": URI, "languageId": "flowchart", "version": 1, "text": text }
+        });
+        let notification = Notification::new(String::from(DidOpenTextDocument::METHOD), params);
+        let published = server.handle_notification(notification).unwrap();
+        (server, published.params)
+    }
+
+    /// The result of `method`, or panics with its error.
+    fn request(server: &Server, method: &str, params: Value) -> Value {
+        let response = server.handle_request(Request::new(1.into(), String::from(method), params));
+        if let Some(error) = response.error {
+            panic!("{}", error.message);
+        }
+        response.result.unwrap()
+    }
+
+    fn at(line: u32, character: u32) -> Value {
+        json!({
+            "textDocument": { "uri": URI },
+            "position": { "line": line, "character": character }
+        })
+    }
+
+    fn labels(completion: &Value) -> Vec<&str> {
+        completion
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["label"].as_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn positions_count_utf16_units() {
+        let text = "proc a\n  io \u{1f600}é x\nend\n";
+        assert_eq!(position(text, 2, 6), Position::new(1, 5));
+        assert_eq!(position(text, 2, 7), Position::new(1, 7));
+        assert_eq!(position(text, 2, 8), Position::new(1, 8));
+        for column in 1..=10 {
+            assert_eq!(line_column(text, position(text, 2, column)), (2, column));
+        }
+        // A position inside a surrogate pair is the character it splits.
+        assert_eq!(line_column(text, Position::new(1, 6)), (2, 6));
+    }
+
+    #[test]
+    fn diagnostics_of_errors_and_missing_procedures() {
+        let (_, published) = open(SOURCE);
+        assert_eq!(published["uri"], URI);
+        let warnings = published["diagnostics"].as_array().unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0]["message"], "there is no procedure `missing`");
+        assert_eq!(warnings[0]["severity"], 2);
+        assert_eq!(
+            warnings[0]["range"],
+            json!({
+                "start": { "line": 3, "character": 2 },
+                "end": { "line": 3, "character": 14 }
+            })
+        );
+
+        // A parse error covers the rest of its line.
+        let errors = diagnostics("proc a\n  é jump\nend\n");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "unknown statement `é`");
+        assert_eq!(errors[0].severity, Some(DiagnosticSeverity::ERROR));
+        assert_eq!(
+            errors[0].range,
+            Range::new(Position::new(1, 2), Position::new(1, 8))
+        );
+    }
+
+    #[test]
+    fn changes_and_closing_publish_diagnostics() {
+        let (mut server, _) = open(SOURCE);
+        let params = json!({
+            "textDocument": { "uri": URI, "version": 2 },
+            "contentChanges": [{ "text": "proc main\n  io x\n" }]
+        });
+        let notification = Notification::new(String::from(DidChangeTextDocument::METHOD), params);
+        let published = server.handle_notification(notification).unwrap();
+        assert_eq!(
+            published.params["diagnostics"][0]["message"],
+            "missing `end`"
+        );
+
+        let params = json!({ "textDocument": { "uri": URI } });
+        let notification = Notification::new(String::from(DidCloseTextDocument::METHOD), params);
+        let published = server.handle_notification(notification).unwrap();
+        assert_eq!(published.params["diagnostics"], json!([]));
+        assert!(server.documents.is_empty());
+    }
+
+    #[test]
+    fn completion() {
+        let (server, _) = open(SOURCE);
+        // At the beginning of a line, and in its first word.
+        let statements = request(&server, Completion::METHOD, at(1, 2));
+        assert_eq!(labels(&statements).len(), STATEMENTS.len());
+        assert_eq!(labels(&statements)[0], "proc");
+        assert_eq!(
+            labels(&request(&server, Completion::METHOD, at(1, 3))),
+            labels(&statements)
+        );
+        // After `call`, and in the name that follows it.
+        let procedures = request(&server, Completion::METHOD, at(2, 7));
+        assert_eq!(labels(&procedures), ["main", "helper"]);
+        assert_eq!(procedures[0]["detail"], "procedure");
+        assert_eq!(
+            labels(&request(&server, Completion::METHOD, at(2, 9))),
+            ["main", "helper"]
+        );
+        // Nothing after the text of other statements.
+        assert_eq!(request(&server, Completion::METHOD, at(1, 7)), Value::Null);
+    }
+
+    #[test]
+    fn definition_and_symbols() {
+        let (server, _) = open(SOURCE);
+        let location = request(&server, GotoDefinition::METHOD, at(2, 4));
+        assert_eq!(
+            location,
+            json!({
+                "uri": URI,
+                "range": {
+                    "start": { "line": 6, "character": 5 },
+                    "end": { "line": 6, "character": 11 }
+                }
+            })
+        );
+        assert_eq!(
+            request(&server, GotoDefinition::METHOD, at(3, 4)),
+            Value::Null
+        );
+        assert_eq!(
+            request(&server, GotoDefinition::METHOD, at(1, 4)),
+            Value::Null
+        );
+
+        let params = json!({ "textDocument": { "uri": URI } });
+        let symbols = request(&server, DocumentSymbolRequest::METHOD, params);
+        let symbols = symbols.as_array().unwrap();
+        assert_eq!(symbols.len(), 2);
+        assert_eq!(symbols[0]["name"], "main");
+        assert_eq!(
+            symbols[0]["range"]["end"],
+            json!({ "line": 4, "character": 3 })
+        );
+        assert_eq!(symbols[1]["name"], "helper");
+        assert_eq!(
+            symbols[1]["selectionRange"]["start"],
+            json!({ "line": 6, "character": 5 })
+        );
+    }
+
+    #[test]
+    fn render_and_errors() {
+        let (server, _) = open(SOURCE);
+        let params = json!({ "textDocument": { "uri": URI }, "procedure": "helper" });
+        let result = request(&server, Render::METHOD, params);
+        let charts = result["charts"].as_array().unwrap();
+        assert_eq!(charts.len(), 1);
+        assert_eq!(charts[0]["name"], "helper");
+        assert!(charts[0]["svg"].as_str().unwrap().contains("<svg"));
+        let params = json!({ "textDocument": { "uri": URI } });
+        let result = request(&server, Render::METHOD, params);
+        assert_eq!(result["charts"].as_array().unwrap().len(), 2);
+
+        let error = |method: &str, params| {
+            let response =
+                server.handle_request(Request::new(1.into(), String::from(method), params));
+            let error = response.error.unwrap();
+            (error.code, error.message)
+        };
+        assert_eq!(
+            error(
+                Render::METHOD,
+                json!({ "textDocument": { "uri": URI }, "procedure": "other" })
+            ),
+            (
+                ErrorCode::RequestFailed as i32,
+                String::from("there is no procedure `other`")
+            )
+        );
+        assert_eq!(
+            error(
+                Render::METHOD,
+                json!({ "textDocument": { "uri": "file:///other" } })
+            ),
+            (
+                ErrorCode::RequestFailed as i32,
+                String::from("`file:///other` is not open")
+            )
+        );
+        assert_eq!(
+            error(Render::METHOD, json!({})).0,
+            ErrorCode::InvalidParams as i32
+        );
+        assert_eq!(
+            error("textDocument/hover", json!({})),
+            (
+                ErrorCode::MethodNotFound as i32,
+                String::from("unknown request `textDocument/hover`")
+            )
+        );
+    }
+}
@@ -1,25 +1,560 @@
+use std::{
+    collections::HashMap,
+    fs,
+    hash::{DefaultHasher, Hash, Hasher},
+    io::{self, Write},
+    net::TcpListener,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
 use flowchart::{
-    basic_block::BasicBlock,
-    block::{BlockBuilder, BlockKind},
-    config::ConfigBuilder,
-    svg::{Svg, SvgShape},
+    animation::Animator,
+    batch::{Batch, Format},
+    chart::file_stem,
+    config::{Config, ConfigBuilder, Settings},
+    expression::Value,
+    frontend::{self, Input},
+    html,
+    interpreter::{Interpreter, Trace},
+    markdown::{self, Preprocessor},
+    preview::PreviewServer,
+    svg::{Svg, SvgWriter},
+    watch::Watcher,
 };
 
-fn main() {
-    let config = ConfigBuilder::new().font_size(20).build();
-    let mut svg = Svg::new(&config);
-    svg.push_shape(SvgShape::Grid {
-        size: config.grid_size(),
-        x_count: 20,
-        y_count: 10,
-    });
-    let builder = BlockBuilder::new(&config);
-    let mut bb = BasicBlock::new(&config, &builder, BlockKind::IO, String::from("input"));
-    bb.push(BlockKind::Process, String::from("01234567890123456789"));
-    bb.push(BlockKind::Process, String::from("ABCDEFGHIJABCDEFGHIJ"));
-    bb.push(BlockKind::Process, String::from("中文中文中文中文中文"));
-    bb.push(BlockKind::Decision, String::from("i < 0"));
-    bb.displace(config.grid_size(), config.grid_size());
-    svg.push_shape(bb.to_svg());
-    print!("{}", svg);
+const USAGE: &str = "usage: flowchart [--html | --viewer] [--layered] [--watch] [--config <file>]
+                 [-o <output>] <input>...
+       flowchart serve [--port <port>] [--layered] [--config <file>] <dir>
+       flowchart batch [--format <format>,...] [--jobs <n>] [--layered]
+                 [--config <file>] -o <output> <dir>
+       flowchart markdown [--images <prefix>] [--layered] [--config <file>]
+                 [-o <output>] <input>
+       flowchart markdown --restore [-o <output>] <input>
+       flowchart --run <procedure> [--input <value>]... [--trace] [--animate]
+                 [--config <file>] [-o <output>] <input>
+
+Renders every procedure of each <input> to <output>/<name>.svg, where
+<output> defaults to the current directory. Inputs ending in `.c`, `.py` or
+`.rs` are read as C, Python or Rust source, with one procedure per function.
+An input ending in `.json` is a control-flow graph, rendered to
+<output>/<input>.svg with its loops and branches recovered, or in layers as it
+is with --layered or if its flow is not structured. Inputs ending in `.s`,
+`.asm` or `.dis` are assembly listings or `objdump -d` output, with every
+function rendered in layers to <output>/<function>.svg.
+With --viewer, writes every chart to <name>.html instead, a page that pans,
+zooms and shows the source of the block clicked.
+With --html, renders all procedures of one <input> into one page written to
+<output>, or to stdout if it is not given.
+With --config, reads the settings of the charts from a JSON <file> whose keys
+are named after them, such as `font_size` or `direction`.
+With --watch, keeps running and renders each <input> again whenever it or the
+config file changes, writing only the charts that changed; errors are
+reported without stopping.
+With `serve`, serves the charts of the sources under <dir> on localhost, at
+port 8000 unless --port is given, rendering them on request; open pages
+reload when a source or the config file changes. Charts of the chart source
+language are looked for in files ending in `.flow`.
+With `batch`, renders the sources under <dir> into the same tree under
+<output>, in parallel on --jobs threads; the charts of <dir>/a/b.flow go to
+<output>/a/b/<name>.svg with the `svg` format, the default, to
+<output>/a/b/<name>.html with `viewer`, and all to <output>/a/b.html with
+`html`. Sources that did not change since the last batch into <output>, with
+the same options and config file, are skipped.
+With `markdown`, renders every code block tagged `flowchart` of the Markdown
+<input> into <output>, or to stdout if it is not given, keeping the block in
+an HTML comment; a second word in the tag, as in `flowchart c`, reads the
+block as a source with that extension. The charts are inlined as `<svg>`
+elements, or with --images linked to as images written to
+<prefix><block>-<name>.svg, a path from the directory of <output>. With
+--restore, turns the charts of a document rendered so back into their code
+blocks.
+With --run, runs <procedure> of <input> instead, which must be in the chart
+source language, reading each --input value in order, and prints its outputs
+and result; with --trace, also prints every block run and the variables
+after it; with --animate, also renders the run of <procedure> to
+<output>/<procedure>.svg, an animation of the blocks it visits.";
+
+struct Args {
+    html: bool,
+    viewer: bool,
+    layered: bool,
+    watch: bool,
+    serve: bool,
+    port: u16,
+    batch: bool,
+    formats: Vec<Format>,
+    jobs: Option<usize>,
+    markdown: bool,
+    images: Option<String>,
+    restore: bool,
+    config: Option<PathBuf>,
+    output: Option<PathBuf>,
+    run: Option<String>,
+    inputs: Vec<Value>,
+    trace: bool,
+    animate: bool,
+    files: Vec<PathBuf>,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut html = false;
+    let mut viewer = false;
+    let mut layered = false;
+    let mut watch = false;
+    let mut port = 8000;
+    let mut formats = vec![];
+    let mut jobs = None;
+    let mut images = None;
+    let mut restore = false;
+    let mut config = None;
+    let mut output = None;
+    let mut run = None;
+    let mut inputs = vec![];
+    let mut trace = false;
+    let mut animate = false;
+    let mut files = vec![];
+    let mut args = std::env::args().skip(1).peekable();
+    let serve = args.next_if_eq("serve").is_some();
+    let batch = !serve && args.next_if_eq("batch").is_some();
+    let markdown = !serve && !batch && args.next_if_eq("markdown").is_some();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--html" => html = true,
+            "--viewer" => viewer = true,
+            "--layered" => layered = true,
+            "--watch" => watch = true,
+            "--port" => match args.next().and_then(|port| port.parse().ok()) {
+                Some(number) => port = number,
+                None => return Err(String::from("`--port` needs a port number")),
+            },
+            "--format" => match args.next() {
+                Some(names) => {
+                    for name in names.split(',') {
+                        formats.push(name.parse()?);
+                    }
+                }
+                None => return Err(String::from("`--format` needs a format")),
+            },
+            "--jobs" => match args.next().and_then(|jobs| jobs.parse().ok()) {
+                Some(number) if number > 0 => jobs = Some(number),
+                _ => return Err(String::from("`--jobs` needs a number of threads")),
+            },
+            "--images" => match args.next() {
+                Some(prefix) => images = Some(prefix),
+                None => return Err(String::from("`--images` needs a path prefix")),
+            },
+            "--restore" => restore = true,
+            "--config" => match args.next() {
+                Some(path) => config = Some(PathBuf::from(path)),
+                None => return Err(String::from("`--config` needs a path")),
+            },
+            "-o" => match args.next() {
+                Some(path) => output = Some(PathBuf::from(path)),
+                None => return Err(String::from("`-o` needs a path")),
+            },
+            "--run" => match args.next() {
+                Some(name) => run = Some(name),
+                None => return Err(String::from("`--run` needs a procedure")),
+            },
+            "--input" => match args.next() {
+                Some(value) => inputs.push(Value::parse(&value)),
+                None => return Err(String::from("`--input` needs a value")),
+            },
+            "--trace" => trace = true,
+            "--animate" => animate = true,
+            "-h" | "--help" => return Err(String::from(USAGE)),
+            _ if arg.starts_with('-') => return Err(format!("unknown option `{}`", arg)),
+            _ => files.push(PathBuf::from(arg)),
+        }
+    }
+    match files.len() {
+        0 => Err(String::from(USAGE)),
+        2.. if serve => Err(String::from("`serve` takes one directory")),
+        _ if serve && (html || viewer || watch || run.is_some()) => Err(String::from(
+            "`serve` only goes with `--port`, `--layered` and `--config`",
+        )),
+        2.. if batch => Err(String::from("`batch` takes one directory")),
+        _ if batch && (html || viewer || watch || run.is_some()) => Err(String::from(
+            "`batch` only goes with `--format`, `--jobs`, `--layered`, `--config` and `-o`",
+        )),
+        _ if batch && output.is_none() => Err(String::from("`batch` needs `-o`")),
+        _ if !batch && (!formats.is_empty() || jobs.is_some()) => {
+            Err(String::from("`--format` and `--jobs` only go with `batch`"))
+        }
+        2.. if markdown => Err(String::from("`markdown` takes one input")),
+        _ if markdown && (html || viewer || watch || run.is_some()) => Err(String::from(
+            "`markdown` only goes with `--images`, `--restore`, `--layered`, `--config` and `-o`",
+        )),
+        _ if !markdown && (images.is_some() || restore) => Err(String::from(
+            "`--images` and `--restore` only go with `markdown`",
+        )),
+        _ if restore && images.is_some() => {
+            Err(String::from("`--restore` does not go with `--images`"))
+        }
+        _ if html && viewer => Err(String::from("`--html` and `--viewer` do not go together")),
+        _ if watch && run.is_some() => Err(String::from("`--watch` does not go with `--run`")),
+        _ if watch && html && output.is_none() => {
+            Err(String::from("`--watch` needs `-o` with `--html`"))
+        }
+        2.. if html || run.is_some() => {
+            Err(String::from("only one input goes with `--html` or `--run`"))
+        }
+        _ if run.is_some() && !frontend::is_chart_source(&files[0]) => Err(format!(
+            "`--run` only runs the chart source language, not `{}`",
+            files[0].display()
+        )),
+        _ => Ok(Args {
+            html,
+            viewer,
+            layered,
+            watch,
+            serve,
+            port,
+            batch,
+            formats,
+            jobs,
+            markdown,
+            images,
+            restore,
+            config,
+            output,
+            run,
+            inputs,
+            trace,
+            animate,
+            files,
+        }),
+    }
+}
+
+fn run(args: Args) -> Result<(), String> {
+    if args.serve {
+        return serve(&args);
+    }
+    if args.batch {
+        return batch(&args);
+    }
+    if args.markdown {
+        return preprocess(&args);
+    }
+    if args.watch {
+        return watch(&args);
+    }
+    let config = load_config(args.config.as_deref())?;
+    if let Some(name) = &args.run {
+        let input = &args.files[0];
+        let Input::Program(program) =
+            frontend::read(input, &read(input)?).map_err(|e| e.to_string())?
+        else {
+            return Err(String::from("`--run` only takes procedures"));
+        };
+        let trace = Interpreter::new(&program)
+            .run(name, &args.inputs)
+            .map_err(|e| e.to_string())?;
+        print_trace(&trace, args.trace);
+        if args.animate {
+            let procedure = program.procedure(name).unwrap();
+            let path = args
+                .output
+                .unwrap_or_else(|| PathBuf::from("."))
+                .join(format!("{}.svg", file_stem(name)));
+            let svg = Animator::new(&config).animate(procedure, &trace);
+            write_svg(&path, &svg)?;
+        }
+        return Ok(());
+    }
+    for input in &args.files {
+        for (path, rendered) in render(&args, input, &config)? {
+            rendered.write(&path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Renders each input whenever it or the config file changes, until
+/// interrupted. Only the files whose content changed are written again.
+fn watch(args: &Args) -> Result<(), String> {
+    let mut paths = args.files.clone();
+    paths.extend(args.config.clone());
+    let mut watcher = Watcher::new(&paths);
+    let mut written: HashMap<PathBuf, u64> = HashMap::new();
+    let mut config = None;
+    let mut changed = paths;
+    loop {
+        let config_changed = args
+            .config
+            .as_ref()
+            .is_some_and(|path| changed.contains(path));
+        if config.is_none() || config_changed {
+            match load_config(args.config.as_deref()) {
+                Ok(new) => {
+                    config = Some(new);
+                    changed = args.files.clone();
+                }
+                Err(message) => report(&message),
+            }
+        }
+        if let Some(config) = &config {
+            for input in args.files.iter().filter(|input| changed.contains(input)) {
+                let files = match render(args, input, config) {
+                    Ok(files) => files,
+                    Err(message) => {
+                        report(&message);
+                        continue;
+                    }
+                };
+                for (path, rendered) in files {
+                    let content = rendered.to_bytes();
+                    let mut hasher = DefaultHasher::new();
+                    content.hash(&mut hasher);
+                    let hash = hasher.finish();
+                    if written.get(&path) == Some(&hash) {
+                        continue;
+                    }
+                    match write(&path, &content) {
+                        Ok(()) => {
+                            eprintln!("wrote {}", path.display());
+                            written.insert(path, hash);
+                        }
+                        Err(message) => report(&message),
+                    }
+                }
+            }
+        }
+        changed = watcher.wait();
+    }
+}
+
+/// Serves the charts of the sources under the directory on localhost.
+fn serve(args: &Args) -> Result<(), String> {
+    let dir = &args.files[0];
+    if !dir.is_dir() {
+        return Err(format!("{}: not a directory", dir.display()));
+    }
+    let listener = TcpListener::bind(("127.0.0.1", args.port))
+        .map_err(|e| format!("port {}: {}", args.port, e))?;
+    let mut server = PreviewServer::new(dir).layered(args.layered);
+    if let Some(config) = &args.config {
+        load_config(Some(config))?;
+        server = server.config_file(config);
+    }
+    eprintln!(
+        "serving {} at http://127.0.0.1:{}/",
+        dir.display(),
+        args.port
+    );
+    server.serve(listener).map_err(|e| e.to_string())
+}
+
+/// Renders the sources under the directory that changed since the last
+/// batch into the output directory.
+fn batch(args: &Args) -> Result<(), String> {
+    let dir = &args.files[0];
+    if !dir.is_dir() {
+        return Err(format!("{}: not a directory", dir.display()));
+    }
+    let config = load_config(args.config.as_deref())?;
+    let mut batch = Batch::new(&config).layered(args.layered);
+    if !args.formats.is_empty() {
+        batch = batch.formats(&args.formats);
+    }
+    if let Some(jobs) = args.jobs {
+        batch = batch.jobs(jobs);
+    }
+    if let Some(path) = &args.config {
+        batch = batch.config_key(&read(path)?).exclude(path);
+    }
+    let output = args.output.as_deref().unwrap();
+    let report = batch
+        .run(dir, output)
+        .map_err(|e| format!("{}: {}", output.display(), e))?;
+    for (_, message) in &report.failed {
+        self::report(message);
+    }
+    eprintln!(
+        "{} rendered, {} up to date, {} failed, {} removed",
+        report.rendered.len(),
+        report.skipped.len(),
+        report.failed.len(),
+        report.removed.len()
+    );
+    match report.failed.len() {
+        0 => Ok(()),
+        n => Err(format!(
+            "{} of {} sources failed",
+            n,
+            n + report.rendered.len() + report.skipped.len()
+        )),
+    }
+}
+
+/// Renders the charts of a Markdown document, or turns them back into code
+/// blocks.
+fn preprocess(args: &Args) -> Result<(), String> {
+    let input = &args.files[0];
+    let output = args.output.clone().unwrap_or_default();
+    if args.restore {
+        return write(&output, markdown::restore(&read(input)?));
+    }
+    let config = load_config(args.config.as_deref())?;
+    let mut preprocessor = Preprocessor::new(&config).layered(args.layered);
+    if let Some(prefix) = &args.images {
+        preprocessor = preprocessor.images(prefix);
+    }
+    let document = preprocessor
+        .process(input, &read(input)?)
+        .map_err(|e| e.to_string())?;
+    let dir = output.parent().unwrap_or(Path::new(""));
+    for (href, svg) in &document.images {
+        let path = dir.join(href);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("{}: {}", parent.display(), e))?;
+        }
+        write_svg(&path, svg)?;
+    }
+    write(&output, &document.markdown)
+}
+
+fn load_config(path: Option<&Path>) -> Result<Config, String> {
+    let builder = ConfigBuilder::new();
+    let Some(path) = path else {
+        return Ok(builder.build());
+    };
+    let settings =
+        Settings::from_json(&read(path)?).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(settings.apply(builder).build())
+}
+
+fn read(path: &Path) -> Result<String, String> {
+    fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Writes `content` to `path`, or to stdout if the path is empty.
+fn write(path: &Path, content: impl AsRef<[u8]>) -> Result<(), String> {
+    let result = match path.as_os_str().is_empty() {
+        true => io::stdout().write_all(content.as_ref()),
+        false => fs::write(path, content),
+    };
+    result.map_err(|e| write_error(path, e))
+}
+
+/// Streams `svg` to `path`, or to stdout if the path is empty.
+fn write_svg(path: &Path, svg: &Svg) -> Result<(), String> {
+    let result = match path.as_os_str().is_empty() {
+        true => SvgWriter::new(io::stdout().lock()).write(svg).map(drop),
+        false => fs::File::create(path).and_then(|file| SvgWriter::new(file).write(svg).map(drop)),
+    };
+    result.map_err(|e| write_error(path, e))
+}
+
+fn write_error(path: &Path, error: io::Error) -> String {
+    match path.as_os_str().is_empty() {
+        true => format!("stdout: {}", error),
+        false => format!("{}: {}", path.display(), error),
+    }
+}
+
+fn report(message: &str) {
+    eprintln!("flowchart: {}", message);
+}
+
+/// A file rendered from an input.
+enum Rendered {
+    Svg(Svg),
+    /// An HTML page.
+    Page(String),
+}
+
+impl Rendered {
+    fn write(&self, path: &Path) -> Result<(), String> {
+        match self {
+            Rendered::Svg(svg) => write_svg(path, svg),
+            Rendered::Page(page) => write(path, page),
+        }
+    }
+
+    /// The content of the file, for telling whether it changed.
+    fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Rendered::Svg(svg) => SvgWriter::new(vec![])
+                .write(svg)
+                .expect("writing to memory does not fail"),
+            Rendered::Page(page) => page.clone().into_bytes(),
+        }
+    }
+}
+
+/// The files rendered from `input`, with the paths to write them to.
+fn render(args: &Args, input: &Path, config: &Config) -> Result<Vec<(PathBuf, Rendered)>, String> {
+    let source = read(input)?;
+    let input = frontend::read(input, &source).map_err(|e| e.to_string())?;
+    if args.html {
+        let Input::Program(program) = &input else {
+            return Err(String::from("`--html` only takes procedures"));
+        };
+        let title = args.files[0]
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let page = html::render_program(config, program, &title);
+        return Ok(vec![(
+            args.output.clone().unwrap_or_default(),
+            Rendered::Page(page),
+        )]);
+    }
+    let dir = args.output.clone().unwrap_or_else(|| PathBuf::from("."));
+    let extension = if args.viewer { "html" } else { "svg" };
+    let href = |stem: &str| format!("{}.{}", stem, extension);
+    Ok(input
+        .to_svgs(config, args.layered, &href)
+        .into_iter()
+        .map(|(stem, svg)| {
+            // A viewer page also shows the source the chart came from.
+            let rendered = match args.viewer {
+                true => Rendered::Page(html::render_viewer(&svg, &stem, Some(&source))),
+                false => Rendered::Svg(svg),
+            };
+            (dir.join(href(&stem)), rendered)
+        })
+        .collect())
+}
+
+fn print_trace(trace: &Trace, steps: bool) {
+    if steps {
+        for step in &trace.steps {
+            let variables: Vec<String> = step
+                .variables
+                .iter()
+                .map(|(name, value)| format!("{} = {}", name, value))
+                .collect();
+            match &step.branch {
+                Some(branch) => println!("{}: {} -> {}", step.procedure, step.text, branch),
+                None => println!("{}: {}", step.procedure, step.text),
+            }
+            if let Some(output) = &step.output {
+                println!("    > {}", output);
+            }
+            if !variables.is_empty() {
+                println!("    {}", variables.join(", "));
+            }
+        }
+    } else {
+        for output in &trace.outputs {
+            println!("{}", output);
+        }
+    }
+    if let Some(result) = &trace.result {
+        println!("{}", result);
+    }
+}
+
+fn main() -> ExitCode {
+    match parse_args().and_then(run) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            report(&message);
+            ExitCode::FAILURE
+        }
+    }
 }
@@ -0,0 +1,487 @@
+//! Rendering the charts written in Markdown documents.
+//!
+//! Every fenced code block tagged `flowchart` is replaced by its charts,
+//! inline as `<svg>` elements or as links to images:
+//!
+//! ````markdown
+//! ```flowchart
+//! proc main
+//!     io read n
+//! end
+//! ```
+//! ````
+//!
+//! A second word in the tag, as in `flowchart c`, reads the block as a
+//! source with that extension, such as C source code or a control-flow graph
+//! in JSON. The block is kept in an HTML comment before its charts, from
+//! which [`restore`] brings it back:
+//!
+//! ```markdown
+//! <!-- flowchart
+//! proc main
+//!     io read n
+//! end
+//! -->
+//! <div id="flowchart-1-main"><svg ...>...</svg></div>
+//! <!-- /flowchart -->
+//! ```
+
+use std::{fmt::Write, path::Path};
+
+use crate::{
+    config::Config,
+    frontend::{self, Input, ReadError},
+    svg::Svg,
+};
+
+/// The tag of the code blocks holding charts.
+const TAG: &str = "flowchart";
+
+/// The line that ends the charts of a block.
+const END: &str = "<!-- /flowchart -->";
+
+/// A Markdown document with its charts rendered.
+pub struct Document {
+    pub markdown: String,
+    /// The images the document links to, by the paths it links to them with.
+    pub images: Vec<(String, Svg)>,
+}
+
+pub struct Preprocessor<'a> {
+    config: &'a Config,
+    layered: bool,
+    images: Option<String>,
+}
+
+/// A fenced code block, as it opens.
+struct Fence<'a> {
+    indent: usize,
+    marker: char,
+    length: usize,
+    info: &'a str,
+}
+
+impl<'a> Fence<'a> {
+    /// The fence that opens a code block on `line`, if one does.
+    fn open(line: &'a str) -> Option<Self> {
+        let trimmed = line.trim_start_matches(' ');
+        let indent = line.len() - trimmed.len();
+        let marker = trimmed.chars().next().filter(|c| matches!(c, '`' | '~'))?;
+        let length = trimmed.len() - trimmed.trim_start_matches(marker).len();
+        let info = trimmed[length..].trim();
+        // A backtick fence's info string may not hold backticks.
+        if indent > 3 || length < 3 || (marker == '`' && info.contains('`')) {
+            return None;
+        }
+        Some(Self {
+            indent,
+            marker,
+            length,
+            info,
+        })
+    }
+
+    fn closes(&self, line: &str) -> bool {
+        let trimmed = line.trim_start_matches(' ');
+        let rest = trimmed.trim_start_matches(self.marker);
+        line.len() - trimmed.len() <= 3
+            && trimmed.len() - rest.len() >= self.length
+            && rest.trim().is_empty()
+    }
+
+    /// A line of the block, without as much indentation as the fence had.
+    fn content<'b>(&self, line: &'b str) -> &'b str {
+        let spaces = line.len() - line.trim_start_matches(' ').len();
+        &line[spaces.min(self.indent)..]
+    }
+}
+
+impl<'a> Preprocessor<'a> {
+    pub fn new(config: &'a Config) -> Self {
+        Self {
+            config,
+            layered: false,
+            images: None,
+        }
+    }
+
+    /// Draws graphs in layers, as they are.
+    pub fn layered(mut self, layered: bool) -> Self {
+        self.layered = layered;
+        self
+    }
+
+    /// Links to every chart as an image whose path begins with `prefix`,
+    /// instead of inlining it.
+    pub fn images(mut self, prefix: &str) -> Self {
+        self.images = Some(String::from(prefix));
+        self
+    }
+
+    /// Renders the charts of the document at `path`, failing on the first
+    /// block that cannot be read. Errors and spans have the lines of the
+    /// document.
+    pub fn process(&self, path: &Path, markdown: &str) -> Result<Document, ReadError> {
+        let file = path.display().to_string();
+        let mut document = Document {
+            markdown: String::new(),
+            images: vec![],
+        };
+        let mut lines = markdown.lines().zip(1..);
+        let mut blocks = 0;
+        while let Some((line, number)) = lines.next() {
+            let Some(fence) = Fence::open(line) else {
+                writeln!(document.markdown, "{}", line).unwrap();
+                continue;
+            };
+            // The lines of the block with its fences, and without.
+            let mut block = vec![line];
+            let mut body = vec![];
+            for (line, _) in lines.by_ref() {
+                block.push(line);
+                if fence.closes(line) {
+                    break;
+                }
+                body.push(fence.content(line));
+            }
+            let mut words = fence.info.split_whitespace();
+            if words.next() != Some(TAG) {
+                for line in block {
+                    writeln!(document.markdown, "{}", line).unwrap();
+                }
+                continue;
+            }
+            blocks += 1;
+            let language = words.next();
+            let source: String = body.iter().map(|line| format!("{}\n", line)).collect();
+            let charts = self.render(&file, number, blocks, language, &source)?;
+            let indent = " ".repeat(fence.indent);
+            let mut out = String::new();
+            match language {
+                Some(language) => writeln!(out, "<!-- {} {}", TAG, language).unwrap(),
+                None => writeln!(out, "<!-- {}", TAG).unwrap(),
+            }
+            out.push_str(&escape_comment(&source));
+            writeln!(out, "-->").unwrap();
+            for (index, (stem, svg)) in charts.into_iter().enumerate() {
+                let name = format!("{}-{}", blocks, stem);
+                match &self.images {
+                    Some(prefix) => {
+                        let href = format!("{}{}.svg", prefix, name);
+                        writeln!(out, "![{}]({})", stem, href.replace(' ', "%20")).unwrap();
+                        document.images.push((href, svg));
+                    }
+                    None => {
+                        let id = format!("{}-chart-{}-{}", TAG, blocks, index + 1);
+                        let svg = svg.inline().id(&id);
+                        write!(out, r#"<div id="{}-{}">{}</div>"#, TAG, name, svg).unwrap();
+                        out.push('\n');
+                    }
+                }
+            }
+            writeln!(out, "{}", END).unwrap();
+            for line in out.lines() {
+                match line.is_empty() {
+                    true => document.markdown.push('\n'),
+                    false => writeln!(document.markdown, "{}{}", indent, line).unwrap(),
+                }
+            }
+        }
+        if !markdown.ends_with('\n') {
+            document.markdown.pop();
+        }
+        Ok(document)
+    }
+
+    /// Renders the `block`th block, whose fence is on line `fence` of
+    /// `file`, read as a source with the extension `language`.
+    fn render(
+        &self,
+        file: &str,
+        fence: usize,
+        block: usize,
+        language: Option<&str>,
+        source: &str,
+    ) -> Result<Vec<(String, Svg)>, ReadError> {
+        let path = format!("{}.{}", TAG, language.unwrap_or("flow"));
+        let mut input = frontend::read(Path::new(&path), source).map_err(|error| match error {
+            ReadError::Parse { mut error, .. } => {
+                error.line += fence;
+                ReadError::Parse {
+                    file: String::from(file),
+                    error,
+                }
+            }
+            ReadError::Graph { error, .. } => ReadError::Graph {
+                file: String::from(file),
+                error,
+            },
+        })?;
+        // The spans are moved to where the block is in the document, except
+        // those of a graph that name a file of their own.
+        let mut spans = vec![];
+        match &mut input {
+            Input::Program(program) => {
+                for procedure in &mut program.procedures {
+                    spans.extend(procedure.span.iter_mut());
+                    spans.extend(procedure.spans.iter_mut().flatten());
+                }
+            }
+            Input::Graph(_, graph) => {
+                spans.extend(graph.nodes.iter_mut().flat_map(|n| &mut n.span))
+            }
+            Input::Listing(functions) => {
+                for (_, graph) in functions {
+                    spans.extend(graph.nodes.iter_mut().flat_map(|n| &mut n.span));
+                }
+            }
+        }
+        for span in spans
+            .into_iter()
+            .filter(|span| span.file.as_deref() == Some(&path))
+        {
+            span.file = Some(String::from(file));
+            span.line += fence;
+            span.end_line += fence;
+        }
+        // Images link to the images next to them, and inline charts to the
+        // elements around the others.
+        let href = |stem: &str| match &self.images {
+            Some(prefix) => {
+                let name = prefix.rsplit('/').next().unwrap_or_default();
+                format!("{}{}-{}.svg", name, block, stem)
+            }
+            None => format!("#{}-{}-{}", TAG, block, stem),
+        };
+        Ok(input.to_svgs(self.config, self.layered, &href))
+    }
+}
+
+/// Turns the charts of a document back into the code blocks they were
+/// rendered from, fenced with backticks.
+pub fn restore(markdown: &str) -> String {
+    let mut restored = String::new();
+    let mut lines = markdown.lines();
+    while let Some(line) = lines.next() {
+        let trimmed = line.trim_start_matches(' ');
+        let indent = &line[..line.len() - trimmed.len()];
+        let tag = trimmed
+            .strip_prefix("<!-- ")
+            .and_then(|rest| rest.strip_prefix(TAG))
+            .filter(|rest| rest.is_empty() || rest.starts_with(' '));
+        let Some(tag) = tag else {
+            restored.push_str(line);
+            restored.push('\n');
+            continue;
+        };
+        let mut source = String::new();
+        for line in lines.by_ref() {
+            let line = line.strip_prefix(indent).unwrap_or(line);
+            if line == "-->" {
+                break;
+            }
+            source.push_str(line);
+            source.push('\n');
+        }
+        // The charts go.
+        for line in lines.by_ref() {
+            if line.trim() == END {
+                break;
+            }
+        }
+        let source = unescape_comment(&source);
+        // The fence is longer than any run of backticks in the block.
+        let longest = source
+            .lines()
+            .map(|line| line.trim_start().len() - line.trim_start().trim_start_matches('`').len())
+            .max()
+            .unwrap_or(0);
+        let fence = "`".repeat(longest.max(2) + 1);
+        writeln!(restored, "{}{}{}{}", indent, fence, TAG, tag).unwrap();
+        for line in source.lines() {
+            match line.is_empty() {
+                true => restored.push('\n'),
+                false => writeln!(restored, "{}{}", indent, line).unwrap(),
+            }
+        }
+        writeln!(restored, "{}{}", indent, fence).unwrap();
+    }
+    if !markdown.ends_with('\n') {
+        restored.pop();
+    }
+    restored
+}
+
+/// Escapes text so that it can stand in an HTML comment, which may not hold
+/// `--`: every dash after a dash becomes `&#45;`, and an ampersand that
+/// would begin an escape becomes `&amp;`.
+fn escape_comment(text: &str) -> String {
+    let mut escaped = String::new();
+    for (i, c) in text.char_indices() {
+        match c {
+            '-' if escaped.ends_with('-') => escaped.push_str("&#45;"),
+            '&' if text[i + 1..].starts_with("#45;") || text[i + 1..].starts_with("amp;") => {
+                escaped.push_str("&amp;")
+            }
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape_comment(text: &str) -> String {
+    let mut unescaped = String::new();
+    let mut rest = text;
+    while let Some(i) = rest.find('&') {
+        unescaped.push_str(&rest[..i]);
+        rest = &rest[i..];
+        let (c, length) = match rest {
+            _ if rest.starts_with("&#45;") => ('-', 5),
+            _ if rest.starts_with("&amp;") => ('&', 5),
+            _ => ('&', 1),
+        };
+        unescaped.push(c);
+        rest = &rest[length..];
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigBuilder;
+
+    const DOCUMENT: &str = "# Title
+
+```flowchart
+proc main
+    io read n
+
+    call helper
+end
+
+proc helper
+end
+```
+
+```rust
+fn main() {}
+```
+
+- A list item:
+
+  ````flowchart c
+  int f(int a) { while (a--) g(); }
+  /*
+  ```
+  */
+  ````
+";
+
+    fn process(preprocessor: &Preprocessor, markdown: &str) -> Document {
+        preprocessor
+            .process(Path::new("doc.md"), markdown)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    #[test]
+    fn comments_escape_and_unescape() {
+        for text in ["a--b", "---->", "&#45;", "&amp;#45;", "a & b -", "&"] {
+            let escaped = escape_comment(text);
+            assert!(!escaped.contains("--"), "{}", escaped);
+            assert_eq!(unescape_comment(&escaped), text);
+        }
+        assert_eq!(escape_comment("a---b"), "a-&#45;-b");
+        assert_eq!(escape_comment("&#45;"), "&amp;#45;");
+    }
+
+    #[test]
+    fn inline_charts_restore_to_their_blocks() {
+        let config = ConfigBuilder::new().build();
+        let document = process(&Preprocessor::new(&config), DOCUMENT);
+        let markdown = &document.markdown;
+        assert!(document.images.is_empty());
+        assert!(markdown.contains("```rust\nfn main() {}\n```\n"));
+        assert!(markdown.contains("<!-- flowchart\nproc main\n"));
+        assert!(markdown.contains(r#"<div id="flowchart-1-main"><svg id="flowchart-chart-1-1""#));
+        assert!(markdown.contains(r#"<div id="flowchart-1-helper"><svg id="flowchart-chart-1-2""#));
+        assert!(markdown.contains("  <!-- flowchart c\n  int f(int a) { while (a-&#45;)"));
+        assert!(markdown.contains(r#"  <div id="flowchart-2-f"><svg id="flowchart-chart-2-1""#));
+        assert!(markdown.ends_with("  <!-- /flowchart -->\n"));
+        // The call links to the chart of the procedure it calls.
+        assert!(markdown.contains("#flowchart-1-helper"));
+
+        assert_eq!(restore(markdown), DOCUMENT);
+        let unterminated = DOCUMENT.trim_end();
+        assert_eq!(
+            restore(&process(&Preprocessor::new(&config), unterminated).markdown),
+            unterminated
+        );
+    }
+
+    #[test]
+    fn images() {
+        let config = ConfigBuilder::new().build();
+        let preprocessor = Preprocessor::new(&config).images("charts/doc ");
+        let document = process(&preprocessor, DOCUMENT);
+        assert!(document
+            .markdown
+            .contains("![main](charts/doc%201-main.svg)\n![helper](charts/doc%201-helper.svg)\n"));
+        assert!(document.markdown.contains("  ![f](charts/doc%202-f.svg)\n"));
+        let hrefs: Vec<&str> = document
+            .images
+            .iter()
+            .map(|(href, _)| href.as_str())
+            .collect();
+        assert_eq!(
+            hrefs,
+            [
+                "charts/doc 1-main.svg",
+                "charts/doc 1-helper.svg",
+                "charts/doc 2-f.svg"
+            ]
+        );
+        assert_eq!(restore(&document.markdown), DOCUMENT);
+    }
+
+    #[test]
+    fn errors_have_the_lines_of_the_document() {
+        let config = ConfigBuilder::new().build();
+        let error = Preprocessor::new(&config)
+            .process(
+                Path::new("doc.md"),
+                "text\n\n```flowchart\nproc a\n  jump\nend\n```\n",
+            )
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(), "doc.md:5:3: unknown statement `jump`");
+    }
+
+    #[test]
+    fn graphs_keep_the_files_of_their_spans() {
+        let config = ConfigBuilder::new().build();
+        let markdown = r#"```flowchart json
+{ "nodes": [
+  { "id": 0, "kind": "Process", "label": "x",
+    "span": { "file": "src/main.c", "line": 3, "column": 5, "end_line": 3, "end_column": 9 } },
+  { "id": 1, "kind": "Process", "label": "y",
+    "span": { "line": 4, "column": 1, "end_line": 4, "end_column": 2 } }
+], "edges": [{ "from": 0, "to": 1 }] }
+```
+"#;
+        let document = process(&Preprocessor::new(&config), markdown);
+        assert!(document
+            .markdown
+            .contains(r#"data-file="src/main.c" data-line="3""#));
+        assert!(document
+            .markdown
+            .contains(r#"data-file="doc.md" data-line="5""#));
+    }
+
+    #[test]
+    fn restore_keeps_other_comments() {
+        let markdown = "<!-- flowcharts -->\n<!-- a comment -->\n";
+        assert_eq!(restore(markdown), markdown);
+    }
+}
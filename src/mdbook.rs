@@ -0,0 +1,219 @@
+//! A preprocessor of [mdBook](https://rust-lang.github.io/mdBook/) that
+//! renders the `flowchart` code blocks of every chapter, as the
+//! [`markdown`](crate::markdown) preprocessor does.
+//!
+//! Its settings are those of [`Settings`], in the preprocessor's table of
+//! `book.toml`, with `layered` to draw graphs in layers:
+//!
+//! ```toml
+//! [preprocessor.flowchart]
+//! font-size = 14
+//! direction = "LeftToRight"
+//! ```
+
+use std::{fmt, path::Path};
+
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::{
+    config::{ConfigBuilder, Settings},
+    frontend::ReadError,
+    markdown::Preprocessor,
+};
+
+/// The name of the preprocessor, and of its table in `book.toml`.
+pub const NAME: &str = "flowchart";
+
+/// The keys of the preprocessor's table that are read by mdBook.
+const MDBOOK_KEYS: &[&str] = &["command", "renderers", "before", "after", "optional"];
+
+#[derive(Debug)]
+pub enum MdBookError {
+    /// The input is not the context and book mdBook sends.
+    Input(String),
+    /// The preprocessor's table has settings that are not known, or of the
+    /// wrong type.
+    Settings(serde_json::Error),
+    /// A chart of a chapter cannot be read.
+    Chapter(ReadError),
+}
+
+impl fmt::Display for MdBookError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MdBookError::Input(message) => write!(f, "bad input from mdBook: {}", message),
+            MdBookError::Settings(e) => write!(f, "[preprocessor.{}]: {}", NAME, e),
+            MdBookError::Chapter(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for MdBookError {}
+
+/// Whether the charts can be rendered for `renderer`, as they are inlined
+/// as SVG into HTML.
+pub fn supports(renderer: &str) -> bool {
+    renderer == "html"
+}
+
+/// Renders the charts of the book sent by mdBook as JSON, and returns the
+/// book to send back.
+pub fn preprocess(input: &str) -> Result<String, MdBookError> {
+    let input: Value =
+        serde_json::from_str(input).map_err(|e| MdBookError::Input(e.to_string()))?;
+    let [context, mut book] = <[Value; 2]>::deserialize(input)
+        .map_err(|_| MdBookError::Input(String::from("expected a context and a book")))?;
+
+    let mut table = match &context["config"]["preprocessor"][NAME] {
+        Value::Object(table) => table.clone(),
+        _ => Map::new(),
+    };
+    for key in MDBOOK_KEYS {
+        table.remove(*key);
+    }
+    let layered = match table.remove("layered") {
+        Some(Value::Bool(layered)) => layered,
+        None => false,
+        Some(_) => {
+            let e = serde::de::Error::custom("`layered` must be a boolean");
+            return Err(MdBookError::Settings(e));
+        }
+    };
+    let settings = Settings::deserialize(Value::Object(table)).map_err(MdBookError::Settings)?;
+    let config = settings.apply(ConfigBuilder::new()).build();
+    let preprocessor = Preprocessor::new(&config).layered(layered);
+
+    // The items are `sections` in the books of older versions of mdBook.
+    let key = match book.get("items") {
+        Some(_) => "items",
+        None => "sections",
+    };
+    let Some(Value::Array(items)) = book.get_mut(key) else {
+        return Err(MdBookError::Input(String::from("the book has no items")));
+    };
+    process_items(&preprocessor, items)?;
+    Ok(book.to_string())
+}
+
+/// Renders the charts of every chapter among `items` and their sub-items.
+fn process_items(preprocessor: &Preprocessor, items: &mut [Value]) -> Result<(), MdBookError> {
+    for item in items {
+        let Some(Value::Object(chapter)) = item.get_mut("Chapter") else {
+            continue;
+        };
+        // Draft chapters have no file, nor content.
+        if let (Some(Value::String(path)), Some(Value::String(content))) =
+            (chapter.get("path"), chapter.get("content"))
+        {
+            let document = preprocessor
+                .process(Path::new(path), content)
+                .map_err(MdBookError::Chapter)?;
+            chapter.insert(String::from("content"), Value::String(document.markdown));
+        }
+        if let Some(Value::Array(sub_items)) = chapter.get_mut("sub_items") {
+            process_items(preprocessor, sub_items)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    const CHAPTER: &str = "# Chapter\n\n```flowchart\nproc main\n  io read n\nend\n```\n";
+
+    /// The input mdBook sends for a book of `items`, with the preprocessor's
+    /// `table`.
+    fn input(table: Value, items: Value) -> String {
+        let context = json!({
+            "root": "/book",
+            "renderer": "html",
+            "config": { "preprocessor": { "flowchart": table } }
+        });
+        json!([context, { "items": items }]).to_string()
+    }
+
+    fn chapter(path: &str, content: &str, sub_items: Value) -> Value {
+        json!({ "Chapter": {
+            "name": path,
+            "content": content,
+            "path": path,
+            "sub_items": sub_items
+        } })
+    }
+
+    #[test]
+    fn renders_every_chapter() {
+        let items = json!([
+            chapter("a.md", CHAPTER, json!([chapter("b.md", CHAPTER, json!([]))])),
+            "Separator",
+            { "Chapter": { "name": "Draft", "path": null, "sub_items": [] } }
+        ]);
+        let table =
+            json!({ "command": "flowchart mdbook", "renderers": ["html"], "font-size": 23 });
+        let book: Value = serde_json::from_str(&preprocess(&input(table, items)).unwrap()).unwrap();
+
+        let content = book["items"][0]["Chapter"]["content"].as_str().unwrap();
+        assert!(content.starts_with("# Chapter\n\n<!-- flowchart\nproc main\n"));
+        assert!(content.contains(r#"<div id="flowchart-1-main"><svg"#));
+        assert!(content.contains("font-size: 23px;"));
+        assert!(content.contains(r#"data-file="a.md" data-line="5""#));
+        let sub_item = &book["items"][0]["Chapter"]["sub_items"][0]["Chapter"]["content"];
+        assert!(sub_item
+            .as_str()
+            .unwrap()
+            .contains(r#"data-file="b.md" data-line="5""#));
+        assert_eq!(book["items"][1], "Separator");
+        assert_eq!(book["items"][2]["Chapter"]["name"], "Draft");
+    }
+
+    #[test]
+    fn older_books_have_sections() {
+        let input =
+            json!([{ "config": {} }, { "sections": [chapter("a.md", CHAPTER, json!([]))] }]);
+        let book: Value = serde_json::from_str(&preprocess(&input.to_string()).unwrap()).unwrap();
+        let content = book["sections"][0]["Chapter"]["content"].as_str().unwrap();
+        assert!(content.contains("<svg"));
+    }
+
+    #[test]
+    fn errors() {
+        let error = |input: &str| preprocess(input).err().unwrap().to_string();
+        assert_eq!(
+            error("[{}]"),
+            "bad input from mdBook: expected a context and a book"
+        );
+        assert_eq!(
+            error(r#"[{}, {"title": null}]"#),
+            "bad input from mdBook: the book has no items"
+        );
+        assert!(error("[").starts_with("bad input from mdBook: "));
+        assert_eq!(
+            error(&input(json!({ "layered": "yes" }), json!([]))),
+            "[preprocessor.flowchart]: `layered` must be a boolean"
+        );
+        assert!(error(&input(json!({ "grid-size": 0 }), json!([])))
+            .starts_with("[preprocessor.flowchart]: invalid value: integer `0`"));
+        assert!(error(&input(json!({ "colour": "red" }), json!([])))
+            .starts_with("[preprocessor.flowchart]: unknown field `colour`"));
+        let broken = chapter(
+            "broken.md",
+            "```flowchart\nproc a\n  jump\nend\n```\n",
+            json!([]),
+        );
+        assert_eq!(
+            error(&input(json!({}), json!([broken]))),
+            "broken.md:3:3: unknown statement `jump`"
+        );
+    }
+
+    #[test]
+    fn renderers() {
+        assert!(supports("html"));
+        assert!(!supports("markdown"));
+    }
+}
@@ -0,0 +1,480 @@
+//! Parser for the chart source language.
+//!
+//! A source is a list of procedures, one statement per line:
+//!
+//! ```text
+//! # Greatest common divisor.
+//! proc gcd
+//!     io read a, b
+//!     call swap
+//!     process r = a % b
+//! end
+//! ```
+//!
+//! The statements are:
+//!
+//! - `io <text>` and `process <text>` for plain blocks,
+//! - `call <name>` for a call to another procedure,
+//! - `if <condition>`, an optional `else`, and `end`,
+//! - `switch <subject>`, one `case <label>` per branch, and `end`,
+//! - `while <condition>`, `for <header>` or `loop`, and `end`,
+//! - `break` and `continue` inside loops, and `return [<text>]`.
+//!
+//! Blank lines and lines starting with `#` are ignored. In the text of a
+//! statement, `\n` breaks the line inside the block and `\\` is a backslash.
+
+use std::fmt;
+
+use crate::{
+    block::{BlockKind, Span},
+    chart::{LoopKind, Procedure, Program, Statement},
+};
+
+#[derive(Debug)]
+pub struct ParseError {
+    /// 1-based line of the error.
+    pub line: usize,
+    /// 1-based column of the error, counted in characters.
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// A non-empty source line split into its keyword and the rest.
+struct Line<'a> {
+    number: usize,
+    indent: usize,
+    keyword: &'a str,
+    rest: &'a str,
+    rest_column: usize,
+    /// The column after the last character of the line.
+    end_column: usize,
+}
+
+impl Line<'_> {
+    fn error(&self, column: usize, message: String) -> ParseError {
+        ParseError {
+            line: self.number,
+            column,
+            message,
+        }
+    }
+
+    fn span(&self) -> Span {
+        Span::new(self.number, self.indent + 1, self.number, self.end_column)
+    }
+
+    fn keyword_error(&self, message: String) -> ParseError {
+        self.error(self.indent + 1, message)
+    }
+
+    fn text(&self) -> Result<String, ParseError> {
+        if self.rest.is_empty() {
+            return Err(self.keyword_error(format!("`{}` needs a text", self.keyword)));
+        }
+        Ok(unescape(self.rest))
+    }
+
+    fn name(&self) -> Result<String, ParseError> {
+        if !is_identifier(self.rest) {
+            return Err(self.error(
+                self.rest_column,
+                format!("`{}` needs a procedure name", self.keyword),
+            ));
+        }
+        Ok(String::from(self.rest))
+    }
+}
+
+fn split_lines(source: &str) -> Vec<Line<'_>> {
+    source
+        .lines()
+        .zip(1..)
+        .filter_map(|(line, number)| {
+            let trimmed = line.trim_start();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                return None;
+            }
+            let indent = line[..line.len() - trimmed.len()].chars().count();
+            let trimmed = trimmed.trim_end();
+            let (keyword, rest) = trimmed
+                .split_once(char::is_whitespace)
+                .unwrap_or((trimmed, ""));
+            let rest_start = trimmed.len() - rest.trim_start().len();
+            Some(Line {
+                number,
+                indent,
+                keyword,
+                rest: rest.trim_start(),
+                rest_column: indent + trimmed[..rest_start].chars().count() + 1,
+                end_column: indent + trimmed.chars().count() + 1,
+            })
+        })
+        .collect()
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+fn unescape(text: &str) -> String {
+    let mut s = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                s.push('\n');
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                s.push('\\');
+                chars.next();
+            }
+            _ => s.push(c),
+        }
+    }
+    s
+}
+
+struct Parser<'a> {
+    lines: Vec<Line<'a>>,
+    pos: usize,
+    /// The spans of the statements of the procedure being parsed, in
+    /// pre-order.
+    spans: Vec<Option<Span>>,
+}
+
+impl Parser<'_> {
+    fn parse_program(&mut self) -> Result<Program, ParseError> {
+        let mut procedures: Vec<Procedure> = vec![];
+        while let Some(line) = self.lines.get(self.pos) {
+            if line.keyword != "proc" {
+                return Err(
+                    line.keyword_error(format!("expected `proc`, found `{}`", line.keyword))
+                );
+            }
+            let name = line.name()?;
+            if procedures.iter().any(|procedure| procedure.name == name) {
+                return Err(line.error(
+                    line.rest_column,
+                    format!("procedure `{}` is defined more than once", name),
+                ));
+            }
+            let (number, start) = (line.number, line.span());
+            self.pos += 1;
+            let body = self.parse_body(number, false)?;
+            let end = self.lines[self.pos - 1].span();
+            procedures.push(Procedure {
+                name,
+                body,
+                span: Some(Span::new(
+                    start.line,
+                    start.column,
+                    end.end_line,
+                    end.end_column,
+                )),
+                spans: std::mem::take(&mut self.spans),
+            });
+        }
+        Ok(Program { procedures })
+    }
+
+    /// Parses statements up to and including the `end` that closes the
+    /// construct opened on line `opened`.
+    fn parse_body(&mut self, opened: usize, in_loop: bool) -> Result<Vec<Statement>, ParseError> {
+        let (body, _) = self.parse_until(opened, &["end"], in_loop)?;
+        Ok(body)
+    }
+
+    /// Parses statements up to and including one of the `stops` keywords,
+    /// and returns them with the keyword that stopped them.
+    fn parse_until(
+        &mut self,
+        opened: usize,
+        stops: &[&'static str],
+        in_loop: bool,
+    ) -> Result<(Vec<Statement>, &'static str), ParseError> {
+        let mut body = vec![];
+        loop {
+            let Some(line) = self.lines.get(self.pos) else {
+                return Err(ParseError {
+                    line: opened,
+                    column: 1,
+                    message: String::from("missing `end`"),
+                });
+            };
+            self.pos += 1;
+            if let Some(stop) = stops.iter().find(|stop| **stop == line.keyword) {
+                return Ok((body, stop));
+            }
+            let number = line.number;
+            // Taken before the statements nested in this one, as the spans
+            // are in pre-order.
+            self.spans.push(Some(line.span()));
+            body.push(match line.keyword {
+                "io" => Statement::Block(BlockKind::IO, line.text()?),
+                "process" => Statement::Block(BlockKind::Process, line.text()?),
+                "call" => Statement::Call(line.name()?),
+                "if" => {
+                    let condition = line.text()?;
+                    let (then_body, stop) = self.parse_until(number, &["else", "end"], in_loop)?;
+                    let else_body = match stop {
+                        "else" => self.parse_body(number, in_loop)?,
+                        _ => vec![],
+                    };
+                    Statement::If {
+                        condition,
+                        then_body,
+                        else_body,
+                    }
+                }
+                "switch" => {
+                    let subject = line.text()?;
+                    let mut cases = vec![];
+                    let (body, mut stop) = self.parse_until(number, &["case", "end"], in_loop)?;
+                    if !body.is_empty() {
+                        return Err(ParseError {
+                            line: number,
+                            column: 1,
+                            message: String::from("statements before the first `case`"),
+                        });
+                    }
+                    while stop == "case" {
+                        let case = &self.lines[self.pos - 1];
+                        let (label, case_number) = (case.text()?, case.number);
+                        let (body, next) =
+                            self.parse_until(case_number, &["case", "end"], in_loop)?;
+                        cases.push((label, body));
+                        stop = next;
+                    }
+                    Statement::Switch {
+                        subject,
+                        cases,
+                        fall_through: false,
+                    }
+                }
+                "while" => Statement::Loop {
+                    kind: LoopKind::While(line.text()?),
+                    body: self.parse_body(number, true)?,
+                },
+                "for" => Statement::Loop {
+                    kind: LoopKind::For(line.text()?),
+                    body: self.parse_body(number, true)?,
+                },
+                "loop" => Statement::Loop {
+                    kind: LoopKind::Infinite,
+                    body: self.parse_body(number, true)?,
+                },
+                "break" | "continue" if !in_loop => {
+                    return Err(line.keyword_error(format!("`{}` outside of a loop", line.keyword)))
+                }
+                "break" => Statement::Break,
+                "continue" => Statement::Continue,
+                "return" => Statement::Return(match line.rest {
+                    "" => None,
+                    _ => Some(format!("return {}", line.text()?)),
+                }),
+                keyword => {
+                    return Err(line.keyword_error(format!("unknown statement `{}`", keyword)))
+                }
+            });
+        }
+    }
+}
+
+/// A procedure as written in the source, found without parsing it so that a
+/// source with errors still has them.
+pub(crate) struct Definition<'a> {
+    pub name: &'a str,
+    pub name_span: Span,
+    /// From `proc` to its `end`, or to the last line if it has none.
+    pub span: Span,
+}
+
+/// Every procedure defined in `source`, in order.
+pub(crate) fn definitions(source: &str) -> Vec<Definition<'_>> {
+    let lines = split_lines(source);
+    let mut definitions = vec![];
+    let mut i = 0;
+    while i < lines.len() {
+        let line = &lines[i];
+        i += 1;
+        if line.keyword != "proc" {
+            continue;
+        }
+        let mut last = line;
+        let mut depth = 1;
+        while depth > 0 && i < lines.len() && lines[i].keyword != "proc" {
+            last = &lines[i];
+            i += 1;
+            match last.keyword {
+                "if" | "switch" | "while" | "for" | "loop" => depth += 1,
+                "end" => depth -= 1,
+                _ => {}
+            }
+        }
+        definitions.push(Definition {
+            name: line.rest,
+            name_span: Span::new(line.number, line.rest_column, line.number, line.end_column),
+            span: Span::new(line.number, line.indent + 1, last.number, last.end_column),
+        });
+    }
+    definitions
+}
+
+/// The procedure called on line `number` of `source`, if it holds a `call`,
+/// with the span of its name.
+pub(crate) fn call_on_line(source: &str, number: usize) -> Option<(&str, Span)> {
+    split_lines(source)
+        .into_iter()
+        .find(|line| line.number == number && line.keyword == "call")
+        .map(|line| {
+            let span = Span::new(line.number, line.rest_column, line.number, line.end_column);
+            (line.rest, span)
+        })
+}
+
+pub fn parse(source: &str) -> Result<Program, ParseError> {
+    Parser {
+        lines: split_lines(source),
+        pos: 0,
+        spans: vec![],
+    }
+    .parse_program()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chart;
+
+    /// The error of parsing `source`, as `line:column: message`.
+    fn error(source: &str) -> String {
+        parse(source).err().expect("a parse error").to_string()
+    }
+
+    #[test]
+    fn statements() {
+        let program = parse(
+            "# A comment.
+proc main
+    io read n
+    if n > 1
+        call helper
+    else
+        return 1\\n2
+    end
+    switch n
+    case 1
+        process a = 1
+    case 2
+    end
+    loop
+        break
+    end
+end
+
+proc helper
+end
+",
+        )
+        .unwrap();
+        let names: Vec<&str> = program.procedures.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["main", "helper"]);
+        assert_eq!(
+            chart::outline(&program.procedures[0]),
+            [
+                "read n",
+                "if n > 1",
+                "  call helper",
+                "else",
+                "  return",
+                "match n",
+                "case 1",
+                "  a = 1",
+                "case 2",
+                "loop",
+                "  break",
+            ]
+        );
+        let Statement::If { else_body, .. } = &program.procedures[0].body[1] else {
+            panic!("the second statement is an `if`");
+        };
+        assert!(matches!(
+            &else_body[0],
+            Statement::Return(Some(text)) if text == "return 1\n2"
+        ));
+    }
+
+    #[test]
+    fn spans() {
+        let program =
+            parse("proc main\n    io read n\n    while n > 0\n      break\n    end\nend\n")
+                .unwrap();
+        let procedure = &program.procedures[0];
+        assert_eq!(procedure.span, Some(Span::new(1, 1, 6, 4)));
+        assert_eq!(
+            procedure.spans,
+            [
+                Some(Span::new(2, 5, 2, 14)),
+                Some(Span::new(3, 5, 3, 16)),
+                Some(Span::new(4, 7, 4, 12)),
+            ]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(error("io x\n"), "1:1: expected `proc`, found `io`");
+        assert_eq!(
+            error("proc 1x\nend\n"),
+            "1:6: `proc` needs a procedure name"
+        );
+        assert_eq!(
+            error("proc a\nend\n  proc  a\nend\n"),
+            "3:9: procedure `a` is defined more than once"
+        );
+        assert_eq!(error("proc a\n  if x\n"), "2:1: missing `end`");
+        assert_eq!(
+            error("proc a\n    process\nend\n"),
+            "2:5: `process` needs a text"
+        );
+        assert_eq!(
+            error("proc a\n  break\nend\n"),
+            "2:3: `break` outside of a loop"
+        );
+        assert_eq!(
+            error("proc a\n  jump x\nend\n"),
+            "2:3: unknown statement `jump`"
+        );
+        assert_eq!(
+            error("proc a\n  switch x\n    io y\n  case 1\n  end\nend\n"),
+            "2:1: statements before the first `case`"
+        );
+    }
+
+    #[test]
+    fn definitions_of_broken_sources() {
+        let source = "proc a\n  if x\n    call b\nproc b\n  io y\nend\n";
+        let found = definitions(source);
+        let names: Vec<&str> = found.iter().map(|definition| definition.name).collect();
+        assert_eq!(names, ["a", "b"]);
+        assert_eq!(found[0].name_span, Span::new(1, 6, 1, 7));
+        assert_eq!(found[0].span, Span::new(1, 1, 3, 11));
+        assert_eq!(found[1].span, Span::new(4, 1, 6, 4));
+        assert_eq!(
+            call_on_line(source, 3),
+            Some(("b", Span::new(3, 10, 3, 11)))
+        );
+        assert_eq!(call_on_line(source, 2), None);
+    }
+}
@@ -0,0 +1,416 @@
+//! A server previewing the charts of a directory of sources on localhost,
+//! built on the standard library only.
+//!
+//! `/` lists the sources under the directory, and `/<path>` renders every
+//! chart of the source at `<path>` when it is requested. Every page listens
+//! to the server-sent events of `/events`, and reloads when a source or the
+//! config file changes.
+
+use std::{
+    fmt::Write as _,
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    config::{Config, ConfigBuilder, Settings},
+    frontend,
+    watch::Watcher,
+    xml::escape,
+};
+
+/// Reloads the page on every event.
+const RELOAD_SCRIPT: &str = r#"new EventSource("/events").onmessage = () => location.reload();"#;
+
+/// How many polls of the sources go by between comments sent to keep the
+/// event streams open and find the clients that left.
+const KEEP_ALIVE_POLLS: usize = 75;
+
+pub struct PreviewServer {
+    root: PathBuf,
+    config_file: Option<PathBuf>,
+    layered: bool,
+    interval: Duration,
+}
+
+impl PreviewServer {
+    /// Serves the sources under `root`.
+    pub fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            config_file: None,
+            layered: false,
+            interval: Duration::from_millis(200),
+        }
+    }
+
+    /// Reads the settings of the charts from a JSON file, as
+    /// [`Settings`], whenever one is rendered.
+    pub fn config_file(mut self, path: &Path) -> Self {
+        self.config_file = Some(path.to_path_buf());
+        self
+    }
+
+    /// Draws graphs in layers, as they are.
+    pub fn layered(mut self, layered: bool) -> Self {
+        self.layered = layered;
+        self
+    }
+
+    /// Sets how often the sources are checked for changes, five times a
+    /// second by default.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Answers the connections to `listener`, every one on its own thread,
+    /// until accepting one fails.
+    pub fn serve(self, listener: TcpListener) -> io::Result<()> {
+        let server = Arc::new(self);
+        let clients: Arc<Mutex<Vec<TcpStream>>> = Arc::default();
+        {
+            let (server, clients) = (server.clone(), clients.clone());
+            thread::spawn(move || server.notify(&clients));
+        }
+        for stream in listener.incoming() {
+            let stream = stream?;
+            let (server, clients) = (server.clone(), clients.clone());
+            thread::spawn(move || {
+                // A client that goes away midway is no concern of the others.
+                let _ = server.answer(stream, &clients);
+            });
+        }
+        Ok(())
+    }
+
+    /// Sends an event to every client whenever the sources change.
+    fn notify(&self, clients: &Mutex<Vec<TcpStream>>) {
+        let paths = |server: &Self| {
+            let mut paths = server.sources();
+            paths.extend(server.config_file.clone());
+            paths
+        };
+        let mut watched = paths(self);
+        let mut watcher = Watcher::new(&watched);
+        for poll in 1.. {
+            thread::sleep(self.interval);
+            let now = paths(self);
+            let changed = if now != watched {
+                watcher = Watcher::new(&now);
+                watched = now;
+                true
+            } else {
+                !watcher.poll().is_empty()
+            };
+            let message = match changed {
+                true => "data: reload\n\n",
+                false if poll % KEEP_ALIVE_POLLS == 0 => ": keep-alive\n\n",
+                false => continue,
+            };
+            let mut clients = clients.lock().unwrap();
+            clients.retain_mut(|stream| stream.write_all(message.as_bytes()).is_ok());
+        }
+    }
+
+    fn answer(&self, stream: TcpStream, clients: &Mutex<Vec<TcpStream>>) -> io::Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut request = String::new();
+        reader.read_line(&mut request)?;
+        // The headers tell nothing the server needs.
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 2 {
+            header.clear();
+        }
+        let mut words = request.split_whitespace();
+        let (Some(method), Some(target)) = (words.next(), words.next()) else {
+            return respond(stream, "400 Bad Request", "text/plain", "bad request");
+        };
+        if method != "GET" {
+            return respond(stream, "405 Method Not Allowed", "text/plain", "only GET");
+        }
+        let path = percent_decode(target.split(['?', '#']).next().unwrap_or_default());
+        match path.as_str() {
+            "/events" => {
+                let mut stream = stream;
+                stream.write_all(
+                    b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\
+                      Cache-Control: no-store\r\n\r\n",
+                )?;
+                clients.lock().unwrap().push(stream);
+                Ok(())
+            }
+            "/" => respond(stream, "200 OK", "text/html", &self.index()),
+            _ => match self.source(&path) {
+                Some(source) => {
+                    let (status, page) = self.page(&source);
+                    respond(stream, status, "text/html", &page)
+                }
+                None => respond(stream, "404 Not Found", "text/plain", "no such source"),
+            },
+        }
+    }
+
+    /// The sources under the root, other than the config file.
+    fn sources(&self) -> Vec<PathBuf> {
+        let mut sources = frontend::find_sources(&self.root);
+        sources.retain(|source| !self.is_config_file(source));
+        sources
+    }
+
+    fn is_config_file(&self, path: &Path) -> bool {
+        let canonical = |path: &Path| fs::canonicalize(path).ok();
+        self.config_file
+            .as_deref()
+            .is_some_and(|config_file| canonical(config_file) == canonical(path))
+    }
+
+    /// The source at the path of a request, if it is one of the sources and
+    /// does not lead out of the root.
+    fn source(&self, path: &str) -> Option<PathBuf> {
+        let relative = Path::new(path.trim_start_matches('/'));
+        if !relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return None;
+        }
+        let source = self.root.join(relative);
+        self.sources().contains(&source).then_some(source)
+    }
+
+    fn config(&self) -> Result<Config, String> {
+        let builder = ConfigBuilder::new();
+        let Some(path) = &self.config_file else {
+            return Ok(builder.build());
+        };
+        let json = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let settings =
+            Settings::from_json(&json).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(settings.apply(builder).build())
+    }
+
+    fn index(&self) -> String {
+        let mut body = String::from("<ul>\n");
+        for source in self.sources() {
+            let relative = self.relative(&source);
+            writeln!(
+                body,
+                r#"<li><a href="/{}">{}</a></li>"#,
+                percent_encode(&relative),
+                escape(&relative)
+            )
+            .unwrap();
+        }
+        body.push_str("</ul>\n");
+        document(&self.root.display().to_string(), &body)
+    }
+
+    /// The charts of `source`, or the error that keeps it from being
+    /// rendered, with the status to answer with.
+    fn page(&self, source: &Path) -> (&'static str, String) {
+        let title = self.relative(source);
+        let charts = self.config().and_then(|config| {
+            let text =
+                fs::read_to_string(source).map_err(|e| format!("{}: {}", source.display(), e))?;
+            let input = frontend::read(Path::new(&title), &text).map_err(|e| e.to_string())?;
+            Ok(input.to_svgs(&config, self.layered, &|stem| format!("#{}", stem)))
+        });
+        let mut body = String::from(r#"<p><a href="/">All sources</a></p>"#);
+        body.push('\n');
+        let status = match charts {
+            Ok(charts) => {
+                for (index, (stem, svg)) in charts.into_iter().enumerate() {
+                    let stem = escape(&stem);
+                    let id = format!("chart-{}", index + 1);
+                    writeln!(body, r#"<section id="{}">"#, stem).unwrap();
+                    writeln!(body, "<h2>{}</h2>", stem).unwrap();
+                    write!(body, "{}", svg.inline().id(&id)).unwrap();
+                    writeln!(body, "</section>").unwrap();
+                }
+                "200 OK"
+            }
+            Err(message) => {
+                writeln!(body, r#"<pre class="error">{}</pre>"#, escape(&message)).unwrap();
+                "500 Internal Server Error"
+            }
+        };
+        (status, document(&title, &body))
+    }
+
+    /// The path of a source from the root, with slashes.
+    fn relative(&self, source: &Path) -> String {
+        let relative = source.strip_prefix(&self.root).unwrap_or(source);
+        let parts: Vec<_> = relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect();
+        parts.join("/")
+    }
+}
+
+/// A page that reloads when the sources change.
+fn document(title: &str, body: &str) -> String {
+    let mut html = String::new();
+    writeln!(html, "<!DOCTYPE html>").unwrap();
+    writeln!(html, "<html>").unwrap();
+    writeln!(html, "<head>").unwrap();
+    writeln!(html, r#"<meta charset="utf-8">"#).unwrap();
+    writeln!(html, "<title>{}</title>", escape(title)).unwrap();
+    writeln!(html, "<style>.error {{ color: #c62828; }}</style>").unwrap();
+    writeln!(html, "</head>").unwrap();
+    writeln!(html, "<body>").unwrap();
+    writeln!(html, "<h1>{}</h1>", escape(title)).unwrap();
+    html.push_str(body);
+    writeln!(html, "<script>{}</script>", RELOAD_SCRIPT).unwrap();
+    writeln!(html, "</body>").unwrap();
+    writeln!(html, "</html>").unwrap();
+    html
+}
+
+fn respond(mut stream: TcpStream, status: &str, content_type: &str, body: &str) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}; charset=utf-8\r\nContent-Length: {}\r\n\
+         Cache-Control: no-store\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+/// Escapes every byte of a path that may not stand as it is in a URL.
+fn percent_encode(path: &str) -> String {
+    let mut encoded = String::new();
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            _ => write!(encoded, "%{:02X}", byte).unwrap(),
+        }
+    }
+    encoded
+}
+
+/// Decodes the `%XX` escapes of a path, keeping malformed ones as they are.
+fn percent_decode(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn percent_escapes() {
+        assert_eq!(percent_encode("a b/ü&.flow"), "a%20b/%C3%BC%26.flow");
+        assert_eq!(percent_decode("a%20b/%C3%BC%26.flow"), "a b/ü&.flow");
+        assert_eq!(percent_decode("100%/%zz%2"), "100%/%zz%2");
+    }
+
+    #[test]
+    fn sources_and_paths() {
+        let dir = TempDir::new("sources");
+        dir.write("b/c d.flow", "proc c\nend\n");
+        dir.write("a.flow", "proc a\nend\n");
+        dir.write("config.json", "{}");
+        dir.write("notes.txt", "");
+        let server = PreviewServer::new(&dir.0).config_file(&dir.0.join("config.json"));
+
+        let relative: Vec<String> = server
+            .sources()
+            .iter()
+            .map(|source| server.relative(source))
+            .collect();
+        assert_eq!(relative, ["a.flow", "b/c d.flow"]);
+        assert!(server
+            .index()
+            .contains(r#"<li><a href="/b/c%20d.flow">b/c d.flow</a></li>"#));
+
+        assert_eq!(server.source("/b/c d.flow"), Some(dir.0.join("b/c d.flow")));
+        assert_eq!(server.source("/config.json"), None);
+        assert_eq!(server.source("/notes.txt"), None);
+        assert_eq!(server.source("/b/../a.flow"), None);
+        assert_eq!(server.source("//etc/passwd"), None);
+    }
+
+    #[test]
+    fn pages_and_errors() {
+        let dir = TempDir::new("pages");
+        dir.write("a.flow", "proc a\n  io x\nend\n\nproc b\nend\n");
+        dir.write("broken.flow", "proc a\n  if <x>\n");
+        let server = PreviewServer::new(&dir.0);
+
+        let (status, page) = server.page(&dir.0.join("a.flow"));
+        assert_eq!(status, "200 OK");
+        assert!(page.contains("<title>a.flow</title>"));
+        assert!(page.contains(r#"<section id="a">"#));
+        assert!(page.contains(r#"<svg id="chart-2""#));
+        assert!(page.contains(RELOAD_SCRIPT));
+
+        let (status, page) = server.page(&dir.0.join("broken.flow"));
+        assert_eq!(status, "500 Internal Server Error");
+        assert!(page.contains(r#"<pre class="error">broken.flow:2:1: missing `end`</pre>"#));
+
+        dir.write("config.json", "{\"unknown\": 1}");
+        let server = server.config_file(&dir.0.join("config.json"));
+        let (status, page) = server.page(&dir.0.join("a.flow"));
+        assert_eq!(status, "500 Internal Server Error");
+        assert!(page.contains("config.json: "));
+    }
+
+    #[test]
+    fn answers_requests() {
+        let dir = TempDir::new("requests");
+        dir.write("a b.flow", "proc a\nend\n");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = PreviewServer::new(&dir.0);
+        thread::spawn(move || server.serve(listener));
+
+        let get = |request: &str| {
+            let mut stream = TcpStream::connect(address).unwrap();
+            write!(stream, "{}\r\nHost: localhost\r\n\r\n", request).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        let response = get("GET /a%20b.flow?x#y HTTP/1.1");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\nContent-Type: text/html"));
+        assert!(response.contains("<h2>a</h2>"));
+        assert!(get("GET / HTTP/1.1").contains(r#"<a href="/a%20b.flow">"#));
+        assert!(get("GET /missing.flow HTTP/1.1").starts_with("HTTP/1.1 404 Not Found"));
+        assert!(get("POST / HTTP/1.1").starts_with("HTTP/1.1 405 Method Not Allowed"));
+        assert!(get("nonsense").starts_with("HTTP/1.1 400 Bad Request"));
+    }
+}
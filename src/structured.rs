@@ -0,0 +1,1177 @@
+//! Layout of structured procedures.
+//!
+//! Every statement is laid out as a region with a single entry on its top
+//! edge. A region keeps the paths that leave it open as exits: the fall
+//! through to the next statement, which always ends at one point, and jumps
+//! (`break`, `continue` and `return`), which always end on the right edge of
+//! the region. The enclosing constructs extend these paths until they reach
+//! their targets, so lines only ever run in the gaps between blocks.
+//!
+//! Procedures are laid out from top to bottom, and the finished layout is
+//! turned to flow in the direction of the config. Sideways, the blocks are
+//! laid out transposed, so that they keep their size once turned.
+
+use crate::{
+    block::{Block, BlockBuilder, BlockKind},
+    chart::{count, LoopKind, Procedure, Statement},
+    config::{Config, Direction},
+    label::LabelPlacer,
+    svg::SvgShape,
+};
+
+pub(crate) type Point = (usize, usize);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ExitKind {
+    Next,
+    Break,
+    Continue,
+    Return,
+}
+
+#[derive(Clone)]
+pub(crate) struct Path {
+    points: Vec<Point>,
+    // The text and the index of the segment it annotates.
+    label: Option<(String, usize)>,
+}
+
+impl Path {
+    pub(crate) fn new(points: Vec<Point>) -> Self {
+        Self {
+            points,
+            label: None,
+        }
+    }
+
+    pub(crate) fn labeled(points: Vec<Point>, label: &str, segment: usize) -> Self {
+        Self {
+            points,
+            label: Some((String::from(label), segment)),
+        }
+    }
+
+    fn end(&self) -> Point {
+        *self.points.last().unwrap()
+    }
+
+    fn push(&mut self, point: Point) {
+        self.points.push(point);
+    }
+
+    /// Continues the path with `tail`, which starts where the path ends.
+    fn join(mut self, tail: &Path) -> Path {
+        let offset = self.points.len() - 1;
+        self.points.extend_from_slice(&tail.points[1..]);
+        if self.label.is_none() {
+            self.label = tail
+                .label
+                .as_ref()
+                .map(|(text, segment)| (text.clone(), segment + offset));
+        }
+        self
+    }
+
+    fn displace(&mut self, dx: usize, dy: usize) {
+        self.points.iter_mut().for_each(|(x, y)| {
+            *x += dx;
+            *y += dy;
+        });
+    }
+}
+
+struct Exit {
+    kind: ExitKind,
+    path: Path,
+}
+
+struct Region {
+    width: usize,
+    height: usize,
+    // The x of the entry point, whose y is always 0.
+    entry: usize,
+    // The entry block comes first.
+    blocks: Vec<Block>,
+    edges: Vec<Path>,
+    exits: Vec<Exit>,
+}
+
+impl Region {
+    /// A region without blocks, which passes its entry straight to an exit
+    /// of `kind`.
+    fn empty(kind: ExitKind) -> Self {
+        Self {
+            width: 0,
+            height: 0,
+            entry: 0,
+            blocks: vec![],
+            edges: vec![],
+            exits: vec![Exit {
+                kind,
+                path: Path::new(vec![(0, 0)]),
+            }],
+        }
+    }
+
+    /// Whether the flow passes the region without entering a block.
+    /// Annotations beside the flow do not count.
+    fn is_empty(&self) -> bool {
+        self.blocks
+            .iter()
+            .all(|block| block.kind() == BlockKind::Annotation)
+    }
+
+    /// The block the flow enters first.
+    fn first_block(&self) -> &Block {
+        self.blocks
+            .iter()
+            .find(|block| block.kind() != BlockKind::Annotation)
+            .unwrap()
+    }
+
+    fn displace(&mut self, dx: usize, dy: usize) {
+        self.width += dx;
+        self.height += dy;
+        self.entry += dx;
+        self.blocks
+            .iter_mut()
+            .for_each(|block| block.displace(dx, dy));
+        self.edges.iter_mut().for_each(|edge| edge.displace(dx, dy));
+        self.exits
+            .iter_mut()
+            .for_each(|exit| exit.path.displace(dx, dy));
+    }
+
+    fn next_exit(&self) -> Option<Point> {
+        self.exits
+            .iter()
+            .find(|exit| exit.kind == ExitKind::Next)
+            .map(|exit| exit.path.end())
+    }
+
+    fn count_jumps(&self) -> usize {
+        self.exits
+            .iter()
+            .filter(|exit| exit.kind != ExitKind::Next)
+            .count()
+    }
+
+    /// Grows the region to cover everything in it.
+    fn fit(&mut self) {
+        let points = self
+            .edges
+            .iter()
+            .chain(self.exits.iter().map(|exit| &exit.path))
+            .flat_map(|path| path.points.iter().copied());
+        let corners = self.blocks.iter().map(|block| {
+            let bounds = block.bounds();
+            (bounds.x + bounds.width, bounds.y + bounds.height)
+        });
+        for (x, y) in points.chain(corners) {
+            self.width = self.width.max(x);
+            self.height = self.height.max(y);
+        }
+    }
+
+    /// Moves the ends of the jumps out to the right edge of the region.
+    fn extend_jumps(&mut self) {
+        for exit in &mut self.exits {
+            let (x, y) = exit.path.end();
+            if exit.kind != ExitKind::Next && x < self.width {
+                exit.path.push((self.width, y));
+            }
+        }
+    }
+
+    /// Leads `paths`, which end at the entry, into the region. Returns the
+    /// edges to draw, or nothing if the region is empty and the paths went on
+    /// as its exits instead.
+    fn enter(&mut self, paths: Vec<Path>) -> Vec<Path> {
+        if !self.is_empty() {
+            // An edge from the entry past annotations to the first block
+            // continues the paths, so no arrow head is drawn at the entry.
+            let Some(entry) = paths.first().map(Path::end) else {
+                return paths;
+            };
+            let (leads, edges): (Vec<Path>, Vec<Path>) = std::mem::take(&mut self.edges)
+                .into_iter()
+                .partition(|edge| edge.points[0] == entry);
+            self.edges = edges;
+            if leads.is_empty() {
+                return paths;
+            }
+            return paths
+                .iter()
+                .flat_map(|path| leads.iter().map(|lead| path.clone().join(lead)))
+                .collect();
+        }
+        self.exits = paths
+            .iter()
+            .flat_map(|path| {
+                self.exits.iter().map(|exit| Exit {
+                    kind: exit.kind,
+                    path: path.clone().join(&exit.path),
+                })
+            })
+            .collect();
+        vec![]
+    }
+}
+
+pub struct Layout {
+    blocks: Vec<Block>,
+    edges: Vec<Path>,
+}
+
+impl Layout {
+    pub(crate) fn new(blocks: Vec<Block>, edges: Vec<Path>) -> Self {
+        Self { blocks, edges }
+    }
+
+    /// The block that shows the statement at `index` in
+    /// [`Procedure::statements`], if it has one.
+    pub(crate) fn block_of(&self, index: usize) -> Option<&Block> {
+        self.blocks.iter().find(|block| block.shows(index))
+    }
+
+    #[cfg(test)]
+    pub(crate) fn blocks(&self) -> &[Block] {
+        &self.blocks
+    }
+
+    /// The start and the end terminal.
+    pub(crate) fn terminals(&self) -> (&Block, &Block) {
+        (self.blocks.first().unwrap(), self.blocks.last().unwrap())
+    }
+
+    /// The edges from `from` to `to`, keeping only those labeled `branch`
+    /// if there are any.
+    pub(crate) fn edges_between(
+        &self,
+        from: &Block,
+        to: &Block,
+        branch: Option<&str>,
+    ) -> Vec<Vec<Point>> {
+        let touches = |block: &Block, (x, y): Point| {
+            let bounds = block.bounds();
+            (bounds.x..=bounds.x + bounds.width).contains(&x)
+                && (bounds.y..=bounds.y + bounds.height).contains(&y)
+        };
+        let edges: Vec<&Path> = self
+            .edges
+            .iter()
+            .filter(|edge| touches(from, edge.points[0]) && touches(to, edge.end()))
+            .collect();
+        let labeled: Vec<&Path> = edges
+            .iter()
+            .copied()
+            .filter(|edge| edge.label.as_ref().map(|(text, _)| text.as_str()) == branch)
+            .collect();
+        match (branch, labeled.is_empty()) {
+            (Some(_), false) => labeled,
+            _ => edges,
+        }
+        .into_iter()
+        .map(|edge| simplify(&edge.points))
+        .collect()
+    }
+
+    pub fn displace(&mut self, dx: usize, dy: usize) {
+        self.blocks
+            .iter_mut()
+            .for_each(|block| block.displace(dx, dy));
+        self.edges.iter_mut().for_each(|edge| edge.displace(dx, dy));
+    }
+
+    /// Turns a layout made from top to bottom to flow in `direction`. The
+    /// blocks of a layout that flows sideways must have been transposed
+    /// while it was made.
+    pub(crate) fn orient(&mut self, direction: Direction) {
+        if direction.is_sideways() {
+            self.blocks.iter_mut().for_each(Block::transpose);
+            for edge in &mut self.edges {
+                edge.points
+                    .iter_mut()
+                    .for_each(|(x, y)| (*x, *y) = (*y, *x));
+            }
+        }
+        match direction {
+            Direction::TopToBottom | Direction::LeftToRight => {}
+            Direction::BottomToTop => {
+                let height = self.extent().1;
+                for block in &mut self.blocks {
+                    let (x, y) = block.pos();
+                    block.move_to(x, height - y - block.height());
+                }
+                for edge in &mut self.edges {
+                    edge.points.iter_mut().for_each(|(_, y)| *y = height - *y);
+                }
+            }
+            Direction::RightToLeft => {
+                let width = self.extent().0;
+                for block in &mut self.blocks {
+                    let (x, y) = block.pos();
+                    block.move_to(width - x - block.width(), y);
+                }
+                for edge in &mut self.edges {
+                    edge.points.iter_mut().for_each(|(x, _)| *x = width - *x);
+                }
+            }
+        }
+    }
+
+    /// How far the blocks and edges reach.
+    fn extent(&self) -> Point {
+        let corners = self.blocks.iter().map(|block| {
+            let bounds = block.bounds();
+            (bounds.x + bounds.width, bounds.y + bounds.height)
+        });
+        let points = self
+            .edges
+            .iter()
+            .flat_map(|edge| edge.points.iter().copied());
+        corners
+            .chain(points)
+            .fold((0, 0), |(width, height), (x, y)| {
+                (width.max(x), height.max(y))
+            })
+    }
+
+    /// Renders the layout and returns it with the size it covers.
+    pub fn to_svg(&self, config: &Config) -> (SvgShape, (usize, usize)) {
+        let mut placer = LabelPlacer::new(config);
+        self.blocks
+            .iter()
+            .for_each(|block| placer.add_obstacle(block.bounds()));
+        let mut group: Vec<SvgShape> = self.blocks.iter().map(|block| block.to_svg()).collect();
+        let mut labels = vec![];
+        for edge in &self.edges {
+            let points = simplify(&edge.points);
+            if points.len() < 2 {
+                continue;
+            }
+            if let Some((text, segment)) = &edge.label {
+                // Skip the zero-length segments left by aligned turns.
+                let (from, to) = edge
+                    .points
+                    .get(*segment..)
+                    .unwrap_or_default()
+                    .windows(2)
+                    .map(|pair| (pair[0], pair[1]))
+                    .find(|(from, to)| from != to)
+                    .unwrap_or((edge.points[0], edge.end()));
+                labels.push(placer.place(from, to, text));
+            }
+            group.push(SvgShape::Polyline(points));
+        }
+        group.extend(labels);
+        let extent = self
+            .edges
+            .iter()
+            .flat_map(|edge| edge.points.iter())
+            .fold(placer.extent(), |(width, height), &(x, y)| {
+                (width.max(x), height.max(y))
+            });
+        (SvgShape::Group(group), extent)
+    }
+}
+
+/// Drops repeated points and the middle of straight runs.
+fn simplify(points: &[Point]) -> Vec<Point> {
+    let mut simplified: Vec<Point> = vec![];
+    for &point in points {
+        if simplified.last() == Some(&point) {
+            continue;
+        }
+        if let [.., a, b] = simplified.as_slice() {
+            if (a.0 == b.0 && b.0 == point.0) || (a.1 == b.1 && b.1 == point.1) {
+                simplified.pop();
+            }
+        }
+        simplified.push(point);
+    }
+    simplified
+}
+
+pub struct Layouter<'a> {
+    block_builder: BlockBuilder,
+    direction: Direction,
+    distance: usize,
+    lane: usize,
+    link: &'a dyn Fn(&str) -> Option<String>,
+}
+
+impl<'a> Layouter<'a> {
+    /// Calls are linked to whatever `link` returns for the callee.
+    pub fn new(config: &Config, link: &'a dyn Fn(&str) -> Option<String>) -> Self {
+        Self {
+            block_builder: BlockBuilder::new(config),
+            direction: config.direction(),
+            distance: config.distance(),
+            lane: config.grid_size(),
+            link,
+        }
+    }
+
+    /// Lays out the procedure between a start and an end terminal, with
+    /// every `return` leading to the end.
+    pub fn layout(&self, procedure: &Procedure) -> Layout {
+        let start = self.block_region(self.build(BlockKind::Terminal, procedure.name.clone()));
+        let body = self.layout_body(&procedure.body, 0);
+        let mut region = self.then(start, body);
+        let mut end = self.block_region(self.build(BlockKind::Terminal, String::from("end")));
+        if region.next_exit().is_some() {
+            region = self.then(region, end);
+        } else {
+            let x = region.entry.max(end.entry);
+            region.displace(x - region.entry, 0);
+            end.displace(x - end.entry, region.height + self.distance);
+            region.blocks.extend(end.blocks);
+            region.fit();
+            region.extend_jumps();
+        }
+        let (end_x, end_y) = region.blocks.last().unwrap().right_pos();
+        let mut edges = region.edges;
+        let mut jumps: Vec<Path> = region
+            .exits
+            .into_iter()
+            .filter(|exit| exit.kind != ExitKind::Next)
+            .map(|exit| exit.path)
+            .collect();
+        // Lower jumps take the inner lanes, so their horizontal runs never
+        // cross the lanes of the jumps above them.
+        jumps.sort_by_key(|path| std::cmp::Reverse(path.end().1));
+        for (k, mut path) in jumps.into_iter().enumerate() {
+            let (_, y) = path.end();
+            let x = region.width + (k + 1) * self.lane;
+            path.push((x, y));
+            path.push((x, end_y));
+            path.push((end_x, end_y));
+            edges.push(path);
+        }
+        let mut blocks = region.blocks;
+        if let Some(span) = &procedure.span {
+            blocks[0].set_span(span.clone());
+        }
+        debug_assert!(
+            procedure.spans.is_empty() || procedure.spans.len() == procedure.statements().len()
+        );
+        for (index, span) in procedure.spans.iter().enumerate() {
+            let Some(span) = span else {
+                continue;
+            };
+            if let Some(block) = blocks.iter_mut().find(|block| block.shows(index)) {
+                block.set_span(span.clone());
+            }
+        }
+        let mut layout = Layout { blocks, edges };
+        layout.orient(self.direction);
+        layout
+    }
+
+    /// Builds a block, transposed if the layout is turned sideways.
+    fn build(&self, kind: BlockKind, content: String) -> Block {
+        let mut block = self.block_builder.build(kind, content);
+        if self.direction.is_sideways() {
+            block.transpose();
+        }
+        block
+    }
+
+    fn block_region(&self, block: Block) -> Region {
+        let (entry, _) = block.top_pos();
+        Region {
+            width: block.width(),
+            height: block.height(),
+            entry,
+            exits: vec![Exit {
+                kind: ExitKind::Next,
+                path: Path::new(vec![block.bottom_pos()]),
+            }],
+            blocks: vec![block],
+            edges: vec![],
+        }
+    }
+
+    /// Lays out `body`, whose first statement is at `index` in
+    /// [`Procedure::statements`].
+    fn layout_body(&self, body: &[Statement], mut index: usize) -> Region {
+        let mut region = Region::empty(ExitKind::Next);
+        for statement in body {
+            if region.next_exit().is_some() {
+                region = self.then(region, self.layout_statement(statement, index));
+            } else if let Statement::Label(_) = statement {
+                // A label is reached through its connector, not from above.
+                region = self.below(region, self.layout_statement(statement, index));
+            }
+            // Anything else after a region without a fall through is dead
+            // code.
+            index += statement.count();
+        }
+        region
+    }
+
+    fn layout_statement(&self, statement: &Statement, index: usize) -> Region {
+        let mut region = self.layout_region(statement, index + 1);
+        // The block of the statement itself comes first, except for the
+        // condition of a do-while under its body; a `loop` has none.
+        let own = match statement {
+            Statement::Loop {
+                kind: LoopKind::Infinite,
+                ..
+            } => None,
+            Statement::Loop {
+                kind: LoopKind::DoWhile(_),
+                ..
+            } => region.blocks.last_mut(),
+            _ => region.blocks.first_mut(),
+        };
+        if let Some(block) = own {
+            block.set_statement(index);
+        }
+        region
+    }
+
+    /// Lays out `statement`, whose nested statements start at `index`.
+    fn layout_region(&self, statement: &Statement, index: usize) -> Region {
+        match statement {
+            Statement::Block(kind, content) => {
+                self.block_region(self.build(*kind, content.clone()))
+            }
+            Statement::Call(name) => {
+                let mut block = self.build(BlockKind::Subroutine, name.clone());
+                if let Some(href) = (self.link)(name) {
+                    block.set_link(href);
+                }
+                self.block_region(block)
+            }
+            Statement::Try(content) => {
+                let block = self.build(BlockKind::Process, content.clone());
+                let failure = Path::labeled(vec![block.right_pos()], "Err", 0);
+                let mut region = self.block_region(block);
+                region.exits.push(Exit {
+                    kind: ExitKind::Return,
+                    path: failure,
+                });
+                region
+            }
+            Statement::If {
+                condition,
+                then_body,
+                else_body,
+            } => self.layout_if(condition, then_body, else_body, index),
+            Statement::Switch {
+                subject,
+                cases,
+                fall_through,
+            } => self.layout_switch(subject, cases, *fall_through, index),
+            Statement::Loop {
+                kind: LoopKind::DoWhile(condition),
+                body,
+            } => self.layout_do_while(condition, body, index),
+            Statement::Loop { kind, body } => self.layout_loop(kind, body, index),
+            Statement::Return(Some(content)) => {
+                let block = self.build(BlockKind::Process, content.clone());
+                let mut region = self.block_region(block);
+                // Leave through the gap under the block, not along its edge.
+                let (x, y) = region.exits[0].path.end();
+                region.height = y + self.distance / 2;
+                region.exits[0].kind = ExitKind::Return;
+                region.exits[0].path.push((x, region.height));
+                region.extend_jumps();
+                region
+            }
+            Statement::Return(None) => Region::empty(ExitKind::Return),
+            Statement::Break => Region::empty(ExitKind::Break),
+            Statement::Continue => Region::empty(ExitKind::Continue),
+            Statement::Goto(label) => {
+                let mut region = self.block_region(self.build(BlockKind::Connector, label.clone()));
+                region.exits.clear();
+                region
+            }
+            Statement::Label(label) => {
+                self.block_region(self.build(BlockKind::Connector, label.clone()))
+            }
+            Statement::Annotation(content) => {
+                // The flow runs straight past the annotation, whose leader
+                // starts on it.
+                let mut note = self.build(BlockKind::Annotation, content.clone());
+                note.displace(0, self.distance / 2);
+                let (_, bottom) = note.bottom_pos();
+                Region {
+                    width: note.width(),
+                    height: bottom,
+                    entry: 0,
+                    blocks: vec![note],
+                    edges: vec![],
+                    exits: vec![Exit {
+                        kind: ExitKind::Next,
+                        path: Path::new(vec![(0, 0), (0, bottom)]),
+                    }],
+                }
+            }
+        }
+    }
+
+    /// Places `b` under `a` without leading anything from `a` into it.
+    fn below(&self, mut a: Region, mut b: Region) -> Region {
+        let x = a.entry.max(b.entry);
+        a.displace(x - a.entry, 0);
+        b.displace(x - b.entry, a.height + self.distance);
+        a.blocks.extend(b.blocks);
+        a.edges.extend(b.edges);
+        a.exits.extend(b.exits);
+        a.fit();
+        a.extend_jumps();
+        a
+    }
+
+    /// Places `b` under `a` and leads the fall through of `a` into it.
+    fn then(&self, mut a: Region, mut b: Region) -> Region {
+        let Some((x, y)) = a.next_exit() else {
+            return a;
+        };
+        if a.blocks.is_empty() {
+            return b;
+        }
+        let (next, mut exits): (Vec<Exit>, Vec<Exit>) = std::mem::take(&mut a.exits)
+            .into_iter()
+            .partition(|exit| exit.kind == ExitKind::Next);
+        let paths = next.into_iter().map(|exit| exit.path).collect();
+        if b.is_empty() {
+            b.displace(x, y);
+            b.enter(paths);
+            // Jumps leave through the gap under the region.
+            let below = a.height + self.distance / 2;
+            for mut exit in b.exits {
+                if exit.kind != ExitKind::Next {
+                    exit.path.push((x, below));
+                    a.height = below;
+                }
+                exits.push(exit);
+            }
+            a.exits = exits;
+            a.blocks.extend(b.blocks);
+            a.fit();
+            a.extend_jumps();
+            return a;
+        }
+        let (dx_a, dx_b) = (b.entry.saturating_sub(x), x.saturating_sub(b.entry));
+        a.displace(dx_a, 0);
+        exits
+            .iter_mut()
+            .for_each(|exit| exit.path.displace(dx_a, 0));
+        let top = a.height + self.distance;
+        b.displace(dx_b, top);
+        let paths = paths
+            .into_iter()
+            .map(|mut path: Path| {
+                path.displace(dx_a, 0);
+                path.push((b.entry, top));
+                path
+            })
+            .collect();
+        let edges = b.enter(paths);
+        a.blocks.extend(b.blocks);
+        a.edges.extend(edges);
+        a.edges.extend(b.edges);
+        exits.extend(b.exits);
+        a.exits = exits;
+        a.width = a.width.max(b.width);
+        a.height = b.height;
+        a.fit();
+        a.extend_jumps();
+        a
+    }
+
+    /// Leads the fall throughs of `branches` to the point under the entry, and
+    /// the jumps of all but the last branch down the lanes after them.
+    fn merge(&self, region: &mut Region, branches: Vec<Region>, x: usize) {
+        let bottom = branches
+            .iter()
+            .map(|branch| branch.height)
+            .max()
+            .unwrap_or(0);
+        let y = bottom + self.distance / 2;
+        let count = branches.len();
+        let mut k = 0;
+        for (i, branch) in branches.into_iter().enumerate() {
+            let right = branch.width;
+            let mut lane = right;
+            for mut exit in branch.exits {
+                let (end_x, end_y) = exit.path.end();
+                if exit.kind == ExitKind::Next {
+                    exit.path.push((end_x, y));
+                    exit.path.push((x, y));
+                } else if i + 1 < count {
+                    k += 1;
+                    lane += self.lane;
+                    exit.path.push((lane, end_y));
+                    exit.path.push((lane, y + k * self.lane));
+                }
+                region.exits.push(exit);
+            }
+            region.blocks.extend(branch.blocks);
+            region.edges.extend(branch.edges);
+        }
+        region.height = y;
+        region.fit();
+        region.extend_jumps();
+    }
+
+    fn layout_if(
+        &self,
+        condition: &str,
+        then_body: &[Statement],
+        else_body: &[Statement],
+        index: usize,
+    ) -> Region {
+        let mut decision = self.build(BlockKind::Decision, String::from(condition));
+        let t = self.layout_body(then_body, index);
+        let e_index = index + count(then_body);
+        let e = self.layout_body(else_body, e_index);
+        // The branch under the decision runs on into the next statement, so
+        // a branch that only jumps away goes to the side instead.
+        let only_jumps = |region: &Region| region.is_empty() && region.next_exit().is_none();
+        let ((mut t, yes), (mut e, no)) = if only_jumps(&t) && !only_jumps(&e) {
+            ((e, "no"), (t, "yes"))
+        } else {
+            ((t, "yes"), (e, "no"))
+        };
+        let x = (decision.width() / 2).max(t.entry);
+        decision.displace(x - decision.width() / 2, 0);
+        let top = decision.height() + self.distance;
+        t.displace(x - t.entry, top);
+        let (right_x, right_y) = decision.right_pos();
+        let left = (t.width + (t.count_jumps() + 1) * self.lane).max(right_x + self.lane);
+        let no = if e.is_empty() {
+            e.displace(left, right_y);
+            Path::labeled(vec![(right_x, right_y), (left, right_y)], no, 0)
+        } else {
+            e.displace(left, top);
+            Path::labeled(
+                vec![(right_x, right_y), (e.entry, right_y), (e.entry, top)],
+                no,
+                0,
+            )
+        };
+        let yes = Path::labeled(vec![decision.bottom_pos(), (x, top)], yes, 0);
+        let mut region = Region {
+            width: 0,
+            height: 0,
+            entry: x,
+            blocks: vec![decision],
+            edges: t.enter(vec![yes]),
+            exits: vec![],
+        };
+        region.edges.extend(e.enter(vec![no]));
+        self.merge(&mut region, vec![t, e], x);
+        region
+    }
+
+    fn layout_switch(
+        &self,
+        subject: &str,
+        cases: &[(String, Vec<Statement>)],
+        fall_through: bool,
+        mut index: usize,
+    ) -> Region {
+        let mut decision = self.build(BlockKind::Decision, String::from(subject));
+        let top = decision.height() + self.distance;
+        let bus = decision.height() + self.distance / 2;
+        let mut arms = vec![];
+        let mut tops = vec![];
+        let (mut left, mut arm_top) = (0, top);
+        for (_, body) in cases {
+            let mut arm = self.layout_body(body, index);
+            index += count(body);
+            arm.displace(left, arm_top);
+            left = arm.width + (arm.count_jumps() + 1) * self.lane;
+            tops.push(arm_top);
+            // A case that falls through leads down into the next one, which
+            // therefore starts under it.
+            arm_top = match arm.next_exit() {
+                Some(_) if fall_through => arm.height + self.distance,
+                _ => top,
+            };
+            arms.push(arm);
+        }
+        let middle = match (arms.first(), arms.last()) {
+            (Some(first), Some(last)) => (first.entry + last.entry) / 2,
+            _ => 0,
+        };
+        let x = middle.max(decision.width() / 2);
+        arms.iter_mut().for_each(|arm| arm.displace(x - middle, 0));
+        decision.displace(x - decision.width() / 2, 0);
+        let mut region = Region {
+            width: 0,
+            height: 0,
+            entry: x,
+            blocks: vec![],
+            edges: vec![],
+            exits: vec![],
+        };
+        let mut falls = vec![];
+        for i in 0..arms.len() {
+            let next = arms.get(i + 1).map(|arm| (arm.entry, tops[i + 1]));
+            let arm = &mut arms[i];
+            let mut paths = vec![Path::labeled(
+                vec![
+                    decision.bottom_pos(),
+                    (x, bus),
+                    (arm.entry, bus),
+                    (arm.entry, tops[i]),
+                ],
+                &cases[i].0,
+                2,
+            )];
+            paths.append(&mut falls);
+            region.edges.extend(arm.enter(paths));
+            if !fall_through {
+                continue;
+            }
+            for exit in &mut arm.exits {
+                match (exit.kind, next) {
+                    (ExitKind::Break, _) => exit.kind = ExitKind::Next,
+                    (ExitKind::Next, Some((entry, next_top))) => {
+                        let (end_x, _) = exit.path.end();
+                        let y = arm.height + self.distance / 2;
+                        exit.path.push((end_x, y));
+                        exit.path.push((entry, y));
+                        exit.path.push((entry, next_top));
+                        exit.kind = ExitKind::Break;
+                    }
+                    _ => {}
+                }
+            }
+            // The fall throughs were marked as breaks above, now that the
+            // breaks became fall throughs of the switch.
+            let (moved, exits): (Vec<Exit>, Vec<Exit>) = std::mem::take(&mut arm.exits)
+                .into_iter()
+                .partition(|exit| exit.kind == ExitKind::Break);
+            arm.exits = exits;
+            falls = moved.into_iter().map(|exit| exit.path).collect();
+        }
+        if arms.is_empty() {
+            region.exits.push(Exit {
+                kind: ExitKind::Next,
+                path: Path::new(vec![decision.bottom_pos()]),
+            });
+        }
+        region.blocks.push(decision);
+        self.merge(&mut region, arms, x);
+        region
+    }
+
+    fn layout_loop(&self, kind: &LoopKind, body: &[Statement], index: usize) -> Region {
+        let header = match kind {
+            LoopKind::Infinite => None,
+            LoopKind::While(condition) => Some((condition, "yes", "no")),
+            LoopKind::For(header) => Some((header, "next", "done")),
+            LoopKind::DoWhile(_) => unreachable!(),
+        };
+        let mut b = self.layout_body(body, index);
+        if b.is_empty() && header.is_none() {
+            b = self.then(self.placeholder(), b);
+        }
+        // The back edges run up a lane on the left.
+        let back_lane = self.lane;
+        let mut region = Region {
+            width: 0,
+            height: 0,
+            entry: 0,
+            blocks: vec![],
+            edges: vec![],
+            exits: vec![],
+        };
+        let (target, exit) = match header {
+            Some((text, yes, no)) => {
+                let mut decision = self.build(BlockKind::Decision, text.clone());
+                let x = (2 * self.lane + decision.width() / 2).max(2 * self.lane + b.entry);
+                decision.displace(x - decision.width() / 2, 0);
+                let top = decision.height() + self.distance;
+                b.displace(x - b.entry, top);
+                let path = Path::labeled(vec![decision.bottom_pos(), (x, top)], yes, 0);
+                region.edges.extend(b.enter(vec![path]));
+                region.entry = x;
+                let target = decision.left_pos();
+                let exit = Path::labeled(vec![decision.right_pos()], no, 0);
+                region.blocks.push(decision);
+                (target, Some(exit))
+            }
+            None => {
+                let x = 2 * self.lane + b.entry;
+                b.displace(x - b.entry, 0);
+                region.entry = x;
+                (b.first_block().left_pos(), None)
+            }
+        };
+        let back = b.height + self.distance / 2;
+        let right = b.width.max(
+            region
+                .blocks
+                .first()
+                .map_or(0, |d| d.bounds().x + d.width()),
+        );
+        let mut continues = 0;
+        let mut breaks = vec![];
+        let mut jumps = 0;
+        for mut exit in std::mem::take(&mut b.exits) {
+            let (end_x, end_y) = exit.path.end();
+            match exit.kind {
+                ExitKind::Next => {
+                    exit.path.push((end_x, back));
+                    exit.path.push((back_lane, back));
+                    exit.path.push((back_lane, target.1));
+                    exit.path.push(target);
+                    region.edges.push(exit.path);
+                }
+                ExitKind::Continue => {
+                    jumps += 1;
+                    continues += 1;
+                    let lane = right + jumps * self.lane;
+                    let y = back + continues * self.lane;
+                    exit.path.push((lane, end_y));
+                    exit.path.push((lane, y));
+                    exit.path.push((back_lane, y));
+                    exit.path.push((back_lane, target.1));
+                    exit.path.push(target);
+                    region.edges.push(exit.path);
+                }
+                ExitKind::Break => {
+                    jumps += 1;
+                    exit.path.push((right + jumps * self.lane, end_y));
+                    breaks.push(exit.path);
+                }
+                ExitKind::Return => region.exits.push(exit),
+            }
+        }
+        let bottom = back + continues * self.lane + self.distance / 2;
+        let mut nexts = breaks;
+        if let Some(mut exit) = exit {
+            let (_, y) = exit.end();
+            exit.push((right + (jumps + 1) * self.lane, y));
+            nexts.push(exit);
+        }
+        for mut path in nexts {
+            let (x, _) = path.end();
+            path.push((x, bottom));
+            path.push((region.entry, bottom));
+            region.exits.push(Exit {
+                kind: ExitKind::Next,
+                path,
+            });
+        }
+        region.blocks.extend(b.blocks);
+        region.edges.extend(b.edges);
+        region.height = bottom;
+        region.fit();
+        region.extend_jumps();
+        region
+    }
+
+    /// A block for a loop body without one, for the back edge to enter.
+    fn placeholder(&self) -> Region {
+        self.block_region(self.build(BlockKind::Process, String::from("loop")))
+    }
+
+    /// Lays out the body first and the condition under it, whose `yes` leads
+    /// back up to the body.
+    fn layout_do_while(&self, condition: &str, body: &[Statement], index: usize) -> Region {
+        let mut b = self.layout_body(body, index);
+        if b.is_empty() {
+            b = self.then(self.placeholder(), b);
+        }
+        let back_lane = self.lane;
+        let mut decision = self.build(BlockKind::Decision, String::from(condition));
+        let x = (2 * self.lane + decision.width() / 2).max(2 * self.lane + b.entry);
+        b.displace(x - b.entry, 0);
+        let top = b.height + self.distance;
+        decision.displace(x - decision.width() / 2, top);
+        let right = b.width.max(decision.bounds().x + decision.width());
+        let bottom = decision.bottom_pos().1 + self.distance / 2;
+        let mut region = Region {
+            width: 0,
+            height: 0,
+            entry: x,
+            blocks: vec![],
+            edges: vec![],
+            exits: vec![],
+        };
+        let mut nexts = vec![Path::labeled(
+            vec![decision.bottom_pos(), (x, bottom)],
+            "no",
+            0,
+        )];
+        let mut jumps = 0;
+        for mut exit in std::mem::take(&mut b.exits) {
+            let (end_x, end_y) = exit.path.end();
+            match exit.kind {
+                ExitKind::Next => {
+                    exit.path.push((end_x, top));
+                    region.edges.push(exit.path);
+                }
+                ExitKind::Continue => {
+                    jumps += 1;
+                    let lane = right + jumps * self.lane;
+                    let y = top - self.distance / 2;
+                    exit.path.push((lane, end_y));
+                    exit.path.push((lane, y));
+                    exit.path.push((x, y));
+                    exit.path.push((x, top));
+                    region.edges.push(exit.path);
+                }
+                ExitKind::Break => {
+                    jumps += 1;
+                    let lane = right + jumps * self.lane;
+                    exit.path.push((lane, end_y));
+                    exit.path.push((lane, bottom));
+                    exit.path.push((x, bottom));
+                    nexts.push(exit.path);
+                }
+                ExitKind::Return => region.exits.push(exit),
+            }
+        }
+        let target = b.first_block().left_pos();
+        let (left_x, left_y) = decision.left_pos();
+        region.edges.push(Path::labeled(
+            vec![
+                (left_x, left_y),
+                (back_lane, left_y),
+                (back_lane, target.1),
+                target,
+            ],
+            "yes",
+            0,
+        ));
+        region.exits.extend(nexts.into_iter().map(|path| Exit {
+            kind: ExitKind::Next,
+            path,
+        }));
+        region.blocks.extend(b.blocks);
+        region.blocks.push(decision);
+        region.edges.extend(b.edges);
+        region.height = bottom;
+        region.fit();
+        region.extend_jumps();
+        region
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::ConfigBuilder, frontend, parser::parse};
+
+    /// The `data-` attributes of the blocks of `svg` that have a line.
+    fn block_lines(svg: &str) -> Vec<&str> {
+        svg.split(r#"<g class="block" "#)
+            .skip(1)
+            .map(|rest| &rest[..rest.find('>').unwrap()])
+            .filter(|data| data.contains("data-line"))
+            .collect()
+    }
+
+    #[test]
+    fn blocks_keep_the_span_of_their_statement() {
+        let config = ConfigBuilder::new().build();
+        let source =
+            "proc main\n    io read n\n    if n > 1\n        process n = 1\n    end\nend\n";
+        let mut program = parse(source).unwrap();
+        program.set_file("a.flow");
+        let procedure = &program.procedures[0];
+        let link = |_: &str| None;
+        let layout = Layouter::new(&config, &link).layout(procedure);
+        for index in 0..procedure.statements().len() {
+            let block = layout.block_of(index).unwrap();
+            assert_eq!(block.span(), procedure.span(index));
+        }
+        // The terminals show the header and the end of the procedure.
+        let (start, _) = layout.terminals();
+        assert!(start.span().is_some_and(|span| span.line == 1));
+        let svg = procedure.to_svg(&config, &link).to_string();
+        assert!(block_lines(&svg).contains(
+            &r#"data-file="a.flow" data-line="4" data-column="9" data-end-line="4" data-end-column="22""#
+        ));
+    }
+
+    #[test]
+    fn directions() {
+        let source = "proc main\n  io read n\n  if n > 1\n    process n = 1\n  end\nend\n";
+        let procedure = &parse(source).unwrap().procedures[0];
+        let link = |_: &str| None;
+        let mut svgs = vec![];
+        for direction in [
+            Direction::TopToBottom,
+            Direction::BottomToTop,
+            Direction::LeftToRight,
+            Direction::RightToLeft,
+        ] {
+            let config = ConfigBuilder::new().direction(direction).build();
+            let layout = Layouter::new(&config, &link).layout(procedure);
+            let (start, end) = layout.terminals();
+            let ((start_x, start_y), (end_x, end_y)) = (start.pos(), end.pos());
+            let flows = match direction {
+                Direction::TopToBottom => start_x == end_x && start_y < end_y,
+                Direction::BottomToTop => start_x == end_x && start_y > end_y,
+                Direction::LeftToRight => start_x < end_x && start_y == end_y,
+                Direction::RightToLeft => start_x > end_x && start_y == end_y,
+            };
+            assert!(flows, "the terminals of {:?}", (start.pos(), end.pos()));
+            // Blocks keep their size and never overlap when turned.
+            let read = layout.block_of(0).unwrap();
+            assert!(read.width() > read.height());
+            let blocks = layout.blocks();
+            for (i, a) in blocks.iter().enumerate() {
+                for b in &blocks[i + 1..] {
+                    assert!(!a.bounds().overlaps(&b.bounds()));
+                }
+            }
+            // Every edge runs from one block to the next in the direction.
+            let process = layout.block_of(2).unwrap();
+            let edges = layout.edges_between(read, layout.block_of(1).unwrap(), None);
+            assert_eq!(edges.len(), 1);
+            assert_eq!(edges[0][0], read.exit_pos(direction));
+            let decision = layout.block_of(1).unwrap();
+            assert_eq!(
+                layout.edges_between(decision, process, Some("yes"))[0].last(),
+                Some(&process.entry_pos(direction))
+            );
+            svgs.push(procedure.to_svg(&config, &link).to_string());
+        }
+        for (i, svg) in svgs.iter().enumerate() {
+            assert!(!svgs[i + 1..].contains(svg));
+        }
+    }
+
+    #[test]
+    fn frontends_track_spans() {
+        let config = ConfigBuilder::new().build();
+        let sources = [
+            (
+                "a.rs",
+                "fn f(x: u32) -> u32 {\n    let y = x + 1;\n    y\n}\n",
+            ),
+            ("a.py", "def f(x):\n    y = x + 1\n    return y\n"),
+            (
+                "a.c",
+                "int f(int x) {\n    int y = x + 1;\n    return y;\n}\n",
+            ),
+        ];
+        for (path, source) in sources {
+            let input = frontend::read(std::path::Path::new(path), source).unwrap();
+            let svgs = input.to_svgs(&config, false, &|stem| format!("{}.svg", stem));
+            let svg = svgs[0].1.to_string();
+            let lines = block_lines(&svg);
+            assert!(
+                lines.iter().any(|data| data.contains(r#"data-line="2""#)),
+                "{}: {:?}",
+                path,
+                lines
+            );
+            assert!(lines
+                .iter()
+                .all(|data| data.contains(&format!(r#"data-file="{}""#, path))));
+        }
+    }
+}
@@ -0,0 +1,802 @@
+//! Recovers structured control flow from an arbitrary graph.
+//!
+//! The entry of the graph is its first node without predecessors. Loops are
+//! found as natural loops: a loop is headed by every node that dominates a
+//! predecessor of its own, and holds the nodes that reach that predecessor
+//! without passing the header. A loop whose header is a decision leaving it
+//! becomes a `while`, and one whose only way back is a decision leaving it
+//! becomes a `do`-`while`. Every other decision becomes an `if` or a
+//! `switch` that ends at the node immediately post-dominating it.
+//!
+//! Flow that does not fit these constructs is kept as `goto`s to labels, so
+//! nothing of the graph is lost. Nodes not reachable from the entry are left
+//! out.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    block::{BlockKind, Span},
+    cfg::Graph,
+    chart::{LoopKind, Procedure, Statement},
+};
+
+/// Where the flow goes after the last node, as if it were a node itself.
+const EXIT: usize = usize::MAX;
+
+/// What running off the end of a sequence of statements leads to.
+#[derive(Clone, Copy)]
+enum Scope {
+    /// The node after the construct the sequence is a branch of.
+    Follow(usize),
+    /// The node `continue` goes to and the node `break` goes to, if any.
+    Loop {
+        header: usize,
+        continue_to: usize,
+        break_to: Option<usize>,
+    },
+}
+
+impl Scope {
+    fn fall(&self) -> usize {
+        match *self {
+            Scope::Follow(node) => node,
+            Scope::Loop { continue_to, .. } => continue_to,
+        }
+    }
+}
+
+/// How the flow reaches a node from where it is.
+enum Flow {
+    /// It runs off the end of the sequence.
+    Fall,
+    /// It jumps there.
+    Jump(Statement),
+    /// The node comes next in the sequence.
+    Inline,
+}
+
+struct NaturalLoop {
+    body: Vec<bool>,
+    follow: Option<usize>,
+    /// The immediate post-dominators within the body, where the way back
+    /// to the header and the ways out all end the flow.
+    post_dominators: Vec<Option<usize>>,
+}
+
+struct Structurer<'a> {
+    graph: &'a Graph,
+    successors: Vec<Vec<(usize, Option<&'a str>)>>,
+    predecessors: Vec<Vec<usize>>,
+    post_dominators: Vec<Option<usize>>,
+    loops: HashMap<usize, NaturalLoop>,
+    emitted: Vec<bool>,
+    pending: Vec<usize>,
+    /// The spans of the statements laid out so far, in pre-order.
+    spans: Vec<Option<Span>>,
+}
+
+/// Rebuilds a graph as a procedure named after its entry, if that is a
+/// terminal, or `start` otherwise. Returns `None` if the graph has no nodes.
+pub fn structure(graph: &Graph) -> Option<Procedure> {
+    let n = graph.nodes.len();
+    let index: HashMap<&str, usize> = graph
+        .nodes
+        .iter()
+        .enumerate()
+        .map(|(i, node)| (node.id.as_str(), i))
+        .collect();
+    let mut successors = vec![vec![]; n];
+    let mut predecessors = vec![vec![]; n];
+    for edge in &graph.edges {
+        let (from, to) = (index[edge.from.as_str()], index[edge.to.as_str()]);
+        successors[from].push((to, edge.label.as_deref()));
+        predecessors[to].push(from);
+    }
+    let entry = (0..n)
+        .find(|&node| predecessors[node].is_empty())
+        .or((n > 0).then_some(0))?;
+
+    let targets: Vec<Vec<usize>> = successors
+        .iter()
+        .map(|successors| successors.iter().map(|&(to, _)| to).collect())
+        .collect();
+    let order = reverse_postorder(&targets, entry);
+    let dominators = immediate_dominators(&targets, entry);
+    let mut reversed: Vec<Vec<usize>> = predecessors.clone();
+    reversed.push((0..n).filter(|&node| successors[node].is_empty()).collect());
+    let post_dominators = immediate_post_dominators(&reversed, n);
+
+    let mut loops = HashMap::new();
+    for &header in &order {
+        let latches: Vec<usize> = predecessors[header]
+            .iter()
+            .copied()
+            .filter(|&node| dominates(&dominators, header, node))
+            .collect();
+        if latches.is_empty() {
+            continue;
+        }
+        let mut body = vec![false; n];
+        body[header] = true;
+        let mut stack = latches;
+        while let Some(node) = stack.pop() {
+            if !body[node] && dominators[node].is_some() {
+                body[node] = true;
+                stack.extend(&predecessors[node]);
+            }
+        }
+        let leaving = |node: usize| {
+            successors[node]
+                .iter()
+                .map(|&(to, _)| to)
+                .find(|&to| !body[to])
+        };
+        let follow = leaving(header).or_else(|| {
+            order
+                .iter()
+                .filter(|&&node| body[node])
+                .find_map(|&node| leaving(node))
+        });
+        // Within the loop, branches meet again before going back to the
+        // header, and edges leaving it are `break`s that need no meeting.
+        let mut reversed = vec![vec![]; n + 1];
+        for node in (0..n).filter(|&node| body[node]) {
+            let inner: Vec<usize> = targets[node]
+                .iter()
+                .copied()
+                .filter(|&to| body[to] && to != header)
+                .collect();
+            if targets[node].contains(&header) || inner.is_empty() {
+                reversed[n].push(node);
+            }
+            inner.iter().for_each(|&to| reversed[to].push(node));
+        }
+        let post_dominators = immediate_post_dominators(&reversed, n);
+        loops.insert(
+            header,
+            NaturalLoop {
+                body,
+                follow,
+                post_dominators,
+            },
+        );
+    }
+
+    let mut structurer = Structurer {
+        graph,
+        successors,
+        predecessors,
+        post_dominators,
+        loops,
+        emitted: vec![false; n],
+        pending: vec![],
+        spans: vec![],
+    };
+    let scopes = [Scope::Follow(EXIT)];
+    let entry_node = &graph.nodes[entry];
+    let (name, span, mut body) = match (entry_node.kind, structurer.successors[entry].as_slice()) {
+        (BlockKind::Terminal, [(next, _)]) if *next != entry => {
+            structurer.emitted[entry] = true;
+            let next = *next;
+            (
+                entry_node.label.clone(),
+                entry_node.span.clone(),
+                structurer.sequence(next, &scopes, true),
+            )
+        }
+        _ => (
+            String::from("start"),
+            None,
+            structurer.sequence(entry, &scopes, true),
+        ),
+    };
+    // Whatever is only reached by a jump follows the rest, behind its label.
+    while let Some(node) = structurer.pending.pop() {
+        if !structurer.emitted[node] {
+            body.push(Statement::Return(None));
+            structurer.spans.push(None);
+            body.extend(structurer.sequence(node, &scopes, true));
+        }
+    }
+    let mut gotos = HashSet::new();
+    collect_gotos(&body, &mut gotos);
+    let mut procedure = Procedure {
+        name,
+        body,
+        span,
+        spans: structurer.spans,
+    };
+    let kept: Vec<bool> = procedure
+        .statements()
+        .iter()
+        .map(|statement| !matches!(statement, Statement::Label(label) if !gotos.contains(label)))
+        .collect();
+    let mut kept = kept.into_iter();
+    procedure.spans.retain(|_| kept.next().unwrap());
+    remove_labels(&mut procedure.body, &gotos);
+    Some(procedure)
+}
+
+/// Whether a procedure has a `goto`, so its flow is not fully structured.
+pub fn has_goto(body: &[Statement]) -> bool {
+    let mut gotos = HashSet::new();
+    collect_gotos(body, &mut gotos);
+    !gotos.is_empty()
+}
+
+impl Structurer<'_> {
+    /// The statements from `node` on, up to where the flow leaves the
+    /// innermost scope. With `inline`, `node` is laid out here even if other
+    /// nodes lead to it.
+    fn sequence(&mut self, mut node: usize, scopes: &[Scope], mut inline: bool) -> Vec<Statement> {
+        let mut body = vec![];
+        loop {
+            match self.flow(node, scopes, inline) {
+                Flow::Fall => break,
+                Flow::Jump(statement) => {
+                    body.push(statement);
+                    self.spans.push(None);
+                    break;
+                }
+                Flow::Inline => {}
+            }
+            self.emitted[node] = true;
+            body.push(Statement::Label(self.graph.nodes[node].id.clone()));
+            self.spans.push(self.graph.nodes[node].span.clone());
+            let next = match self.loops.contains_key(&node) {
+                true => self.emit_loop(node, scopes, &mut body),
+                false => self.emit(node, scopes, &mut body),
+            };
+            match next {
+                Some(next) => node = next,
+                None => break,
+            }
+            inline = true;
+        }
+        body
+    }
+
+    fn flow(&mut self, node: usize, scopes: &[Scope], inline: bool) -> Flow {
+        if node == scopes[scopes.len() - 1].fall() {
+            return Flow::Fall;
+        }
+        let innermost_loop = scopes.iter().rev().find_map(|scope| match *scope {
+            Scope::Loop {
+                continue_to,
+                break_to,
+                ..
+            } => Some((continue_to, break_to)),
+            Scope::Follow(_) => None,
+        });
+        match innermost_loop {
+            Some((continue_to, _)) if node == continue_to => Flow::Jump(Statement::Continue),
+            Some((_, Some(break_to))) if node == break_to => Flow::Jump(Statement::Break),
+            _ if node == EXIT => Flow::Jump(Statement::Return(None)),
+            _ if self.emitted[node] => self.goto(node),
+            _ if inline
+                || (self.predecessors[node].len() == 1 && !self.loops.contains_key(&node)) =>
+            {
+                Flow::Inline
+            }
+            _ => {
+                self.pending.push(node);
+                self.goto(node)
+            }
+        }
+    }
+
+    fn goto(&self, node: usize) -> Flow {
+        Flow::Jump(Statement::Goto(self.graph.nodes[node].id.clone()))
+    }
+
+    /// Lays out a node that does not head a loop, and returns where the flow
+    /// goes after it, if anywhere.
+    fn emit(&mut self, node: usize, scopes: &[Scope], body: &mut Vec<Statement>) -> Option<usize> {
+        let graph_node = &self.graph.nodes[node];
+        let label = graph_node.label.clone();
+        let successors = &self.successors[node];
+        match (graph_node.kind, successors.len()) {
+            (BlockKind::Terminal, 0) => {}
+            (_, 2..) => return self.emit_branch(node, scopes, body),
+            (BlockKind::Subroutine, _) => body.push(Statement::Call(label)),
+            (BlockKind::Annotation, _) => body.push(Statement::Annotation(label)),
+            (kind, _) => body.push(Statement::Block(kind, label)),
+        }
+        if graph_node.kind != BlockKind::Terminal || !successors.is_empty() {
+            self.spans.push(graph_node.span.clone());
+        }
+        Some(successors.first().map_or(EXIT, |&(to, _)| to))
+    }
+
+    fn emit_branch(
+        &mut self,
+        node: usize,
+        scopes: &[Scope],
+        body: &mut Vec<Statement>,
+    ) -> Option<usize> {
+        let follow = self.follow(node, scopes);
+        let mut inner = scopes.to_vec();
+        inner.extend(follow.map(Scope::Follow));
+        // The labels of every distinct target.
+        let mut targets: Vec<(usize, Vec<Option<&str>>)> = vec![];
+        for &(to, label) in &self.successors[node] {
+            match targets.iter_mut().find(|(target, _)| *target == to) {
+                Some((_, labels)) => labels.push(label),
+                None => targets.push((to, vec![label])),
+            }
+        }
+        let branch = |this: &mut Self, to: usize| match Some(to) == follow {
+            true => vec![],
+            false => this.sequence(to, &inner, false),
+        };
+        let condition = self.graph.nodes[node].label.clone();
+        // The decision comes before the statements of its branches.
+        self.spans.push(self.graph.nodes[node].span.clone());
+        match if_else(&targets) {
+            Some((then, otherwise)) => {
+                let then_body = branch(self, then);
+                let else_body = branch(self, otherwise);
+                body.push(Statement::If {
+                    condition,
+                    then_body,
+                    else_body,
+                });
+            }
+            None => {
+                let cases = targets
+                    .iter()
+                    .map(|(to, labels)| {
+                        let labels: Vec<&str> = labels.iter().flatten().copied().collect();
+                        (labels.join(", "), branch(self, *to))
+                    })
+                    .collect();
+                body.push(Statement::Switch {
+                    subject: condition,
+                    cases,
+                    fall_through: false,
+                });
+            }
+        }
+        follow
+    }
+
+    /// Where the branches of a decision meet again: the node immediately
+    /// post-dominating it within the innermost loop, if any.
+    fn follow(&self, node: usize, scopes: &[Scope]) -> Option<usize> {
+        let header = scopes.iter().rev().find_map(|scope| match *scope {
+            Scope::Loop { header, .. } => Some(header),
+            Scope::Follow(_) => None,
+        });
+        let post_dominators = match header {
+            Some(header) => &self.loops[&header].post_dominators,
+            None => &self.post_dominators,
+        };
+        post_dominators[node].filter(|&follow| follow != EXIT)
+    }
+
+    /// Lays out the loop headed by `header`, and returns the node after it.
+    fn emit_loop(
+        &mut self,
+        header: usize,
+        scopes: &[Scope],
+        body: &mut Vec<Statement>,
+    ) -> Option<usize> {
+        let natural_loop = &self.loops[&header];
+        let follow = natural_loop.follow;
+        let in_loop = |node: usize| natural_loop.body[node];
+        let decision = |node: usize| {
+            let targets: Vec<(usize, Vec<Option<&str>>)> = self.successors[node]
+                .iter()
+                .map(|&(to, label)| (to, vec![label]))
+                .collect();
+            match self.graph.nodes[node].kind {
+                BlockKind::Decision => if_else(&targets),
+                _ => None,
+            }
+        };
+        let latches: Vec<usize> = self.predecessors[header]
+            .iter()
+            .copied()
+            .filter(|&node| in_loop(node))
+            .collect();
+
+        let (kind, start, continue_to) = match decision(header) {
+            Some((then, otherwise)) if in_loop(then) && Some(otherwise) == follow => (
+                LoopKind::While(self.graph.nodes[header].label.clone()),
+                Some(then),
+                header,
+            ),
+            _ => match latches.as_slice() {
+                &[latch]
+                    if latch != header
+                        && !self.emitted[latch]
+                        && self.predecessors[latch].iter().all(|&node| in_loop(node))
+                        && decision(latch).is_some_and(|(then, otherwise)| {
+                            then == header && Some(otherwise) == follow
+                        }) =>
+                {
+                    (
+                        LoopKind::DoWhile(self.graph.nodes[latch].label.clone()),
+                        None,
+                        latch,
+                    )
+                }
+                _ => (LoopKind::Infinite, None, header),
+            },
+        };
+        // The loop comes before the statements of its body, and is shown by
+        // the decision that repeats it.
+        let span = match kind {
+            LoopKind::While(_) => self.graph.nodes[header].span.clone(),
+            LoopKind::DoWhile(_) => self.graph.nodes[continue_to].span.clone(),
+            _ => None,
+        };
+        self.spans.push(span);
+        let scopes = [
+            scopes,
+            &[Scope::Loop {
+                header,
+                continue_to,
+                break_to: follow,
+            }],
+        ]
+        .concat();
+        let loop_body = match start {
+            Some(start) => self.sequence(start, &scopes, true),
+            None => {
+                let mut loop_body = vec![];
+                if let Some(next) = self.emit(header, &scopes, &mut loop_body) {
+                    loop_body.extend(self.sequence(next, &scopes, true));
+                }
+                loop_body
+            }
+        };
+        self.emitted[continue_to] = true;
+        body.push(Statement::Loop {
+            kind,
+            body: loop_body,
+        });
+        follow
+    }
+}
+
+/// Which of two targets is the `yes` branch of a decision and which the
+/// `no` branch, if their labels say so or there are none.
+fn if_else(targets: &[(usize, Vec<Option<&str>>)]) -> Option<(usize, usize)> {
+    let [(a, a_labels), (b, b_labels)] = targets else {
+        return None;
+    };
+    let polarity = |labels: &[Option<&str>]| match labels {
+        [None] => Some(None),
+        [Some(label)] => match label.to_lowercase().as_str() {
+            "yes" | "true" | "y" | "t" => Some(Some(true)),
+            "no" | "false" | "n" | "f" => Some(Some(false)),
+            _ => None,
+        },
+        _ => None,
+    };
+    match (polarity(a_labels)?, polarity(b_labels)?) {
+        (Some(true) | None, Some(false) | None) => Some((*a, *b)),
+        (Some(false) | None, Some(true) | None) => Some((*b, *a)),
+        _ => None,
+    }
+}
+
+fn reverse_postorder(successors: &[Vec<usize>], entry: usize) -> Vec<usize> {
+    let mut visited = vec![false; successors.len()];
+    let mut order = vec![];
+    let mut stack = vec![(entry, 0)];
+    visited[entry] = true;
+    while let Some((node, next)) = stack.last_mut() {
+        match successors[*node].get(*next) {
+            Some(&to) => {
+                *next += 1;
+                if !visited[to] {
+                    visited[to] = true;
+                    stack.push((to, 0));
+                }
+            }
+            None => {
+                order.push(*node);
+                stack.pop();
+            }
+        }
+    }
+    order.reverse();
+    order
+}
+
+/// The immediate dominator of every node reachable from `entry`, by the
+/// iterative algorithm of Cooper, Harvey and Kennedy. The entry is its own.
+fn immediate_dominators(successors: &[Vec<usize>], entry: usize) -> Vec<Option<usize>> {
+    let order = reverse_postorder(successors, entry);
+    let mut rank = vec![usize::MAX; successors.len()];
+    order
+        .iter()
+        .enumerate()
+        .for_each(|(i, &node)| rank[node] = i);
+    let mut predecessors = vec![vec![]; successors.len()];
+    for (from, successors) in successors.iter().enumerate() {
+        successors
+            .iter()
+            .for_each(|&to| predecessors[to].push(from));
+    }
+    let mut dominators: Vec<Option<usize>> = vec![None; successors.len()];
+    dominators[entry] = Some(entry);
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &node in order.iter().skip(1) {
+            let mut dominator: Option<usize> = None;
+            for &predecessor in &predecessors[node] {
+                if dominators[predecessor].is_none() {
+                    continue;
+                }
+                dominator = Some(match dominator {
+                    None => predecessor,
+                    Some(mut a) => {
+                        let mut b = predecessor;
+                        while a != b {
+                            while rank[a] > rank[b] {
+                                a = dominators[a].unwrap();
+                            }
+                            while rank[b] > rank[a] {
+                                b = dominators[b].unwrap();
+                            }
+                        }
+                        a
+                    }
+                });
+            }
+            if dominator != dominators[node] {
+                dominators[node] = dominator;
+                changed = true;
+            }
+        }
+    }
+    dominators
+}
+
+/// The immediate post-dominator of every node, from the edges of a graph
+/// turned around and an extra node `n` before all the nodes it ends in.
+fn immediate_post_dominators(reversed: &[Vec<usize>], n: usize) -> Vec<Option<usize>> {
+    immediate_dominators(reversed, n)
+        .into_iter()
+        .take(n)
+        .map(|dominator| dominator.map(|node| if node == n { EXIT } else { node }))
+        .collect()
+}
+
+fn dominates(dominators: &[Option<usize>], a: usize, mut b: usize) -> bool {
+    loop {
+        if a == b {
+            return true;
+        }
+        match dominators[b] {
+            Some(dominator) if dominator != b => b = dominator,
+            _ => return false,
+        }
+    }
+}
+
+fn collect_gotos(body: &[Statement], gotos: &mut HashSet<String>) {
+    for statement in body {
+        match statement {
+            Statement::Goto(label) => {
+                gotos.insert(label.clone());
+            }
+            Statement::If {
+                then_body,
+                else_body,
+                ..
+            } => {
+                collect_gotos(then_body, gotos);
+                collect_gotos(else_body, gotos);
+            }
+            Statement::Switch { cases, .. } => {
+                cases
+                    .iter()
+                    .for_each(|(_, body)| collect_gotos(body, gotos));
+            }
+            Statement::Loop { body, .. } => collect_gotos(body, gotos),
+            _ => {}
+        }
+    }
+}
+
+fn remove_labels(body: &mut Vec<Statement>, gotos: &HashSet<String>) {
+    body.retain(
+        |statement| !matches!(statement, Statement::Label(label) if !gotos.contains(label)),
+    );
+    for statement in body {
+        match statement {
+            Statement::If {
+                then_body,
+                else_body,
+                ..
+            } => {
+                remove_labels(then_body, gotos);
+                remove_labels(else_body, gotos);
+            }
+            Statement::Switch { cases, .. } => {
+                cases
+                    .iter_mut()
+                    .for_each(|(_, body)| remove_labels(body, gotos));
+            }
+            Statement::Loop { body, .. } => remove_labels(body, gotos),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chart;
+
+    /// A graph from `start` to `end`, whose nodes are decisions if their
+    /// name ends in `?`, and whose edges are `from to [label]`.
+    fn graph(nodes: &[&str], edges: &[&str]) -> Graph {
+        let nodes: Vec<String> = nodes
+            .iter()
+            .map(|name| {
+                let kind = match *name {
+                    "start" | "end" => "Terminal",
+                    _ if name.ends_with('?') => "Decision",
+                    _ => "Process",
+                };
+                format!(
+                    r#"{{ "id": "{}", "kind": "{}", "label": "{}" }}"#,
+                    name, kind, name
+                )
+            })
+            .collect();
+        let edges: Vec<String> = edges
+            .iter()
+            .map(|edge| {
+                let words: Vec<&str> = edge.split(' ').collect();
+                let label = match words.get(2) {
+                    Some(label) => format!(r#", "label": "{}""#, label),
+                    None => String::new(),
+                };
+                format!(
+                    r#"{{ "from": "{}", "to": "{}"{} }}"#,
+                    words[0], words[1], label
+                )
+            })
+            .collect();
+        let json = format!(
+            r#"{{ "nodes": [{}], "edges": [{}] }}"#,
+            nodes.join(", "),
+            edges.join(", ")
+        );
+        Graph::from_json(&json).unwrap()
+    }
+
+    fn outline(nodes: &[&str], edges: &[&str]) -> Vec<String> {
+        let procedure = structure(&graph(nodes, edges)).unwrap();
+        assert_eq!(procedure.name, "start");
+        chart::outline(&procedure)
+    }
+
+    /// Asserts that a block or a condition of `lines` shows `node`.
+    fn assert_shown(lines: &[String], node: &str) {
+        let shown = lines.iter().any(|line| {
+            let line = line.trim_start();
+            let condition = ["if ", "match ", "while ", "do while "]
+                .iter()
+                .find_map(|keyword| line.strip_prefix(keyword));
+            line == node || condition == Some(node)
+        });
+        assert!(shown, "{} is not in {:?}", node, lines);
+    }
+
+    #[test]
+    fn branches_meet_again() {
+        let lines = outline(
+            &["start", "c?", "a", "b", "d", "end"],
+            &["start c?", "c? a yes", "c? b no", "a d", "b d", "d end"],
+        );
+        assert_eq!(lines, ["if c?", "  a", "else", "  b", "d"]);
+    }
+
+    #[test]
+    fn loops() {
+        let lines = outline(
+            &["start", "w?", "a", "end"],
+            &["start w?", "w? a yes", "a w?", "w? end no"],
+        );
+        assert_eq!(lines, ["while w?", "  a"]);
+        let lines = outline(
+            &["start", "a", "d?", "end"],
+            &["start a", "a d?", "d? a yes", "d? end no"],
+        );
+        assert_eq!(lines, ["do while d?", "  a"]);
+    }
+
+    #[test]
+    fn switches() {
+        let lines = outline(
+            &["start", "s?", "a", "b", "c", "end"],
+            &[
+                "start s?", "s? a 1", "s? b 2", "s? c 3", "a end", "b end", "c end",
+            ],
+        );
+        assert_eq!(
+            lines,
+            ["match s?", "case 1", "  a", "case 2", "  b", "case 3", "  c"]
+        );
+    }
+
+    #[test]
+    fn loops_with_several_exits() {
+        // The loop is left from its header and from its body.
+        let lines = outline(
+            &["start", "w?", "f?", "a", "b", "end"],
+            &[
+                "start w?",
+                "w? f? yes",
+                "w? b no",
+                "f? b yes",
+                "f? a no",
+                "a w?",
+                "b end",
+            ],
+        );
+        assert_eq!(
+            lines,
+            ["while w?", "  if f?", "    break", "  else", "  a", "b"]
+        );
+        // The exits lead to different places.
+        let procedure = structure(&graph(
+            &["start", "w?", "f?", "a", "x", "y", "end"],
+            &[
+                "start w?",
+                "w? f? yes",
+                "w? x no",
+                "f? y found",
+                "f? a else",
+                "a w?",
+                "x end",
+                "y end",
+            ],
+        ))
+        .unwrap();
+        let lines = chart::outline(&procedure);
+        for node in ["w?", "f?", "a", "x", "y"] {
+            assert_shown(&lines, node);
+        }
+    }
+
+    #[test]
+    fn irreducible_flow_keeps_every_node() {
+        // The cycle between `a` and `b` can be entered at either.
+        let graph = graph(
+            &["start", "c?", "a", "b", "end"],
+            &["start c?", "c? a yes", "c? b no", "a b", "b a", "a end"],
+        );
+        let procedure = structure(&graph).unwrap();
+        assert!(has_goto(&procedure.body));
+        let lines = chart::outline(&procedure);
+        for node in ["c?", "a", "b"] {
+            assert_shown(&lines, node);
+        }
+        // Every `goto` has its label.
+        for line in &lines {
+            if let Some(label) = line.trim_start().strip_prefix("goto ") {
+                assert!(lines
+                    .iter()
+                    .any(|line| line.trim_start() == format!("label {}", label)));
+            }
+        }
+    }
+
+    #[test]
+    fn unreachable_nodes_are_left_out() {
+        let lines = outline(
+            &["start", "a", "lost", "end"],
+            &["start a", "a end", "lost end"],
+        );
+        assert_eq!(lines, ["a"]);
+    }
+}
@@ -1,9 +1,23 @@
-use std::collections::BTreeMap;
+use std::{
+    collections::BTreeMap,
+    fmt,
+    io::{self, BufWriter},
+};
 
-use crate::config::Config;
+use crate::{
+    config::{Config, Direction},
+    xml::XmlWriter,
+};
 
 pub enum SvgShape {
     Group(Vec<SvgShape>),
+    /// A group with a `class`, such as `block` for the shapes of a block,
+    /// and `data-` attributes given without the prefix.
+    Classed {
+        class: String,
+        data: Vec<(String, String)>,
+        shapes: Vec<SvgShape>,
+    },
     Grid {
         size: usize,
         x_count: usize,
@@ -45,11 +59,52 @@ pub enum SvgShape {
         width: usize,
         height: usize,
     },
+    Subroutine {
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    },
+    /// A comment: a dashed leader from the left edge to an open bracket
+    /// around the text.
+    Annotation {
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        leader: usize,
+    },
+    Frame {
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        r: usize,
+    },
+    Link {
+        href: String,
+        shape: Box<SvgShape>,
+    },
     DownArrow {
         x: usize,
         y: usize,
         height: usize,
     },
+    UpArrow {
+        x: usize,
+        y: usize,
+        height: usize,
+    },
+    RightArrow {
+        x: usize,
+        y: usize,
+        width: usize,
+    },
+    LeftArrow {
+        x: usize,
+        y: usize,
+        width: usize,
+    },
     Circle {
         cx: usize,
         cy: usize,
@@ -60,11 +115,61 @@ pub enum SvgShape {
         cy: usize,
         content: String,
     },
+    Background {
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    },
+    /// A shape shown only in `spans`, the `(start, end)` seconds of a timeline
+    /// that repeats every `period` seconds.
+    Timed {
+        class: String,
+        period: f64,
+        spans: Vec<(f64, f64)>,
+        shape: Box<SvgShape>,
+    },
+    /// A shape whose strokes fade in and out every `period` seconds.
+    Pulse {
+        period: f64,
+        shape: Box<SvgShape>,
+    },
+}
+
+impl SvgShape {
+    /// Builds a straight arrow of `length` that starts at `from` and points
+    /// in `direction`. An arrow pointing up or left stops at the origin
+    /// rather than crossing it.
+    pub fn arrow(direction: Direction, (x, y): (usize, usize), length: usize) -> SvgShape {
+        match direction {
+            Direction::TopToBottom => SvgShape::DownArrow {
+                x,
+                y,
+                height: length,
+            },
+            Direction::BottomToTop => SvgShape::UpArrow {
+                x,
+                y: y.saturating_sub(length),
+                height: length.min(y),
+            },
+            Direction::LeftToRight => SvgShape::RightArrow {
+                x,
+                y,
+                width: length,
+            },
+            Direction::RightToLeft => SvgShape::LeftArrow {
+                x: x.saturating_sub(length),
+                y,
+                width: length.min(x),
+            },
+        }
+    }
 }
 
 pub struct Svg {
     style: BTreeMap<String, BTreeMap<String, String>>,
     shapes: Vec<SvgShape>,
+    size: Option<(usize, usize)>,
 }
 
 impl Svg {
@@ -89,6 +194,10 @@ impl Svg {
                     "polygon",
                     vec![("fill", "none"), ("stroke", "black"), ("stroke-width", "1")],
                 ),
+                (
+                    "circle",
+                    vec![("fill", "none"), ("stroke", "black"), ("stroke-width", "1")],
+                ),
                 (
                     "text",
                     vec![
@@ -101,6 +210,12 @@ impl Svg {
                 ),
                 ("tspan", vec![("alignment-baseline", "central")]),
                 (".grid", vec![("stroke", "yellow"), ("stroke-width", "1")]),
+                (".background", vec![("fill", "white"), ("stroke", "none")]),
+                (".leader", vec![("stroke-dasharray", "4 2")]),
+                (
+                    ".frame",
+                    vec![("stroke", "gray"), ("stroke-dasharray", "4 2")],
+                ),
             ]
             .into_iter()
             .map(|(selector, declarations)| {
@@ -114,73 +229,186 @@ impl Svg {
             })
             .collect(),
             shapes: vec![],
+            size: None,
+        }
+    }
+
+    /// Adds `declarations` to the rule for `selector`.
+    pub fn add_style(&mut self, selector: &str, declarations: &[(&str, &str)]) {
+        let rule = self.style.entry(String::from(selector)).or_default();
+        for (property, value) in declarations {
+            rule.insert(String::from(*property), String::from(*value));
         }
     }
 
     pub fn push_shape(&mut self, shape: SvgShape) {
         self.shapes.push(shape);
     }
-}
 
-macro_rules! write_indent {
-    ($dst:expr, $indent:expr, $($arg:tt)*) => {{
-        for _ in 0..$indent {
-            write!($dst, "  ")?;
+    /// Sets the `width`, `height` and `viewBox` of the document, which
+    /// otherwise fall back to the viewer's defaults.
+    pub fn set_size(&mut self, width: usize, height: usize) {
+        self.size = Some((width, height));
+    }
+
+    /// Displays the `<svg>` element alone, without the XML declaration, so it
+    /// can be embedded in an HTML page.
+    pub fn inline(&self) -> InlineSvg<'_> {
+        InlineSvg {
+            svg: self,
+            id: None,
         }
-        write!($dst, $($arg)*)}
-    };
+    }
 }
 
-macro_rules! writeln_indent {
-    ($dst:expr, $indent:expr, $($arg:tt)*) => {{
-        for _ in 0..$indent {
-            write!($dst, "  ")?;
-        }
-        writeln!($dst, $($arg)*)}
-    };
+pub struct InlineSvg<'a> {
+    svg: &'a Svg,
+    id: Option<&'a str>,
+}
+
+impl<'a> InlineSvg<'a> {
+    /// Gives the `<svg>` element the `id`, which scopes its style to it and
+    /// prefixes the ids it defines, so that several charts can be embedded
+    /// in one page. The id is used in selectors and `url()` references as
+    /// it is, and should be made of ASCII letters, digits, `-` and `_`.
+    pub fn id(mut self, id: &'a str) -> Self {
+        self.id = Some(id);
+        self
+    }
 }
 
+/// The id of the arrowhead marker of the document with the `id`.
+fn marker(id: Option<&str>) -> String {
+    match id {
+        Some(id) => format!("{}-arrow", id),
+        None => String::from("arrow"),
+    }
+}
+
+/// Writes the style, with every selector scoped to the document with the
+/// `id`, if any.
 fn write_style(
-    f: &mut std::fmt::Formatter<'_>,
+    f: &mut XmlWriter<impl fmt::Write>,
     style: &BTreeMap<String, BTreeMap<String, String>>,
-) -> std::fmt::Result {
-    writeln_indent!(f, 1, "<style>")?;
+    id: Option<&str>,
+) -> fmt::Result {
+    let arrow = format!("url(#{})", marker(id));
+    f.tag("style").open()?;
     for (selector, declarations) in style {
-        writeln_indent!(f, 2, "{} {{", selector)?;
+        match id {
+            Some(id) => f.text(0, &format!("#{} {} {{", id, selector))?,
+            None => f.text(0, &format!("{} {{", selector))?,
+        }
         for (property, value) in declarations {
-            writeln_indent!(f, 3, "{}: {};", property, value)?;
+            let value = value.replace("url(#arrow)", &arrow);
+            f.text(1, &format!("{}: {};", property, value))?;
         }
-        writeln_indent!(f, 2, "}}")?;
+        f.text(0, "}")?;
     }
-    writeln_indent!(f, 1, "</style>")?;
-    Ok(())
+    f.end()
 }
 
-fn write_defs(f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-    writeln_indent!(f, 1, "<defs>")?;
-    writeln_indent!(
-        f,
-        2,
-        r#"<marker id="arrow" viewBox="0 0 10 10" {} orient="auto-start-reverse">"#,
-        r#"refX="10" refY="5" markerWidth="6" markerHeight="6""#
-    )?;
-    writeln_indent!(f, 3, r#"<path d="M 0 0 L 10 5 L 0 10 z" />"#)?;
-    writeln_indent!(f, 2, "</marker>")?;
-    writeln_indent!(f, 1, "</defs>")
+fn write_defs(f: &mut XmlWriter<impl fmt::Write>, marker: &str) -> fmt::Result {
+    f.tag("defs").open()?;
+    f.tag("marker")
+        .attr("id", marker)
+        .attr("viewBox", "0 0 10 10")
+        .attr("refX", 10)
+        .attr("refY", 5)
+        .attr("markerWidth", 6)
+        .attr("markerHeight", 6)
+        .attr("orient", "auto-start-reverse")
+        .open()?;
+    f.tag("path").attr("d", "M 0 0 L 10 5 L 0 10 z").close()?;
+    f.end()?;
+    f.end()
 }
 
-fn write_shape(
-    f: &mut std::fmt::Formatter<'_>,
-    indent: usize,
-    shape: &SvgShape,
-) -> std::fmt::Result {
+/// Writes a `line` from `(x1, y1)` to `(x2, y2)`, of `class` if any.
+fn write_line(
+    f: &mut XmlWriter<impl fmt::Write>,
+    class: Option<&str>,
+    (x1, y1): (usize, usize),
+    (x2, y2): (usize, usize),
+) -> fmt::Result {
+    let mut tag = f.tag("line");
+    if let Some(class) = class {
+        tag = tag.attr("class", class);
+    }
+    tag.attr("x1", x1)
+        .attr("y1", y1)
+        .attr("x2", x2)
+        .attr("y2", y2)
+        .close()
+}
+
+/// Writes a `line` from `from` to `to` that ends in an arrowhead.
+fn write_arrow(
+    f: &mut XmlWriter<impl fmt::Write>,
+    marker: &str,
+    (x1, y1): (usize, usize),
+    (x2, y2): (usize, usize),
+) -> fmt::Result {
+    f.tag("line")
+        .attr("x1", x1)
+        .attr("y1", y1)
+        .attr("x2", x2)
+        .attr("y2", y2)
+        .attr("marker-end", format!("url(#{})", marker))
+        .close()
+}
+
+fn write_rect(
+    f: &mut XmlWriter<impl fmt::Write>,
+    class: Option<&str>,
+    rx: Option<usize>,
+    (x, y): (usize, usize),
+    (width, height): (usize, usize),
+) -> fmt::Result {
+    let mut tag = f.tag("rect");
+    if let Some(class) = class {
+        tag = tag.attr("class", class);
+    }
+    if let Some(rx) = rx {
+        tag = tag.attr("rx", rx);
+    }
+    tag.attr("x", x)
+        .attr("y", y)
+        .attr("width", width)
+        .attr("height", height)
+        .close()
+}
+
+/// The value of a `points` attribute.
+fn points(points: &[(usize, usize)]) -> String {
+    let points: Vec<String> = points.iter().map(|(x, y)| format!("{},{}", x, y)).collect();
+    points.join(" ")
+}
+
+/// Writes `shape`, whose arrows end in the `marker`.
+fn write_shape(f: &mut XmlWriter<impl fmt::Write>, marker: &str, shape: &SvgShape) -> fmt::Result {
     match shape {
         SvgShape::Group(children) => {
-            writeln_indent!(f, indent, "<g>")?;
+            f.tag("g").open()?;
             for child in children {
-                write_shape(f, indent + 1, child)?;
+                write_shape(f, marker, child)?;
+            }
+            f.end()
+        }
+        SvgShape::Classed {
+            class,
+            data,
+            shapes,
+        } => {
+            let mut tag = f.tag("g").attr("class", class);
+            for (name, value) in data {
+                tag = tag.attr(&format!("data-{}", name), value);
+            }
+            tag.open()?;
+            for shape in shapes {
+                write_shape(f, marker, shape)?;
             }
-            writeln_indent!(f, indent, "</g>")
+            f.end()
         }
         SvgShape::Grid {
             size,
@@ -189,95 +417,41 @@ fn write_shape(
         } => {
             let width = size * x_count;
             let height = size * y_count;
-            writeln_indent!(f, indent, "<g>")?;
+            f.tag("g").open()?;
             for x in 0..=*x_count {
-                writeln_indent!(
-                    f,
-                    indent + 1,
-                    r#"<line class="grid" x1="{}" y1="{}" x2="{}" y2="{}" />"#,
-                    x * size,
-                    0,
-                    x * size,
-                    height
-                )?;
+                write_line(f, Some("grid"), (x * size, 0), (x * size, height))?;
             }
             for y in 0..=*y_count {
-                writeln_indent!(
-                    f,
-                    indent + 1,
-                    r#"<line class="grid" x1="{}" y1="{}" x2="{}" y2="{}" />"#,
-                    0,
-                    y * size,
-                    width,
-                    y * size
-                )?;
+                write_line(f, Some("grid"), (0, y * size), (width, y * size))?;
             }
-            writeln_indent!(f, indent, "</g>")?;
-            Ok(())
-        }
-        SvgShape::HLine { x, y, width } => writeln_indent!(
-            f,
-            indent,
-            r#"<line x1="{}" y1="{}" x2="{}" y2="{}" />"#,
-            x,
-            y,
-            x + width,
-            y
-        ),
-        SvgShape::VLine { x, y, height } => writeln_indent!(
-            f,
-            indent,
-            r#"<line x1="{}" y1="{}" x2="{}" y2="{}" />"#,
-            x,
-            y,
-            x,
-            y + height
-        ),
-        SvgShape::Polyline(points) => {
-            write_indent!(f, indent, "<polyline points=\"")?;
-            match points.as_slice() {
-                [] => (),
-                [(x, y), tail @ ..] => {
-                    write!(f, "{},{}", x, y)?;
-                    for (x, y) in tail {
-                        write!(f, " {},{}", x, y)?;
-                    }
-                }
-            }
-            writeln!(f, "\" />")
+            f.end()
         }
+        SvgShape::HLine { x, y, width } => write_line(f, None, (*x, *y), (x + width, *y)),
+        SvgShape::VLine { x, y, height } => write_line(f, None, (*x, *y), (*x, y + height)),
+        SvgShape::Polyline(points) => f
+            .tag("polyline")
+            .attr("points", self::points(points))
+            .close(),
         SvgShape::Rect {
             x,
             y,
             width,
             height,
-        } => writeln_indent!(
-            f,
-            indent,
-            r#"<rect x="{}" y="{}" width="{}" height="{}" />"#,
-            x,
-            y,
-            width,
-            height
-        ),
+        } => write_rect(f, None, None, (*x, *y), (*width, *height)),
         SvgShape::Diamond {
             x,
             y,
             width,
             height,
-        } => writeln_indent!(
-            f,
-            indent,
-            r#"<polygon points="{},{} {},{} {},{} {},{}" />"#,
-            x,
-            y + height / 2,
-            x + width / 2,
-            y + height,
-            x + width,
-            y + height / 2,
-            x + width / 2,
-            y
-        ),
+        } => {
+            let corners = [
+                (*x, y + height / 2),
+                (x + width / 2, y + height),
+                (x + width, y + height / 2),
+                (x + width / 2, *y),
+            ];
+            f.tag("polygon").attr("points", points(&corners)).close()
+        }
         SvgShape::Parallelogram {
             x,
             y,
@@ -286,82 +460,472 @@ fn write_shape(
             height,
         } => {
             let d = (*height as f64 / theta.tan()) as usize;
-            writeln_indent!(
-                f,
-                indent,
-                r#"<polygon points="{},{} {},{} {},{} {},{}" />"#,
-                x,
-                y + height,
-                x + width - d,
-                y + height,
-                x + width,
-                y,
-                x + d,
-                y
-            )
+            let corners = [
+                (*x, y + height),
+                (x + width - d, y + height),
+                (x + width, *y),
+                (x + d, *y),
+            ];
+            f.tag("polygon").attr("points", points(&corners)).close()
         }
         SvgShape::Stadium {
             x,
             y,
             width,
             height,
-        } => writeln_indent!(
-            f,
-            indent,
-            r#"<rect rx="{}" x="{}" y="{}" width="{}" height="{}" />"#,
-            height / 2,
+        } => write_rect(f, None, Some(height / 2), (*x, *y), (*width, *height)),
+        SvgShape::Subroutine {
             x,
             y,
             width,
-            height
-        ),
-        SvgShape::DownArrow { x, y, height } => {
-            writeln_indent!(
+            height,
+        } => {
+            let inset = height / 4;
+            write_rect(f, None, None, (*x, *y), (*width, *height))?;
+            write_line(f, None, (x + inset, *y), (x + inset, y + height))?;
+            write_line(
                 f,
-                indent,
-                r#"<line x1="{}" y1="{}" x2="{}" y2="{}" marker-end="url(#arrow)"/>"#,
-                x,
-                y,
-                x,
-                y + height
+                None,
+                (x + width - inset, *y),
+                (x + width - inset, y + height),
             )
         }
-        SvgShape::Circle { cx, cy, r } => {
-            writeln_indent!(f, indent, r#"<circle cx="{}" cy="{}" r="{}" />"#, cx, cy, r)
+        SvgShape::Annotation {
+            x,
+            y,
+            width,
+            height,
+            leader,
+        } => {
+            let bracket = x + leader;
+            let arm = (width - leader).min(height / 4);
+            let middle = y + height / 2;
+            write_line(f, Some("leader"), (*x, middle), (bracket, middle))?;
+            write_line(f, None, (bracket + arm, *y), (bracket, *y))?;
+            write_line(f, None, (bracket, *y), (bracket, y + height))?;
+            write_line(f, None, (bracket, y + height), (bracket + arm, y + height))
         }
-        SvgShape::Text { cx, cy, content } => {
-            writeln_indent!(
-                f,
-                indent,
-                r#"<text x="{}" y="{}">{}</text>"#,
-                cx,
-                cy,
-                content
-            )
+        SvgShape::Frame {
+            x,
+            y,
+            width,
+            height,
+            r,
+        } => write_rect(f, Some("frame"), Some(*r), (*x, *y), (*width, *height)),
+        SvgShape::Link { href, shape } => {
+            f.tag("a")
+                .attr("href", href)
+                .attr("xlink:href", href)
+                .open()?;
+            write_shape(f, marker, shape)?;
+            f.end()
+        }
+        SvgShape::DownArrow { x, y, height } => write_arrow(f, marker, (*x, *y), (*x, y + height)),
+        SvgShape::UpArrow { x, y, height } => write_arrow(f, marker, (*x, y + height), (*x, *y)),
+        SvgShape::RightArrow { x, y, width } => write_arrow(f, marker, (*x, *y), (x + width, *y)),
+        SvgShape::LeftArrow { x, y, width } => write_arrow(f, marker, (x + width, *y), (*x, *y)),
+        SvgShape::Circle { cx, cy, r } => f
+            .tag("circle")
+            .attr("cx", cx)
+            .attr("cy", cy)
+            .attr("r", r)
+            .close(),
+        SvgShape::Text { cx, cy, content } => f
+            .tag("text")
+            .attr("x", cx)
+            .attr("y", cy)
+            .close_with_text(content),
+        SvgShape::Background {
+            x,
+            y,
+            width,
+            height,
+        } => write_rect(f, Some("background"), None, (*x, *y), (*width, *height)),
+        SvgShape::Timed {
+            class,
+            period,
+            spans,
+            shape,
+        } => {
+            let (times, values) = key_frames(*period, spans);
+            f.tag("g").attr("class", class).attr("opacity", 0).open()?;
+            f.tag("animate")
+                .attr("attributeName", "opacity")
+                .attr("calcMode", "discrete")
+                .attr("dur", format!("{}s", period))
+                .attr("keyTimes", times)
+                .attr("values", values)
+                .attr("repeatCount", "indefinite")
+                .close()?;
+            write_shape(f, marker, shape)?;
+            f.end()
+        }
+        SvgShape::Pulse { period, shape } => {
+            f.tag("g").open()?;
+            f.tag("animate")
+                .attr("attributeName", "stroke-opacity")
+                .attr("dur", format!("{}s", period))
+                .attr("values", "1;0.2;1")
+                .attr("repeatCount", "indefinite")
+                .close()?;
+            write_shape(f, marker, shape)?;
+            f.end()
         }
     }
 }
 
-fn write_shapes(f: &mut std::fmt::Formatter<'_>, shapes: &[SvgShape]) -> std::fmt::Result {
-    for shape in shapes {
-        write_shape(f, 1, shape)?;
+/// The `keyTimes` and `values` of a discrete opacity animation that shows a
+/// shape in `spans`, which are in order.
+fn key_frames(period: f64, spans: &[(f64, f64)]) -> (String, String) {
+    // Spans that meet or overlap join into one.
+    let mut merged: Vec<(f64, f64)> = vec![];
+    for &(start, end) in spans {
+        let (start, end) = (start.max(0.0), end.min(period));
+        match merged.last_mut() {
+            _ if end <= start => {}
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    let mut frames = vec![(0.0, 0)];
+    for (start, end) in merged {
+        match start {
+            0.0 => frames[0].1 = 1,
+            _ => frames.push((start / period, 1)),
+        }
+        if end < period {
+            frames.push((end / period, 0));
+        }
+    }
+    let times: Vec<String> = frames
+        .iter()
+        .map(|(time, _)| format!("{:.5}", time))
+        .collect();
+    let values: Vec<String> = frames.iter().map(|(_, value)| value.to_string()).collect();
+    (times.join(";"), values.join(";"))
+}
+
+/// Writes the start tag of the document with the `id`, if any, with its
+/// style, definitions and shapes.
+fn write_start(f: &mut XmlWriter<impl fmt::Write>, svg: &Svg, id: Option<&str>) -> fmt::Result {
+    let mut tag = f.tag("svg");
+    if let Some(id) = id {
+        tag = tag.attr("id", id);
+    }
+    let mut tag = tag
+        .attr("xmlns", "http://www.w3.org/2000/svg")
+        .attr("xmlns:xlink", "http://www.w3.org/1999/xlink");
+    if let Some((width, height)) = svg.size {
+        tag = tag
+            .attr("width", width)
+            .attr("height", height)
+            .attr("viewBox", format!("0 0 {} {}", width, height));
+    }
+    tag.open()?;
+    let marker = marker(id);
+    write_style(f, &svg.style, id)?;
+    write_defs(f, &marker)?;
+    for shape in &svg.shapes {
+        write_shape(f, &marker, shape)?;
     }
     Ok(())
 }
 
-impl std::fmt::Display for Svg {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        writeln!(
-            f,
-            r#"<?xml version="1.0" encoding="UTF-8" standalone="no"?>"#
-        )?;
-        writeln!(
-            f,
-            r#"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink">"#
-        )?;
-        write_style(f, &self.style)?;
-        write_defs(f)?;
-        write_shapes(f, &self.shapes)?;
-        writeln!(f, "</svg>")
+impl fmt::Display for InlineSvg<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut xml = XmlWriter::new(f);
+        write_start(&mut xml, self.svg, self.id)?;
+        xml.end()
+    }
+}
+
+impl fmt::Display for Svg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut xml = XmlWriter::new(f);
+        xml.declaration()?;
+        write_start(&mut xml, self, None)?;
+        xml.end()
+    }
+}
+
+/// Passes text on to an [`io::Write`], keeping the error that made it fail,
+/// which [`fmt::Error`] cannot carry.
+struct IoWriter<W> {
+    out: W,
+    error: Option<io::Error>,
+}
+
+impl<W: io::Write> fmt::Write for IoWriter<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        io::Write::write_all(&mut self.out, s.as_bytes()).map_err(|e| {
+            self.error = Some(e);
+            fmt::Error
+        })
+    }
+}
+
+/// Writes documents to an [`io::Write`] as they are serialized, through a
+/// buffer, rather than into a string first. Shapes can be written one at a
+/// time after the start of a document, without being kept.
+///
+/// ```no_run
+/// # use flowchart::{config::ConfigBuilder, svg::{Svg, SvgShape, SvgWriter}};
+/// # fn main() -> std::io::Result<()> {
+/// let config = ConfigBuilder::new().build();
+/// let mut writer = SvgWriter::new(std::fs::File::create("chart.svg")?).compact(true);
+/// writer.start(&Svg::new(&config))?;
+/// for i in 0..10_000 {
+///     writer.shape(&SvgShape::Circle { cx: i, cy: 10, r: 5 })?;
+/// }
+/// writer.finish()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct SvgWriter<W: io::Write> {
+    xml: XmlWriter<IoWriter<BufWriter<W>>>,
+    inline: bool,
+    id: Option<String>,
+    started: bool,
+}
+
+impl<W: io::Write> SvgWriter<W> {
+    pub fn new(out: W) -> Self {
+        let out = IoWriter {
+            out: BufWriter::new(out),
+            error: None,
+        };
+        Self {
+            xml: XmlWriter::new(out),
+            inline: false,
+            id: None,
+            started: false,
+        }
+    }
+
+    /// Leaves out the indentation and the line breaks between elements.
+    pub fn compact(mut self, compact: bool) -> Self {
+        self.xml = self.xml.compact(compact);
+        self
+    }
+
+    /// Leaves out the XML declaration, as [`Svg::inline`] does.
+    pub fn inline(mut self, inline: bool) -> Self {
+        self.inline = inline;
+        self
+    }
+
+    /// Gives the document the `id`, as [`InlineSvg::id`] does.
+    pub fn id(mut self, id: &str) -> Self {
+        self.id = Some(String::from(id));
+        self
+    }
+
+    /// Writes the start of a document with the size and style of `svg`,
+    /// and the shapes it has. A writer holds one document, so it can only be
+    /// started once.
+    pub fn start(&mut self, svg: &Svg) -> io::Result<()> {
+        if self.started {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the document was already started",
+            ));
+        }
+        self.started = true;
+        if !self.inline {
+            let result = self.xml.declaration();
+            self.check(result)?;
+        }
+        let result = write_start(&mut self.xml, svg, self.id.as_deref());
+        self.check(result)
+    }
+
+    /// Writes a shape of the document that was started.
+    pub fn shape(&mut self, shape: &SvgShape) -> io::Result<()> {
+        self.check_started()?;
+        let result = write_shape(&mut self.xml, &marker(self.id.as_deref()), shape);
+        self.check(result)
+    }
+
+    /// Ends the document, flushes the buffer, and returns the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.check_started()?;
+        let result = self.xml.end();
+        self.check(result)?;
+        self.xml
+            .into_inner()
+            .out
+            .into_inner()
+            .map_err(|e| e.into_error())
+    }
+
+    /// Writes the whole of `svg` as a document, and returns the writer.
+    pub fn write(mut self, svg: &Svg) -> io::Result<W> {
+        self.start(svg)?;
+        self.finish()
+    }
+
+    fn check_started(&self) -> io::Result<()> {
+        if self.started {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the document was not started",
+            ))
+        }
+    }
+
+    /// Turns a failure to write into the error of the writer.
+    fn check(&mut self, result: fmt::Result) -> io::Result<()> {
+        result.map_err(|fmt::Error| {
+            self.xml
+                .get_mut()
+                .error
+                .take()
+                .unwrap_or_else(|| io::Error::other("formatting failed"))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigBuilder;
+
+    /// A writer that fails every write with `kind`.
+    struct Failing(io::ErrorKind);
+
+    impl io::Write for Failing {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::new(self.0, "failing"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn svg(circles: usize) -> Svg {
+        let mut svg = Svg::new(&ConfigBuilder::new().build());
+        svg.set_size(100, 50);
+        for i in 0..circles {
+            svg.push_shape(SvgShape::Circle {
+                cx: i,
+                cy: 10,
+                r: 5,
+            });
+        }
+        svg
+    }
+
+    fn written(writer: SvgWriter<Vec<u8>>, svg: &Svg) -> String {
+        String::from_utf8(writer.write(svg).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn writes_as_displayed() {
+        let svg = svg(3);
+        assert_eq!(written(SvgWriter::new(vec![]), &svg), svg.to_string());
+        assert_eq!(
+            written(SvgWriter::new(vec![]).inline(true), &svg),
+            svg.inline().to_string()
+        );
+        assert_eq!(
+            written(SvgWriter::new(vec![]).inline(true).id("chart-1"), &svg),
+            svg.inline().id("chart-1").to_string()
+        );
+    }
+
+    #[test]
+    fn writes_shapes_one_at_a_time() {
+        let mut writer = SvgWriter::new(vec![]).id("a");
+        writer.start(&svg(1)).unwrap();
+        for i in 1..3 {
+            writer
+                .shape(&SvgShape::Circle {
+                    cx: i,
+                    cy: 10,
+                    r: 5,
+                })
+                .unwrap();
+        }
+        let out = String::from_utf8(writer.finish().unwrap()).unwrap();
+        let mut whole = SvgWriter::new(vec![]).id("a");
+        whole.start(&svg(3)).unwrap();
+        assert_eq!(out, String::from_utf8(whole.finish().unwrap()).unwrap());
+    }
+
+    #[test]
+    fn documents_are_started_once() {
+        let circle = SvgShape::Circle { cx: 0, cy: 0, r: 1 };
+        let mut writer = SvgWriter::new(vec![]);
+        let error = writer.shape(&circle).unwrap_err();
+        assert_eq!(error.to_string(), "the document was not started");
+        let error = SvgWriter::new(vec![]).finish().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+
+        writer.start(&svg(0)).unwrap();
+        let error = writer.start(&svg(0)).unwrap_err();
+        assert_eq!(error.to_string(), "the document was already started");
+        writer.shape(&circle).unwrap();
+        let out = String::from_utf8(writer.finish().unwrap()).unwrap();
+        assert_eq!(out.matches("<svg").count(), 1);
+    }
+
+    #[test]
+    fn scoped_ids() {
+        let out = svg(0).inline().id("chart-2").to_string();
+        assert!(out.starts_with(r#"<svg id="chart-2" "#));
+        assert!(out.contains("#chart-2 polyline {"));
+        assert!(out.contains("marker-end: url(#chart-2-arrow);"));
+        assert!(out.contains(r#"<marker id="chart-2-arrow""#));
+        assert!(!out.contains("url(#arrow)"));
+    }
+
+    #[test]
+    fn arrows_stop_at_the_origin() {
+        let lines = |direction, from| {
+            let mut svg = Svg::new(&ConfigBuilder::new().build());
+            svg.push_shape(SvgShape::arrow(direction, from, 40));
+            let out = svg.to_string();
+            let start = out.find("<line").unwrap();
+            String::from(&out[start..start + out[start..].find("/>").unwrap()])
+        };
+        assert!(lines(Direction::BottomToTop, (10, 100)).contains(r#"y1="100" x2="10" y2="60""#));
+        assert!(lines(Direction::BottomToTop, (10, 30)).contains(r#"y1="30" x2="10" y2="0""#));
+        assert!(lines(Direction::RightToLeft, (20, 5)).contains(r#"x1="20" y1="5" x2="0""#));
+        assert!(lines(Direction::LeftToRight, (20, 5)).contains(r#"x1="20" y1="5" x2="60""#));
+    }
+
+    #[test]
+    fn compact() {
+        let out = written(SvgWriter::new(vec![]).compact(true), &svg(2));
+        assert!(out.contains(r#"<circle cx="0" cy="10" r="5" /><circle cx="1""#));
+        assert!(!out.contains("\n  <"));
+    }
+
+    #[test]
+    fn keeps_the_error_of_the_writer() {
+        // The buffer fails on flushing a small document...
+        let error = SvgWriter::new(Failing(io::ErrorKind::StorageFull))
+            .write(&svg(1))
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::StorageFull);
+
+        // ...and on overflowing with a large one.
+        let mut writer = SvgWriter::new(Failing(io::ErrorKind::BrokenPipe));
+        let result = writer.start(&svg(0)).and_then(|()| {
+            (0..10_000).try_for_each(|i| {
+                writer.shape(&SvgShape::Circle {
+                    cx: i,
+                    cy: 10,
+                    r: 5,
+                })
+            })
+        });
+        let error = result.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::BrokenPipe);
+        assert_eq!(error.to_string(), "failing");
     }
 }
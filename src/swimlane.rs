@@ -0,0 +1,408 @@
+use crate::{
+    block::{get_num_columns_num_lines, Block, BlockBuilder, BlockKind, Span},
+    config::{Config, Direction},
+    label::LabelPlacer,
+    svg::SvgShape,
+};
+
+struct Step {
+    lane: usize,
+    block: Block,
+    label: Option<String>,
+}
+
+/// The blocks of a [`Swimlane`] in place, with the extent of its lanes.
+struct Arrangement {
+    blocks: Vec<Block>,
+    /// The size of the headers along the flow.
+    header: usize,
+    lane_offsets: Vec<usize>,
+    lane_sizes: Vec<usize>,
+    width: usize,
+    height: usize,
+}
+
+/// A cross-functional chart where every block belongs to a named lane.
+///
+/// Lanes are vertical columns when the flow goes up or down and horizontal
+/// rows when it goes left or right. Every step gets its own row (or column),
+/// so the flow order is kept even when consecutive steps switch lanes.
+pub struct Swimlane<'a> {
+    config: &'a Config,
+    block_builder: &'a BlockBuilder,
+    lanes: Vec<String>,
+    steps: Vec<Step>,
+    x: usize,
+    y: usize,
+}
+
+impl<'a> Swimlane<'a> {
+    pub fn new(config: &'a Config, block_builder: &'a BlockBuilder) -> Self {
+        Self {
+            config,
+            block_builder,
+            lanes: vec![],
+            steps: vec![],
+            x: 0,
+            y: 0,
+        }
+    }
+
+    /// Adds an empty lane, so lanes can be ordered before any block is pushed.
+    pub fn add_lane(&mut self, lane: &str) {
+        self.lane_index(lane);
+    }
+
+    pub fn push(&mut self, lane: &str, kind: BlockKind, content: String) {
+        self.push_edge(lane, kind, content, None);
+    }
+
+    /// Pushes a block whose incoming edge is annotated with `label`.
+    pub fn push_with_label(&mut self, lane: &str, kind: BlockKind, content: String, label: String) {
+        self.push_edge(lane, kind, content, Some(label));
+    }
+
+    /// Names the source of the block pushed last.
+    pub fn set_span(&mut self, span: Span) {
+        if let Some(step) = self.steps.last_mut() {
+            step.block.set_span(span);
+        }
+    }
+
+    pub fn displace(&mut self, dx: usize, dy: usize) {
+        self.x += dx;
+        self.y += dy;
+    }
+
+    fn lane_index(&mut self, lane: &str) -> usize {
+        match self.lanes.iter().position(|name| name == lane) {
+            Some(index) => index,
+            None => {
+                self.lanes.push(String::from(lane));
+                self.lanes.len() - 1
+            }
+        }
+    }
+
+    fn push_edge(&mut self, lane: &str, kind: BlockKind, content: String, label: Option<String>) {
+        let lane = self.lane_index(lane);
+        let block = self.block_builder.build(kind, content);
+        self.steps.push(Step { lane, block, label });
+    }
+
+    fn fit_to_grid(&self, length: usize) -> usize {
+        let grid_size = self.config.grid_size();
+        length.div_ceil(grid_size) * grid_size
+    }
+
+    fn header_extent(&self, name: &str) -> (usize, usize) {
+        let font_size = self.config.font_size();
+        let (num_columns, num_lines) = get_num_columns_num_lines(name);
+        (
+            font_size / 2 * num_columns + 2 * font_size,
+            font_size * num_lines.max(1) + font_size,
+        )
+    }
+
+    /// Places the blocks in their lanes, one step after another.
+    fn arrange(&self) -> Arrangement {
+        let direction = self.config.direction();
+        let distance = self.config.distance();
+        let padding = distance / 2;
+        let vertical = matches!(direction, Direction::TopToBottom | Direction::BottomToTop);
+        let reversed = matches!(direction, Direction::BottomToTop | Direction::RightToLeft);
+
+        // The cross size of a lane is its width for vertical lanes and its
+        // height for horizontal ones.
+        let header = self.fit_to_grid(
+            self.lanes
+                .iter()
+                .map(|name| {
+                    let (width, height) = self.header_extent(name);
+                    if vertical {
+                        height
+                    } else {
+                        width
+                    }
+                })
+                .max()
+                .unwrap_or(0),
+        );
+        let lane_sizes: Vec<usize> = (0..self.lanes.len())
+            .map(|lane| {
+                let (header_width, header_height) = self.header_extent(&self.lanes[lane]);
+                let size = self
+                    .steps
+                    .iter()
+                    .filter(|step| step.lane == lane)
+                    .map(|step| {
+                        if vertical {
+                            step.block.width()
+                        } else {
+                            step.block.height()
+                        }
+                    })
+                    .max()
+                    .unwrap_or(0)
+                    + 2 * padding;
+                self.fit_to_grid(size.max(if vertical {
+                    header_width
+                } else {
+                    header_height
+                }))
+            })
+            .collect();
+        let lane_offsets: Vec<usize> = lane_sizes
+            .iter()
+            .scan(0, |offset, size| {
+                let start = *offset;
+                *offset += size;
+                Some(start)
+            })
+            .collect();
+        let cross_total: usize = lane_sizes.iter().sum();
+
+        let mut blocks: Vec<Block> = self.steps.iter().map(|step| step.block.clone()).collect();
+        let mut order: Vec<usize> = (0..blocks.len()).collect();
+        if reversed {
+            order.reverse();
+        }
+        let mut cursor = header + distance;
+        for index in order {
+            let block = &mut blocks[index];
+            let lane = self.steps[index].lane;
+            if vertical {
+                let x = lane_offsets[lane] + (lane_sizes[lane] - block.width()) / 2;
+                block.displace(self.x + x, self.y + cursor);
+                cursor += block.height() + distance;
+            } else {
+                let y = lane_offsets[lane] + (lane_sizes[lane] - block.height()) / 2;
+                block.displace(self.x + cursor, self.y + y);
+                cursor += block.width() + distance;
+            }
+        }
+        let primary_total = cursor;
+        let (width, height) = if vertical {
+            (cross_total, primary_total)
+        } else {
+            (primary_total, cross_total)
+        };
+        Arrangement {
+            blocks,
+            header,
+            lane_offsets,
+            lane_sizes,
+            width,
+            height,
+        }
+    }
+
+    /// Renders the lanes with their headers and the blocks with the edges
+    /// between them, and returns the shape with the size it takes from the
+    /// origin.
+    pub fn to_svg(&self) -> (SvgShape, (usize, usize)) {
+        let direction = self.config.direction();
+        let vertical = matches!(direction, Direction::TopToBottom | Direction::BottomToTop);
+        let Arrangement {
+            blocks,
+            header,
+            lane_offsets,
+            lane_sizes,
+            width,
+            height,
+        } = self.arrange();
+
+        let mut group = vec![SvgShape::Rect {
+            x: self.x,
+            y: self.y,
+            width,
+            height,
+        }];
+        if vertical {
+            group.push(SvgShape::HLine {
+                x: self.x,
+                y: self.y + header,
+                width,
+            });
+        } else {
+            group.push(SvgShape::VLine {
+                x: self.x + header,
+                y: self.y,
+                height,
+            });
+        }
+        for (lane, name) in self.lanes.iter().enumerate() {
+            let offset = lane_offsets[lane];
+            if lane > 0 {
+                group.push(if vertical {
+                    SvgShape::VLine {
+                        x: self.x + offset,
+                        y: self.y,
+                        height,
+                    }
+                } else {
+                    SvgShape::HLine {
+                        x: self.x,
+                        y: self.y + offset,
+                        width,
+                    }
+                });
+            }
+            let (cx, cy) = if vertical {
+                (offset + lane_sizes[lane] / 2, header / 2)
+            } else {
+                (header / 2, offset + lane_sizes[lane] / 2)
+            };
+            group.push(SvgShape::Text {
+                cx: self.x + cx,
+                cy: self.y + cy,
+                content: name.clone(),
+            });
+        }
+
+        let mut placer = LabelPlacer::new(self.config);
+        blocks
+            .iter()
+            .for_each(|block| placer.add_obstacle(block.bounds()));
+        group.extend(blocks.iter().map(|block| block.to_svg()));
+        for (pair, step) in blocks.windows(2).zip(&self.steps[1..]) {
+            let (from_x, from_y) = pair[0].exit_pos(direction);
+            let (to_x, to_y) = pair[1].entry_pos(direction);
+            let label_from = if vertical && from_x == to_x {
+                group.push(SvgShape::arrow(
+                    direction,
+                    (from_x, from_y),
+                    from_y.abs_diff(to_y),
+                ));
+                (from_x, from_y)
+            } else if !vertical && from_y == to_y {
+                group.push(SvgShape::arrow(
+                    direction,
+                    (from_x, from_y),
+                    from_x.abs_diff(to_x),
+                ));
+                (from_x, from_y)
+            } else if vertical {
+                let mid = (from_y + to_y) / 2;
+                group.push(SvgShape::Polyline(vec![
+                    (from_x, from_y),
+                    (from_x, mid),
+                    (to_x, mid),
+                    (to_x, to_y),
+                ]));
+                (to_x, mid)
+            } else {
+                let mid = (from_x + to_x) / 2;
+                group.push(SvgShape::Polyline(vec![
+                    (from_x, from_y),
+                    (mid, from_y),
+                    (mid, to_y),
+                    (to_x, to_y),
+                ]));
+                (mid, to_y)
+            };
+            if let Some(label) = &step.label {
+                group.push(placer.place(label_from, (to_x, to_y), label));
+            }
+        }
+        (SvgShape::Group(group), (self.x + width, self.y + height))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigBuilder;
+
+    /// A request that goes from the client to the server and back.
+    fn arrange(direction: Direction) -> (Arrangement, Vec<usize>) {
+        let config = ConfigBuilder::new().direction(direction).build();
+        let block_builder = BlockBuilder::new(&config);
+        let mut swimlane = Swimlane::new(&config, &block_builder);
+        swimlane.add_lane("Server");
+        let steps = [
+            ("Client", "send request"),
+            ("Server", "handle request"),
+            ("Server", "send response"),
+            ("Client", "show response"),
+        ];
+        for (lane, content) in steps {
+            swimlane.push(lane, BlockKind::Process, String::from(content));
+        }
+        assert_eq!(swimlane.lanes, ["Server", "Client"]);
+        let lanes = swimlane.steps.iter().map(|step| step.lane).collect();
+        (swimlane.arrange(), lanes)
+    }
+
+    #[test]
+    fn blocks_stay_in_their_lanes() {
+        for direction in [Direction::TopToBottom, Direction::LeftToRight] {
+            let (arrangement, lanes) = arrange(direction);
+            for (block, lane) in arrangement.blocks.iter().zip(lanes) {
+                let start = arrangement.lane_offsets[lane];
+                let end = start + arrangement.lane_sizes[lane];
+                let (cross, size) = match direction {
+                    Direction::TopToBottom => (block.pos().0, block.width()),
+                    _ => (block.pos().1, block.height()),
+                };
+                assert!(start <= cross && cross + size <= end);
+            }
+        }
+    }
+
+    #[test]
+    fn steps_follow_the_flow_across_lanes() {
+        for direction in [
+            Direction::TopToBottom,
+            Direction::BottomToTop,
+            Direction::LeftToRight,
+            Direction::RightToLeft,
+        ] {
+            let (arrangement, _) = arrange(direction);
+            let mut along: Vec<usize> = arrangement
+                .blocks
+                .iter()
+                .map(|block| match direction {
+                    Direction::TopToBottom | Direction::BottomToTop => block.pos().1,
+                    _ => block.pos().0,
+                })
+                .collect();
+            if matches!(direction, Direction::BottomToTop | Direction::RightToLeft) {
+                along.reverse();
+            }
+            assert!(along.windows(2).all(|pair| pair[0] < pair[1]));
+            // Every step is past the headers.
+            assert!(along.iter().all(|&at| at > arrangement.header));
+        }
+    }
+
+    #[test]
+    fn lanes_are_separated_and_named() {
+        let config = ConfigBuilder::new().build();
+        let block_builder = BlockBuilder::new(&config);
+        let mut swimlane = Swimlane::new(&config, &block_builder);
+        swimlane.push("A", BlockKind::Process, String::from("x"));
+        swimlane.push("B", BlockKind::Process, String::from("y"));
+        swimlane.push("C", BlockKind::Process, String::from("z"));
+        let (SvgShape::Group(shapes), (width, height)) = swimlane.to_svg() else {
+            panic!("a group");
+        };
+        let separators = shapes
+            .iter()
+            .filter(|shape| matches!(shape, SvgShape::VLine { .. }))
+            .count();
+        assert_eq!(separators, 2);
+        let texts: Vec<&str> = shapes
+            .iter()
+            .filter_map(|shape| match shape {
+                SvgShape::Text { content, .. } => Some(content.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(texts, ["A", "B", "C"]);
+        assert!(matches!(
+            shapes[0],
+            SvgShape::Rect { x: 0, y: 0, width: w, height: h } if (w, h) == (width, height)
+        ));
+    }
+}
@@ -0,0 +1,88 @@
+//! Waiting for files to change, by polling their modification time and size
+//! so that it works the same everywhere, and also for editors that save by
+//! replacing the file.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+/// What a file looked like when last polled, or `None` if it was missing.
+type Stamp = Option<(SystemTime, u64)>;
+
+pub struct Watcher {
+    files: Vec<(PathBuf, Stamp)>,
+    interval: Duration,
+}
+
+impl Watcher {
+    pub fn new(paths: &[PathBuf]) -> Self {
+        Self {
+            files: paths
+                .iter()
+                .map(|path| (path.clone(), stamp(path)))
+                .collect(),
+            interval: Duration::from_millis(200),
+        }
+    }
+
+    /// Sets how often the files are polled, five times a second by default.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// The files that changed, appeared or disappeared since the last poll.
+    pub fn poll(&mut self) -> Vec<PathBuf> {
+        let mut changed = vec![];
+        for (path, last) in &mut self.files {
+            let now = stamp(path);
+            if now != *last {
+                *last = now;
+                changed.push(path.clone());
+            }
+        }
+        changed
+    }
+
+    /// Blocks until some of the files change, and returns them.
+    pub fn wait(&mut self) -> Vec<PathBuf> {
+        loop {
+            thread::sleep(self.interval);
+            let changed = self.poll();
+            if !changed.is_empty() {
+                return changed;
+            }
+        }
+    }
+}
+
+fn stamp(path: &Path) -> Stamp {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn changes_appearing_and_disappearing_files() {
+        let dir = TempDir::new("watch");
+        dir.write("a.flow", "proc a\nend\n");
+        let (a, b) = (dir.0.join("a.flow"), dir.0.join("b.flow"));
+
+        let mut watcher = Watcher::new(&[a.clone(), b.clone()]).interval(Duration::ZERO);
+        assert!(watcher.poll().is_empty());
+        // The size changes even where the modification time is coarse.
+        fs::write(&a, "proc a\n  io x\nend\n").unwrap();
+        assert_eq!(watcher.poll(), std::slice::from_ref(&a));
+        assert!(watcher.poll().is_empty());
+        fs::write(&b, "").unwrap();
+        fs::remove_file(&a).unwrap();
+        assert_eq!(watcher.wait(), [a, b]);
+    }
+}
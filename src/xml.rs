@@ -0,0 +1,205 @@
+//! A small XML writer that escapes every attribute value and text it is
+//! given, and closes the elements it opened in order.
+
+use std::fmt::{self, Display, Write};
+
+/// Escapes `content` for an attribute value or the text of an element.
+pub(crate) fn escape(content: &str) -> String {
+    let mut s = String::new();
+    Escaped::text(&mut s).write_str(content).unwrap();
+    s
+}
+
+/// Passes text on to a writer, escaped. Characters that XML does not allow
+/// become U+FFFD, and in attribute values, the whitespace that parsers would
+/// turn into spaces is written as references.
+struct Escaped<'a, W> {
+    out: &'a mut W,
+    attribute: bool,
+}
+
+impl<'a, W> Escaped<'a, W> {
+    fn text(out: &'a mut W) -> Self {
+        Self {
+            out,
+            attribute: false,
+        }
+    }
+
+    fn attribute(out: &'a mut W) -> Self {
+        Self {
+            out,
+            attribute: true,
+        }
+    }
+}
+
+impl<W: Write> Write for Escaped<'_, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut rest = s;
+        while let Some(i) = rest.find(|c| self.escapes(c)) {
+            self.out.write_str(&rest[..i])?;
+            let c = rest[i..].chars().next().unwrap();
+            match c {
+                '"' => self.out.write_str("&quot;")?,
+                '\'' => self.out.write_str("&apos;")?,
+                '<' => self.out.write_str("&lt;")?,
+                '>' => self.out.write_str("&gt;")?,
+                '&' => self.out.write_str("&amp;")?,
+                '\t' | '\n' | '\r' => write!(self.out, "&#{};", c as u32)?,
+                _ => self.out.write_char(char::REPLACEMENT_CHARACTER)?,
+            }
+            rest = &rest[i + c.len_utf8()..];
+        }
+        self.out.write_str(rest)
+    }
+}
+
+impl<W> Escaped<'_, W> {
+    fn escapes(&self, c: char) -> bool {
+        match c {
+            '"' | '\'' | '<' | '>' | '&' | '\r' => true,
+            '\t' | '\n' => self.attribute,
+            '\u{fffe}' | '\u{ffff}' => true,
+            _ => c < ' ',
+        }
+    }
+}
+
+/// Whether `name` can name an element or an attribute, with a prefix if
+/// any. Only the ASCII subset of the names XML allows is taken.
+fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | ':'))
+}
+
+/// Writes elements one per line, indented by their depth, unless compact.
+pub(crate) struct XmlWriter<W> {
+    out: W,
+    compact: bool,
+    /// The names of the elements opened and not yet ended.
+    open: Vec<&'static str>,
+}
+
+impl<W: Write> XmlWriter<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            compact: false,
+            open: vec![],
+        }
+    }
+
+    /// Leaves out the indentation and the line breaks between elements.
+    pub fn compact(mut self, compact: bool) -> Self {
+        self.compact = compact;
+        self
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.out
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    fn indent(&mut self, extra: usize) -> fmt::Result {
+        if !self.compact {
+            for _ in 0..self.open.len() + extra {
+                self.out.write_str("  ")?;
+            }
+        }
+        Ok(())
+    }
+
+    fn newline(&mut self) -> fmt::Result {
+        match self.compact {
+            true => Ok(()),
+            false => self.out.write_char('\n'),
+        }
+    }
+
+    pub fn declaration(&mut self) -> fmt::Result {
+        self.out
+            .write_str(r#"<?xml version="1.0" encoding="UTF-8" standalone="no"?>"#)?;
+        self.newline()
+    }
+
+    /// Begins the start tag of an element, whose attributes follow.
+    pub fn tag(&mut self, name: &'static str) -> Tag<'_, W> {
+        debug_assert!(is_name(name), "bad element name `{}`", name);
+        let result = self.indent(0).and_then(|()| write!(self.out, "<{}", name));
+        Tag {
+            writer: self,
+            name,
+            result,
+        }
+    }
+
+    /// Writes a line of text, indented `extra` levels deeper than the
+    /// elements of the one open.
+    pub fn text(&mut self, extra: usize, text: &str) -> fmt::Result {
+        self.indent(extra)?;
+        Escaped::text(&mut self.out).write_str(text)?;
+        self.newline()
+    }
+
+    /// Ends the element opened last.
+    pub fn end(&mut self) -> fmt::Result {
+        let name = self.open.pop().expect("no element to end");
+        self.indent(0)?;
+        write!(self.out, "</{}>", name)?;
+        self.newline()
+    }
+}
+
+/// The start tag of an element being written. Writing stops at the first
+/// error, which ending the tag returns.
+pub(crate) struct Tag<'a, W> {
+    writer: &'a mut XmlWriter<W>,
+    name: &'static str,
+    result: fmt::Result,
+}
+
+impl<W: Write> Tag<'_, W> {
+    pub fn attr(mut self, name: &str, value: impl Display) -> Self {
+        debug_assert!(is_name(name), "bad attribute name `{}`", name);
+        if self.result.is_ok() {
+            let out = &mut self.writer.out;
+            self.result = write!(out, r#" {}=""#, name)
+                .and_then(|()| write!(Escaped::attribute(out), "{}", value))
+                .and_then(|()| out.write_char('"'));
+        }
+        self
+    }
+
+    /// Ends the tag of an element whose content follows, up to
+    /// [`XmlWriter::end`].
+    pub fn open(self) -> fmt::Result {
+        self.result?;
+        self.writer.out.write_char('>')?;
+        self.writer.newline()?;
+        self.writer.open.push(self.name);
+        Ok(())
+    }
+
+    /// Ends an element without content.
+    pub fn close(self) -> fmt::Result {
+        self.result?;
+        self.writer.out.write_str(" />")?;
+        self.writer.newline()
+    }
+
+    /// Ends an element with `text` as its content, on the same line.
+    pub fn close_with_text(self, text: &str) -> fmt::Result {
+        self.result?;
+        self.writer.out.write_char('>')?;
+        Escaped::text(&mut self.writer.out).write_str(text)?;
+        write!(self.writer.out, "</{}>", self.name)?;
+        self.writer.newline()
+    }
+}
@@ -0,0 +1,590 @@
+//! Every kind of document the crate writes is parsed as XML, and checked
+//! against the SVG elements and attributes that the charts are drawn with.
+
+use std::{collections::HashSet, path::Path};
+
+use flowchart::{
+    animation::Animator,
+    config::{ConfigBuilder, Direction},
+    expression::Value,
+    frontend::{self, Input},
+    interpreter::Interpreter,
+    parser,
+    svg::{Svg, SvgShape, SvgWriter},
+};
+use roxmltree::{Document, Node, NodeType};
+
+const SVG: &str = "http://www.w3.org/2000/svg";
+const XLINK: &str = "http://www.w3.org/1999/xlink";
+
+/// The elements that are written, with the attributes they may have. `g`
+/// may also have `data-` attributes.
+const ELEMENTS: &[(&str, &[&str])] = &[
+    ("svg", &["id", "width", "height", "viewBox"]),
+    ("style", &[]),
+    ("defs", &[]),
+    (
+        "marker",
+        &[
+            "id",
+            "viewBox",
+            "refX",
+            "refY",
+            "markerWidth",
+            "markerHeight",
+            "orient",
+        ],
+    ),
+    ("path", &["d"]),
+    ("g", &["class", "opacity"]),
+    ("line", &["class", "x1", "y1", "x2", "y2", "marker-end"]),
+    ("polyline", &["points"]),
+    ("polygon", &["points"]),
+    ("rect", &["class", "rx", "x", "y", "width", "height"]),
+    ("circle", &["cx", "cy", "r"]),
+    ("text", &["x", "y"]),
+    ("a", &["href", "xlink:href"]),
+    (
+        "animate",
+        &[
+            "attributeName",
+            "calcMode",
+            "dur",
+            "keyTimes",
+            "values",
+            "repeatCount",
+        ],
+    ),
+];
+
+/// The attributes whose values are numbers.
+const NUMBERS: &[&str] = &[
+    "width",
+    "height",
+    "x",
+    "y",
+    "x1",
+    "y1",
+    "x2",
+    "y2",
+    "cx",
+    "cy",
+    "r",
+    "rx",
+    "refX",
+    "refY",
+    "markerWidth",
+    "markerHeight",
+    "opacity",
+];
+
+/// Labels with everything that could end an attribute, a text, a comment
+/// or a CDATA section early.
+const LABELS: &[&str] = &[
+    r#"x < y && y > "z""#,
+    "it's ]]> done",
+    "<!-- not a comment -->",
+    "&amp; &#60; &lt",
+    "π ≤ 3.15, 日本",
+];
+
+fn number(value: &str) -> f64 {
+    match value.parse::<f64>() {
+        Ok(number) if number.is_finite() => number,
+        _ => panic!("`{}` is not a number", value),
+    }
+}
+
+/// The name of an attribute as it is written, with its prefix.
+fn attribute_name(attribute: &roxmltree::Attribute) -> String {
+    match attribute.namespace() {
+        Some(XLINK) => format!("xlink:{}", attribute.name()),
+        Some(namespace) => panic!("attribute in namespace {}", namespace),
+        None => String::from(attribute.name()),
+    }
+}
+
+/// The ids that `url(#id)` references in `text`.
+fn references(text: &str) -> Vec<&str> {
+    text.split("url(#")
+        .skip(1)
+        .map(|rest| &rest[..rest.find(')').expect("unclosed `url(`")])
+        .collect()
+}
+
+/// Parses `document` and checks it, returning the lines of text it shows.
+fn check(document: &str) -> Vec<String> {
+    let document = Document::parse(document)
+        .unwrap_or_else(|e| panic!("not well-formed: {}\n{}", e, document));
+    let root = document.root_element();
+    assert_eq!(root.tag_name().name(), "svg");
+    assert_eq!(root.tag_name().namespace(), Some(SVG));
+    assert_eq!(root.lookup_namespace_uri(Some("xlink")), Some(XLINK));
+
+    let mut ids = HashSet::new();
+    let mut referenced = vec![];
+    let mut texts = vec![];
+    for node in root.descendants().filter(Node::is_element) {
+        check_element(node);
+        for attribute in node.attributes() {
+            if attribute.name() == "id" {
+                assert!(ids.insert(attribute.value()), "duplicate id");
+            }
+            referenced.extend(references(attribute.value()));
+        }
+        match node.tag_name().name() {
+            "style" => referenced.extend(references(node.text().unwrap_or_default())),
+            "text" => texts.push(String::from(node.text().unwrap_or_default())),
+            _ => {}
+        }
+    }
+    for id in referenced {
+        assert!(ids.contains(id), "`url(#{})` references no element", id);
+    }
+    texts
+}
+
+fn check_element(node: Node) {
+    let name = node.tag_name().name();
+    assert_eq!(node.tag_name().namespace(), Some(SVG), "<{}>", name);
+    let Some((_, allowed)) = ELEMENTS.iter().find(|(element, _)| *element == name) else {
+        panic!("unexpected element <{}>", name);
+    };
+    for attribute in node.attributes() {
+        let attribute_name = attribute_name(&attribute);
+        let value = attribute.value();
+        assert!(
+            allowed.contains(&attribute_name.as_str())
+                || (name == "g" && attribute_name.starts_with("data-")),
+            "unexpected attribute {} of <{}>",
+            attribute_name,
+            name
+        );
+        if NUMBERS.contains(&attribute_name.as_str()) {
+            number(value);
+        }
+        match attribute_name.as_str() {
+            "viewBox" => {
+                let numbers: Vec<f64> = value.split_whitespace().map(number).collect();
+                assert_eq!(numbers.len(), 4, "viewBox `{}`", value);
+            }
+            "points" => {
+                for point in value.split_whitespace() {
+                    let (x, y) = point.split_once(',').expect("a point is a pair");
+                    number(x);
+                    number(y);
+                }
+            }
+            "dur" => {
+                let seconds = number(value.strip_suffix('s').expect("dur is in seconds"));
+                assert!(seconds > 0.0);
+            }
+            "keyTimes" => {
+                let times: Vec<f64> = value.split(';').map(number).collect();
+                assert_eq!(times[0], 0.0);
+                assert!(times.windows(2).all(|pair| pair[0] <= pair[1]));
+                assert!(times.iter().all(|time| (0.0..=1.0).contains(time)));
+                let values = node.attribute("values").expect("keyTimes without values");
+                assert_eq!(times.len(), values.split(';').count());
+            }
+            _ => {}
+        }
+    }
+    if name == "a" {
+        assert_eq!(node.attribute("href"), node.attribute((XLINK, "href")));
+    }
+    for child in node.children() {
+        match child.node_type() {
+            NodeType::Element => {
+                assert!(
+                    !matches!(name, "style" | "text"),
+                    "<{}> in <{}>",
+                    child.tag_name().name(),
+                    name
+                );
+            }
+            NodeType::Text => {
+                let text = child.text().unwrap_or_default();
+                assert!(
+                    matches!(name, "style" | "text") || text.trim().is_empty(),
+                    "text `{}` in <{}>",
+                    text,
+                    name
+                );
+            }
+            NodeType::Comment | NodeType::PI => panic!("unexpected node in <{}>", name),
+            NodeType::Root => unreachable!(),
+        }
+    }
+}
+
+/// Checks a chart as a document and as an inline element, with and without
+/// an id.
+fn check_svg(svg: &Svg) -> Vec<String> {
+    let texts = check(&svg.to_string());
+    assert_eq!(check(&svg.inline().to_string()), texts);
+    let scoped = svg.inline().id("chart-1").to_string();
+    assert_eq!(check(&scoped), texts);
+    assert!(!scoped.contains("url(#arrow)"));
+    texts
+}
+
+fn read(path: &str, source: &str) -> Input {
+    frontend::read(Path::new(path), source).unwrap_or_else(|e| panic!("{}", e))
+}
+
+/// Checks every chart of `input`, in layers and not, and returns the
+/// lines of text they show.
+fn check_input(input: &Input) -> Vec<String> {
+    let config = ConfigBuilder::new().build();
+    let mut texts = vec![];
+    for layered in [false, true] {
+        let href = |stem: &str| format!("{}.svg?a=1&b=<{}>", stem, stem);
+        for (_, svg) in input.to_svgs(&config, layered, &href) {
+            texts.extend(check_svg(&svg));
+        }
+    }
+    texts
+}
+
+fn assert_shown(texts: &[String], label: &str) {
+    assert!(
+        texts.iter().any(|text| text.contains(label)),
+        "`{}` is not shown in {:?}",
+        label,
+        texts
+    );
+}
+
+#[test]
+fn program() {
+    let mut source = String::from("# <svg> & \"comments\"\nproc main\n");
+    for label in LABELS {
+        source.push_str(&format!("    io {}\n    process {}\n", label, label));
+        source.push_str(&format!("    if {}\n        call helper\n    end\n", label));
+    }
+    source.push_str(
+        "    switch s\n    case \"<a>\"\n        process a <- b\n    case default\n    end\n",
+    );
+    source.push_str("    while i < n & m\n        continue\n    end\n");
+    source.push_str("    return \"<done>\"\nend\n\nproc helper\n    process a\\nb\nend\n");
+    let texts = check_input(&read("labels.flow", &source));
+    for label in LABELS {
+        assert_shown(&texts, label);
+    }
+
+    // Every direction lays the blocks out differently.
+    let program = parser::parse(&source).unwrap();
+    let mut rendered: Vec<String> = vec![];
+    for direction in [
+        Direction::TopToBottom,
+        Direction::BottomToTop,
+        Direction::LeftToRight,
+        Direction::RightToLeft,
+    ] {
+        let config = ConfigBuilder::new().direction(direction).build();
+        let mut charts = String::new();
+        for (_, svg) in program.to_svgs(&config) {
+            check_svg(&svg);
+            charts.push_str(&svg.to_string());
+        }
+        assert!(!rendered.contains(&charts));
+        rendered.push(charts);
+    }
+}
+
+#[test]
+fn sources() {
+    let c = r#"
+/* ]]> <!-- */
+int main(int argc, char **argv)
+{
+    // "quoted" & 'single'
+    if (argc < 2 && argv[0][0] > '<')
+        puts("<usage>");
+    for (int i = 0; i < argc; i++)
+        if (i & 1)
+            continue;
+    return helper(argc) >> 1;
+}
+
+int helper(int n) { return n; }
+"#;
+    let texts = check_input(&read("a.c", c));
+    assert_shown(&texts, "argc < 2 && argv[0][0] > '<'");
+
+    let python = r#"
+def main(xs):
+    """<doc> & ]]>"""
+    for x in xs:
+        if x < 3 and x > "<a>":
+            print(f"{x!r} & <{x}>")
+        elif x == '&amp;':
+            break
+    while True:
+        helper(xs)
+    return xs
+
+def helper(xs):
+    return [x for x in xs if x < 1]
+"#;
+    let texts = check_input(&read("a.py", python));
+    assert_shown(&texts, r#"x < 3 and x > "<a>""#);
+
+    let rust = r#"
+fn main() {
+    // <!-- & -->
+    let s = "<\"'&>";
+    if s.len() < 3 && s > "a" {
+        helper(&s);
+    }
+    for c in s.chars() {
+        match c {
+            '<' => continue,
+            _ => break,
+        }
+    }
+}
+
+fn helper(s: &&str) -> bool { s.is_empty() }
+"#;
+    let texts = check_input(&read("a.rs", rust));
+    assert_shown(&texts, "s.len() < 3 && s > \"a\"");
+
+    let asm = r#"
+main:
+	cmpl	$0, %edi	# argc <= 0 & "none"
+	jle	.L2
+	call	helper
+.L2:
+	ret
+helper:
+	movl	$1, %eax	# <helper>
+	ret
+"#;
+    check_input(&read("a.s", asm));
+}
+
+#[test]
+fn graph() {
+    let json = r#"{
+        "nodes": [
+            {"id": 0, "kind": "Terminal", "label": "<start> & \"go\""},
+            {"id": 1, "kind": "Decision", "label": "i < n && ok"},
+            {"id": 2, "kind": "Process", "label": "it's ]]> done"},
+            {"id": 3, "kind": "IO", "label": "<!-- print -->"},
+            {"id": 4, "kind": "Terminal", "label": "end"}
+        ],
+        "edges": [
+            {"from": 0, "to": 1, "label": null},
+            {"from": 1, "to": 2, "label": "<yes>"},
+            {"from": 1, "to": 3, "label": "\"no\""},
+            {"from": 2, "to": 1, "label": "&"},
+            {"from": 3, "to": 4, "label": null}
+        ]
+    }"#;
+    let texts = check_input(&read("a.json", json));
+    assert_shown(&texts, "i < n && ok");
+    assert_shown(&texts, "it's ]]> done");
+    assert_shown(&texts, "<!-- print -->");
+}
+
+#[test]
+fn animation() {
+    let source = "proc main\n    io read a, b\n    while b != 0\n        process a, b = b, a % b\n    end\n    io print \"<gcd> &\", a\n    call other\nend\n\nproc other\n    process x = \"]]>\"\nend\n";
+    let program = parser::parse(source).unwrap();
+    let inputs = [Value::parse("12"), Value::parse("8")];
+    let trace = Interpreter::new(&program).run("main", &inputs).unwrap();
+    let config = ConfigBuilder::new().build();
+    for procedure in &program.procedures {
+        let svg = Animator::new(&config)
+            .step_duration(0.25)
+            .animate(procedure, &trace);
+        let texts = check_svg(&svg);
+        if procedure.name == "main" {
+            assert_shown(&texts, "\"<gcd> &\"");
+        }
+    }
+}
+
+#[test]
+fn shapes() {
+    let config = ConfigBuilder::new().build();
+    let mut svg = Svg::new(&config);
+    svg.set_size(100, 50);
+    svg.add_style(
+        "g[data-x=\"<&>\"] > text",
+        &[
+            ("font-family", "'Fira </style>', \"Mono\""),
+            ("fill", "url(#arrow)"),
+        ],
+    );
+    svg.add_style(".a]]>b", &[("content", "'<!--'")]);
+    let rect = |x| SvgShape::Rect {
+        x,
+        y: 0,
+        width: 10,
+        height: 10,
+    };
+    let shapes = vec![
+        SvgShape::Grid {
+            size: 10,
+            x_count: 2,
+            y_count: 3,
+        },
+        SvgShape::HLine {
+            x: 0,
+            y: 0,
+            width: 5,
+        },
+        SvgShape::VLine {
+            x: 0,
+            y: 0,
+            height: 5,
+        },
+        SvgShape::Polyline(vec![(0, 0), (5, 5), (10, 0)]),
+        rect(0),
+        SvgShape::Diamond {
+            x: 0,
+            y: 0,
+            width: 10,
+            height: 10,
+        },
+        SvgShape::Parallelogram {
+            x: 0,
+            y: 0,
+            theta: 1.0,
+            width: 20,
+            height: 10,
+        },
+        SvgShape::Stadium {
+            x: 0,
+            y: 0,
+            width: 20,
+            height: 10,
+        },
+        SvgShape::Subroutine {
+            x: 0,
+            y: 0,
+            width: 20,
+            height: 10,
+        },
+        SvgShape::Annotation {
+            x: 0,
+            y: 0,
+            width: 20,
+            height: 10,
+            leader: 5,
+        },
+        SvgShape::Frame {
+            x: 0,
+            y: 0,
+            width: 20,
+            height: 10,
+            r: 2,
+        },
+        SvgShape::Link {
+            href: String::from("other.svg?a=\"1\"&b='<2>'"),
+            shape: Box::new(rect(1)),
+        },
+        SvgShape::DownArrow {
+            x: 0,
+            y: 0,
+            height: 5,
+        },
+        SvgShape::UpArrow {
+            x: 0,
+            y: 0,
+            height: 5,
+        },
+        SvgShape::RightArrow {
+            x: 0,
+            y: 0,
+            width: 5,
+        },
+        SvgShape::LeftArrow {
+            x: 0,
+            y: 0,
+            width: 5,
+        },
+        SvgShape::Circle { cx: 5, cy: 5, r: 5 },
+        SvgShape::Text {
+            cx: 5,
+            cy: 5,
+            content: String::from("tab\tbell\u{7}<&>"),
+        },
+        SvgShape::Background {
+            x: 0,
+            y: 0,
+            width: 100,
+            height: 50,
+        },
+        SvgShape::Timed {
+            class: String::from("step \"<1>\""),
+            period: 4.0,
+            spans: vec![(0.0, 1.0), (1.0, 2.0), (3.0, 5.0)],
+            shape: Box::new(rect(2)),
+        },
+        SvgShape::Pulse {
+            period: 1.5,
+            shape: Box::new(rect(3)),
+        },
+    ];
+    svg.push_shape(SvgShape::Classed {
+        class: String::from("block 'a' & <b>"),
+        data: vec![
+            (String::from("file"), String::from("dir/<a & b>.flow")),
+            (String::from("text"), String::from("line\none\r\n\"two\"")),
+        ],
+        shapes,
+    });
+    let texts = check_svg(&svg);
+    assert_eq!(texts, ["tab\tbell\u{fffd}<&>"]);
+
+    let text = svg.to_string();
+    let document = Document::parse(&text).unwrap();
+    let find = |name: &str| {
+        document
+            .descendants()
+            .find(|node| node.has_tag_name((SVG, name)))
+            .unwrap()
+    };
+    let style = find("style").text().unwrap();
+    assert!(style.contains("g[data-x=\"<&>\"] > text {"));
+    assert!(style.contains("font-family: 'Fira </style>', \"Mono\";"));
+    assert!(style.contains(".a]]>b {"));
+    let group = find("g");
+    assert_eq!(group.attribute("class"), Some("block 'a' & <b>"));
+    assert_eq!(group.attribute("data-file"), Some("dir/<a & b>.flow"));
+    assert_eq!(group.attribute("data-text"), Some("line\none\r\n\"two\""));
+    let link = find("a");
+    assert_eq!(link.attribute("href"), Some("other.svg?a=\"1\"&b='<2>'"));
+}
+
+#[test]
+fn writer() {
+    let config = ConfigBuilder::new().build();
+    let mut svg = Svg::new(&config);
+    svg.set_size(30, 30);
+    let shape = SvgShape::Link {
+        href: String::from("#<x>"),
+        shape: Box::new(SvgShape::Text {
+            cx: 1,
+            cy: 2,
+            content: String::from("</svg> & ]]>"),
+        }),
+    };
+    for compact in [false, true] {
+        for inline in [false, true] {
+            let mut writer = SvgWriter::new(vec![]).compact(compact).inline(inline);
+            writer.start(&svg).unwrap();
+            for _ in 0..3 {
+                writer.shape(&shape).unwrap();
+            }
+            let document = String::from_utf8(writer.finish().unwrap()).unwrap();
+            assert_eq!(document.starts_with("<?xml"), !inline);
+            assert_eq!(document.contains('\n'), !compact);
+            assert_eq!(check(&document), ["</svg> & ]]>"; 3]);
+        }
+    }
+}